@@ -12,7 +12,7 @@ use crate::{Engine, Error, Result};
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct Lexer<'engine, 'source> {
     /// A reference to the engine containing the syntax searcher.
-    engine: &'engine Engine<'engine>,
+    pub engine: &'engine Engine<'engine>,
 
     /// The original template source.
     pub source: &'source str,
@@ -26,6 +26,20 @@ pub struct Lexer<'engine, 'source> {
     /// Whether to left trim the next raw token.
     left_trim: bool,
 
+    /// Whether to remove a single newline from the start of the next raw
+    /// token, set after an end block tag when `trim_blocks` is enabled.
+    trim_newline: bool,
+
+    /// The whitespace that was trimmed from raw tokens, in source order.
+    pub trims: Vec<Span>,
+
+    /// The begin tag of a `{% raw %}` block whose end tag has not been lexed.
+    raw_begin: Option<Span>,
+
+    /// The previous non-whitespace token in the current tag, which determines
+    /// whether a word is lexed as a keyword or an identifier.
+    prev: Option<(Token, Span)>,
+
     /// A buffer to store the next token.
     next: Option<(Token, Span)>,
 }
@@ -63,6 +77,12 @@ enum State {
         /// The end token we are expecting.
         end: Token,
     },
+
+    /// Within a `{% raw %}` block.
+    Raw {
+        /// The span of the `{% raw %}` block.
+        begin: Span,
+    },
 }
 
 #[derive(Clone, Copy)]
@@ -93,6 +113,18 @@ pub enum Token {
     Dot,
     /// `?.`
     QuestionDot,
+    /// `??`
+    QuestionQuestion,
+    /// `~`
+    Tilde,
+    /// `(`
+    OpenParen,
+    /// `)`
+    CloseParen,
+    /// `..`
+    DotDot,
+    /// `..=`
+    DotDotEq,
     /// `|`
     Pipe,
     /// `,`
@@ -103,6 +135,20 @@ pub enum Token {
     Plus,
     /// `-`
     Minus,
+    /// `=`
+    Assign,
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
     /// Sequence of tab (0x09) and/or spaces (0x20)
     Whitespace,
     /// A keyword like `if` or `for`
@@ -126,6 +172,10 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
             cursor: 0,
             state: State::Template,
             left_trim: false,
+            trim_newline: false,
+            trims: Vec::new(),
+            raw_begin: None,
+            prev: None,
             next: None,
         }
     }
@@ -158,6 +208,7 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
             State::Block { begin, end } => self.lex_block(BlockState::Unknown, begin, end, i),
             State::BlockPath { begin, end } => self.lex_block(BlockState::Path, begin, end, i),
             State::Comment { begin, end } => self.lex_comment(begin, end, i),
+            State::Raw { begin } => self.lex_raw(begin, i),
         }
     }
 
@@ -171,18 +222,6 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
         //    ^   ^ ^
         //    i   j k
 
-        let mut trim_raw_token = |mut i, mut j, right_trim| {
-            if right_trim {
-                j = self.source[..j].trim_end().len();
-            }
-            if self.left_trim {
-                self.left_trim = false;
-                let s = &self.source[i..j];
-                i += s.len() - s.trim_start().len();
-            }
-            Ok(Some((Token::Raw, Span::from(i..j))))
-        };
-
         match self.engine.searcher.find_at(self.source, i) {
             Some((kind, j, k)) => {
                 let (tk, trim) = Token::from_kind(kind);
@@ -202,6 +241,7 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
                     } else {
                         State::Block { begin, end }
                     };
+                    self.prev = Some((tk, begin));
                     Ok(Some((tk, begin)))
                 };
 
@@ -212,15 +252,120 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
                     // We must first emit the raw token, so we store the
                     // begin tag token in the `next` buffer.
                     self.next = lex(j, k)?;
-                    trim_raw_token(i, j, trim)
+                    let lstrip = tk == Token::BeginBlock && !trim;
+                    let trim = trim || (tk.is_begin_comment() && self.engine.trim_comments);
+                    Ok(Some(self.trim_raw_token(i, j, trim, lstrip)))
                 }
             }
             None => {
                 let j = self.source.len();
                 self.cursor = j;
-                trim_raw_token(i, j, false)
+                Ok(Some(self.trim_raw_token(i, j, false, false)))
+            }
+        }
+    }
+
+    fn lex_raw(&mut self, begin: Span, i: usize) -> Result<Option<(Token, Span)>> {
+        // We are within a `{% raw %}` block, that means we must find the next
+        // begin block tag that is followed by the `endraw` keyword and an end
+        // block tag. Everything before it is emitted as a single raw token.
+        //
+        // xxxx{{xxx{% endraw %}
+        //    ^     ^  ^
+        //    i     j  k
+
+        let mut m = i;
+        loop {
+            let (kind, j, k) = self
+                .engine
+                .searcher
+                .find_at(self.source, m)
+                .ok_or_else(|| Error::syntax("unclosed `raw` block", self.source, begin))?;
+            let (tk, trim) = Token::from_kind(kind);
+
+            if tk != Token::BeginBlock || !self.is_endraw_at(k) {
+                m = k;
+                continue;
+            }
+
+            let begin = Span::from(j..k);
+            self.cursor = k;
+            self.state = State::Block {
+                begin,
+                end: Token::EndBlock,
+            };
+            self.prev = Some((tk, begin));
+
+            if i == j {
+                // The current cursor is exactly at the token.
+                return Ok(Some((tk, begin)));
+            } else {
+                // We must first emit the raw token, so we store the begin tag
+                // token in the `next` buffer.
+                self.next = Some((tk, begin));
+                return Ok(Some(self.trim_raw_token(i, j, trim, !trim)));
+            }
+        }
+    }
+
+    /// Returns whether the source at `k` is the rest of an `endraw` block.
+    fn is_endraw_at(&self, k: usize) -> bool {
+        let rest = self.source[k..].trim_start_matches(is_whitespace);
+        match self.strip_keyword(rest, "endraw") {
+            Some(rest) => {
+                let rest = rest.trim_start_matches(is_whitespace);
+                let n = self.source.len() - rest.len();
+                self.engine
+                    .searcher
+                    .starts_with(self.source, n)
+                    .map(|(kind, _)| Token::from_kind(kind).0 == Token::EndBlock)
+                    .unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns a raw token for the given range, trimming it as necessary.
+    ///
+    /// If `lstrip` is set and `lstrip_blocks` is enabled then any spaces and
+    /// tabs between the start of the line and the end of the token are
+    /// removed. Any whitespace that is removed is recorded in `trims`.
+    fn trim_raw_token(
+        &mut self,
+        mut i: usize,
+        mut j: usize,
+        right_trim: bool,
+        lstrip: bool,
+    ) -> (Token, Span) {
+        let (i0, j0) = (i, j);
+        if right_trim {
+            j = self.source[..j].trim_end().len();
+        }
+        if self.left_trim {
+            self.left_trim = false;
+            let s = &self.source[i..j];
+            i += s.len() - s.trim_start().len();
+        } else if std::mem::take(&mut self.trim_newline) {
+            let s = &self.source[i..j];
+            if s.starts_with("\r\n") {
+                i += 2;
+            } else if s.starts_with('\n') {
+                i += 1;
+            }
+        }
+        if lstrip && self.engine.lstrip_blocks {
+            let k = self.source[..j].trim_end_matches(is_whitespace).len();
+            if k >= i0 && (k == 0 || self.source[..k].ends_with('\n')) {
+                j = k.max(i);
             }
         }
+        if i0 < i {
+            self.trims.push(Span::from(i0..i));
+        }
+        if j < j0 {
+            self.trims.push(Span::from(j..j0));
+        }
+        (Token::Raw, Span::from(i..j))
     }
 
     fn lex_block(
@@ -246,9 +391,16 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
                 }
 
                 // A matching end tag! Update the state and
-                // return the token.
-                self.state = State::Template;
+                // return the token. If this is the end of a `{% raw %}`
+                // block then the following template must not be lexed.
+                self.state = match self.raw_begin.take() {
+                    Some(raw) => State::Raw {
+                        begin: raw.combine(Span::from(i..j)),
+                    },
+                    None => State::Template,
+                };
                 self.left_trim = trim;
+                self.trim_newline = tk == Token::EndBlock && !trim && self.engine.trim_blocks;
                 (tk, j)
             }
             None => {
@@ -263,15 +415,25 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
 
                 match c {
                     // Single character to token mappings.
-                    '.' => (Token::Dot, i + 1),
                     '|' => (Token::Pipe, i + 1),
                     ',' => (Token::Comma, i + 1),
                     ':' => (Token::Colon, i + 1),
                     '+' => (Token::Plus, i + 1),
+                    '~' => (Token::Tilde, i + 1),
+                    '(' => (Token::OpenParen, i + 1),
+                    ')' => (Token::CloseParen, i + 1),
+                    // A negative index in a path, e.g. `-1` in `users.-1`.
+                    '-' if matches!(block_state, BlockState::Path)
+                        && matches!(iter.clone().next(), Some((_, c)) if c.is_ascii_digit()) =>
+                    {
+                        self.lex_index(iter)
+                    }
                     '-' => (Token::Minus, i + 1),
 
                     // Multi-character tokens with a distinct start character.
-                    '?' => self.lex_question_dot(iter, i)?,
+                    '.' => self.lex_dot(iter, i),
+                    '?' => self.lex_question(iter, i)?,
+                    '=' | '!' | '<' | '>' => self.lex_comparison(iter, i, c)?,
                     '"' => self.lex_string(iter, i)?,
                     c if c.is_ascii_digit() => match block_state {
                         BlockState::Path => self.lex_index(iter),
@@ -289,10 +451,38 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
         };
 
         match (block_state, tk) {
+            (BlockState::Unknown, Token::Keyword)
+                if end == Token::EndBlock
+                    && self.is_keyword(&self.source[i..j], "raw")
+                    && self.source[begin.n..i]
+                        .trim_matches(is_whitespace)
+                        .is_empty() =>
+            {
+                self.raw_begin = Some(begin);
+            }
             (BlockState::Unknown, Token::Ident) => {
                 self.state = State::BlockPath { begin, end };
             }
-            (BlockState::Path, Token::Pipe | Token::Comma | Token::Colon) => {
+            (
+                BlockState::Path,
+                Token::Pipe
+                | Token::Comma
+                | Token::Colon
+                | Token::Keyword
+                | Token::DotDot
+                | Token::DotDotEq
+                | Token::QuestionQuestion
+                | Token::Tilde
+                | Token::OpenParen
+                | Token::CloseParen
+                | Token::Assign
+                | Token::Eq
+                | Token::Ne
+                | Token::Lt
+                | Token::Le
+                | Token::Gt
+                | Token::Ge,
+            ) => {
                 self.state = State::Block { begin, end };
             }
             _ => {}
@@ -300,6 +490,9 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
 
         // Finally, we need to update the cursor.
         self.cursor = j;
+        if !tk.is_whitespace() {
+            self.prev = Some((tk, Span::from(i..j)));
+        }
 
         Ok(Some((tk, Span::from(i..j))))
     }
@@ -329,7 +522,8 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
                 let mut lex = |m, n| {
                     self.cursor = n;
                     self.state = State::Template;
-                    self.left_trim = trim;
+                    self.left_trim = trim || self.engine.trim_comments;
+                    self.trim_newline = false;
                     let end = Span::from(m..n);
                     Ok(Some((tk, end)))
                 };
@@ -352,17 +546,48 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
         }
     }
 
-    fn lex_question_dot<I>(&mut self, mut iter: I, i: usize) -> Result<(Token, usize)>
+    fn lex_dot<I>(&mut self, mut iter: I, i: usize) -> (Token, usize)
+    where
+        I: Iterator<Item = (usize, char)> + Clone,
+    {
+        if !matches!(iter.next(), Some((_, '.'))) {
+            return (Token::Dot, i + 1);
+        }
+        match iter.next() {
+            Some((_, '=')) => (Token::DotDotEq, i + 3),
+            _ => (Token::DotDot, i + 2),
+        }
+    }
+
+    fn lex_question<I>(&mut self, mut iter: I, i: usize) -> Result<(Token, usize)>
     where
         I: Iterator<Item = (usize, char)> + Clone,
     {
         match iter.next() {
             Some((_, '.')) => Ok((Token::QuestionDot, i + 2)),
+            Some((_, '?')) => Ok((Token::QuestionQuestion, i + 2)),
             Some((j, c)) => Err(self.err_unexpected_character(i..j + c.len_utf8())),
             None => Err(self.err_unexpected_character(i..self.source.len())),
         }
     }
 
+    fn lex_comparison<I>(&mut self, mut iter: I, i: usize, c: char) -> Result<(Token, usize)>
+    where
+        I: Iterator<Item = (usize, char)> + Clone,
+    {
+        let eq = matches!(iter.next(), Some((_, '=')));
+        match (c, eq) {
+            ('=', true) => Ok((Token::Eq, i + 2)),
+            ('!', true) => Ok((Token::Ne, i + 2)),
+            ('<', true) => Ok((Token::Le, i + 2)),
+            ('>', true) => Ok((Token::Ge, i + 2)),
+            ('=', false) => Ok((Token::Assign, i + 1)),
+            ('<', false) => Ok((Token::Lt, i + 1)),
+            ('>', false) => Ok((Token::Gt, i + 1)),
+            _ => Err(self.err_unexpected_character(i..i + 1)),
+        }
+    }
+
     fn lex_string<I>(&mut self, mut iter: I, i: usize) -> Result<(Token, usize)>
     where
         I: Iterator<Item = (usize, char)> + Clone,
@@ -386,11 +611,25 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
         }
     }
 
-    fn lex_number<I>(&mut self, iter: I) -> (Token, usize)
+    fn lex_number<I>(&mut self, mut iter: I) -> (Token, usize)
     where
         I: Iterator<Item = (usize, char)> + Clone,
     {
-        (Token::Number, self.lex_while(iter, is_number))
+        // A number can contain a `.` but a `..` is always a range operator,
+        // e.g. `0..10`.
+        loop {
+            let mut peek = iter.clone();
+            match peek.next() {
+                Some((j, '.')) if matches!(peek.next(), Some((_, '.'))) => {
+                    return (Token::Number, j);
+                }
+                Some((_, c)) if is_number(c) => {
+                    iter.next().unwrap();
+                }
+                Some((j, _)) => return (Token::Number, j),
+                None => return (Token::Number, self.source.len()),
+            }
+        }
     }
 
     fn lex_index<I>(&mut self, iter: I) -> (Token, usize)
@@ -412,13 +651,51 @@ impl<'engine, 'source> Lexer<'engine, 'source> {
         I: Iterator<Item = (usize, char)> + Clone,
     {
         let j = self.lex_while(iter, is_ident);
-        let tk = match Keyword::all().contains(&&self.source[i..j]) {
-            true => Token::Keyword,
-            false => Token::Ident,
+        let ident = &self.source[i..j];
+        let is_any = |kws: &[&str]| kws.iter().any(|kw| self.is_keyword(ident, kw));
+        let tk = match self.prev {
+            // Any keyword can be used as a member name, e.g. `user.if`.
+            Some((Token::Dot | Token::QuestionDot, _)) => Token::Ident,
+            _ if is_any(Keyword::reserved()) => Token::Keyword,
+            Some((Token::BeginBlock, _)) if is_any(Keyword::statements()) => Token::Keyword,
+            Some((tk, span)) if self.is_operand_end(tk, span) && is_any(Keyword::operators()) => {
+                Token::Keyword
+            }
+            _ => Token::Ident,
         };
         (tk, j)
     }
 
+    /// Returns whether the given token can be the end of an operand, in which
+    /// case it can be followed by an operator keyword.
+    fn is_operand_end(&self, tk: Token, span: Span) -> bool {
+        match tk {
+            Token::Ident | Token::Index | Token::Number | Token::String | Token::CloseParen => true,
+            Token::Keyword => {
+                let kw = &self.source[span];
+                self.is_keyword(kw, "true") || self.is_keyword(kw, "false")
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether the string is the given keyword, taking into account
+    /// whether keywords are case insensitive.
+    fn is_keyword(&self, s: &str, kw: &str) -> bool {
+        match self.engine.case_insensitive_keywords {
+            true => s.eq_ignore_ascii_case(kw),
+            false => s == kw,
+        }
+    }
+
+    /// Strips the given keyword from the start of the string.
+    fn strip_keyword<'a>(&self, s: &'a str, kw: &str) -> Option<&'a str> {
+        match s.get(..kw.len()) {
+            Some(prefix) if self.is_keyword(prefix, kw) => Some(&s[kw.len()..]),
+            _ => None,
+        }
+    }
+
     fn lex_while<I, P>(&mut self, mut iter: I, pred: P) -> usize
     where
         I: Iterator<Item = (usize, char)> + Clone,
@@ -466,11 +743,24 @@ impl Token {
             Self::EndComment => "end comment",
             Self::Dot => "member access operator",
             Self::QuestionDot => "optional member access operator",
+            Self::QuestionQuestion => "coalescing operator",
+            Self::Tilde => "concatenation operator",
+            Self::OpenParen => "open parenthesis",
+            Self::CloseParen => "close parenthesis",
+            Self::DotDot => "range operator",
+            Self::DotDotEq => "inclusive range operator",
             Self::Pipe => "pipe",
             Self::Comma => "comma",
             Self::Colon => "colon",
             Self::Minus => "minus",
             Self::Plus => "plus",
+            Self::Assign => "assignment operator",
+            Self::Eq => "equality operator",
+            Self::Ne => "inequality operator",
+            Self::Lt => "less than operator",
+            Self::Le => "less than or equal operator",
+            Self::Gt => "greater than operator",
+            Self::Ge => "greater than or equal operator",
             Self::Whitespace => "whitespace",
             Self::Keyword => "keyword",
             Self::Ident => "identifier",
@@ -551,7 +841,7 @@ fn is_ident(c: char) -> bool {
 }
 
 fn is_index(c: char) -> bool {
-    c.is_ascii_digit()
+    matches!(c, '0'..='9' | 'A'..='Z' | 'a'..='z' | '_')
 }
 
 fn is_number(c: char) -> bool {
@@ -710,6 +1000,103 @@ mod tests {
         )
     }
 
+    #[test]
+    fn lex_expr_path_with_negative_index() {
+        let tokens = lex(r#"lorem {{ ipsum.-12?.-1 | dolor: -1 }}"#).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::Raw, "lorem "),
+                (Token::BeginExpr, "{{"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "ipsum"),
+                (Token::Dot, "."),
+                (Token::Index, "-12"),
+                (Token::QuestionDot, "?."),
+                (Token::Index, "-1"),
+                (Token::Whitespace, " "),
+                (Token::Pipe, "|"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "dolor"),
+                (Token::Colon, ":"),
+                (Token::Whitespace, " "),
+                (Token::Minus, "-"),
+                (Token::Number, "1"),
+                (Token::Whitespace, " "),
+                (Token::EndExpr, "}}"),
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_expr_coalesce() {
+        let tokens = lex(r#"{{ ipsum.0??1 ?? "dolor" }}"#).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginExpr, "{{"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "ipsum"),
+                (Token::Dot, "."),
+                (Token::Index, "0"),
+                (Token::QuestionQuestion, "??"),
+                (Token::Number, "1"),
+                (Token::Whitespace, " "),
+                (Token::QuestionQuestion, "??"),
+                (Token::Whitespace, " "),
+                (Token::String, "\"dolor\""),
+                (Token::Whitespace, " "),
+                (Token::EndExpr, "}}"),
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_expr_concat() {
+        let tokens = lex(r#"{{ ipsum.0~"dolor" ~ sit }}"#).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginExpr, "{{"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "ipsum"),
+                (Token::Dot, "."),
+                (Token::Index, "0"),
+                (Token::Tilde, "~"),
+                (Token::String, "\"dolor\""),
+                (Token::Whitespace, " "),
+                (Token::Tilde, "~"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "sit"),
+                (Token::Whitespace, " "),
+                (Token::EndExpr, "}}"),
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_expr_group() {
+        let tokens = lex(r#"{{ (ipsum.0)~(dolor) }}"#).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginExpr, "{{"),
+                (Token::Whitespace, " "),
+                (Token::OpenParen, "("),
+                (Token::Ident, "ipsum"),
+                (Token::Dot, "."),
+                (Token::Index, "0"),
+                (Token::CloseParen, ")"),
+                (Token::Tilde, "~"),
+                (Token::OpenParen, "("),
+                (Token::Ident, "dolor"),
+                (Token::CloseParen, ")"),
+                (Token::Whitespace, " "),
+                (Token::EndExpr, "}}"),
+            ]
+        )
+    }
+
     #[test]
     fn lex_expr_trim() {
         let tokens = lex("lorem ipsum    {{- .|\t aZ_0 -}}    dolor sit amet").unwrap();
@@ -784,6 +1171,137 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_contextual_keywords() {
+        let tokens = lex("{% set and = a.if or is %}{{ set and true is x }}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "set"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "and"),
+                (Token::Whitespace, " "),
+                (Token::Assign, "="),
+                (Token::Whitespace, " "),
+                (Token::Ident, "a"),
+                (Token::Dot, "."),
+                (Token::Ident, "if"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "or"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "is"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+                (Token::BeginExpr, "{{"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "set"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "and"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "true"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "is"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "x"),
+                (Token::Whitespace, " "),
+                (Token::EndExpr, "}}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_comparison_operators() {
+        let tokens = lex("{% if a.b == 1 %}{% if c != d < e <= f > g >= -2 %}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "if"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "a"),
+                (Token::Dot, "."),
+                (Token::Ident, "b"),
+                (Token::Whitespace, " "),
+                (Token::Eq, "=="),
+                (Token::Whitespace, " "),
+                (Token::Number, "1"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "if"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "c"),
+                (Token::Whitespace, " "),
+                (Token::Ne, "!="),
+                (Token::Whitespace, " "),
+                (Token::Ident, "d"),
+                (Token::Whitespace, " "),
+                (Token::Lt, "<"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "e"),
+                (Token::Whitespace, " "),
+                (Token::Le, "<="),
+                (Token::Whitespace, " "),
+                (Token::Ident, "f"),
+                (Token::Whitespace, " "),
+                (Token::Gt, ">"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "g"),
+                (Token::Whitespace, " "),
+                (Token::Ge, ">="),
+                (Token::Whitespace, " "),
+                (Token::Minus, "-"),
+                (Token::Number, "2"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_range_operators() {
+        let tokens = lex("{% for i in 0..10 %}{% for j in 1.5..=n.0..x %}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "for"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "i"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "in"),
+                (Token::Whitespace, " "),
+                (Token::Number, "0"),
+                (Token::DotDot, ".."),
+                (Token::Number, "10"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "for"),
+                (Token::Whitespace, " "),
+                (Token::Ident, "j"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "in"),
+                (Token::Whitespace, " "),
+                (Token::Number, "1.5"),
+                (Token::DotDotEq, "..="),
+                (Token::Ident, "n"),
+                (Token::Dot, "."),
+                (Token::Index, "0"),
+                (Token::DotDot, ".."),
+                (Token::Ident, "x"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+            ]
+        );
+    }
+
     #[test]
     fn lex_begin_comment() {
         let tokens = lex("lorem ipsum {#").unwrap();
@@ -871,6 +1389,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_raw_block() {
+        let tokens = lex("{% raw %}{{ lorem }} {% if %}{% endraw %}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "raw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+                (Token::Raw, "{{ lorem }} {% if %}"),
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "endraw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_raw_block_trim() {
+        let tokens = lex("{% raw -%}\t{{ lorem }}\n{%- endraw %}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "raw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "-%}"),
+                (Token::Raw, "{{ lorem }}"),
+                (Token::BeginBlock, "{%-"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "endraw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_raw_block_empty() {
+        let tokens = lex("{% raw %}{% endraw %}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "raw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "endraw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_raw_block_not_endraw() {
+        let tokens = lex("{% raw %}{% endrawx %}{%endraw%}").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::BeginBlock, "{%"),
+                (Token::Whitespace, " "),
+                (Token::Keyword, "raw"),
+                (Token::Whitespace, " "),
+                (Token::EndBlock, "%}"),
+                (Token::Raw, "{% endrawx %}"),
+                (Token::BeginBlock, "{%"),
+                (Token::Keyword, "endraw"),
+                (Token::EndBlock, "%}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_raw_block_err_unclosed() {
+        let err = lex("{% raw %}{{ lorem }}").unwrap_err();
+        assert_eq!(err.to_string(), "invalid syntax: unclosed `raw` block");
+    }
+
     #[track_caller]
     fn lex(source: &str) -> Result<Vec<(Token, &str)>> {
         let engine = Engine::default();
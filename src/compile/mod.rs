@@ -16,7 +16,7 @@ pub use crate::compile::search::Searcher;
 use crate::types::ast;
 use crate::types::program::{Instr, Template, FIXME};
 use crate::types::span::Span;
-use crate::{Engine, Result};
+use crate::{Engine, Error, Result, Value};
 
 /// Compile a template into a program.
 pub fn template<'engine, 'source>(
@@ -24,33 +24,287 @@ pub fn template<'engine, 'source>(
     source: Cow<'source, str>,
 ) -> Result<Template<'source>> {
     let ast = parse::Parser::new(engine, &source).parse_template()?;
+    if engine.strict {
+        Checker::new(&source).check_scope(&ast.scope)?;
+    }
     Ok(Compiler::new().compile_template(source, ast))
 }
 
+/// Checks that every name bound using `with` or `set` is used in its scope.
+#[cfg_attr(internal_debug, derive(Debug))]
+struct Checker<'source> {
+    source: &'source str,
+    bindings: Vec<Binding<'source>>,
+}
+
+/// A name that is currently in scope.
+#[cfg_attr(internal_debug, derive(Debug))]
+struct Binding<'source> {
+    name: &'source str,
+    span: Span,
+    used: bool,
+    /// Whether it is an error if the name is not used, this is not the case
+    /// for loop variables which are only tracked because they shadow other
+    /// names.
+    checked: bool,
+}
+
+impl<'source> Checker<'source> {
+    fn new(source: &'source str) -> Self {
+        Self {
+            source,
+            bindings: Vec::new(),
+        }
+    }
+
+    fn check_scope(&mut self, scope: &ast::Scope) -> Result<()> {
+        let n = self.bindings.len();
+        for stmt in &scope.stmts {
+            self.check_stmt(stmt)?;
+        }
+        // Any names bound using `set` go out of scope here.
+        self.unbind(n)
+    }
+
+    fn check_stmt(&mut self, stmt: &ast::Stmt) -> Result<()> {
+        match stmt {
+            ast::Stmt::Raw(_)
+            | ast::Stmt::IncludeRaw(_)
+            | ast::Stmt::Break
+            | ast::Stmt::Continue => {}
+
+            ast::Stmt::InlineExpr(ast::InlineExpr { expr, .. }) => {
+                self.use_expr(expr);
+            }
+
+            ast::Stmt::Include(ast::Include { name, globals, .. }) => {
+                self.use_base_expr(name);
+                match globals {
+                    Some(globals) => self.use_expr(globals),
+                    // The included template shares the current scope so it
+                    // might use any of the names.
+                    None => {
+                        for binding in &mut self.bindings {
+                            binding.used = true;
+                        }
+                    }
+                }
+            }
+
+            ast::Stmt::IfElse(ast::IfElse {
+                cond,
+                then_branch,
+                else_branch,
+            }) => {
+                self.use_cond(cond);
+                self.check_scope(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.check_scope(else_branch)?;
+                }
+            }
+
+            ast::Stmt::ForLoop(ast::ForLoop {
+                vars,
+                iterable,
+                body,
+                else_branch,
+                ..
+            }) => {
+                match iterable {
+                    ast::Iterable::Expr(expr) => self.use_expr(expr),
+                    ast::Iterable::Range(ast::Range { start, end, .. }) => {
+                        self.use_base_expr(start);
+                        self.use_base_expr(end);
+                    }
+                }
+                let n = self.bindings.len();
+                self.bindings.push(Binding {
+                    name: "loop",
+                    span: Span::from(0..0),
+                    used: false,
+                    checked: false,
+                });
+                match vars {
+                    ast::LoopVars::Item(item) => self.bind(*item, false),
+                    ast::LoopVars::KeyValue(kv) => {
+                        self.bind(kv.key, false);
+                        self.bind(kv.value, false);
+                    }
+                    ast::LoopVars::Tuple(tuple) => {
+                        for var in &tuple.vars {
+                            self.bind(*var, false);
+                        }
+                    }
+                }
+                self.check_scope(body)?;
+                self.unbind(n)?;
+                if let Some(else_branch) = else_branch {
+                    self.check_scope(else_branch)?;
+                }
+            }
+
+            ast::Stmt::With(ast::With { bindings, body }) => {
+                // Each binding is in scope for the bindings after it.
+                let n = self.bindings.len();
+                for (expr, name) in bindings {
+                    self.use_expr(expr);
+                    self.bind(*name, true);
+                }
+                self.check_scope(body)?;
+                self.unbind(n)?;
+            }
+
+            ast::Stmt::Autoescape(ast::Autoescape { body, .. }) => {
+                self.check_scope(body)?;
+            }
+
+            ast::Stmt::Block(ast::Block { body, .. }) => {
+                // A template that extends this one can override the block and
+                // might use any of the names.
+                for binding in &mut self.bindings {
+                    binding.used = true;
+                }
+                self.check_scope(body)?;
+            }
+
+            ast::Stmt::Set(ast::Set { name, expr }) => {
+                self.use_expr(expr);
+                self.bind(*name, true);
+            }
+        }
+        Ok(())
+    }
+
+    fn use_cond(&mut self, cond: &ast::Cond) {
+        match cond {
+            ast::Cond::Expr(expr) | ast::Cond::Test(ast::Test { expr, .. }) => {
+                self.use_expr(expr);
+            }
+            ast::Cond::Compare(ast::Compare { lhs, rhs, .. }) => {
+                self.use_expr(lhs);
+                self.use_expr(rhs);
+            }
+            ast::Cond::Not(cond) => self.use_cond(cond),
+            ast::Cond::And(conds) | ast::Cond::Or(conds) => {
+                for cond in conds {
+                    self.use_cond(cond);
+                }
+            }
+        }
+    }
+
+    fn use_expr(&mut self, expr: &ast::Expr) {
+        match expr {
+            ast::Expr::Base(base_expr) => self.use_base_expr(base_expr),
+            ast::Expr::Call(ast::Call { args, receiver, .. }) => {
+                self.use_expr(receiver);
+                if let Some(args) = args {
+                    let named = args.named.iter().map(|arg| &arg.value);
+                    for arg in args.values.iter().chain(named) {
+                        self.use_base_expr(arg);
+                    }
+                }
+            }
+            ast::Expr::Coalesce(ast::Coalesce { exprs, .. })
+            | ast::Expr::Concat(ast::Concat { exprs, .. }) => {
+                for expr in exprs {
+                    self.use_expr(expr);
+                }
+            }
+        }
+    }
+
+    fn use_base_expr(&mut self, base_expr: &ast::BaseExpr) {
+        if let ast::BaseExpr::Var(var) = base_expr {
+            if let ast::Access::Key(key) = var.first().access {
+                let name = &self.source[key.span];
+                if let Some(binding) = self.bindings.iter_mut().rev().find(|b| b.name == name) {
+                    binding.used = true;
+                }
+            }
+        }
+    }
+
+    fn bind(&mut self, ident: ast::Ident, checked: bool) {
+        self.bindings.push(Binding {
+            name: &self.source[ident.span],
+            span: ident.span,
+            used: false,
+            checked,
+        });
+    }
+
+    /// Removes the names bound after the first `n` names, returning an error
+    /// if any of them were not used.
+    fn unbind(&mut self, n: usize) -> Result<()> {
+        match self.bindings.drain(n..).find(|b| b.checked && !b.used) {
+            Some(binding) => Err(Error::syntax(
+                format!("unused binding `{}`", binding.name),
+                self.source,
+                binding.span,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
 /// A compiler that constructs a program from an AST.
 #[cfg_attr(internal_debug, derive(Debug))]
 struct Compiler {
     instrs: Vec<Instr>,
+    tags: Vec<Span>,
+    loops: Vec<Loop>,
+}
+
+/// Tracks a `for` loop that is currently being compiled.
+#[cfg_attr(internal_debug, derive(Debug))]
+struct Loop {
+    /// The index of the `LoopNext` instruction.
+    next: usize,
+    /// The `LoopBreak` instructions that must jump to the end of the loop.
+    breaks: Vec<usize>,
+    /// The number of `autoescape` blocks opened inside the loop that must be
+    /// closed when exiting the loop early.
+    autoescapes: usize,
 }
 
 impl Compiler {
     fn new() -> Self {
-        Self { instrs: Vec::new() }
+        Self {
+            instrs: Vec::new(),
+            tags: Vec::new(),
+            loops: Vec::new(),
+        }
     }
 
     fn compile_template(mut self, source: Cow<'_, str>, template: ast::Template) -> Template<'_> {
-        let ast::Template { scope } = template;
+        let ast::Template {
+            scope,
+            trims,
+            extends,
+        } = template;
         self.compile_scope(scope);
         Template {
             source,
             instrs: self.instrs,
+            trims,
+            tags: self.tags,
+            extends,
         }
     }
 
     fn compile_scope(&mut self, scope: ast::Scope) {
+        let mut sets = 0;
         for stmt in scope.stmts {
+            if let ast::Stmt::Set(_) = stmt {
+                sets += 1;
+            }
             self.compile_stmt(stmt);
         }
+        // Any names bound using `set` go out of scope here.
+        for _ in 0..sets {
+            self.push(Instr::WithEnd);
+        }
     }
 
     fn compile_stmt(&mut self, stmt: ast::Stmt) {
@@ -59,49 +313,57 @@ impl Compiler {
                 self.push(Instr::EmitRaw(raw));
             }
 
-            ast::Stmt::InlineExpr(ast::InlineExpr { expr, .. }) => {
+            ast::Stmt::InlineExpr(ast::InlineExpr { expr, span: tag }) => {
+                self.tags.push(tag);
                 let span = expr.span();
+                // The operands of `??` jump to the instruction after the last
+                // operand, so it must not be merged into the emit.
+                let coalesce = matches!(expr, ast::Expr::Coalesce(_));
                 self.compile_expr(expr);
-                self.pop_emit_expr(span);
+                if coalesce {
+                    self.push(Instr::Emit(span));
+                } else {
+                    self.pop_emit_expr(span);
+                }
             }
 
-            ast::Stmt::Include(ast::Include { name, globals }) => match globals {
+            ast::Stmt::Include(ast::Include {
+                name,
+                globals,
+                ignore_missing,
+            }) => match globals {
                 Some(globals) => {
                     self.compile_expr(globals);
-                    self.push(Instr::IncludeWith(name));
+                    self.push(Instr::IncludeWith(name, ignore_missing));
                 }
                 None => {
-                    self.push(Instr::Include(name));
+                    self.push(Instr::Include(name, ignore_missing));
                 }
             },
 
+            ast::Stmt::IncludeRaw(name) => {
+                self.push(Instr::IncludeRaw(name));
+            }
+
             ast::Stmt::IfElse(ast::IfElse {
-                not,
                 cond,
                 then_branch,
                 else_branch,
             }) => {
-                self.compile_expr(cond);
-
                 // then branch
-                let instr = if not {
-                    Instr::JumpIfTrue(FIXME)
-                } else {
-                    Instr::JumpIfFalse(FIXME)
-                };
-                let j = self.push(instr);
+                let jumps = self.compile_cond(cond, false);
                 self.compile_scope(then_branch);
 
                 match else_branch {
                     Some(else_branch) => {
                         // else branch
                         let j2 = self.push(Instr::Jump(FIXME));
-                        self.update_jump(j);
+                        self.update_jumps(jumps);
                         self.compile_scope(else_branch);
                         self.update_jump(j2)
                     }
                     None => {
-                        self.update_jump(j);
+                        self.update_jumps(jumps);
                     }
                 }
             }
@@ -109,26 +371,190 @@ impl Compiler {
             ast::Stmt::ForLoop(ast::ForLoop {
                 vars,
                 iterable,
+                reversed,
                 body,
+                else_branch,
             }) => {
-                let span = iterable.span();
-                self.compile_expr(iterable);
-                self.push(Instr::LoopStart(vars, span));
-                let j = self.push(Instr::LoopNext(FIXME));
+                match iterable {
+                    ast::Iterable::Expr(expr) => {
+                        let span = expr.span();
+                        self.compile_expr(expr);
+                        self.push(Instr::LoopStart(vars, span, reversed));
+                    }
+                    ast::Iterable::Range(ast::Range {
+                        start,
+                        end,
+                        inclusive,
+                    }) => {
+                        let spans = [start.span(), end.span()];
+                        self.compile_base_expr(start);
+                        self.push(Instr::ExprPush);
+                        self.compile_base_expr(end);
+                        self.push(Instr::LoopStartRange(vars, inclusive, spans, reversed));
+                    }
+                }
+                let else_jump = else_branch.as_ref().map(|_| FIXME);
+                let j = self.push(Instr::LoopNext(FIXME, else_jump));
+                self.loops.push(Loop {
+                    next: j,
+                    breaks: Vec::new(),
+                    autoescapes: 0,
+                });
                 self.compile_scope(body);
                 self.push(Instr::Jump(j));
                 self.update_jump(j);
+                let breaks = self.loops.pop().unwrap().breaks;
+                self.update_jumps(breaks);
+                // The `else` branch is only rendered if the loop did not
+                // yield any items, otherwise it is skipped.
+                if let Some(else_branch) = else_branch {
+                    let end = self.push(Instr::Jump(FIXME));
+                    let n = self.instrs.len();
+                    match &mut self.instrs[j] {
+                        Instr::LoopNext(_, Some(k)) => *k = n,
+                        _ => unreachable!(),
+                    }
+                    self.compile_scope(else_branch);
+                    self.update_jump(end);
+                }
+            }
+
+            ast::Stmt::With(ast::With { bindings, body }) => {
+                // Each binding is in scope for the bindings after it.
+                let n = bindings.len();
+                for (expr, name) in bindings {
+                    self.compile_expr(expr);
+                    self.push(Instr::WithStart(name));
+                }
+                self.compile_scope(body);
+                for _ in 0..n {
+                    self.push(Instr::WithEnd);
+                }
             }
 
-            ast::Stmt::With(ast::With { expr, name, body }) => {
+            ast::Stmt::Autoescape(ast::Autoescape { enabled, body }) => {
+                self.push(Instr::AutoescapeStart(enabled));
+                if let Some(l) = self.loops.last_mut() {
+                    l.autoescapes += 1;
+                }
+                self.compile_scope(body);
+                if let Some(l) = self.loops.last_mut() {
+                    l.autoescapes -= 1;
+                }
+                self.push(Instr::AutoescapeEnd);
+            }
+
+            ast::Stmt::Set(ast::Set { name, expr }) => {
                 self.compile_expr(expr);
                 self.push(Instr::WithStart(name));
+            }
+
+            ast::Stmt::Block(ast::Block { name, body }) => {
+                let j = self.push(Instr::BlockStart(name, FIXME));
                 self.compile_scope(body);
-                self.push(Instr::WithEnd);
+                self.update_jump(j);
+            }
+
+            ast::Stmt::Break => {
+                self.compile_loop_exit();
+                let j = self.push(Instr::LoopBreak(FIXME));
+                self.loops.last_mut().unwrap().breaks.push(j);
+            }
+
+            ast::Stmt::Continue => {
+                self.compile_loop_exit();
+                let j = self.loops.last().unwrap().next;
+                self.push(Instr::LoopContinue(j));
             }
         }
     }
 
+    /// Restores the escaping mode for any `autoescape` blocks opened inside
+    /// the current loop. Variables are removed by the renderer.
+    fn compile_loop_exit(&mut self) {
+        for _ in 0..self.loops.last().unwrap().autoescapes {
+            self.push(Instr::AutoescapeEnd);
+        }
+    }
+
+    /// Compiles a condition that jumps when the condition evaluates to `when`
+    /// and otherwise falls through, returning the jumps that must be updated.
+    ///
+    /// The `and` and `or` conditions short-circuit, so later operands are not
+    /// evaluated once the result is known.
+    fn compile_cond(&mut self, cond: ast::Cond, when: bool) -> Vec<usize> {
+        let jump_if = |when| match when {
+            true => Instr::JumpIfTrue(FIXME),
+            false => Instr::JumpIfFalse(FIXME),
+        };
+        match cond {
+            ast::Cond::Expr(expr) => {
+                self.compile_expr(expr);
+                vec![self.push(jump_if(when))]
+            }
+            // A variable that is not found is not defined, so the lookup must
+            // not fail.
+            ast::Cond::Test(ast::Test {
+                expr: ast::Expr::Base(ast::BaseExpr::Var(var)),
+                not,
+                predicate: predicate @ ast::Predicate::Defined,
+            }) => {
+                let span = var.span();
+                let j = self.push(Instr::ExprStartMaybe(var, FIXME));
+                self.push(Instr::Test(predicate, span));
+                let k = self.push(Instr::Jump(FIXME));
+                self.update_jump(j);
+                self.push(Instr::ExprStartLit(Value::Bool(false)));
+                self.update_jump(k);
+                vec![self.push(jump_if(when != not))]
+            }
+            ast::Cond::Test(ast::Test {
+                expr,
+                not,
+                predicate,
+            }) => {
+                let span = expr.span();
+                self.compile_expr(expr);
+                self.push(Instr::Test(predicate, span));
+                vec![self.push(jump_if(when != not))]
+            }
+            ast::Cond::Compare(ast::Compare { lhs, op, rhs, span }) => {
+                self.compile_expr(lhs);
+                self.push(Instr::ExprPush);
+                self.compile_expr(rhs);
+                self.push(Instr::Compare(op, span));
+                vec![self.push(jump_if(when))]
+            }
+            ast::Cond::Not(cond) => self.compile_cond(*cond, !when),
+            ast::Cond::And(conds) => self.compile_cond_chain(conds, false, when),
+            ast::Cond::Or(conds) => self.compile_cond_chain(conds, true, when),
+        }
+    }
+
+    /// Compiles a chain of `and` or `or` conditions. The chain short-circuits
+    /// as soon as an operand evaluates to `short`.
+    fn compile_cond_chain(&mut self, conds: Vec<ast::Cond>, short: bool, when: bool) -> Vec<usize> {
+        let mut jumps = Vec::new();
+        if short == when {
+            // Any operand that short-circuits decides the result.
+            for cond in conds {
+                jumps.extend(self.compile_cond(cond, when));
+            }
+        } else {
+            // Only the last operand decides the result, any operand that
+            // short-circuits before it skips to the end.
+            let mut conds = conds;
+            let last = conds.pop().unwrap();
+            let mut skips = Vec::new();
+            for cond in conds {
+                skips.extend(self.compile_cond(cond, short));
+            }
+            jumps.extend(self.compile_cond(last, when));
+            self.update_jumps(skips);
+        }
+        jumps
+    }
+
     fn compile_expr(&mut self, expr: ast::Expr) {
         match expr {
             ast::Expr::Base(base_expr) => {
@@ -139,11 +565,70 @@ impl Compiler {
                 name,
                 args,
                 receiver,
-                span,
+                ..
             }) => {
+                let span = receiver.span();
                 self.compile_expr(*receiver);
                 self.push(Instr::Apply(name, span, args));
             }
+
+            ast::Expr::Coalesce(ast::Coalesce { mut exprs, .. }) => {
+                // All operands except the last skip to the next operand if
+                // the variable is not found and jump to the end if the value
+                // is not `None`. The last operand is compiled as usual so that
+                // any errors are still reported.
+                let last = exprs.pop().unwrap();
+                let mut ends = Vec::new();
+                for expr in exprs {
+                    let skip = self.compile_expr_maybe(expr);
+                    ends.push(self.push(Instr::JumpIfNotNone(FIXME)));
+                    if let Some(j) = skip {
+                        self.update_jump(j);
+                    }
+                }
+                self.compile_expr(last);
+                self.update_jumps(ends);
+            }
+
+            ast::Expr::Concat(ast::Concat { exprs, .. }) => {
+                let mut exprs = exprs.into_iter();
+                let first = exprs.next().unwrap();
+                let mut lhs = first.span();
+                self.compile_expr(first);
+                for expr in exprs {
+                    let rhs = expr.span();
+                    self.push(Instr::ExprPush);
+                    self.compile_expr(expr);
+                    self.push(Instr::Concat(lhs, rhs));
+                    lhs = lhs.combine(rhs);
+                }
+            }
+        }
+    }
+
+    /// Compiles an expression where the variable it starts with might not
+    /// exist, returning the jump that must be updated to skip the rest of the
+    /// expression if it does not.
+    fn compile_expr_maybe(&mut self, expr: ast::Expr) -> Option<usize> {
+        match expr {
+            ast::Expr::Base(ast::BaseExpr::Var(var)) => {
+                Some(self.push(Instr::ExprStartMaybe(var, FIXME)))
+            }
+            ast::Expr::Call(ast::Call {
+                name,
+                args,
+                receiver,
+                ..
+            }) => {
+                let span = receiver.span();
+                let j = self.compile_expr_maybe(*receiver);
+                self.push(Instr::Apply(name, span, args));
+                j
+            }
+            expr => {
+                self.compile_expr(expr);
+                None
+            }
         }
     }
 
@@ -163,7 +648,7 @@ impl Compiler {
             Some(Instr::Apply(_, _, None)) => {
                 let instr = self.instrs.pop().unwrap();
                 match instr {
-                    Instr::Apply(ident, _, _) => Instr::EmitWith(ident, span),
+                    Instr::Apply(ident, span, _) => Instr::EmitWith(ident, span),
                     _ => unreachable!(),
                 }
             }
@@ -175,12 +660,25 @@ impl Compiler {
     fn update_jump(&mut self, i: usize) {
         let n = self.instrs.len();
         let j = match &mut self.instrs[i] {
-            Instr::Jump(j) | Instr::JumpIfTrue(j) | Instr::JumpIfFalse(j) | Instr::LoopNext(j) => j,
+            Instr::Jump(j)
+            | Instr::JumpIfTrue(j)
+            | Instr::JumpIfFalse(j)
+            | Instr::JumpIfNotNone(j)
+            | Instr::ExprStartMaybe(_, j)
+            | Instr::LoopNext(j, _)
+            | Instr::LoopBreak(j)
+            | Instr::BlockStart(_, j) => j,
             _ => panic!("not a jump instr"),
         };
         *j = n;
     }
 
+    fn update_jumps(&mut self, jumps: Vec<usize>) {
+        for j in jumps {
+            self.update_jump(j);
+        }
+    }
+
     fn push(&mut self, instr: Instr) -> usize {
         let i = self.instrs.len();
         self.instrs.push(instr);
@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::mem;
 
 use crate::compile::lex::{Lexer, Token};
 use crate::types::ast;
@@ -24,10 +25,8 @@ enum State {
     If {
         /// Whether or not this `if` statement is an `else if` clause.
         is_else_if: bool,
-        /// Whether this is an an `if not` or a `if` statement.
-        not: bool,
         /// The condition in the `if` block.
-        cond: ast::Expr,
+        cond: ast::Cond,
         /// The span of the `if` block.
         span: Span,
         /// Whether or not this `if` statement has an `else` clause.
@@ -39,33 +38,69 @@ enum State {
         /// The loop variables.
         vars: ast::LoopVars,
         /// The value we are iterating over.
-        iterable: ast::Expr,
+        iterable: ast::Iterable,
+        /// Whether the iterable is iterated in reverse.
+        reversed: bool,
         /// The span of the `for` block.
         span: Span,
+        /// Whether or not this `for` statement has an `else` clause.
+        has_else: bool,
+    },
+
+    /// A partial `autoescape` statement.
+    Autoescape {
+        /// Whether escaping is enabled within the block.
+        enabled: bool,
+        /// The span of the `autoescape` block.
+        span: Span,
+    },
+
+    /// A partial `raw` statement.
+    Raw {
+        /// The span of the `raw` block.
+        span: Span,
+    },
+
+    /// A partial `block` statement.
+    Block {
+        /// The name of the block.
+        name: ast::Ident,
+        /// The span of the `block` block.
+        span: Span,
     },
 
     /// A partial `with` statement.
     With {
-        /// The expression to shadow.
-        expr: ast::Expr,
-        /// The name to assign to this expression.
-        name: ast::Ident,
+        /// The expressions to shadow along with the names to assign to them.
+        bindings: Vec<(ast::Expr, ast::Ident)>,
         /// The span of the `with` block.
         span: Span,
     },
 }
 
 /// A parsed block definition.
+#[allow(clippy::enum_variant_names)]
 enum Block {
-    If(bool, ast::Expr),
+    If(ast::Cond),
     Else,
-    ElseIf(bool, ast::Expr),
+    ElseIf(ast::Cond),
     EndIf,
-    For(ast::LoopVars, ast::Expr),
+    For(ast::LoopVars, ast::Iterable, bool),
     EndFor,
-    With(ast::Expr, ast::Ident),
+    With(Vec<(ast::Expr, ast::Ident)>),
     EndWith,
-    Include(ast::String, Option<ast::Expr>),
+    Autoescape(bool),
+    EndAutoescape,
+    Include(ast::BaseExpr, Option<ast::Expr>, bool),
+    IncludeRaw(ast::String),
+    Raw,
+    EndRaw,
+    Break(Span),
+    Continue(Span),
+    Set(ast::Ident, ast::Expr),
+    Extends(ast::String),
+    Block(ast::Ident),
+    EndBlock,
 }
 
 /// A keyword in the template syntax.
@@ -73,7 +108,10 @@ enum Block {
 pub(crate) enum Keyword {
     If,
     Not,
+    And,
+    Or,
     Else,
+    Elif,
     EndIf,
     For,
     In,
@@ -81,7 +119,19 @@ pub(crate) enum Keyword {
     With,
     As,
     EndWith,
+    Is,
+    Autoescape,
+    EndAutoescape,
     Include,
+    IncludeRaw,
+    Raw,
+    EndRaw,
+    Break,
+    Continue,
+    Set,
+    Extends,
+    Block,
+    EndBlock,
     True,
     False,
 }
@@ -92,6 +142,14 @@ enum Sign {
     Pos,
 }
 
+/// The operands of the `??` and `~` operators in a group that have been
+/// parsed so far.
+#[derive(Default)]
+struct Operands {
+    coalesce: Vec<ast::Expr>,
+    concat: Vec<ast::Expr>,
+}
+
 impl<'engine, 'source> Parser<'engine, 'source> {
     /// Construct a new parser.
     pub fn new(engine: &'engine Engine<'engine>, source: &'source str) -> Self {
@@ -109,6 +167,8 @@ impl<'engine, 'source> Parser<'engine, 'source> {
     pub fn parse_template(mut self) -> Result<ast::Template> {
         let mut blocks = vec![];
         let mut scopes = vec![ast::Scope::new()];
+        let mut extends = None;
+        let mut names: Vec<ast::Ident> = Vec::new();
 
         while let Some(next) = self.next()? {
             let stmt = match next {
@@ -144,10 +204,9 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                         // We must push a block to the block stack and a scope
                         // to the scope stack because an if statement starts a
                         // new scope.
-                        Block::If(not, cond) => {
+                        Block::If(cond) => {
                             blocks.push(State::If {
                                 is_else_if: false,
-                                not,
                                 cond,
                                 span,
                                 has_else: false,
@@ -160,11 +219,13 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                         //
                         //   {% else if cond %}
                         //
+                        // The `elif` keyword is an alias for `else if`.
+                        //
                         // We expect that the previous block was an `if` block
                         // and update it accordingly. We must also push two
                         // scopes to the scope stack, one for the `else` and one
                         // for the `if`.
-                        Block::ElseIf(not, cond) => {
+                        Block::ElseIf(cond) => {
                             let err =
                                 || Error::syntax("unexpected `else if` block", self.source(), span);
                             match blocks.last_mut().ok_or_else(err)? {
@@ -178,7 +239,6 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                             }
                             blocks.push(State::If {
                                 is_else_if: true,
-                                not,
                                 cond,
                                 span,
                                 has_else: false,
@@ -188,14 +248,15 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                             continue;
                         }
 
-                        // The `else` clause of an `if` statement. For example:
+                        // The `else` clause of an `if` or `for` statement. For
+                        // example:
                         //
                         //   {% else %}
                         //
-                        // We expect that the previous block was an `if` block
-                        // and update it accordingly. We must also push to the
-                        // scope stack since an `else` clause starts a new
-                        // scope.
+                        // We expect that the previous block was an `if` or
+                        // `for` block and update it accordingly. We must also
+                        // push to the scope stack since an `else` clause
+                        // starts a new scope.
                         Block::Else => {
                             let err =
                                 || Error::syntax("unexpected `else` block", self.source(), span);
@@ -203,6 +264,10 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                                 State::If {
                                     has_else: has_else @ false,
                                     ..
+                                }
+                                | State::For {
+                                    has_else: has_else @ false,
+                                    ..
                                 } => {
                                     *has_else = true;
                                 }
@@ -227,7 +292,6 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                                 match blocks.pop().ok_or_else(err)? {
                                     State::If {
                                         is_else_if,
-                                        not,
                                         cond,
                                         has_else,
                                         ..
@@ -235,7 +299,6 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                                         let else_branch = has_else.then(|| scopes.pop().unwrap());
                                         let then_branch = scopes.pop().unwrap();
                                         let stmt = ast::Stmt::IfElse(ast::IfElse {
-                                            not,
                                             cond,
                                             then_branch,
                                             else_branch,
@@ -254,14 +317,20 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                         //
                         //   {% for vars in iterable %}
                         //
+                        // Or with the `reversed` modifier:
+                        //
+                        //   {% for vars in iterable reversed %}
+                        //
                         // We must push a block to the block stack and a scope
                         // to the scope stack because a for statement starts a
                         // new scope.
-                        Block::For(vars, iterable) => {
+                        Block::For(vars, iterable, reversed) => {
                             blocks.push(State::For {
                                 vars,
                                 iterable,
+                                reversed,
                                 span,
+                                has_else: false,
                             });
                             scopes.push(ast::Scope::new());
                             continue;
@@ -271,18 +340,29 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                         //
                         //   {% endfor %}
                         //
-                        // We expect that the previous block was a `for` block.
+                        // We expect that the previous block was a `for` block,
+                        // if it has an `else` clause then there are two scopes
+                        // to pop.
                         Block::EndFor => {
                             let err =
                                 || Error::syntax("unexpected `endfor` block", self.source(), span);
 
                             let for_loop = match blocks.pop().ok_or_else(err)? {
-                                State::For { vars, iterable, .. } => {
+                                State::For {
+                                    vars,
+                                    iterable,
+                                    reversed,
+                                    has_else,
+                                    ..
+                                } => {
+                                    let else_branch = has_else.then(|| scopes.pop().unwrap());
                                     let body = scopes.pop().unwrap();
                                     ast::ForLoop {
                                         vars,
                                         iterable,
+                                        reversed,
                                         body,
+                                        else_branch,
                                     }
                                 }
                                 _ => return Err(err()),
@@ -292,13 +372,13 @@ impl<'engine, 'source> Parser<'engine, 'source> {
 
                         // The start of a `with` statement. For example:
                         //
-                        //   {% with expr as name %}
+                        //   {% with expr as name, other as another %}
                         //
                         // We must push a block to the block stack and a scope
                         // to the scope stack because a with statement starts a
                         // new scope.
-                        Block::With(expr, name) => {
-                            blocks.push(State::With { expr, name, span });
+                        Block::With(bindings) => {
+                            blocks.push(State::With { bindings, span });
                             scopes.push(ast::Scope::new());
                             continue;
                         }
@@ -313,21 +393,201 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                                 || Error::syntax("unexpected `endwith` block", self.source(), span);
 
                             let with = match blocks.pop().ok_or_else(err)? {
-                                State::With { expr, name, .. } => {
+                                State::With { bindings, .. } => {
                                     let body = scopes.pop().unwrap();
-                                    ast::With { expr, name, body }
+                                    ast::With { bindings, body }
                                 }
                                 _ => return Err(err()),
                             };
                             ast::Stmt::With(with)
                         }
 
+                        // The start of an `autoescape` statement. For example:
+                        //
+                        //   {% autoescape off %}
+                        //
+                        // We must push a block to the block stack and a scope
+                        // to the scope stack because an autoescape statement
+                        // starts a new scope.
+                        Block::Autoescape(enabled) => {
+                            blocks.push(State::Autoescape { enabled, span });
+                            scopes.push(ast::Scope::new());
+                            continue;
+                        }
+
+                        // The end of an `autoescape` statement. For example:
+                        //
+                        //   {% endautoescape %}
+                        //
+                        // We expect that the previous block was an `autoescape`
+                        // block.
+                        Block::EndAutoescape => {
+                            let err = || {
+                                Error::syntax(
+                                    "unexpected `endautoescape` block",
+                                    self.source(),
+                                    span,
+                                )
+                            };
+
+                            let autoescape = match blocks.pop().ok_or_else(err)? {
+                                State::Autoescape { enabled, .. } => {
+                                    let body = scopes.pop().unwrap();
+                                    ast::Autoescape { enabled, body }
+                                }
+                                _ => return Err(err()),
+                            };
+                            ast::Stmt::Autoescape(autoescape)
+                        }
+
                         // An `include` statement. For example:
                         //
-                        //   {% include name with expr %}
+                        //   {% include name ignore missing with expr %}
+                        //
+                        Block::Include(name, globals, ignore_missing) => {
+                            ast::Stmt::Include(ast::Include {
+                                name,
+                                globals,
+                                ignore_missing,
+                            })
+                        }
+
+                        // The start of a `raw` statement. For example:
+                        //
+                        //   {% raw %}
+                        //
+                        // The lexer emits everything up until the matching
+                        // `endraw` block as a single raw token, so we only
+                        // need to push a block to the block stack to check
+                        // that it is closed.
+                        Block::Raw => {
+                            blocks.push(State::Raw { span });
+                            continue;
+                        }
+
+                        // The end of a `raw` statement. For example:
                         //
-                        Block::Include(name, globals) => {
-                            ast::Stmt::Include(ast::Include { name, globals })
+                        //   {% endraw %}
+                        //
+                        // We expect that the previous block was a `raw` block.
+                        Block::EndRaw => {
+                            let err =
+                                || Error::syntax("unexpected `endraw` block", self.source(), span);
+                            match blocks.pop().ok_or_else(err)? {
+                                State::Raw { .. } => continue,
+                                _ => return Err(err()),
+                            }
+                        }
+
+                        // An `include_raw` statement. For example:
+                        //
+                        //   {% include_raw name %}
+                        //
+                        Block::IncludeRaw(name) => ast::Stmt::IncludeRaw(name),
+
+                        // A `break` statement. For example:
+                        //
+                        //   {% break %}
+                        //
+                        // We expect that we are somewhere inside a `for`
+                        // block.
+                        Block::Break(kw) => {
+                            if !in_loop(&blocks) {
+                                return Err(Error::syntax(
+                                    "unexpected `break` outside of loop",
+                                    self.source(),
+                                    kw,
+                                ));
+                            }
+                            ast::Stmt::Break
+                        }
+
+                        // A `continue` statement. For example:
+                        //
+                        //   {% continue %}
+                        //
+                        // We expect that we are somewhere inside a `for`
+                        // block.
+                        Block::Continue(kw) => {
+                            if !in_loop(&blocks) {
+                                return Err(Error::syntax(
+                                    "unexpected `continue` outside of loop",
+                                    self.source(),
+                                    kw,
+                                ));
+                            }
+                            ast::Stmt::Continue
+                        }
+
+                        // A `set` statement. For example:
+                        //
+                        //   {% set name = expr %}
+                        //
+                        // This doesn't start a new scope, the name is bound
+                        // until the end of the current scope.
+                        Block::Set(name, expr) => ast::Stmt::Set(ast::Set { name, expr }),
+
+                        // An `extends` statement. For example:
+                        //
+                        //   {% extends "base" %}
+                        //
+                        // This must be at the top level of the template and
+                        // may only be given once.
+                        Block::Extends(name) => {
+                            if !blocks.is_empty() || extends.is_some() {
+                                return Err(Error::syntax(
+                                    "unexpected `extends` block",
+                                    self.source(),
+                                    span,
+                                ));
+                            }
+                            extends = Some(name);
+                            continue;
+                        }
+
+                        // The start of a `block` statement. For example:
+                        //
+                        //   {% block content %}
+                        //
+                        // We must push a block to the block stack and a scope
+                        // to the scope stack because a block statement starts
+                        // a new scope. Block names must be unique within a
+                        // template.
+                        Block::Block(name) => {
+                            let source = self.source();
+                            let raw = &source[name.span];
+                            if names.iter().any(|n| &source[n.span] == raw) {
+                                return Err(Error::syntax(
+                                    format!("duplicate block `{raw}`"),
+                                    source,
+                                    name.span,
+                                ));
+                            }
+                            names.push(name);
+                            blocks.push(State::Block { name, span });
+                            scopes.push(ast::Scope::new());
+                            continue;
+                        }
+
+                        // The end of a `block` statement. For example:
+                        //
+                        //   {% endblock %}
+                        //
+                        // We expect that the previous block was a `block`
+                        // block.
+                        Block::EndBlock => {
+                            let err = || {
+                                Error::syntax("unexpected `endblock` block", self.source(), span)
+                            };
+
+                            let block = match blocks.pop().ok_or_else(err)? {
+                                State::Block { name, .. } => {
+                                    let body = scopes.pop().unwrap();
+                                    ast::Block { name, body }
+                                }
+                                _ => return Err(err()),
+                            };
+                            ast::Stmt::Block(block)
                         }
                     }
                 }
@@ -343,6 +603,9 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                 State::If { span, .. } => ("unclosed `if` block", span),
                 State::For { span, .. } => ("unclosed `for` block", span),
                 State::With { span, .. } => ("unclosed `with` block", span),
+                State::Autoescape { span, .. } => ("unclosed `autoescape` block", span),
+                State::Raw { span } => ("unclosed `raw` block", span),
+                State::Block { span, .. } => ("unclosed `block` block", span),
             };
             return Err(Error::syntax(msg, self.source(), *span));
         }
@@ -354,6 +617,8 @@ impl<'engine, 'source> Parser<'engine, 'source> {
 
         Ok(ast::Template {
             scope: scopes.remove(0),
+            trims: std::mem::take(&mut self.tokens.trims),
+            extends,
         })
     }
 
@@ -371,67 +636,280 @@ impl<'engine, 'source> Parser<'engine, 'source> {
     ///
     ///   with loop.index | is_even as even
     ///
+    ///   with user.name as name, user.age as age
+    ///
+    ///   autoescape off
+    ///
+    ///   set name = user.name | upper
+    ///
+    ///   extends "base"
+    ///
+    ///   block content
+    ///
     fn parse_block(&mut self) -> Result<Block> {
         let (kw, span) = self.parse_keyword()?;
         match kw {
             Keyword::If => {
-                let (not, expr) = self.parse_if_cond()?;
-                Ok(Block::If(not, expr))
+                let cond = self.parse_if_cond()?;
+                Ok(Block::If(cond))
             }
             Keyword::Else => {
                 if self.is_next_keyword(Keyword::If)? {
                     self.expect_keyword(Keyword::If)?;
-                    let (not, expr) = self.parse_if_cond()?;
-                    Ok(Block::ElseIf(not, expr))
+                    let cond = self.parse_if_cond()?;
+                    Ok(Block::ElseIf(cond))
                 } else {
                     Ok(Block::Else)
                 }
             }
+            Keyword::Elif => {
+                let cond = self.parse_if_cond()?;
+                Ok(Block::ElseIf(cond))
+            }
             Keyword::EndIf => Ok(Block::EndIf),
             Keyword::For => {
                 let vars = self.parse_loop_vars()?;
                 self.expect_keyword(Keyword::In)?;
-                let iterable = self.parse_expr()?;
-                Ok(Block::For(vars, iterable))
+                let iterable = self.parse_iterable()?;
+                let reversed = match self.peek()? {
+                    Some((Token::Ident, span)) if &self.source()[span] == "reversed" => {
+                        self.next()?;
+                        true
+                    }
+                    _ => false,
+                };
+                Ok(Block::For(vars, iterable, reversed))
             }
             Keyword::EndFor => Ok(Block::EndFor),
             Keyword::With => {
-                let expr = self.parse_expr()?;
-                self.expect_keyword(Keyword::As)?;
-                let name = self.parse_ident()?;
-                Ok(Block::With(expr, name))
+                let mut bindings = Vec::new();
+                loop {
+                    let expr = self.parse_expr()?;
+                    self.expect_keyword(Keyword::As)?;
+                    let name = self.parse_ident()?;
+                    bindings.push((expr, name));
+                    if !self.is_next(Token::Comma)? {
+                        break;
+                    }
+                    self.expect(Token::Comma)?;
+                }
+                Ok(Block::With(bindings))
             }
             Keyword::EndWith => Ok(Block::EndWith),
+            Keyword::Autoescape => {
+                let enabled = self.parse_autoescape_mode()?;
+                Ok(Block::Autoescape(enabled))
+            }
+            Keyword::EndAutoescape => Ok(Block::EndAutoescape),
             Keyword::Include => {
-                let span = self.expect(Token::String)?;
-                let name = self.parse_string(span)?;
-                let name = ast::String { name, span };
+                let name = self.parse_base_expr()?;
+                if let ast::BaseExpr::Literal(lit) = &name {
+                    if !matches!(lit.value, Value::String(_)) {
+                        return Err(Error::syntax(
+                            "expected string or variable",
+                            self.source(),
+                            lit.span,
+                        ));
+                    }
+                }
+                let ignore_missing = self.parse_ignore_missing()?;
                 let globals = if self.is_next_keyword(Keyword::With)? {
                     self.expect_keyword(Keyword::With)?;
                     Some(self.parse_expr()?)
                 } else {
                     None
                 };
-                Ok(Block::Include(name, globals))
+                Ok(Block::Include(name, globals, ignore_missing))
+            }
+            Keyword::Raw => Ok(Block::Raw),
+            Keyword::EndRaw => Ok(Block::EndRaw),
+            Keyword::Break => Ok(Block::Break(span)),
+            Keyword::Continue => Ok(Block::Continue(span)),
+            Keyword::Set => {
+                let name = self.parse_ident()?;
+                self.expect(Token::Assign)?;
+                let expr = self.parse_expr()?;
+                Ok(Block::Set(name, expr))
             }
+            Keyword::IncludeRaw => {
+                let span = self.expect(Token::String)?;
+                let name = self.parse_string(span)?;
+                Ok(Block::IncludeRaw(ast::String { name, span }))
+            }
+            Keyword::Extends => {
+                let span = self.expect(Token::String)?;
+                let name = self.parse_string(span)?;
+                Ok(Block::Extends(ast::String { name, span }))
+            }
+            Keyword::Block => {
+                let name = self.parse_ident()?;
+                Ok(Block::Block(name))
+            }
+            Keyword::EndBlock => Ok(Block::EndBlock),
             kw => Err(self.err_unexpected_keyword(kw.human(), span)),
         }
     }
 
     /// Parses an if condition.
     ///
-    /// This is an expression with an optional `not`.
+    /// This is one or more conditions combined using `and` and `or`, where
+    /// `and` binds tighter than `or`. Since there is no grouping the result is
+    /// always an `or` of `and`s and so can be parsed without recursion.
+    ///
+    ///   user.is_enabled and user.age >= 18 or user.is_admin
+    ///
+    fn parse_if_cond(&mut self) -> Result<ast::Cond> {
+        let mut ors = Vec::new();
+        let mut ands = Vec::new();
+        loop {
+            ands.push(self.parse_if_cond_operand()?);
+            if self.is_next_keyword(Keyword::And)? {
+                self.expect_keyword(Keyword::And)?;
+                continue;
+            }
+            ors.push(match ands.len() {
+                1 => ands.pop().unwrap(),
+                _ => ast::Cond::And(std::mem::take(&mut ands)),
+            });
+            if self.is_next_keyword(Keyword::Or)? {
+                self.expect_keyword(Keyword::Or)?;
+                continue;
+            }
+            break;
+        }
+        match ors.len() {
+            1 => Ok(ors.pop().unwrap()),
+            _ => Ok(ast::Cond::Or(ors)),
+        }
+    }
+
+    /// Parses a single operand in an if condition.
+    ///
+    /// This is an expression with an optional `not` and an optional test or
+    /// comparison with another expression.
     ///
     ///   not user.is_enabled
     ///
-    fn parse_if_cond(&mut self) -> Result<(bool, ast::Expr)> {
-        if self.is_next_keyword(Keyword::Not)? {
+    ///   user.roles is not empty
+    ///
+    ///   user.age >= 18
+    ///
+    fn parse_if_cond_operand(&mut self) -> Result<ast::Cond> {
+        let not = if self.is_next_keyword(Keyword::Not)? {
             self.expect_keyword(Keyword::Not)?;
-            let expr = self.parse_expr()?;
-            Ok((true, expr))
+            true
         } else {
-            let expr = self.parse_expr()?;
-            Ok((false, expr))
+            false
+        };
+        let expr = self.parse_expr()?;
+        let cond = if let Some(op) = self.parse_compare_op()? {
+            let rhs = self.parse_expr()?;
+            let span = expr.span().combine(rhs.span());
+            ast::Cond::Compare(ast::Compare {
+                lhs: expr,
+                op,
+                rhs,
+                span,
+            })
+        } else if self.is_next_keyword(Keyword::Is)? {
+            self.expect_keyword(Keyword::Is)?;
+            let test_not = if self.is_next_keyword(Keyword::Not)? {
+                self.expect_keyword(Keyword::Not)?;
+                true
+            } else {
+                false
+            };
+            let predicate = self.parse_predicate()?;
+            ast::Cond::Test(ast::Test {
+                expr,
+                not: test_not,
+                predicate,
+            })
+        } else {
+            ast::Cond::Expr(expr)
+        };
+        match not {
+            true => Ok(ast::Cond::Not(Box::new(cond))),
+            false => Ok(cond),
+        }
+    }
+
+    /// Parses a comparison operator if the next token is one.
+    ///
+    ///   ==
+    ///
+    fn parse_compare_op(&mut self) -> Result<Option<ast::CompareOp>> {
+        let op = match self.peek()? {
+            Some((Token::Eq, _)) => ast::CompareOp::Eq,
+            Some((Token::Ne, _)) => ast::CompareOp::Ne,
+            Some((Token::Lt, _)) => ast::CompareOp::Lt,
+            Some((Token::Le, _)) => ast::CompareOp::Le,
+            Some((Token::Gt, _)) => ast::CompareOp::Gt,
+            Some((Token::Ge, _)) => ast::CompareOp::Ge,
+            _ => return Ok(None),
+        };
+        self.next()?;
+        Ok(Some(op))
+    }
+
+    /// Parses the name of a test in a condition.
+    ///
+    ///   empty
+    ///
+    fn parse_predicate(&mut self) -> Result<ast::Predicate> {
+        let ident = self.parse_ident()?;
+        match &self.source()[ident.span] {
+            "empty" => Ok(ast::Predicate::Empty),
+            "defined" => Ok(ast::Predicate::Defined),
+            "none" => Ok(ast::Predicate::None),
+            "string" => Ok(ast::Predicate::String),
+            "number" => Ok(ast::Predicate::Number),
+            "list" => Ok(ast::Predicate::List),
+            "map" => Ok(ast::Predicate::Map),
+            "bool" => Ok(ast::Predicate::Bool),
+            _ => Err(Error::syntax("unknown test", self.source(), ident.span)),
+        }
+    }
+
+    /// Parses an autoescape mode.
+    ///
+    /// This is either the identifier `on` or `off`.
+    ///
+    ///   off
+    ///
+    fn parse_autoescape_mode(&mut self) -> Result<bool> {
+        let ident = self.parse_ident()?;
+        match &self.source()[ident.span] {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(Error::syntax(
+                "expected `on` or `off`",
+                self.source(),
+                ident.span,
+            )),
+        }
+    }
+
+    /// Parses the optional `ignore missing` after the name in an `include`
+    /// block.
+    ///
+    /// These are not keywords so that they can still be used as variable
+    /// names.
+    fn parse_ignore_missing(&mut self) -> Result<bool> {
+        match self.peek()? {
+            Some((Token::Ident, span)) if &self.source()[span] == "ignore" => {
+                self.next()?;
+                let ident = self.parse_ident()?;
+                match &self.source()[ident.span] {
+                    "missing" => Ok(true),
+                    _ => Err(Error::syntax(
+                        "expected `missing`",
+                        self.source(),
+                        ident.span,
+                    )),
+                }
+            }
+            _ => Ok(false),
         }
     }
 
@@ -441,8 +919,94 @@ impl<'engine, 'source> Parser<'engine, 'source> {
     ///
     ///   user.name | lower | prefix: "Mr. "
     ///
+    /// Operands of the `~` and `??` operators can have function calls applied
+    /// to them, and any operand can be a parenthesized expression, for example
+    ///
+    ///   base_url ~ "/users/" ~ user.id ?? "me"
+    ///
+    ///   (user.first_name ~ " " ~ user.last_name) | upper
+    ///
+    /// Parentheses can be nested arbitrarily deep, so instead of recursing
+    /// the partially parsed operators of each enclosing group are kept on a
+    /// stack until the group is closed.
     fn parse_expr(&mut self) -> Result<ast::Expr> {
-        let mut expr = ast::Expr::Base(self.parse_base_expr()?);
+        let mut groups: Vec<(Operands, Span)> = Vec::new();
+        let mut operands = Operands::default();
+        loop {
+            if self.is_next(Token::OpenParen)? {
+                let span = self.expect(Token::OpenParen)?;
+                groups.push((mem::take(&mut operands), span));
+                continue;
+            }
+            let base = self.parse_base_expr()?;
+            let mut expr = self.parse_calls(ast::Expr::Base(base))?;
+            loop {
+                if self.is_next(Token::QuestionQuestion)? {
+                    self.expect(Token::QuestionQuestion)?;
+                    operands.coalesce.push(expr);
+                    break;
+                }
+                let operand =
+                    finish_operands(mem::take(&mut operands.coalesce), expr, |exprs, span| {
+                        ast::Expr::Coalesce(ast::Coalesce { exprs, span })
+                    });
+                if self.is_next(Token::Tilde)? {
+                    self.expect(Token::Tilde)?;
+                    operands.concat.push(operand);
+                    break;
+                }
+                let group =
+                    finish_operands(mem::take(&mut operands.concat), operand, |exprs, span| {
+                        ast::Expr::Concat(ast::Concat { exprs, span })
+                    });
+                match groups.pop() {
+                    Some((outer, span)) => {
+                        self.expect_close_paren(span)?;
+                        operands = outer;
+                        expr = self.parse_calls(group)?;
+                    }
+                    None => return Ok(group),
+                }
+            }
+        }
+    }
+
+    /// Parses the `)` that closes the group opened at the given span.
+    fn expect_close_paren(&mut self, open: Span) -> Result<()> {
+        match self.peek()? {
+            Some((Token::CloseParen, _)) => {
+                self.next()?;
+                Ok(())
+            }
+            None | Some((Token::EndExpr | Token::EndBlock, _)) => {
+                Err(Error::syntax("unclosed parenthesis", self.source(), open))
+            }
+            Some(_) => self.expect(Token::CloseParen).map(|_| ()),
+        }
+    }
+
+    /// Parses zero or more `??` operators following the given expression.
+    ///
+    /// Each operand can have function calls applied to it, for example
+    ///
+    ///   user.nickname | upper ?? user.name ?? "Anonymous"
+    ///
+    fn parse_coalesce(&mut self, expr: ast::Expr) -> Result<ast::Expr> {
+        if !self.is_next(Token::QuestionQuestion)? {
+            return Ok(expr);
+        }
+        let mut exprs = vec![expr];
+        while self.is_next(Token::QuestionQuestion)? {
+            self.expect(Token::QuestionQuestion)?;
+            let base = self.parse_base_expr()?;
+            exprs.push(self.parse_calls(ast::Expr::Base(base))?);
+        }
+        let span = exprs[0].span().combine(exprs[exprs.len() - 1].span());
+        Ok(ast::Expr::Coalesce(ast::Coalesce { exprs, span }))
+    }
+
+    /// Parses zero or more function calls applied to the given expression.
+    fn parse_calls(&mut self, mut expr: ast::Expr) -> Result<ast::Expr> {
         while self.is_next(Token::Pipe)? {
             self.expect(Token::Pipe)?;
             let name = self.parse_ident()?;
@@ -464,6 +1028,39 @@ impl<'engine, 'source> Parser<'engine, 'source> {
         Ok(expr)
     }
 
+    /// Parses the iterable in a `for` block.
+    ///
+    /// This is either an expression or a range of integers. For example
+    ///
+    ///   users | reverse
+    ///
+    ///   0..10
+    ///
+    ///   1..=page_count
+    ///
+    fn parse_iterable(&mut self) -> Result<ast::Iterable> {
+        if self.is_next(Token::OpenParen)? {
+            return Ok(ast::Iterable::Expr(self.parse_expr()?));
+        }
+        let start = self.parse_base_expr()?;
+        let inclusive = match self.peek()? {
+            Some((Token::DotDot, _)) => false,
+            Some((Token::DotDotEq, _)) => true,
+            _ => {
+                let expr = self.parse_calls(ast::Expr::Base(start))?;
+                let expr = self.parse_coalesce(expr)?;
+                return Ok(ast::Iterable::Expr(expr));
+            }
+        };
+        self.next()?;
+        let end = self.parse_base_expr()?;
+        Ok(ast::Iterable::Range(ast::Range {
+            start,
+            end,
+            inclusive,
+        }))
+    }
+
     /// Parses a variable or literal.
     ///
     /// This is either a variable like
@@ -568,25 +1165,28 @@ impl<'engine, 'source> Parser<'engine, 'source> {
     ///
     ///   2
     ///
+    ///   -1
+    ///
     ///   name
     ///
     fn parse_access(&mut self) -> Result<ast::Access> {
         match self.parse()? {
             (Token::Index, span) => {
-                let value = match self.source()[span].parse() {
-                    Ok(value) => value,
-                    Err(_) => {
-                        return Err(Error::syntax(
-                            format!(
-                                "base 10 literal out of range for unsigned {}-bit integer",
-                                usize::BITS
-                            ),
-                            self.source(),
-                            span,
-                        ));
-                    }
+                let raw = &self.source()[span];
+                let (negative, raw) = match raw.strip_prefix('-') {
+                    Some(raw) => (true, raw),
+                    None => (false, raw),
                 };
-                Ok(ast::Access::Index(ast::Index { value, span }))
+                let int = format!("unsigned {}-bit integer", usize::BITS);
+                let value = self.parse_digits(raw, span, &int, |acc: usize, radix, x| {
+                    acc.checked_mul(radix as usize)?.checked_add(x as usize)
+                })?;
+                Ok(ast::Access::Index(ast::Index {
+                    value,
+                    // `-0` is the same as `0`
+                    negative: negative && value != 0,
+                    span,
+                }))
             }
             (Token::Ident, span) => Ok(ast::Access::Key(ast::Ident { span })),
             (tk, span) => Err(self.err_unexpected_token("identifier or index", tk, span)),
@@ -595,32 +1195,92 @@ impl<'engine, 'source> Parser<'engine, 'source> {
 
     /// Parses filter arguments.
     ///
-    /// This is just a comma separate list of base expressions. For example
+    /// This is a comma separate list of base expressions, optionally followed
+    /// by named arguments. Positional arguments may not follow named arguments.
+    /// For example
     ///
     ///   user.name, "a string", true
     ///
+    ///   user.name, width = 10, fill = "0"
+    ///
+    /// A single trailing comma is allowed before the end of the tag.
+    ///
+    ///   user.name, "a string",
+    ///
     fn parse_args(&mut self, span: Span) -> Result<ast::Args> {
         let mut values = Vec::new();
+        let mut named: Vec<ast::NamedArg> = Vec::new();
+        let mut last;
         loop {
-            values.push(self.parse_base_expr()?);
+            let value = self.parse_base_expr()?;
+            if self.is_next(Token::Assign)? {
+                let name = match value {
+                    ast::BaseExpr::Var(ast::Var { path }) if path.len() == 1 => {
+                        match path[0].access {
+                            ast::Access::Key(ident) => ident,
+                            ast::Access::Index(_) => unreachable!(),
+                        }
+                    }
+                    value => {
+                        return Err(Error::syntax(
+                            "expected identifier for named argument",
+                            self.source(),
+                            value.span(),
+                        ))
+                    }
+                };
+                let name_raw = &self.source()[name.span];
+                if named
+                    .iter()
+                    .any(|arg| &self.source()[arg.name.span] == name_raw)
+                {
+                    return Err(Error::syntax(
+                        format!("duplicate named argument `{name_raw}`"),
+                        self.source(),
+                        name.span,
+                    ));
+                }
+                self.expect(Token::Assign)?;
+                let value = self.parse_base_expr()?;
+                last = value.span();
+                named.push(ast::NamedArg { name, value });
+            } else if !named.is_empty() {
+                return Err(Error::syntax(
+                    "positional argument after named argument",
+                    self.source(),
+                    value.span(),
+                ));
+            } else {
+                last = value.span();
+                values.push(value);
+            }
             if !self.is_next(Token::Comma)? {
                 break;
             }
             self.expect(Token::Comma)?;
+            if self.is_next(Token::EndExpr)? || self.is_next(Token::EndBlock)? {
+                break;
+            }
         }
-        let span = span.combine(values.last().unwrap().span());
-        Ok(ast::Args { values, span })
+        let span = span.combine(last);
+        Ok(ast::Args {
+            values,
+            named,
+            span,
+        })
     }
 
     /// Parses loop variable(s).
     ///
-    /// This is either a single identifier or two comma separated identifiers.
-    /// Both of the following are valid:
+    /// This is either a single identifier or two or more comma separated
+    /// identifiers. All of the following are valid:
     ///
     ///   item
     ///
     ///   key, value
     ///
+    ///   id, label, count
+    ///
     fn parse_loop_vars(&mut self) -> Result<ast::LoopVars> {
         let key = self.parse_ident()?;
         if !self.is_next(Token::Comma)? {
@@ -628,16 +1288,26 @@ impl<'engine, 'source> Parser<'engine, 'source> {
         }
         self.expect(Token::Comma)?;
         let value = self.parse_ident()?;
-        let span = key.span.combine(value.span);
-        Ok(ast::LoopVars::KeyValue(ast::KeyValue { key, value, span }))
+        if !self.is_next(Token::Comma)? {
+            let span = key.span.combine(value.span);
+            return Ok(ast::LoopVars::KeyValue(ast::KeyValue { key, value, span }));
+        }
+        let mut vars = vec![key, value];
+        while self.is_next(Token::Comma)? {
+            self.expect(Token::Comma)?;
+            vars.push(self.parse_ident()?);
+        }
+        let span = vars[0].span.combine(vars[vars.len() - 1].span);
+        Ok(ast::LoopVars::Tuple(ast::Tuple { vars, span }))
     }
 
     /// Parses a boolean argument.
     fn parse_literal_bool(&mut self, span: Span) -> Result<ast::Literal> {
-        let bool = match &self.source()[span] {
-            "false" => false,
-            "true" => true,
-            kw => {
+        let bool = match self.keyword(span) {
+            Keyword::False => false,
+            Keyword::True => true,
+            _ => {
+                let kw = &self.source()[span];
                 return Err(self.err_unexpected_keyword(kw, span));
             }
         };
@@ -669,6 +1339,27 @@ impl<'engine, 'source> Parser<'engine, 'source> {
 
     /// Parse an integer.
     fn parse_literal_integer(&self, raw: &str, span: Span, sign: Sign) -> Result<ast::Literal> {
+        let int = self.parse_digits(raw, span, "64-bit integer", |acc: i64, radix, x| {
+            let value = acc.checked_mul(radix.into())?;
+            match sign {
+                Sign::Pos => value.checked_add(x.into()),
+                Sign::Neg => value.checked_sub(x.into()),
+            }
+        })?;
+        let value = Value::Integer(int);
+        Ok(ast::Literal { value, span })
+    }
+
+    /// Parses the digits of an integer with an optional `0b`, `0o` or `0x`
+    /// prefix, folding each digit into the result using `f`.
+    ///
+    /// The raw digits must be at the end of `span`. If `f` overflows and
+    /// returns `None` then the error describes the integer type using `int`.
+    fn parse_digits<T, F>(&self, raw: &str, span: Span, int: &str, f: F) -> Result<T>
+    where
+        T: Default,
+        F: Fn(T, u32, u32) -> Option<T>,
+    {
         let digits = raw.as_bytes();
         let (i, radix) = match digits {
             [b'0', b'b', ..] => (2, 2),
@@ -676,35 +1367,28 @@ impl<'engine, 'source> Parser<'engine, 'source> {
             [b'0', b'x', ..] => (2, 16),
             _ => (0, 10),
         };
-        let int = digits[i..]
+        let offset = span.n - raw.len();
+        digits[i..]
             .iter()
             .enumerate()
             .filter(|(_, &d)| d != b'_')
-            .try_fold(0i64, |acc, (j, &d)| {
+            .try_fold(T::default(), |acc, (j, &d)| {
                 let x = (d as char).to_digit(radix).ok_or_else(|| {
-                    let m = span.m + i + j;
+                    let m = offset + i + j;
                     Error::syntax(
                         format!("invalid digit for base {radix} literal"),
                         self.source(),
                         m..m + 1,
                     )
                 })?;
-                let err = || {
+                f(acc, radix, x).ok_or_else(|| {
                     Error::syntax(
-                        format!("base {radix} literal out of range for 64-bit integer"),
+                        format!("base {radix} literal out of range for {int}"),
                         self.source(),
                         span,
                     )
-                };
-                let value = acc.checked_mul(radix.into()).ok_or_else(err)?;
-                match sign {
-                    Sign::Pos => value.checked_add(x.into()),
-                    Sign::Neg => value.checked_sub(x.into()),
-                }
-                .ok_or_else(err)
-            })?;
-        let value = Value::Integer(int);
-        Ok(ast::Literal { value, span })
+                })
+            })
     }
 
     /// Parses a float.
@@ -742,6 +1426,8 @@ impl<'engine, 'source> Parser<'engine, 'source> {
                             't' => '\t',
                             '\\' => '\\',
                             '"' => '"',
+                            '0' => '\0',
+                            'u' => self.parse_unicode_escape(&mut iter, i)?,
                             _ => {
                                 let j = iter.next().unwrap().0;
                                 return Err(Error::syntax(
@@ -763,6 +1449,40 @@ impl<'engine, 'source> Parser<'engine, 'source> {
         Ok(string)
     }
 
+    /// Parses the remainder of a unicode escape sequence like `\u{1F600}`. The
+    /// given index is the index of the `u` character.
+    fn parse_unicode_escape<I>(&self, iter: &mut I, i: usize) -> Result<char>
+    where
+        I: Iterator<Item = (usize, char)>,
+    {
+        let err = |j| Error::syntax("invalid unicode escape", self.source(), i..j);
+        match iter.next().unwrap() {
+            (_, '{') => {}
+            (j, _) => return Err(err(j)),
+        }
+        let mut value: u32 = 0;
+        let mut digits = 0;
+        let end = loop {
+            match iter.next().unwrap() {
+                (j, '}') if digits > 0 => break j + 1,
+                (_, c) if digits < 6 && c.is_ascii_hexdigit() => {
+                    value = value * 16 + c.to_digit(16).unwrap();
+                    digits += 1;
+                }
+                (j, '"') => return Err(err(j)),
+                (j, c) => return Err(err(j + c.len_utf8())),
+            }
+        };
+        char::from_u32(value).ok_or_else(|| {
+            let reason = if (0xD800..=0xDFFF).contains(&value) {
+                "unicode escape must not be a surrogate"
+            } else {
+                "unicode escape must be at most 10FFFF"
+            };
+            Error::syntax(reason, self.source(), i..end)
+        })
+    }
+
     /// Expects the given keyword.
     fn expect_keyword(&mut self, exp: Keyword) -> Result<Span> {
         let (kw, span) = self.parse_keyword()?;
@@ -781,8 +1501,7 @@ impl<'engine, 'source> Parser<'engine, 'source> {
     /// Parses a keyword.
     fn parse_keyword(&mut self) -> Result<(Keyword, Span)> {
         let span = self.expect(Token::Keyword)?;
-        let kw = &self.source()[span];
-        Ok((Keyword::from_str(kw), span))
+        Ok((self.keyword(span), span))
     }
 
     /// Parses an identifier.
@@ -812,10 +1531,19 @@ impl<'engine, 'source> Parser<'engine, 'source> {
     fn is_next_keyword(&mut self, exp: Keyword) -> Result<bool> {
         Ok(self
             .peek()?
-            .map(|(tk, sp)| tk == Token::Keyword && Keyword::from_str(&self.source()[sp]) == exp)
+            .map(|(tk, sp)| tk == Token::Keyword && self.keyword(sp) == exp)
             .unwrap_or(false))
     }
 
+    /// Returns the keyword for the given keyword token.
+    fn keyword(&self, span: Span) -> Keyword {
+        let kw = &self.source()[span];
+        match self.tokens.engine.case_insensitive_keywords {
+            true => Keyword::from_str(&kw.to_ascii_lowercase()),
+            false => Keyword::from_str(kw),
+        }
+    }
+
     /// Returns `true` if the next token is equal to the provided one.
     fn is_next(&mut self, token: Token) -> Result<bool> {
         Ok(self.peek()?.map(|(tk, _)| tk == token).unwrap_or(false))
@@ -858,18 +1586,48 @@ impl<'engine, 'source> Parser<'engine, 'source> {
 }
 
 impl Keyword {
-    pub(crate) const fn all() -> &'static [&'static str] {
+    /// Keywords that are reserved everywhere except as a member name, e.g.
+    /// `user.if`.
+    pub(crate) const fn reserved() -> &'static [&'static str] {
         &[
             "if", "not", "else", "endif", "for", "in", "endfor", "with", "as", "endwith",
             "include", "true", "false",
         ]
     }
 
+    /// Keywords that are only reserved at the start of a block tag, e.g.
+    /// `{% set ... %}`. Anywhere else they are identifiers.
+    pub(crate) const fn statements() -> &'static [&'static str] {
+        &[
+            "elif",
+            "autoescape",
+            "endautoescape",
+            "include_raw",
+            "raw",
+            "endraw",
+            "break",
+            "continue",
+            "set",
+            "extends",
+            "block",
+            "endblock",
+        ]
+    }
+
+    /// Keywords that are only reserved directly after an operand, e.g.
+    /// `a and b`. Anywhere else they are identifiers.
+    pub(crate) const fn operators() -> &'static [&'static str] {
+        &["and", "or", "is"]
+    }
+
     const fn human(&self) -> &'static str {
         match self {
             Self::If => "if",
             Self::Not => "not",
+            Self::And => "and",
+            Self::Or => "or",
             Self::Else => "else",
+            Self::Elif => "elif",
             Self::EndIf => "endif",
             Self::For => "for",
             Self::In => "in",
@@ -877,7 +1635,19 @@ impl Keyword {
             Self::With => "with",
             Self::As => "as",
             Self::EndWith => "endwith",
+            Self::Is => "is",
+            Self::Autoescape => "autoescape",
+            Self::EndAutoescape => "endautoescape",
             Self::Include => "include",
+            Self::IncludeRaw => "include_raw",
+            Self::Raw => "raw",
+            Self::EndRaw => "endraw",
+            Self::Break => "break",
+            Self::Continue => "continue",
+            Self::Set => "set",
+            Self::Extends => "extends",
+            Self::Block => "block",
+            Self::EndBlock => "endblock",
             Self::True => "true",
             Self::False => "false",
         }
@@ -887,7 +1657,10 @@ impl Keyword {
         match s {
             "if" => Self::If,
             "not" => Self::Not,
+            "and" => Self::And,
+            "or" => Self::Or,
             "else" => Self::Else,
+            "elif" => Self::Elif,
             "endif" => Self::EndIf,
             "for" => Self::For,
             "in" => Self::In,
@@ -895,10 +1668,56 @@ impl Keyword {
             "with" => Self::With,
             "as" => Self::As,
             "endwith" => Self::EndWith,
+            "is" => Self::Is,
+            "autoescape" => Self::Autoescape,
+            "endautoescape" => Self::EndAutoescape,
             "include" => Self::Include,
+            "include_raw" => Self::IncludeRaw,
+            "raw" => Self::Raw,
+            "endraw" => Self::EndRaw,
+            "break" => Self::Break,
+            "continue" => Self::Continue,
+            "set" => Self::Set,
+            "extends" => Self::Extends,
+            "block" => Self::Block,
+            "endblock" => Self::EndBlock,
             "true" => Self::True,
             "false" => Self::False,
             _ => unreachable!(),
         }
     }
 }
+
+/// Returns the last operand of an operator, or combines all the operands using
+/// the given function if there is more than one.
+fn finish_operands<F>(mut exprs: Vec<ast::Expr>, last: ast::Expr, f: F) -> ast::Expr
+where
+    F: FnOnce(Vec<ast::Expr>, Span) -> ast::Expr,
+{
+    if exprs.is_empty() {
+        return last;
+    }
+    exprs.push(last);
+    let span = exprs[0].span().combine(exprs[exprs.len() - 1].span());
+    f(exprs, span)
+}
+
+/// Returns whether the innermost block that is a `for` or `block` statement is
+/// a `for` statement. A `block` may be rendered in place of a block in another
+/// template, so `break` and `continue` can't refer to loops outside of it. The
+/// `else` clause of a `for` statement is not inside the loop.
+fn in_loop(blocks: &[State]) -> bool {
+    blocks
+        .iter()
+        .rev()
+        .take_while(|b| !matches!(b, State::Block { .. }))
+        .any(|b| {
+            matches!(
+                b,
+                State::For {
+                    has_else: false,
+                    ..
+                }
+            )
+        })
+}
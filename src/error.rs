@@ -1,13 +1,17 @@
 use std::cmp::max;
+use std::ops::Range;
 
 use crate::fmt;
 use crate::types::span::Span;
 
 /// An error that can occur during template compilation or rendering.
 pub struct Error {
-    /// The type of error, possibly carries a source error.
+    /// The type of error.
     kind: ErrorKind,
 
+    /// The source IO error, if this is an IO error.
+    io: Option<std::io::Error>,
+
     /// Optional template name.
     name: Option<String>,
 
@@ -16,22 +20,25 @@ pub struct Error {
 
     /// Optional pretty information showing the location in the template of the
     /// reason for the error.
-    pretty: Option<Pretty>,
+    pretty: Option<Box<Pretty>>,
 }
 
-#[derive(Debug)]
-enum ErrorKind {
+/// The kind of an [`Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
     /// The template syntax was incorrect.
     ///
     /// This can happen for a variety of reasons if template compilation fails.
-    /// The reason field on the parent `Error` will carry more information about
-    /// the exact failure.
+    /// The [reason][Error::reason] carries more information about the exact
+    /// failure. It can also happen when building a custom syntax configuration
+    /// using [`SyntaxBuilder::try_build`][crate::SyntaxBuilder::try_build]
+    /// if the delimiters are ambiguous.
     Syntax,
 
     /// A serialization error.
     ///
     /// This can happen when serializing the data to be rendered fails.
-    #[cfg(feature = "serde")]
     Serialize,
 
     /// Rendering failed.
@@ -43,7 +50,6 @@ enum ErrorKind {
     /// A filter error.
     ///
     /// This can happen if a user defined filter returns an error.
-    #[cfg(feature = "filters")]
     Filter,
 
     /// A format error.
@@ -55,8 +61,36 @@ enum ErrorKind {
     /// An IO error.
     ///
     /// This can only happen when rendering to a type implementing
-    /// `std::io::Write` and some IO occurs.
-    Io(std::io::Error),
+    /// `std::io::Write` and some IO occurs. The IO error is available using
+    /// [`source()`][std::error::Error::source].
+    Io,
+
+    /// A precompiled template could not be loaded.
+    ///
+    /// This can happen if the bytes passed to
+    /// [`Template::from_bytes`][crate::Template::from_bytes] are corrupted or
+    /// were created by an incompatible version of this crate.
+    Precompile,
+
+    /// The maximum include depth was reached.
+    ///
+    /// This can happen when rendering templates that include or extend each
+    /// other in a cycle.
+    MaxIncludeDepth,
+
+    /// The maximum output size was reached.
+    ///
+    /// This can happen when rendering produces more output than the limit set
+    /// using [`Renderer::with_max_output_size`][crate::Renderer::with_max_output_size]
+    /// or [`Engine::set_max_output_size`][crate::Engine::set_max_output_size].
+    MaxOutputSize,
+
+    /// Rendering was cancelled.
+    ///
+    /// This can happen when the flag passed to
+    /// [`Renderer::with_cancel`][crate::Renderer::with_cancel] is set while
+    /// rendering.
+    Cancelled,
 }
 
 impl Error {
@@ -64,9 +98,10 @@ impl Error {
     pub(crate) fn syntax(reason: impl Into<String>, source: &str, span: impl Into<Span>) -> Self {
         Self {
             kind: ErrorKind::Syntax,
+            io: None,
             name: None,
             reason: Some(reason.into()),
-            pretty: Some(Pretty::build(source, span.into())),
+            pretty: Some(Box::new(Pretty::build(source, span.into()))),
         }
     }
 
@@ -74,22 +109,159 @@ impl Error {
     pub(crate) fn render(reason: impl Into<String>, source: &str, span: impl Into<Span>) -> Self {
         Self {
             kind: ErrorKind::Render,
+            io: None,
+            name: None,
+            reason: Some(reason.into()),
+            pretty: Some(Box::new(Pretty::build(source, span.into()))),
+        }
+    }
+
+    /// Constructs a new error for an ambiguous syntax configuration.
+    pub(crate) fn ambiguous_syntax(reason: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::Syntax,
+            io: None,
             name: None,
             reason: Some(reason.into()),
-            pretty: Some(Pretty::build(source, span.into())),
+            pretty: None,
+        }
+    }
+
+    /// Constructs an error for when the block to render is not defined.
+    pub(crate) fn unknown_block(name: &str, available: &[&str]) -> Self {
+        let reason = match available {
+            [] => format!("unknown block `{name}`, the template has no blocks"),
+            _ => {
+                let available: Vec<_> = available.iter().map(|n| format!("`{n}`")).collect();
+                format!(
+                    "unknown block `{name}`, available blocks: {}",
+                    available.join(", ")
+                )
+            }
+        };
+        Self {
+            kind: ErrorKind::Render,
+            io: None,
+            name: None,
+            reason: Some(reason),
+            pretty: None,
         }
     }
 
     /// Constructs a max include depth error.
     pub(crate) fn max_include_depth(max: usize) -> Self {
         Self {
-            kind: ErrorKind::Render,
+            kind: ErrorKind::MaxIncludeDepth,
+            io: None,
             name: None,
             reason: Some(format!("reached maximum include depth ({max})")),
             pretty: None,
         }
     }
 
+    /// Constructs an error for when the maximum output size is reached.
+    pub(crate) fn max_output_size(max: usize) -> Self {
+        Self {
+            kind: ErrorKind::MaxOutputSize,
+            io: None,
+            name: None,
+            reason: Some(format!("reached maximum output size ({max} bytes)")),
+            pretty: None,
+        }
+    }
+
+    /// Constructs an error for when rendering is cancelled.
+    pub(crate) fn cancelled() -> Self {
+        Self {
+            kind: ErrorKind::Cancelled,
+            io: None,
+            name: None,
+            reason: Some(String::from("rendering was cancelled")),
+            pretty: None,
+        }
+    }
+
+    /// Returns the kind of error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the name of the template in which the error occurred, if
+    /// known.
+    ///
+    /// This is `None` for templates compiled using
+    /// [`Engine::compile`][crate::Engine::compile] as well as errors that are
+    /// not associated with a template.
+    pub fn template_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the reason for the error, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// Returns the byte range in the template source that caused the error, if
+    /// known.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.pretty.as_ref().map(|p| p.span.m..p.span.n)
+    }
+
+    /// Returns the one-indexed line and column in the template source that
+    /// caused the error, if known.
+    ///
+    /// The column is measured in characters, or in display width if the
+    /// **`unicode`** feature is enabled, as in the pretty error output.
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        self.pretty.as_ref().map(|p| (p.ln + 1, p.col + 1))
+    }
+
+    /// Returns a type that displays the error like the alternate formatting
+    /// `{:#}` but with the given number of source lines before and after the
+    /// line that caused the error.
+    ///
+    /// This is useful for locating errors in long templates. Each line is
+    /// prefixed with its line number. At most ten lines are displayed on each
+    /// side. Errors that are not associated with a location in a template are
+    /// displayed in the same way as `{:#}`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let err = upon::Engine::new()
+    ///     .compile("<ul>\n  <li>{{ user.name }</li>\n</ul>")
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     err.display_with_context(1).to_string(),
+    ///     "invalid syntax
+    ///
+    ///   --> <anonymous>:2:20
+    ///    |
+    ///  1 | <ul>
+    ///  2 |   <li>{{ user.name }</li>
+    ///    |                    ^--
+    ///  3 | </ul>
+    ///    |
+    ///    = reason: unexpected character
+    /// "
+    /// );
+    /// ```
+    pub fn display_with_context(&self, lines: usize) -> impl std::fmt::Display + '_ {
+        DisplayWithContext { err: self, lines }
+    }
+
+    /// Constructs a new error for a precompiled template that can't be loaded.
+    #[cfg(feature = "precompile")]
+    pub(crate) fn precompile(reason: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::Precompile,
+            io: None,
+            name: None,
+            reason: Some(reason.into()),
+            pretty: None,
+        }
+    }
+
     /// Attaches a template name to the error, if it is not already set.
     pub(crate) fn with_template_name(mut self, name: String) -> Self {
         self.name.get_or_insert(name);
@@ -100,7 +272,7 @@ impl Error {
     #[cfg(feature = "filters")]
     pub(crate) fn enrich(mut self, source: &str, span: impl Into<Span>) -> Self {
         self.pretty
-            .get_or_insert_with(|| Pretty::build(source, span.into()));
+            .get_or_insert_with(|| Box::new(Pretty::build(source, span.into())));
         self
     }
 
@@ -108,6 +280,7 @@ impl Error {
     pub(crate) fn filter(reason: impl Into<String>) -> Self {
         Self {
             kind: ErrorKind::Filter,
+            io: None,
             name: None,
             reason: Some(reason.into()),
             pretty: None,
@@ -117,9 +290,10 @@ impl Error {
     pub(crate) fn format(err: fmt::Error, source: &str, span: impl Into<Span>) -> Self {
         Self {
             kind: ErrorKind::Format,
+            io: None,
             name: None,
             reason: err.message(),
-            pretty: Some(Pretty::build(source, span.into())),
+            pretty: Some(Box::new(Pretty::build(source, span.into()))),
         }
     }
 }
@@ -127,7 +301,8 @@ impl Error {
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self {
-            kind: ErrorKind::Io(err),
+            kind: ErrorKind::Io,
+            io: Some(err),
             name: None,
             reason: None,
             pretty: None,
@@ -139,6 +314,7 @@ impl From<std::fmt::Error> for Error {
     fn from(_: std::fmt::Error) -> Self {
         Self {
             kind: ErrorKind::Format,
+            io: None,
             name: None,
             reason: None,
             pretty: None,
@@ -155,6 +331,7 @@ impl serde::ser::Error for Error {
     {
         Self {
             kind: ErrorKind::Serialize,
+            io: None,
             name: None,
             reason: Some(msg.to_string()),
             pretty: None,
@@ -164,9 +341,9 @@ impl serde::ser::Error for Error {
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match &self.kind {
-            ErrorKind::Io(err) => Some(err),
-            _ => None,
+        match &self.io {
+            Some(err) => Some(err),
+            None => None,
         }
     }
 }
@@ -179,6 +356,7 @@ impl std::fmt::Debug for Error {
 
         f.debug_struct("Error")
             .field("kind", &self.kind)
+            .field("io", &self.io)
             .field("name", &self.name)
             .field("reason", &self.reason)
             .field("pretty", &self.pretty)
@@ -189,20 +367,11 @@ impl std::fmt::Debug for Error {
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let msg = match &self.kind {
-            ErrorKind::Syntax => "invalid syntax",
-            ErrorKind::Render => "render error",
-            #[cfg(feature = "filters")]
-            ErrorKind::Filter => "filter error",
-            ErrorKind::Format => "format error",
-            #[cfg(feature = "serde")]
-            ErrorKind::Serialize => "serialize error",
-            ErrorKind::Io(_) => "io error",
-        };
+        let msg = self.kind_msg();
         match (&self.reason, &self.pretty) {
             (Some(r), Some(p)) if f.alternate() => {
                 write!(f, "{msg}")?;
-                p.fmt_with_reason(f, self.name.as_deref(), r)
+                p.fmt_with_reason(f, self.name.as_deref(), r, 0)
             }
             (Some(reason), _) => write!(f, "{msg}: {reason}"),
             _ => write!(f, "{msg}"),
@@ -210,6 +379,43 @@ impl std::fmt::Display for Error {
     }
 }
 
+impl Error {
+    fn kind_msg(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::Syntax => "invalid syntax",
+            ErrorKind::Render
+            | ErrorKind::MaxIncludeDepth
+            | ErrorKind::MaxOutputSize
+            | ErrorKind::Cancelled => "render error",
+            ErrorKind::Filter => "filter error",
+            ErrorKind::Format => "format error",
+            ErrorKind::Serialize => "serialize error",
+            ErrorKind::Io => "io error",
+            ErrorKind::Precompile => "invalid precompiled template",
+        }
+    }
+}
+
+/// Displays an error with surrounding source lines.
+///
+/// Returned from [`Error::display_with_context`].
+struct DisplayWithContext<'a> {
+    err: &'a Error,
+    lines: usize,
+}
+
+impl std::fmt::Display for DisplayWithContext<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.err.reason, &self.err.pretty) {
+            (Some(r), Some(p)) => {
+                write!(f, "{}", self.err.kind_msg())?;
+                p.fmt_with_reason(f, self.err.name.as_deref(), r, self.lines)
+            }
+            _ => write!(f, "{:#}", self.err),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Pretty
 ////////////////////////////////////////////////////////////////////////////////
@@ -223,24 +429,36 @@ struct Pretty {
     col: usize,
     /// The number of characters to highlight after `col`.
     width: usize,
-    /// The relevant section of template (a single line).
-    text: String,
+    /// The line that caused the error and up to [`MAX_CONTEXT`] lines before
+    /// and after it.
+    lines: Vec<String>,
+    /// Zero-indexed line number of the first line in `lines`.
+    first: usize,
+    /// The span in the template source.
+    span: Span,
 }
 
+/// The maximum number of lines displayed before and after the line that caused
+/// an error.
+const MAX_CONTEXT: usize = 10;
+
 impl Pretty {
     fn build(source: &str, span: Span) -> Self {
         let lines: Vec<_> = source.split_terminator('\n').collect();
         let (ln, col) = to_ln_col(&lines, span.m);
         let width = max(1, display_width(&source[span]));
-        let text = lines
-            .get(ln)
-            .unwrap_or_else(|| lines.last().unwrap())
-            .to_string();
+        // The error can be located just past the last line, in which case
+        // the last line is displayed as the line with the error.
+        let end = ln.min(lines.len().saturating_sub(1));
+        let first = end.saturating_sub(MAX_CONTEXT);
+        let last = lines.len().min(end + 1 + MAX_CONTEXT);
         Self {
             ln,
             col,
             width,
-            text,
+            lines: lines[first..last].iter().map(|l| l.to_string()).collect(),
+            first,
+            span,
         }
     }
 
@@ -249,10 +467,20 @@ impl Pretty {
         f: &mut std::fmt::Formatter<'_>,
         name: Option<&str>,
         reason: &str,
+        context: usize,
     ) -> std::fmt::Result {
+        let context = context.min(MAX_CONTEXT);
+        let lines = &self.lines;
+        let end = self.ln.min(self.first + lines.len().saturating_sub(1));
+        let text = &lines[end - self.first];
+        let start = self.ln.saturating_sub(context).min(end);
+        let before = &lines[start - self.first..end - self.first];
+        let after = lines.get(self.ln + 1 - self.first..).unwrap_or_default();
+        let after = &after[..context.min(after.len())];
+
         let num = (self.ln + 1).to_string();
         let col = self.col + 1;
-        let pad = display_width(&num);
+        let pad = display_width(&(self.ln + 1 + after.len()).to_string());
         let align = self.col + self.width;
 
         let z = "";
@@ -261,15 +489,26 @@ impl Pretty {
         let underline = "^".repeat(self.width);
         let extra = "-".repeat(3_usize.saturating_sub(self.width));
         let name = name.unwrap_or("<anonymous>");
-        let text = &self.text;
 
         write!(
             f,
             "\n\n {z:pad$}--> {name}:{num}:{col}\
-             \n {z:pad$} {pipe}\
-             \n {num:>} {pipe} {text}\
-             \n {z:pad$} {pipe} {underline:>align$}{extra}\
-             \n {z:pad$} {pipe}\
+             \n {z:pad$} {pipe}",
+        )?;
+        for (n, line) in (start + 1..).zip(before) {
+            write!(f, "\n {n:>pad$} {pipe} {line}")?;
+        }
+        write!(
+            f,
+            "\n {num:>pad$} {pipe} {text}\
+             \n {z:pad$} {pipe} {underline:>align$}{extra}",
+        )?;
+        for (n, line) in (self.ln + 2..).zip(after) {
+            write!(f, "\n {n:>pad$} {pipe} {line}")?;
+        }
+        write!(
+            f,
+            "\n {z:pad$} {pipe}\
              \n {z:pad$} {equals} reason: {reason}\n",
         )
     }
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::render::FilterState;
+use crate::types::ast::BaseExpr;
+use crate::types::span::Span;
+
+/// An error returned from a filter that points at part of the filter call.
+///
+/// Filters can return this error type instead of a [`String`] to choose what
+/// the error points at when it is displayed using the alternate formatting
+/// `{:#}`. Errors created using [`Error::new`] or converted from a [`String`]
+/// point at the filter name, which is the same as returning a [`String`].
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters;
+///
+/// let mut engine = upon::Engine::new();
+/// engine.add_filter("port", |port: i64| {
+///     if (1..=65535).contains(&port) {
+///         Ok(port)
+///     } else {
+///         Err(filters::Error::at_value("invalid port number"))
+///     }
+/// });
+///
+/// let err = engine
+///     .compile("{{ server.port | port }}")?
+///     .render(&engine, upon::value! { server: { port: 0 } })
+///     .to_string()
+///     .unwrap_err();
+/// assert_eq!(err.span(), Some(3..14));
+/// # Ok::<(), upon::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    reason: String,
+    at: At,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum At {
+    Filter,
+    Value,
+    Arg(usize),
+}
+
+impl Error {
+    /// Construct a new error that points at the filter name.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            at: At::Filter,
+        }
+    }
+
+    /// Construct a new error that points at the value piped to the filter.
+    pub fn at_value(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            at: At::Value,
+        }
+    }
+
+    /// Construct a new error that points at the positional argument with the
+    /// given zero-based index.
+    ///
+    /// If there is no such argument the error points at the filter name.
+    pub fn at_arg(index: usize, reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            at: At::Arg(index),
+        }
+    }
+
+    /// Returns the reason for the error.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub(crate) fn into_error(self, spans: &Spans<'_>) -> crate::Error {
+        let span = match self.at {
+            At::Filter => None,
+            At::Value => Some(spans.value),
+            At::Arg(i) => spans.args.get(i).map(BaseExpr::span),
+        };
+        let err = crate::Error::filter(self.reason);
+        match span {
+            Some(span) => err.enrich(spans.source, span),
+            None => err,
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(reason: String) -> Self {
+        Self::new(reason)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(reason: &str) -> Self {
+        Self::new(reason)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The parts of a filter call that an error can point at.
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Spans<'a> {
+    source: &'a str,
+    value: Span,
+    args: &'a [BaseExpr],
+}
+
+impl<'a> Spans<'a> {
+    pub(crate) fn new(state: &FilterState<'a>) -> Self {
+        Self {
+            source: state.source,
+            value: state.span,
+            args: state.args,
+        }
+    }
+}
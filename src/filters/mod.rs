@@ -29,6 +29,10 @@
 //! Other arguments can also use [`&str`][str] but only if the passed parameter
 //! is always a literal string.
 //!
+//! Filters can return any type implementing [`FilterReturn`]. To avoid
+//! building a large list that is only partially looped over, a filter can
+//! return an iterator wrapped in [`Lazy`].
+//!
 //! # Examples
 //!
 //! ## Using existing functions
@@ -57,6 +61,80 @@
 //! {{ user.age | add: 10 }}
 //! ```
 //!
+//! ## Named arguments
+//!
+//! Filters that accept named arguments can be added using
+//! [`Engine::add_filter_with_named_args`][crate::Engine::add_filter_with_named_args].
+//! These receive the positional arguments as a slice and the named arguments
+//! as a map from name to value. Filters added using
+//! [`Engine::add_filter`][crate::Engine::add_filter] do not accept named
+//! arguments.
+//!
+//! ```text
+//! {{ user.id | pad: width = 10, fill = "0" }}
+//! ```
+//!
+//! ## Errors
+//!
+//! Filters can fail by returning a [`Result`][std::result::Result] with a
+//! [`String`] error. The error then points at the filter name in the template.
+//! To point at the value piped to the filter or at one of its arguments
+//! instead, return an [`Error`].
+//!
+//! ```
+//! use upon::filters::Error;
+//!
+//! let mut engine = upon::Engine::new();
+//! engine.add_filter("repeat", |s: String, n: i64| match usize::try_from(n) {
+//!     Ok(n) => Ok(s.repeat(n)),
+//!     Err(_) => Err(Error::at_arg(0, "expected a positive count")),
+//! });
+//! ```
+//!
+//! ## Stateful filters
+//!
+//! Filters must implement [`Fn`] and be [`Send`] and [`Sync`], so any state
+//! they close over must use thread safe interior mutability like an atomic
+//! integer. Such state is shared by every render using the engine.
+//!
+//! ```
+//! use std::sync::atomic::{AtomicI64, Ordering};
+//!
+//! let mut engine = upon::Engine::new();
+//! let calls = AtomicI64::new(0);
+//! engine.add_filter("calls", move |_: &upon::Value| {
+//!     calls.fetch_add(1, Ordering::Relaxed)
+//! });
+//! ```
+//!
+//! To keep state that is reset for every render use
+//! [`Engine::add_filter_mut`][crate::Engine::add_filter_mut] instead, the
+//! filter then receives a [`RenderContext`] that it can store values in.
+//!
+//! ## Accessing variables
+//!
+//! Filters added using
+//! [`Engine::add_filter_with_context`][crate::Engine::add_filter_with_context]
+//! receive a [`Context`] that can look up any variable that is in scope where
+//! the filter is called, not just the ones passed as arguments. This is useful
+//! for filters that depend on a setting in the render context, like a locale.
+//!
+//! ```
+//! use upon::filters::Context;
+//! use upon::Value;
+//!
+//! let mut engine = upon::Engine::new();
+//! engine.add_filter_with_context("t", |ctx: Context<'_>, key: &Value, _: &[Value]| {
+//!     match (ctx.get("locale").as_deref(), key) {
+//!         (Some(Value::String(l)), Value::String(k)) if l == "fr" && k == "hello" => "bonjour",
+//!         _ => "hello",
+//!     }
+//! });
+//! ```
+//!
+//! Such a filter is not a pure function of its arguments, the same call can
+//! return different values in different places of the same template.
+//!
 //! ## Owned vs reference arguments
 //!
 //! Consider the following template.
@@ -87,15 +165,26 @@
 //! ```
 
 mod args;
+mod error;
 mod impls;
+#[cfg(feature = "stdlib")]
+#[cfg_attr(docsrs, doc(cfg(feature = "stdlib")))]
+pub mod stdlib;
 
-use crate::render::{FilterState, Stack};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::render::{FilterOutput, FilterState, Stack, ValueIter};
+use crate::types::ast;
 use crate::types::ast::BaseExpr;
 use crate::types::span::Span;
 use crate::value::ValueCow;
-use crate::{Error, Result, Value};
+use crate::{Result, Value};
+
+pub use crate::filters::error::Error;
+use crate::filters::error::Spans;
 
-pub(crate) type FilterFn = dyn Fn(FilterState<'_>) -> Result<Value> + Send + Sync + 'static;
+pub(crate) type FilterFn = dyn Fn(FilterState<'_>) -> Result<FilterOutput> + Send + Sync + 'static;
 
 pub(crate) fn new<F, R, A>(f: F) -> Box<FilterFn>
 where
@@ -103,10 +192,83 @@ where
     R: FilterReturn,
     A: FilterArgs,
 {
-    Box::new(move |state: FilterState<'_>| -> Result<Value> {
+    Box::new(move |state: FilterState<'_>| -> Result<FilterOutput> {
+        let spans = Spans::new(&state);
         let args = A::from_state(state)?;
         let result = Filter::filter(&f, args);
-        FilterReturn::to_value(result)
+        FilterReturn::to_output(result, &spans)
+    })
+}
+
+pub(crate) fn new_with_named_args<F, R>(f: F) -> Box<FilterFn>
+where
+    F: Fn(&Value, &[Value], &BTreeMap<String, Value>) -> R + Send + Sync + 'static,
+    R: FilterReturn,
+{
+    Box::new(move |state: FilterState<'_>| -> Result<FilterOutput> {
+        let args = state
+            .args
+            .iter()
+            .map(|arg| eval_arg(state.source, state.stack, arg))
+            .collect::<Result<Vec<_>>>()?;
+        let named_args = state
+            .named_args
+            .iter()
+            .map(|arg| {
+                let name = state.source[arg.name.span].to_owned();
+                let value = eval_arg(state.source, state.stack, &arg.value)?;
+                Ok((name, value))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let spans = Spans::new(&state);
+        let result = f(state.value, &args, &named_args);
+        FilterReturn::to_output(result, &spans)
+    })
+}
+
+pub(crate) fn new_with_context<F, R>(f: F) -> Box<FilterFn>
+where
+    F: Fn(&mut RenderContext, &Value, &[Value]) -> R + Send + Sync + 'static,
+    R: FilterReturn,
+{
+    Box::new(move |state: FilterState<'_>| -> Result<FilterOutput> {
+        if let Some(arg) = state.named_args.first() {
+            return Err(err_named_args(state.source, arg.name.span));
+        }
+        let args = state
+            .args
+            .iter()
+            .map(|arg| eval_arg(state.source, state.stack, arg))
+            .collect::<Result<Vec<_>>>()?;
+        state.context.arg_sources = state
+            .args
+            .iter()
+            .map(|arg| state.source[arg.span()].to_owned())
+            .collect();
+        let spans = Spans::new(&state);
+        let result = f(state.context, state.value, &args);
+        FilterReturn::to_output(result, &spans)
+    })
+}
+
+pub(crate) fn new_with_lookup<F, R>(f: F) -> Box<FilterFn>
+where
+    F: Fn(Context<'_>, &Value, &[Value]) -> R + Send + Sync + 'static,
+    R: FilterReturn,
+{
+    Box::new(move |state: FilterState<'_>| -> Result<FilterOutput> {
+        if let Some(arg) = state.named_args.first() {
+            return Err(err_named_args(state.source, arg.name.span));
+        }
+        let args = state
+            .args
+            .iter()
+            .map(|arg| eval_arg(state.source, state.stack, arg))
+            .collect::<Result<Vec<_>>>()?;
+        let spans = Spans::new(&state);
+        let ctx = Context { stack: state.stack };
+        let result = f(ctx, state.value, &args);
+        FilterReturn::to_output(result, &spans)
     })
 }
 
@@ -156,20 +318,254 @@ pub trait FilterArg {
 /// - `R` where `R` implements `Into<Value>`
 /// - `Result<R, E>` where `R` implements `Into<Value>` and `E` implements
 ///   [`FilterError`].
+/// - [`Lazy<I>`][Lazy] where `I` is an iterator of items implementing
+///   `Into<Value>`.
+/// - `Result<Lazy<I>, E>` where `E` implements [`FilterError`].
 ///
 /// *See the [module][crate::filters] documentation for more information.*
 #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
 pub trait FilterReturn {
     #[doc(hidden)]
-    fn to_value(self) -> Result<Value>;
+    fn to_output(self, spans: &Spans<'_>) -> Result<FilterOutput>;
 }
 
-/// A value returned from a filter.
+/// A lazy sequence of values returned from a filter.
+///
+/// A filter can return an iterator wrapped in this type instead of collecting
+/// it into a list. When the filter is applied to the iterable of a `for` loop
+/// the loop only takes the items that it renders. In any other context the
+/// items are collected into a list.
+///
+/// This includes looping over the filter with `reversed` or passing its result
+/// to another filter, so an iterator that never ends must only be used directly
+/// as the iterable of a `for` loop. Collecting an iterator whose
+/// [size hint][Iterator::size_hint] says it is infinite, like the one below, is
+/// a render error. Any other iterator is collected until it ends, so one that
+/// never ends without saying so is only stopped by the
+/// [cancel flag][crate::Renderer::with_cancel].
+///
+/// Since the iterator is consumed while rendering, `loop.length` is only known
+/// if the iterator has an exact [size hint][Iterator::size_hint], otherwise it
+/// is `None`.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::Lazy;
+///
+/// let mut engine = upon::Engine::new();
+/// engine.add_filter("count_from", |start: i64| Lazy(start..));
+///
+/// let result = engine
+///     .compile("{% for i in 1 | count_from %}{{ i }}{% if i == 3 %}{% break %}{% endif %}{% endfor %}")?
+///     .render(&engine, upon::value! {})
+///     .to_string()?;
+/// assert_eq!(result, "123");
+/// # Ok::<(), upon::Error>(())
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+#[derive(Debug, Clone)]
+pub struct Lazy<I>(pub I);
+
+/// An error returned from a filter.
+///
+/// This trait is implemented for [`String`], [`&str`][str], and [`Error`].
 ///
 /// *See the [module][crate::filters] documentation for more information.*
+#[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
 pub trait FilterError {
     #[doc(hidden)]
-    fn to_error(self) -> Error;
+    fn to_error(self, spans: &Spans<'_>) -> crate::Error;
+}
+
+/// Documentation for a filter.
+///
+/// This can be attached to a filter using
+/// [`Engine::add_filter_with_meta`][crate::Engine::add_filter_with_meta] and
+/// later retrieved using [`Engine::filter_meta`][crate::Engine::filter_meta].
+/// It does not affect rendering in any way, it is useful for building tools
+/// like template editors that list the available filters.
+#[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterMeta {
+    /// A description of what the filter does.
+    pub description: String,
+    /// The names of the filter arguments, not including the value.
+    pub arg_names: Vec<String>,
+}
+
+/// A scratch space that is shared by filters during a single render.
+///
+/// This is passed to filters added using
+/// [`Engine::add_filter_mut`][crate::Engine::add_filter_mut]. It starts out
+/// empty for every render and is shared with any included templates, so it
+/// can be used to keep state across filter calls without it leaking into
+/// other renders.
+#[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+#[derive(Debug, Default)]
+pub struct RenderContext {
+    scratch: BTreeMap<String, Value>,
+    /// The template source of each positional argument of the current call.
+    arg_sources: Vec<String>,
+}
+
+impl RenderContext {
+    /// Returns a reference to the value stored under the given key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.scratch.get(key)
+    }
+
+    /// Returns a mutable reference to the value stored under the given key.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.scratch.get_mut(key)
+    }
+
+    /// Stores a value under the given key, returning the previous value.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<Value>
+    where
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        self.scratch.insert(key.into(), value.into())
+    }
+
+    /// Removes the value stored under the given key and returns it.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.scratch.remove(key)
+    }
+
+    /// Returns the template source of the positional argument with the given
+    /// zero-based index, exactly as it was written in the template.
+    ///
+    /// This refers to the arguments of the filter call that is currently
+    /// receiving this context. Returns an error pointing at the filter name if
+    /// there is no such argument.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::filters::{Error, RenderContext};
+    /// use upon::Value;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter_mut(
+    ///     "show",
+    ///     |ctx: &mut RenderContext, _: &Value, args: &[Value]| -> Result<String, Error> {
+    ///         let src = ctx.arg_source(0)?;
+    ///         Ok(format!("{src} = {:?}", args[0]))
+    ///     },
+    /// );
+    ///
+    /// let result = engine
+    ///     .compile(r#"{{ x | show: "a\tb" }}"#)?
+    ///     .render(&engine, upon::value! { x: None })
+    ///     .to_string()?;
+    /// assert_eq!(result, r#""a\tb" = String("a\tb")"#);
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn arg_source(&self, index: usize) -> std::result::Result<&str, Error> {
+        self.arg_sources
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "filter has no positional argument at index {index}"
+                ))
+            })
+    }
+}
+
+/// Read-only access to the variables that are in scope where a filter is
+/// called.
+///
+/// This is passed to filters added using
+/// [`Engine::add_filter_with_context`][crate::Engine::add_filter_with_context].
+#[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    stack: &'a Stack<'a>,
+}
+
+impl<'a> Context<'a> {
+    /// Returns the value of the variable with the given path, for example
+    /// `locale` or `users.0.name`.
+    ///
+    /// The path is resolved in the same way as a variable at the place where
+    /// the filter is called, so variables bound by loops, `with` blocks and
+    /// `set` statements shadow the render context. Unlike a variable in
+    /// the template, this never fails or applies the undefined variable
+    /// behavior, it returns `None` if any part of the path is not found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::filters::Context;
+    /// use upon::Value;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter_with_context("greet", |ctx: Context<'_>, name: &Value, _: &[Value]| {
+    ///     match (ctx.get("site.greeting").as_deref(), name) {
+    ///         (Some(Value::String(greeting)), Value::String(name)) => format!("{greeting} {name}!"),
+    ///         (_, Value::String(name)) => format!("Hello {name}!"),
+    ///         _ => String::new(),
+    ///     }
+    /// });
+    ///
+    /// let template = engine.compile("{{ name | greet }}")?;
+    /// let result = template
+    ///     .render(&engine, upon::value! { name: "John", site: { greeting: "Howdy" } })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Howdy John!");
+    /// let result = template
+    ///     .render(&engine, upon::value! { name: "John" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn get(&self, path: &str) -> Option<Cow<'a, Value>> {
+        let var = parse_path(path)?;
+        match self.stack.lookup_var_maybe(path, &var).ok()?? {
+            ValueCow::Borrowed(value) => Some(Cow::Borrowed(value)),
+            ValueCow::Owned(value) => Some(Cow::Owned(value)),
+        }
+    }
+}
+
+impl std::fmt::Debug for Context<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context").finish_non_exhaustive()
+    }
+}
+
+/// Parses a path like `users.0.name` into a variable that can be looked up on
+/// the stack, using the path itself as the source. Every member after the
+/// first is optional so that a missing member is not an error.
+fn parse_path(path: &str) -> Option<ast::Var> {
+    let mut i = 0;
+    let mut members = Vec::new();
+    for part in path.split('.') {
+        let span = Span::from(i..i + part.len());
+        i += part.len() + 1;
+        let (negative, digits) = match part.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, part),
+        };
+        let access = match digits.parse() {
+            Ok(value) if !digits.starts_with('+') => ast::Access::Index(ast::Index {
+                value,
+                negative,
+                span,
+            }),
+            _ if negative || part.is_empty() => return None,
+            _ => ast::Access::Key(ast::Ident { span }),
+        };
+        let op = match members.is_empty() {
+            true => ast::AccessOp::Direct,
+            false => ast::AccessOp::Optional,
+        };
+        members.push(ast::Member { op, access, span });
+    }
+    Some(ast::Var { path: members })
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -362,10 +758,12 @@ where
 }
 
 fn check_args(state: &FilterState<'_>, exp: usize) -> Result<()> {
-    if state.args.len() == exp {
+    if let Some(arg) = state.named_args.first() {
+        Err(err_named_args(state.source, arg.name.span))
+    } else if state.args.len() == exp {
         Ok(())
     } else {
-        Err(Error::render(
+        Err(crate::Error::render(
             format!("filter expected {exp} arguments"),
             state.source,
             state.filter.span,
@@ -397,7 +795,18 @@ where
     }
 }
 
-fn err_expected_arg(err: args::Error, source: &str, span: Span) -> Error {
+fn eval_arg(source: &str, stack: &Stack<'_>, arg: &BaseExpr) -> Result<Value> {
+    match arg {
+        BaseExpr::Var(var) => Ok(stack.lookup_var(source, var)?.take()),
+        BaseExpr::Literal(lit) => Ok(lit.value.clone()),
+    }
+}
+
+fn err_named_args(source: &str, span: Span) -> crate::Error {
+    crate::Error::render("filter does not accept named arguments", source, span)
+}
+
+fn err_expected_arg(err: args::Error, source: &str, span: Span) -> crate::Error {
     let msg = match err {
         args::Error::Type(exp, got) => {
             format!("filter expected {exp} argument, found {got}")
@@ -409,10 +818,10 @@ fn err_expected_arg(err: args::Error, source: &str, span: Span) -> Error {
             format!("filter expected {want} argument, but `{value}` is out of range",)
         }
     };
-    Error::render(msg, source, span)
+    crate::Error::render(msg, source, span)
 }
 
-fn err_expected_val(err: args::Error, source: &str, span: Span) -> Error {
+fn err_expected_val(err: args::Error, source: &str, span: Span) -> crate::Error {
     let msg = match err {
         args::Error::Type(exp, got) => {
             format!("filter expected {exp} value, found {got}")
@@ -424,7 +833,7 @@ fn err_expected_val(err: args::Error, source: &str, span: Span) -> Error {
             format!("filter expected {want} value, but `{value}` is out of range",)
         }
     };
-    Error::render(msg, source, span)
+    crate::Error::render(msg, source, span)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -435,8 +844,8 @@ impl<T> FilterReturn for T
 where
     T: Into<Value>,
 {
-    fn to_value(self) -> Result<Value> {
-        Ok(self.into())
+    fn to_output(self, _: &Spans<'_>) -> Result<FilterOutput> {
+        Ok(FilterOutput::Value(self.into()))
     }
 }
 
@@ -445,8 +854,30 @@ where
     T: Into<Value>,
     E: FilterError,
 {
-    fn to_value(self) -> Result<Value> {
-        self.map(Into::into).map_err(FilterError::to_error)
+    fn to_output(self, spans: &Spans<'_>) -> Result<FilterOutput> {
+        self.map(|v| FilterOutput::Value(v.into()))
+            .map_err(|err| err.to_error(spans))
+    }
+}
+
+impl<I> FilterReturn for Lazy<I>
+where
+    I: Iterator + 'static,
+    I::Item: Into<Value>,
+{
+    fn to_output(self, _: &Spans<'_>) -> Result<FilterOutput> {
+        Ok(FilterOutput::Iter(ValueIter::new(self.0)))
+    }
+}
+
+impl<I, E> FilterReturn for std::result::Result<Lazy<I>, E>
+where
+    I: Iterator + 'static,
+    I::Item: Into<Value>,
+    E: FilterError,
+{
+    fn to_output(self, spans: &Spans<'_>) -> Result<FilterOutput> {
+        self.map_err(|err| err.to_error(spans))?.to_output(spans)
     }
 }
 
@@ -455,13 +886,19 @@ where
 ////////////////////////////////////////////////////////////////////////////////
 
 impl FilterError for String {
-    fn to_error(self) -> Error {
-        Error::filter(self)
+    fn to_error(self, _: &Spans<'_>) -> crate::Error {
+        crate::Error::filter(self)
     }
 }
 
 impl FilterError for &str {
-    fn to_error(self) -> Error {
-        Error::filter(self)
+    fn to_error(self, _: &Spans<'_>) -> crate::Error {
+        crate::Error::filter(self)
+    }
+}
+
+impl FilterError for Error {
+    fn to_error(self, spans: &Spans<'_>) -> crate::Error {
+        self.into_error(spans)
     }
 }
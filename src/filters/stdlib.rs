@@ -0,0 +1,738 @@
+//! A standard library of common filters.
+//!
+//! None of these filters are registered by default. All of them can be added
+//! to the engine at once under their function names using
+//! [`Engine::add_stdlib_filters`][crate::Engine::add_stdlib_filters], or
+//! individually using [`Engine::add_filter`][crate::Engine::add_filter].
+//!
+//! ```
+//! let mut engine = upon::Engine::new();
+//! engine.add_stdlib_filters();
+//!
+//! // or
+//!
+//! let mut engine = upon::Engine::new();
+//! engine.add_filter("slugify", upon::filters::stdlib::slugify);
+//! engine.add_filter("timestamp", upon::filters::stdlib::timestamp);
+//! ```
+
+use crate::filters::Error;
+use crate::fmt::{self, Formatter};
+use crate::Value;
+
+/// Converts a string to uppercase.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::upper;
+///
+/// assert_eq!(upper("Hello"), "HELLO");
+/// ```
+pub fn upper(s: &str) -> String {
+    s.to_uppercase()
+}
+
+/// Converts a string to lowercase.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::lower;
+///
+/// assert_eq!(lower("Hello"), "hello");
+/// ```
+pub fn lower(s: &str) -> String {
+    s.to_lowercase()
+}
+
+/// Removes leading and trailing whitespace from a string.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::trim;
+///
+/// assert_eq!(trim("  Hello\n"), "Hello");
+/// ```
+pub fn trim(s: &str) -> String {
+    s.trim().to_owned()
+}
+
+/// Indents each line of a string after the first by the given number of
+/// spaces.
+///
+/// The first line is not indented because the string is usually inserted
+/// after some existing indentation in the template, use [`indent_first`] to
+/// indent it as well. Empty lines are not indented so that no trailing
+/// whitespace is added. Both `\n` and `\r\n` line endings are supported and
+/// are preserved. The width must not be negative or larger than 1024.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::indent;
+///
+/// assert_eq!(indent("fn main() {\n}\n", 4).unwrap(), "fn main() {\n    }\n");
+/// assert_eq!(indent("a\r\n\r\nb", 2).unwrap(), "a\r\n\r\n  b");
+/// assert!(indent("a", -1).is_err());
+/// assert!(indent("a", 1025).is_err());
+/// ```
+pub fn indent(s: &str, width: i64) -> Result<String, Error> {
+    indent_lines(s, width, false)
+}
+
+/// Indents each line of a string by the given number of spaces.
+///
+/// This is the same as [`indent`] except that the first line is also
+/// indented.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::indent_first;
+///
+/// assert_eq!(indent_first("a\n\nb", 2).unwrap(), "  a\n\n  b");
+/// ```
+pub fn indent_first(s: &str, width: i64) -> Result<String, Error> {
+    indent_lines(s, width, true)
+}
+
+/// Removes any whitespace that is common to the start of every line of a
+/// string.
+///
+/// Lines that only contain whitespace are ignored when finding the common
+/// whitespace and are made empty. Spaces and tabs are not treated as equal, so
+/// lines indented with a tab have no common whitespace with lines indented
+/// with spaces. Both `\n` and `\r\n` line endings are supported and are
+/// preserved.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::dedent;
+///
+/// assert_eq!(dedent("    if x {\n        y\n    }\n"), "if x {\n    y\n}\n");
+/// assert_eq!(dedent("  a\r\n   \r\n    b"), "a\r\n\r\n  b");
+/// ```
+pub fn dedent(s: &str) -> String {
+    let mut margin: Option<&str> = None;
+    for line in s.lines() {
+        let content = line.trim_start_matches([' ', '\t']);
+        if content.trim_end_matches('\r').is_empty() {
+            continue;
+        }
+        let spaces = &line[..line.len() - content.len()];
+        margin = Some(match margin {
+            Some(margin) => {
+                let n = margin
+                    .bytes()
+                    .zip(spaces.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &margin[..n]
+            }
+            None => spaces,
+        });
+    }
+    let margin = margin.map_or(0, str::len);
+
+    let mut out = String::with_capacity(s.len());
+    for line in s.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        if content.trim_start_matches([' ', '\t']).is_empty() {
+            out.push_str(&line[content.len()..]);
+        } else {
+            out.push_str(&line[margin..]);
+        }
+    }
+    out
+}
+
+/// Returns the length of a string, list, or map.
+///
+/// The length of a string is the number of characters it contains, not the
+/// number of bytes.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::len;
+/// use upon::Value;
+///
+/// assert_eq!(len(&Value::from("Crème")).unwrap(), 5);
+/// assert_eq!(len(&Value::from([1, 2, 3])).unwrap(), 3);
+/// assert!(len(&Value::from(42)).is_err());
+/// ```
+pub fn len(value: &Value) -> Result<i64, String> {
+    let n = match value {
+        Value::String(s) => s.chars().count(),
+        Value::List(list) => list.len(),
+        Value::Map(map) => map.len(),
+        value => {
+            return Err(format!(
+                "expected string, list, or map, found {}",
+                value.human()
+            ))
+        }
+    };
+    Ok(n as i64)
+}
+
+/// Returns the given default if the value is `None`, otherwise returns the
+/// value unchanged.
+///
+/// Only `None` is replaced, other falsy values like `false`, `0`, or an empty
+/// string are returned as is.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::default;
+/// use upon::Value;
+///
+/// assert_eq!(default(Value::None, Value::from("x")), Value::from("x"));
+/// assert_eq!(default(Value::from(""), Value::from("x")), Value::from(""));
+/// ```
+pub fn default(value: Value, default: Value) -> Value {
+    match value {
+        Value::None => default,
+        value => value,
+    }
+}
+
+/// Joins the items of a list into a string using the given separator.
+///
+/// Strings are joined as is and any other items are formatted using the
+/// [default formatter][crate::fmt::default]. Errors if the list contains a
+/// list or a map.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::join;
+/// use upon::Value;
+///
+/// assert_eq!(join(&[Value::from("a"), Value::from(1), Value::from(true)], ", ").unwrap(), "a, 1, true");
+/// ```
+pub fn join(list: &[Value], sep: &str) -> Result<String, String> {
+    let mut s = String::new();
+    for (i, item) in list.iter().enumerate() {
+        if i > 0 {
+            s.push_str(sep);
+        }
+        fmt::default(&mut Formatter::with_string(&mut s), item).map_err(|err| err.to_string())?;
+    }
+    Ok(s)
+}
+
+/// Reverses a list or the characters in a string.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::reverse;
+/// use upon::Value;
+///
+/// assert_eq!(reverse(Value::from([1, 2, 3])).unwrap(), Value::from([3, 2, 1]));
+/// assert_eq!(reverse(Value::from("abc")).unwrap(), Value::from("cba"));
+/// ```
+pub fn reverse(value: Value) -> Result<Value, String> {
+    match value {
+        Value::String(s) => Ok(Value::String(s.chars().rev().collect())),
+        Value::List(mut list) => {
+            list.reverse();
+            Ok(Value::List(list))
+        }
+        value => Err(format!("expected string or list, found {}", value.human())),
+    }
+}
+
+/// Returns the first item of a list, or `None` if the list is empty.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::first;
+/// use upon::Value;
+///
+/// assert_eq!(first(&[Value::from(1), Value::from(2)]), Some(Value::from(1)));
+/// assert_eq!(first(&[]), None);
+/// ```
+pub fn first(list: &[Value]) -> Option<Value> {
+    list.first().cloned()
+}
+
+/// Returns the last item of a list, or `None` if the list is empty.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::last;
+/// use upon::Value;
+///
+/// assert_eq!(last(&[Value::from(1), Value::from(2)]), Some(Value::from(2)));
+/// assert_eq!(last(&[]), None);
+/// ```
+pub fn last(list: &[Value]) -> Option<Value> {
+    list.last().cloned()
+}
+
+/// Returns the item of a list at the given index, or `None` if the index is
+/// out of range.
+///
+/// A negative index counts from the end of the list, so `-1` is the last item.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::nth;
+/// use upon::Value;
+///
+/// let list = [Value::from("a"), Value::from("b"), Value::from("c")];
+/// assert_eq!(nth(&list, 1), Some(Value::from("b")));
+/// assert_eq!(nth(&list, -1), Some(Value::from("c")));
+/// assert_eq!(nth(&list, 3), None);
+/// ```
+pub fn nth(list: &[Value], index: i64) -> Option<Value> {
+    let len = list.len() as i64;
+    let index = if index < 0 { index + len } else { index };
+    if (0..len).contains(&index) {
+        Some(list[index as usize].clone())
+    } else {
+        None
+    }
+}
+
+/// Returns the items of a list from the start index up to but not including
+/// the end index.
+///
+/// Like slicing in Python, a negative index counts from the end of the list
+/// and out of range indexes are clamped to the bounds of the list. If the
+/// start is not before the end then an empty list is returned.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::slice;
+/// use upon::Value;
+///
+/// let list = [Value::from(1), Value::from(2), Value::from(3), Value::from(4)];
+/// assert_eq!(slice(&list, 1, 3), [Value::from(2), Value::from(3)]);
+/// assert_eq!(slice(&list, -2, 100), [Value::from(3), Value::from(4)]);
+/// assert!(slice(&list, 3, 1).is_empty());
+/// ```
+pub fn slice(list: &[Value], start: i64, end: i64) -> Vec<Value> {
+    let len = list.len() as i64;
+    let clamp = |i: i64| {
+        let i = if i < 0 { i + len } else { i };
+        i.clamp(0, len) as usize
+    };
+    let (start, end) = (clamp(start), clamp(end));
+    if start < end {
+        list[start..end].to_vec()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Replaces all occurrences of a pattern in a string with another string.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::replace;
+///
+/// assert_eq!(replace("a-b-c", "-", " "), "a b c");
+/// ```
+pub fn replace(s: &str, from: &str, to: &str) -> String {
+    s.replace(from, to)
+}
+
+/// Returns whether a string starts with the given prefix.
+///
+/// This returns a `bool` so it can be used as a condition.
+///
+/// ```text
+/// {% if path | starts_with: "/api" %} ... {% endif %}
+/// ```
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::starts_with;
+///
+/// assert!(starts_with("/api/users", "/api"));
+/// assert!(!starts_with("/about", "/api"));
+/// ```
+pub fn starts_with(s: &str, prefix: &str) -> bool {
+    s.starts_with(prefix)
+}
+
+/// Returns whether a string ends with the given suffix.
+///
+/// This returns a `bool` so it can be used as a condition.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::ends_with;
+///
+/// assert!(ends_with("README.md", ".md"));
+/// assert!(!ends_with("README.md", ".txt"));
+/// ```
+pub fn ends_with(s: &str, suffix: &str) -> bool {
+    s.ends_with(suffix)
+}
+
+/// Returns whether a string contains the given substring.
+///
+/// This returns a `bool` so it can be used as a condition. The substring is
+/// matched on whole characters, a substring that is only part of the UTF-8
+/// encoding of a character never matches. An empty substring is always
+/// contained.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::contains;
+///
+/// assert!(contains("Crème Brûlée", "Brû"));
+/// assert!(!contains("Crème Brûlée", "Bru"));
+/// ```
+pub fn contains(s: &str, substring: &str) -> bool {
+    s.contains(substring)
+}
+
+/// Converts a string into a URL-safe "slug".
+///
+/// The string is lowercased and each run of non-alphanumeric characters is
+/// replaced by a single hyphen. Leading and trailing hyphens are never
+/// emitted, so a string with no alphanumeric characters results in an empty
+/// string. Non-ASCII alphanumeric characters are lowercased and kept as is, no
+/// transliteration is performed.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::slugify;
+///
+/// assert_eq!(slugify("Hello, World!"), "hello-world");
+/// assert_eq!(slugify("  --Rust_is   fun--  "), "rust-is-fun");
+/// assert_eq!(slugify("Crème Brûlée"), "crème-brûlée");
+/// assert_eq!(slugify("l’été"), "l-été");
+/// ```
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    let mut hyphen = false;
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            if hyphen && !slug.is_empty() {
+                slug.push('-');
+            }
+            hyphen = false;
+            slug.extend(c.to_lowercase());
+        } else {
+            hyphen = true;
+        }
+    }
+    slug
+}
+
+/// Formats a Unix timestamp in seconds as an ISO 8601 date and time in UTC.
+///
+/// The output has the form `YYYY-MM-DDTHH:MM:SSZ`. Use
+/// [`timestamp_fmt`] to format only the date.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::timestamp;
+///
+/// assert_eq!(timestamp(0), "1970-01-01T00:00:00Z");
+/// assert_eq!(timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+/// ```
+pub fn timestamp(secs: i64) -> String {
+    let (date, time) = civil_from_unix(secs);
+    format!("{date}T{time}Z")
+}
+
+/// Formats a Unix timestamp in seconds as an ISO 8601 string in UTC using the
+/// given format.
+///
+/// The format must be one of the following:
+/// - `"date"`: `YYYY-MM-DD`
+/// - `"datetime"`: `YYYY-MM-DDTHH:MM:SSZ`, the same as [`timestamp`]
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::timestamp_fmt;
+///
+/// assert_eq!(timestamp_fmt(1_700_000_000, "date").unwrap(), "2023-11-14");
+/// assert!(timestamp_fmt(1_700_000_000, "time").is_err());
+/// ```
+pub fn timestamp_fmt(secs: i64, format: &str) -> Result<String, String> {
+    let (date, time) = civil_from_unix(secs);
+    match format {
+        "date" => Ok(date),
+        "datetime" => Ok(format!("{date}T{time}Z")),
+        _ => Err(format!(
+            "unknown timestamp format `{format}`, expected `date` or `datetime`"
+        )),
+    }
+}
+
+/// Serializes a value to a compact JSON string.
+///
+/// The characters `<`, `>` and `&` as well as U+2028 and U+2029 are escaped
+/// using `\uXXXX` sequences, so the output can be embedded in an HTML
+/// `<script>` tag or a JavaScript string. The output is otherwise not escaped,
+/// if it is rendered with an HTML escaping formatter the quotes will be escaped
+/// as well. Use [`json_fmt`] to pretty-print the JSON.
+///
+/// Floats that are NaN or infinite are serialized as `null`.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::json;
+/// use upon::value;
+///
+/// assert_eq!(json(&value! { a: [1, true, None] }).unwrap(), r#"{"a":[1,true,null]}"#);
+/// assert_eq!(json(&value! { a: "</script>" }).unwrap(), r#"{"a":"\u003c/script\u003e"}"#);
+/// ```
+#[cfg(all(feature = "json", feature = "serde"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "json", feature = "serde"))))]
+pub fn json(value: &Value) -> Result<String, String> {
+    to_json(value, false)
+}
+
+/// Serializes a value to a JSON string using the given format.
+///
+/// The format must be one of the following:
+/// - `"compact"`: no whitespace, the same as [`json`]
+/// - `"pretty"`: indented with two spaces
+///
+/// Both formats escape the output in the same way as [`json`].
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::json_fmt;
+/// use upon::value;
+///
+/// assert_eq!(json_fmt(&value! { a: [1] }, "pretty").unwrap(), "{\n  \"a\": [\n    1\n  ]\n}");
+/// assert!(json_fmt(&value! { a: [1] }, "yaml").is_err());
+/// ```
+#[cfg(all(feature = "json", feature = "serde"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "json", feature = "serde"))))]
+pub fn json_fmt(value: &Value, format: &str) -> Result<String, String> {
+    match format {
+        "compact" => to_json(value, false),
+        "pretty" => to_json(value, true),
+        _ => Err(format!(
+            "unknown json format `{format}`, expected `compact` or `pretty`"
+        )),
+    }
+}
+
+#[cfg(all(feature = "json", feature = "serde"))]
+fn to_json(value: &Value, pretty: bool) -> Result<String, String> {
+    let s = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+    .map_err(|err| format!("failed to serialize value to JSON: {err}"))?;
+
+    // These characters can only appear inside JSON strings, so replacing them
+    // with escape sequences does not change the meaning of the JSON.
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("\\u003c"),
+            '>' => escaped.push_str("\\u003e"),
+            '&' => escaped.push_str("\\u0026"),
+            '\u{2028}' => escaped.push_str("\\u2028"),
+            '\u{2029}' => escaped.push_str("\\u2029"),
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+/// Adds two numbers.
+///
+/// If both numbers are integers the result is an integer, otherwise both are
+/// converted to floats. Integer overflow is an error.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::add;
+/// use upon::Value;
+///
+/// assert_eq!(add(Value::from(2), Value::from(3)).unwrap(), Value::from(5));
+/// assert_eq!(add(Value::from(2), Value::from(0.5)).unwrap(), Value::from(2.5));
+/// assert!(add(Value::from(i64::MAX), Value::from(1)).is_err());
+/// ```
+pub fn add(value: Value, other: Value) -> Result<Value, Error> {
+    match operands(&value, &other)? {
+        Operands::Integer(a, b) => a
+            .checked_add(b)
+            .map(Value::Integer)
+            .ok_or_else(|| Error::new("integer overflow while adding")),
+        Operands::Float(a, b) => Ok(Value::Float(a + b)),
+    }
+}
+
+/// Subtracts a number from another.
+///
+/// If both numbers are integers the result is an integer, otherwise both are
+/// converted to floats. Integer overflow is an error.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::sub;
+/// use upon::Value;
+///
+/// assert_eq!(sub(Value::from(5), Value::from(3)).unwrap(), Value::from(2));
+/// assert_eq!(sub(Value::from(5), Value::from(0.5)).unwrap(), Value::from(4.5));
+/// assert!(sub(Value::from(i64::MIN), Value::from(1)).is_err());
+/// ```
+pub fn sub(value: Value, other: Value) -> Result<Value, Error> {
+    match operands(&value, &other)? {
+        Operands::Integer(a, b) => a
+            .checked_sub(b)
+            .map(Value::Integer)
+            .ok_or_else(|| Error::new("integer overflow while subtracting")),
+        Operands::Float(a, b) => Ok(Value::Float(a - b)),
+    }
+}
+
+/// Multiplies two numbers.
+///
+/// If both numbers are integers the result is an integer, otherwise both are
+/// converted to floats. Integer overflow is an error.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::mul;
+/// use upon::Value;
+///
+/// assert_eq!(mul(Value::from(2), Value::from(3)).unwrap(), Value::from(6));
+/// assert_eq!(mul(Value::from(2), Value::from(1.5)).unwrap(), Value::from(3.0));
+/// assert!(mul(Value::from(i64::MAX), Value::from(2)).is_err());
+/// ```
+pub fn mul(value: Value, other: Value) -> Result<Value, Error> {
+    match operands(&value, &other)? {
+        Operands::Integer(a, b) => a
+            .checked_mul(b)
+            .map(Value::Integer)
+            .ok_or_else(|| Error::new("integer overflow while multiplying")),
+        Operands::Float(a, b) => Ok(Value::Float(a * b)),
+    }
+}
+
+/// Divides a number by another.
+///
+/// If both numbers are integers the result is an integer rounded towards zero,
+/// otherwise both are converted to floats. Dividing by zero is an error, even
+/// for floats, and so is integer overflow.
+///
+/// # Examples
+///
+/// ```
+/// use upon::filters::stdlib::div;
+/// use upon::Value;
+///
+/// assert_eq!(div(Value::from(7), Value::from(2)).unwrap(), Value::from(3));
+/// assert_eq!(div(Value::from(7), Value::from(2.0)).unwrap(), Value::from(3.5));
+/// assert!(div(Value::from(7), Value::from(0)).is_err());
+/// ```
+pub fn div(value: Value, other: Value) -> Result<Value, Error> {
+    match operands(&value, &other)? {
+        Operands::Integer(_, 0) => Err(Error::at_arg(0, "division by zero")),
+        Operands::Integer(a, b) => a
+            .checked_div(b)
+            .map(Value::Integer)
+            .ok_or_else(|| Error::new("integer overflow while dividing")),
+        Operands::Float(a, b) => {
+            if b == 0.0 {
+                return Err(Error::at_arg(0, "division by zero"));
+            }
+            Ok(Value::Float(a / b))
+        }
+    }
+}
+
+/// The operands of an arithmetic filter.
+enum Operands {
+    Integer(i64, i64),
+    Float(f64, f64),
+}
+
+/// Checks that both operands are numbers, converting both to floats if either
+/// of them is a float.
+fn operands(value: &Value, other: &Value) -> Result<Operands, Error> {
+    let expected = |v: &Value| format!("expected integer or float, found {}", v.human());
+    match (value, other) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Operands::Integer(*a, *b)),
+        _ => match (value.as_f64(), other.as_f64()) {
+            (Some(a), Some(b)) => Ok(Operands::Float(a, b)),
+            (None, _) => Err(Error::at_value(expected(value))),
+            (_, None) => Err(Error::at_arg(0, expected(other))),
+        },
+    }
+}
+
+/// The largest width accepted by [`indent`] and [`indent_first`].
+const MAX_INDENT: usize = 1024;
+
+/// Indents each line of a string, optionally skipping the first line.
+fn indent_lines(s: &str, width: i64, first: bool) -> Result<String, Error> {
+    let width = usize::try_from(width)
+        .map_err(|_| Error::at_arg(0, format!("expected non-negative width, found {width}")))?;
+    if width > MAX_INDENT {
+        return Err(Error::at_arg(
+            0,
+            format!("expected width of at most {MAX_INDENT}, found {width}"),
+        ));
+    }
+    let prefix = " ".repeat(width);
+    let mut out = String::with_capacity(s.len());
+    for (i, line) in s.split_inclusive('\n').enumerate() {
+        let empty = line.trim_end_matches(['\r', '\n']).is_empty();
+        if (i > 0 || first) && !empty {
+            out.push_str(&prefix);
+        }
+        out.push_str(line);
+    }
+    Ok(out)
+}
+
+/// Splits a Unix timestamp into a formatted date and time.
+fn civil_from_unix(secs: i64) -> (String, String) {
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+    let (hh, mm, ss) = (rem / 3600, rem % 3600 / 60, rem % 60);
+
+    // Converts days since the Unix epoch to a date in the proleptic Gregorian
+    // calendar, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+
+    let date = format!("{y:04}-{m:02}-{d:02}");
+    let time = format!("{hh:02}:{mm:02}:{ss:02}");
+    (date, time)
+}
@@ -2,7 +2,9 @@
 //!
 //! Value formatters allow you to change the way a [`Value`] is formatted in the
 //! rendered template. They can be configured on the engine using
-//! [`set_default_formatter`][crate::Engine::set_default_formatter] or
+//! [`set_default_formatter`][crate::Engine::set_default_formatter],
+//! [`add_type_formatter`][crate::Engine::add_type_formatter],
+//! [`add_chained_formatter`][crate::Engine::add_chained_formatter] or
 //! [`add_formatter`][crate::Engine::add_formatter].
 //!
 //! This module defines a [`Formatter`] type that is similar to
@@ -72,6 +74,31 @@
 //! let mut engine = Engine::new();
 //! engine.set_default_formatter(&error_on_none);
 //! ```
+//!
+//! ### Escape HTML
+//!
+//! No escaping is performed by default. When rendering HTML, the
+//! [`escape_html`] formatter can be set as the default formatter so that all
+//! expressions are escaped unless they opt out. Trusted values can opt out by
+//! using any explicit formatter, since explicit formatters are not affected by
+//! the default formatter. Registering [`default`] under a name like `safe`
+//! makes this read naturally. Whole sections of a template can opt out using
+//! an `{% autoescape off %}` block.
+//!
+//! ```
+//! use upon::{fmt, Engine};
+//!
+//! let mut engine = Engine::new();
+//! engine.set_default_formatter(&fmt::escape_html);
+//! engine.add_formatter("safe", fmt::default);
+//!
+//! let result = engine
+//!     .compile("{{ comment }} {{ signature | safe }}")?
+//!     .render(&engine, upon::value! { comment: "<script>", signature: "<b>Jane</b>" })
+//!     .to_string()?;
+//! assert_eq!(result, "&lt;script&gt; <b>Jane</b>");
+//! # Ok::<(), upon::Error>(())
+//! ```
 
 use std::fmt;
 use std::fmt::Write;
@@ -82,7 +109,53 @@ use crate::Value;
 /// A formatter function or closure.
 pub(crate) type FormatFn = dyn Fn(&mut Formatter<'_>, &Value) -> Result + Sync + Send + 'static;
 
+/// A chained formatter function or closure.
+pub(crate) type ChainFormatFn =
+    dyn Fn(&mut Formatter<'_>, &Value) -> ChainResult + Sync + Send + 'static;
+
 /// A [`std::fmt::Write`] façade.
+///
+/// Formatter functions write to the rendered output using the
+/// [`std::fmt::Write`] methods, such as [`write_str`][fmt::Write::write_str],
+/// [`write_char`][fmt::Write::write_char], and the [`write!`] macro, or using
+/// [`write_escaped`][Formatter::write_escaped] to replace certain characters.
+/// To format a nested value, pass the formatter on to another formatter
+/// function, for example [`default`] or [`escape_html`].
+///
+/// # Examples
+///
+/// A formatter that HTML escapes each string in a list.
+///
+/// ```
+/// use std::fmt::Write;
+/// use upon::{fmt, Value};
+///
+/// fn html_list(f: &mut fmt::Formatter<'_>, value: &Value) -> fmt::Result {
+///     match value {
+///         Value::List(list) => {
+///             f.write_str("<ul>")?;
+///             for item in list {
+///                 f.write_str("<li>")?;
+///                 fmt::escape_html(f, item)?;
+///                 f.write_str("</li>")?;
+///             }
+///             f.write_str("</ul>")?;
+///             Ok(())
+///         }
+///         v => fmt::default(f, v),
+///     }
+/// }
+///
+/// let mut engine = upon::Engine::new();
+/// engine.add_formatter("html_list", html_list);
+///
+/// let result = engine
+///     .compile("{{ items | html_list }}")?
+///     .render(&engine, upon::value! { items: ["a < b", 42] })
+///     .to_string()?;
+/// assert_eq!(result, "<ul><li>a &lt; b</li><li>42</li></ul>");
+/// # Ok::<(), upon::Error>(())
+/// ```
 pub struct Formatter<'a> {
     buf: &'a mut (dyn fmt::Write + 'a),
 }
@@ -94,21 +167,94 @@ pub type Result = std::result::Result<(), Error>;
 #[derive(Debug, Clone)]
 pub struct Error(Option<String>);
 
+/// The result type returned from a chained formatter function.
+///
+/// See [`Engine::add_chained_formatter`][crate::Engine::add_chained_formatter].
+pub type ChainResult = std::result::Result<Handled, Error>;
+
+/// Whether a chained formatter function formatted the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handled {
+    /// The value was formatted.
+    Yes,
+
+    /// The value was not formatted and the next formatter should be tried.
+    /// Nothing should be written to the formatter in this case.
+    No,
+}
+
 pub(crate) struct Writer<W> {
     writer: W,
     err: Option<io::Error>,
 }
 
+/// A [`fmt::Write`] that records whether the underlying writer failed.
+pub(crate) struct FmtWriter<W> {
+    writer: W,
+    failed: bool,
+}
+
+/// A [`fmt::Write`] that fails once writing would exceed a number of bytes.
+pub(crate) struct Limited<'a> {
+    buf: &'a mut (dyn fmt::Write + 'a),
+    remaining: usize,
+    exceeded: bool,
+}
+
 impl<'a> Formatter<'a> {
     pub(crate) fn with_string(buf: &'a mut String) -> Self {
         Self { buf }
     }
 
-    pub(crate) fn with_writer<W>(buf: &'a mut Writer<W>) -> Self
+    pub(crate) fn with_limited<'b: 'a>(buf: &'a mut Limited<'b>) -> Self {
+        Self { buf }
+    }
+
+    /// Writes a string, replacing each character for which `escape` returns
+    /// a replacement.
+    ///
+    /// Characters for which `escape` returns `None` are written unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::{fmt, Value};
+    ///
+    /// fn escape_quotes(f: &mut fmt::Formatter<'_>, value: &Value) -> fmt::Result {
+    ///     match value {
+    ///         Value::String(s) => f.write_escaped(s, |c| match c {
+    ///             '"' => Some("\\\""),
+    ///             _ => None,
+    ///         })?,
+    ///         v => fmt::default(f, v)?,
+    ///     }
+    ///     Ok(())
+    /// }
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_formatter("escape_quotes", escape_quotes);
+    ///
+    /// let result = engine
+    ///     .compile("{{ quote | escape_quotes }}")?
+    ///     .render(&engine, upon::value! { quote: r#"say "hi""# })
+    ///     .to_string()?;
+    /// assert_eq!(result, r#"say \"hi\""#);
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn write_escaped<E>(&mut self, s: &str, escape: E) -> fmt::Result
     where
-        W: io::Write,
+        E: Fn(char) -> Option<&'static str>,
     {
-        Self { buf }
+        // Unescaped runs of characters are written in a single call.
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            if let Some(replacement) = escape(c) {
+                self.buf.write_str(&s[start..i])?;
+                self.buf.write_str(replacement)?;
+                start = i + c.len_utf8();
+            }
+        }
+        self.buf.write_str(&s[start..])
     }
 }
 
@@ -164,6 +310,42 @@ impl From<fmt::Error> for Error {
     }
 }
 
+impl<'a> Limited<'a> {
+    pub fn new(buf: &'a mut (dyn fmt::Write + 'a), remaining: usize) -> Self {
+        Self {
+            buf,
+            remaining,
+            exceeded: false,
+        }
+    }
+
+    /// Returns the number of bytes that can still be written.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Returns whether a write failed because it would exceed the limit.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+}
+
+impl fmt::Write for Limited<'_> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.remaining.checked_sub(s.len()) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                self.buf.write_str(s)
+            }
+            None => {
+                self.exceeded = true;
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
 impl<W> Writer<W>
 where
     W: io::Write,
@@ -200,6 +382,64 @@ where
     }
 }
 
+impl<W> FmtWriter<W>
+where
+    W: fmt::Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            failed: false,
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+impl<W> fmt::Write for FmtWriter<W>
+where
+    W: fmt::Write,
+{
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.writer.write_str(s).map_err(|e| {
+            self.failed = true;
+            e
+        })
+    }
+
+    #[inline]
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        self.writer.write_char(c).map_err(|e| {
+            self.failed = true;
+            e
+        })
+    }
+}
+
+/// Adapts a formatter function into a chained formatter function that always
+/// handles the value.
+///
+/// This allows any existing formatter, like [`escape_html`], to be used at
+/// the end of a formatter chain.
+///
+/// # Examples
+///
+/// ```
+/// use upon::fmt;
+///
+/// let mut engine = upon::Engine::new();
+/// engine.add_chained_formatter(fmt::always_handled(fmt::escape_html));
+/// ```
+pub fn always_handled<F>(f: F) -> impl Fn(&mut Formatter<'_>, &Value) -> ChainResult
+where
+    F: Fn(&mut Formatter<'_>, &Value) -> Result,
+{
+    move |fmt, value| f(fmt, value).map(|()| Handled::Yes)
+}
+
 /// The default value formatter.
 ///
 /// Values are formatted as follows:
@@ -227,3 +467,52 @@ pub fn default(f: &mut Formatter<'_>, value: &Value) -> Result {
     }
     Ok(())
 }
+
+/// A value formatter that escapes HTML.
+///
+/// Strings are formatted with the following characters replaced by their
+/// HTML entities, which makes them safe to use in element content and in
+/// quoted attribute values.
+///
+/// | Character | Replacement |
+/// | --------- | ----------- |
+/// | `&`       | `&amp;`     |
+/// | `<`       | `&lt;`      |
+/// | `>`       | `&gt;`      |
+/// | `"`       | `&quot;`    |
+/// | `'`       | `&#x27;`    |
+///
+/// All other values are formatted using the [`default`] formatter.
+///
+/// # Examples
+///
+/// ```
+/// use upon::fmt;
+///
+/// let mut engine = upon::Engine::new();
+/// engine.set_default_formatter(&fmt::escape_html);
+///
+/// let result = engine
+///     .compile("<a title=\"{{ title }}\">{{ n }}</a>")?
+///     .render(&engine, upon::value! { title: "Tom & Jerry's <\"show\">", n: 1 })
+///     .to_string()?;
+/// assert_eq!(
+///     result,
+///     "<a title=\"Tom &amp; Jerry&#x27;s &lt;&quot;show&quot;&gt;\">1</a>"
+/// );
+/// # Ok::<(), upon::Error>(())
+/// ```
+pub fn escape_html(f: &mut Formatter<'_>, value: &Value) -> Result {
+    match value {
+        Value::String(s) => f.write_escaped(s, |c| match c {
+            '&' => Some("&amp;"),
+            '<' => Some("&lt;"),
+            '>' => Some("&gt;"),
+            '"' => Some("&quot;"),
+            '\'' => Some("&#x27;"),
+            _ => None,
+        })?,
+        value => default(f, value)?,
+    }
+    Ok(())
+}
@@ -112,11 +112,26 @@
 //!   formatters (see [`Engine::add_formatter`]). Disabling this will improve
 //!   compile times.
 //!
+//! - **`stdlib`** — Enables the [`filters::stdlib`] module which contains a
+//!   standard library of common filters. This implies the **`filters`**
+//!   feature.
+//!
 //! - **`serde`** _(enabled by default)_ — Enables all serde support and pulls
 //!   in the [`serde`] crate as a dependency. If disabled then you can use
 //!   [`render_from(..)`][TemplateRef::render_from] to render templates and
 //!   construct the context using [`Value`]'s `From` impls.
 //!
+//! - **`json`** — Enables converting a [`serde_json::Value`] directly into a
+//!   [`Value`] using its `From` impl, without serializing it again. Pulls in
+//!   the [`serde_json`] crate as a dependency. Together with the **`stdlib`**
+//!   and **`serde`** features this also enables the
+//!   [`json`][filters::stdlib::json] filters.
+//!
+//! - **`precompile`** — Enables converting compiled templates to bytes using
+//!   [`Template::to_bytes`] and loading them again using
+//!   [`Template::from_bytes`] or [`Engine::load_template`]. This allows
+//!   templates to be compiled ahead of time, for example in a build script.
+//!
 //! - **`unicode`** _(enabled by default)_ — Enables unicode support and pulls
 //!   in the [`unicode-ident`][unicode_ident] and
 //!   [`unicode-width`][unicode_width] crates. If disabled then unicode
@@ -231,24 +246,31 @@ mod error;
 #[cfg(feature = "serde")]
 mod macros;
 mod render;
+mod store;
 mod types;
 mod value;
 
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
-pub use crate::error::Error;
-pub use crate::render::Renderer;
+pub use crate::error::{Error, ErrorKind};
+pub use crate::render::{Chunks, Profiler, RenderOptions, Renderer};
+pub use crate::store::TemplateStore;
 pub use crate::types::syntax::{Syntax, SyntaxBuilder};
 #[cfg(feature = "serde")]
 #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
-pub use crate::value::to_value;
-pub use crate::value::Value;
+pub use crate::value::{to_value, Serialized};
+pub use crate::value::{IntoValue, MapBuilder, Value, ValueKind};
 
 use crate::compile::Searcher;
 #[cfg(feature = "filters")]
-use crate::filters::{Filter, FilterArgs, FilterFn, FilterReturn};
-use crate::fmt::FormatFn;
+use crate::filters::{Filter, FilterArgs, FilterFn, FilterMeta, FilterReturn};
+use crate::fmt::{ChainFormatFn, FormatFn};
 use crate::types::program;
 
 /// A type alias for results in this crate.
@@ -257,10 +279,22 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// The compilation and rendering engine.
 pub struct Engine<'engine> {
     searcher: Searcher,
-    default_formatter: &'engine FormatFn,
+    case_insensitive_keywords: bool,
+    trim_blocks: bool,
+    lstrip_blocks: bool,
+    trim_comments: bool,
+    strict: bool,
+    default_formatter: DefaultFormatter<'engine>,
+    type_formatters: BTreeMap<ValueKind, Box<FormatFn>>,
+    chained_formatters: Vec<Box<ChainFormatFn>>,
     functions: BTreeMap<Cow<'engine, str>, EngineBoxFn>,
     templates: BTreeMap<Cow<'engine, str>, program::Template<'engine>>,
+    compile_cache: Mutex<HashMap<u64, Template<'static>>>,
+    store: Option<Box<dyn TemplateStore>>,
     max_include_depth: usize,
+    max_output_size: Option<usize>,
+    missing_var_fn: Option<Box<MissingVarFn>>,
+    undefined: Undefined,
 }
 
 /// A type of function stored in the engine.
@@ -275,18 +309,28 @@ pub enum EngineFn {
     Filter,
 }
 
+/// The default formatter, either borrowed or owned by the engine.
+enum DefaultFormatter<'engine> {
+    Borrowed(&'engine FormatFn),
+    Owned(Box<FormatFn>),
+}
+
 enum EngineBoxFn {
     Formatter(Box<FormatFn>),
     #[cfg(feature = "filters")]
-    Filter(Box<FilterFn>),
+    Filter(Box<FilterFn>, Option<FilterMeta>),
 }
 
-type ValueFn<'a> = dyn Fn(&[ValueMember]) -> std::result::Result<Value, String> + 'a;
+type ValueFn<'a> =
+    dyn FnMut(&[ValueMember]) -> std::result::Result<Cow<'a, Value>, ValueFnError> + 'a;
+
+type MissingVarFn = dyn Fn(&[ValueMember]) -> Option<Value> + Sync + Send + 'static;
 
 /// A member in a value path.
 ///
 /// Passed to custom value function when using
-/// [`render_from_fn`][Template::render_from_fn].
+/// [`render_from_fn`][Template::render_from_fn] and to the function set using
+/// [`Engine::set_missing_var_fn`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ValueMember<'a> {
     /// The type of member access (direct or optional).
@@ -301,6 +345,10 @@ pub enum ValueAccess<'a> {
     /// An index into an array like `2` in `user.names.2`.
     Index(usize),
 
+    /// An index from the end of an array like `-1` in `user.names.-1`. This
+    /// holds the absolute value of the index, `1` in this case.
+    NegativeIndex(usize),
+
     /// A key lookup from a map or member access like `name` in `user.name`.
     Key(&'a str),
 }
@@ -315,14 +363,156 @@ pub enum ValueAccessOp {
     Optional,
 }
 
+/// An error returned from a custom value function.
+///
+/// Value functions passed to [`render_from_fn`][Template::render_from_fn] can
+/// return this error type instead of a [`String`] to choose what the error
+/// points at when it is displayed using the alternate formatting `{:#}`.
+/// Errors created using [`ValueFnError::new`] or converted from a [`String`]
+/// point at the entire variable path, which is the same as returning a
+/// [`String`].
+///
+/// # Examples
+///
+/// ```
+/// use upon::{ValueAccess, ValueFnError};
+///
+/// let engine = upon::Engine::new();
+/// let err = engine
+///     .compile("{{ user.email }}")?
+///     .render_from_fn(&engine, |path| match path[1..] {
+///         [] => Ok(upon::Value::None),
+///         [m] if m.access == ValueAccess::Key("name") => Ok("John Smith".into()),
+///         _ => Err(ValueFnError::at_member(1, "unknown user field")),
+///     })
+///     .to_string()
+///     .unwrap_err();
+/// assert_eq!(err.span(), Some(7..13));
+/// # Ok::<(), upon::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueFnError {
+    reason: String,
+    member: Option<usize>,
+}
+
+impl ValueFnError {
+    /// Construct a new error that points at the entire variable path.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            member: None,
+        }
+    }
+
+    /// Construct a new error that points at the member of the path with the
+    /// given zero-based index.
+    ///
+    /// If there is no such member the error points at the entire variable
+    /// path.
+    pub fn at_member(index: usize, reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            member: Some(index),
+        }
+    }
+
+    /// Returns the reason for the error.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl From<String> for ValueFnError {
+    fn from(reason: String) -> Self {
+        Self::new(reason)
+    }
+}
+
+impl From<&str> for ValueFnError {
+    fn from(reason: &str) -> Self {
+        Self::new(reason)
+    }
+}
+
+impl std::fmt::Display for ValueFnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl std::error::Error for ValueFnError {}
+
+/// What to do when a variable is not found while rendering.
+///
+/// Set using [`Engine::set_undefined_behavior`] or per render using
+/// [`Renderer::with_undefined_behavior`]. A variable is undefined if the first
+/// part of its path is not found in the context, in any variables bound by the
+/// template, in the globals, or by the missing variable function. Missing
+/// fields of a value that does exist, like `name` in `{{ user.name }}` when
+/// `user` exists, are always an error, use `?.` to make them optional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Undefined {
+    /// Fail with a "not found in this scope" error. This is the default.
+    Error,
+
+    /// Treat the variable as [`Value::None`], so `{{ name }}` renders nothing
+    /// and `{% if name %}` is false.
+    Empty,
+
+    /// Render a `{{ ... }}` tag verbatim if any variable in its expression is
+    /// undefined, for example `{{ name | upper }}` or `{{ greeting ~ name }}`.
+    /// A variable that is a filter argument, or that is anywhere else like in a
+    /// condition, is treated as [`Value::None`].
+    ///
+    /// This is useful for rendering a template in multiple passes.
+    Keep,
+}
+
+impl Default for Undefined {
+    #[inline]
+    fn default() -> Self {
+        Self::Error
+    }
+}
+
+/// Statistics about a compiled template.
+///
+/// Returned by [`Template::stats`] and [`TemplateRef::stats`]. This can be
+/// used to cheaply reject templates that are too complex before rendering
+/// them. The counts are of the compiled template only, included templates are
+/// not counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct TemplateStats {
+    /// The number of compiled instructions.
+    pub instrs: usize,
+    /// The number of `for` loops.
+    pub loops: usize,
+    /// The number of `include` and `include_raw` statements.
+    pub includes: usize,
+    /// The number of expressions, for example `{{ ... }}` expressions,
+    /// conditions and loop iterables. Each side of a comparison or range is
+    /// counted separately, filter arguments are not counted.
+    pub exprs: usize,
+    /// The number of filter and formatter calls.
+    pub calls: usize,
+}
+
 /// A compiled template created using [`Engine::compile`].
 ///
 /// For convenience this struct's lifetime is not tied to the lifetime of the
 /// engine. However, it is considered a logic error to attempt to render this
 /// template using a different engine than the one that created it. If that
 /// happens the render call may panic or produce incorrect output.
+///
+/// Cloning a template is cheap, the compiled program is shared between the
+/// clones.
+#[derive(Clone)]
 pub struct Template<'source> {
-    template: program::Template<'source>,
+    template: Arc<program::Template<'source>>,
+    /// The name used in errors, if any.
+    name: Option<Arc<str>>,
 }
 
 /// A reference to a compiled template in an [`Engine`].
@@ -360,14 +550,172 @@ impl<'engine> Engine<'engine> {
     #[inline]
     pub fn with_syntax(syntax: Syntax<'engine>) -> Self {
         Self {
+            case_insensitive_keywords: syntax.case_insensitive_keywords,
             searcher: Searcher::new(syntax),
-            default_formatter: &fmt::default,
+            trim_blocks: false,
+            lstrip_blocks: false,
+            trim_comments: false,
+            strict: false,
+            default_formatter: DefaultFormatter::Borrowed(&fmt::default),
+            type_formatters: BTreeMap::new(),
+            chained_formatters: Vec::new(),
             functions: BTreeMap::new(),
             templates: BTreeMap::new(),
+            compile_cache: Mutex::new(HashMap::new()),
+            store: None,
             max_include_depth: 64,
+            max_output_size: None,
+            missing_var_fn: None,
+            undefined: Undefined::Error,
         }
     }
 
+    /// Construct a new engine backed by the given template store.
+    ///
+    /// See [`set_store`][Engine::set_store] for more information.
+    #[inline]
+    pub fn with_store<S>(store: S) -> Self
+    where
+        S: TemplateStore + 'static,
+    {
+        let mut engine = Self::new();
+        engine.set_store(store);
+        engine
+    }
+
+    /// Set the template store used to lookup templates.
+    ///
+    /// Templates added directly to the engine always take precedence, the
+    /// store is only consulted for names that don't exist in the engine. This
+    /// applies to [`template`][Engine::template],
+    /// [`get_template`][Engine::get_template] and `{% include .. %}`
+    /// statements. See [`TemplateStore`] for more information.
+    #[inline]
+    pub fn set_store<S>(&mut self, store: S)
+    where
+        S: TemplateStore + 'static,
+    {
+        self.store = Some(Box::new(store));
+    }
+
+    /// Set whether the first newline after a block tag is removed.
+    ///
+    /// This applies to block tags like `{% if cond %}` but not to expression
+    /// or comment tags. Both `\n` and `\r\n` are removed. Explicit
+    /// whitespace trimming using `-%}` takes precedence. This only affects
+    /// templates that are compiled after it is set.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_trim_blocks(true);
+    ///
+    /// let result = engine
+    ///     .compile("{% for n in nums %}\n{{ n }}\n{% endfor %}\n")?
+    ///     .render(&engine, upon::value! { nums: [1, 2] })
+    ///     .to_string()?;
+    /// assert_eq!(result, "1\n2\n");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_trim_blocks(&mut self, yes: bool) {
+        self.trim_blocks = yes;
+        self.clear_compile_cache();
+    }
+
+    /// Set whether spaces and tabs before a block tag are removed.
+    ///
+    /// The whitespace is only removed if the block tag is the first thing on a
+    /// line, from the start of the line up to the tag. This applies to block
+    /// tags like `{% if cond %}` but not to expression or comment tags.
+    /// Explicit whitespace trimming using `{%-` takes precedence. This only
+    /// affects templates that are compiled after it is set.
+    ///
+    /// This is usually combined with
+    /// [`set_trim_blocks`][Engine::set_trim_blocks] so that indented block tags
+    /// on their own line don't leave any whitespace in the output.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_trim_blocks(true);
+    /// engine.set_lstrip_blocks(true);
+    ///
+    /// let result = engine
+    ///     .compile("<ul>\n  {% for n in nums %}\n  <li>{{ n }}</li>\n  {% endfor %}\n</ul>")?
+    ///     .render(&engine, upon::value! { nums: [1, 2] })
+    ///     .to_string()?;
+    /// assert_eq!(result, "<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_lstrip_blocks(&mut self, yes: bool) {
+        self.lstrip_blocks = yes;
+        self.clear_compile_cache();
+    }
+
+    /// Set whether all whitespace around comment tags is removed.
+    ///
+    /// When enabled every `{# ... #}` behaves like `{#- ... -#}`, so comments
+    /// that document a template don't leave blank lines in the output. This
+    /// only affects templates that are compiled after it is set.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_trim_comments(true);
+    ///
+    /// let result = engine
+    ///     .compile("Hello\n{# the user's name #}\n{{ name }}!")?
+    ///     .render(&engine, upon::value! { name: "John" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "HelloJohn!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_trim_comments(&mut self, yes: bool) {
+        self.trim_comments = yes;
+        self.clear_compile_cache();
+    }
+
+    /// Set whether templates are checked for unused bindings when compiled.
+    ///
+    /// When enabled it is a syntax error to bind a name using `{% with ... as
+    /// name %}` or `{% set name = ... %}` and never use it in its scope, which
+    /// catches typos in variable names. An `{% include %}` without `with`
+    /// shares the current scope, so it counts as using every name in scope, and
+    /// so does a `{% block %}` because a template that extends this one might
+    /// override it. Lookups made by filters are not taken into account. This
+    /// only affects templates that are compiled after it is set.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_strict(true);
+    ///
+    /// let err = engine
+    ///     .compile("{% with user.name as name %}Hello {{ nmae }}!{% endwith %}")
+    ///     .unwrap_err();
+    /// assert_eq!(err.to_string(), "invalid syntax: unused binding `name`");
+    /// ```
+    #[inline]
+    pub fn set_strict(&mut self, yes: bool) {
+        self.strict = yes;
+        self.clear_compile_cache();
+    }
+
     /// Set the maximum length of the template render stack.
     ///
     /// This is the maximum number of nested `{% include ... %}` statements that
@@ -379,18 +727,249 @@ impl<'engine> Engine<'engine> {
         self.max_include_depth = depth;
     }
 
+    /// Set the maximum number of bytes that rendering a template may output.
+    ///
+    /// Rendering fails with an [`ErrorKind::MaxOutputSize`] error as soon as
+    /// any more output would be written. This is useful as a guard when
+    /// rendering untrusted templates.
+    ///
+    /// Defaults to no limit.
+    #[inline]
+    pub fn set_max_output_size(&mut self, bytes: usize) {
+        self.max_output_size = Some(bytes);
+    }
+
+    /// Set a function that is called when a variable is not found.
+    ///
+    /// The function is called with the full path of the variable, for example
+    /// `user.name` when rendering `{{ user.name }}`, and should return the
+    /// value for the entire path. It is only called if the first part of the
+    /// path is not found in the context, in any variables bound by the
+    /// template, or in the globals set using
+    /// [`Renderer::with_globals`][crate::Renderer::with_globals]. Returning
+    /// `None` results in the usual "not found in this scope" error.
+    ///
+    /// This is useful for lazily computing values that are expensive to
+    /// produce and are only used by some templates. The function is called
+    /// each time the variable is looked up, so any caching must be done by the
+    /// function itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::{Value, ValueAccess};
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_missing_var_fn(|path| match path {
+    ///     [first] if first.access == ValueAccess::Key("year") => Some(Value::from(2024)),
+    ///     _ => None,
+    /// });
+    ///
+    /// let result = engine
+    ///     .compile("{{ name }} ({{ year }})")?
+    ///     .render(&engine, upon::value! { name: "John Smith" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "John Smith (2024)");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_missing_var_fn<F>(&mut self, f: F)
+    where
+        F: Fn(&[ValueMember<'_>]) -> Option<Value> + Sync + Send + 'static,
+    {
+        self.missing_var_fn = Some(Box::new(f));
+    }
+
+    /// Set what happens when a variable is not found while rendering.
+    ///
+    /// See [`Undefined`] for the available behaviors. This can be overridden
+    /// for a single render using
+    /// [`Renderer::with_undefined_behavior`][crate::Renderer::with_undefined_behavior].
+    ///
+    /// Defaults to [`Undefined::Error`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Undefined;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_undefined_behavior(Undefined::Keep);
+    ///
+    /// let result = engine
+    ///     .compile("Hello {{ name }}, welcome to {{ site.name }}!")?
+    ///     .render(&engine, upon::value! { name: "John Smith" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John Smith, welcome to {{ site.name }}!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_undefined_behavior(&mut self, undefined: Undefined) {
+        self.undefined = undefined;
+    }
+
     /// Set the default formatter.
     ///
     /// The default formatter defines how values are formatted in the rendered
     /// template. If not configured this defaults to [`fmt::default`] which does
     /// not perform any escaping. See the [`fmt`] module documentation for more
     /// information on value formatters.
+    ///
+    /// # Examples
+    ///
+    /// Escape HTML in all expressions that do not use an explicit formatter.
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_default_formatter(&upon::fmt::escape_html);
+    ///
+    /// let result = engine
+    ///     .compile("{{ name }}")?
+    ///     .render(&engine, upon::value! { name: "<b>John</b>" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "&lt;b&gt;John&lt;/b&gt;");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
     #[inline]
     pub fn set_default_formatter<F>(&mut self, f: &'engine F)
     where
         F: Fn(&mut fmt::Formatter<'_>, &Value) -> fmt::Result + Sync + Send + 'static,
     {
-        self.default_formatter = f;
+        self.default_formatter = DefaultFormatter::Borrowed(f);
+    }
+
+    /// Set the default formatter to an owned function or closure.
+    ///
+    /// This behaves exactly like
+    /// [`set_default_formatter`][Engine::set_default_formatter] except that the
+    /// formatter is moved into the engine, so it does not need to outlive the
+    /// engine. This is useful for closures that are constructed at runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    ///
+    /// let placeholder = String::from("N/A");
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.set_default_formatter_owned(move |f, value| match value {
+    ///     upon::Value::None => f.write_str(&placeholder).map_err(Into::into),
+    ///     value => upon::fmt::default(f, value),
+    /// });
+    ///
+    /// let result = engine
+    ///     .compile("{{ name }} ({{ email }})")?
+    ///     .render(&engine, upon::value! { name: "John Smith", email: None })
+    ///     .to_string()?;
+    /// assert_eq!(result, "John Smith (N/A)");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn set_default_formatter_owned<F>(&mut self, f: F)
+    where
+        F: Fn(&mut fmt::Formatter<'_>, &Value) -> fmt::Result + Sync + Send + 'static,
+    {
+        self.default_formatter = DefaultFormatter::Owned(Box::new(f));
+    }
+
+    /// Returns the default formatter.
+    pub(crate) fn default_formatter(&self) -> &FormatFn {
+        match &self.default_formatter {
+            DefaultFormatter::Borrowed(f) => *f,
+            DefaultFormatter::Owned(f) => &**f,
+        }
+    }
+
+    /// Set the formatter used for a particular kind of value.
+    ///
+    /// When an expression is emitted without an explicit formatter, the
+    /// formatter registered for the kind of the value is used if there is one,
+    /// otherwise the default formatter is used. Formatters that are invoked
+    /// explicitly, e.g. `{{ user.name | escape_html }}`, are not affected. Type
+    /// formatters are not used within an `{% autoescape off %}` block.
+    ///
+    /// If a formatter was already registered for the kind it is replaced and
+    /// `true` is returned.
+    ///
+    /// # Examples
+    ///
+    /// Always format floats with two decimal places.
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    /// use upon::{Value, ValueKind};
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_type_formatter(ValueKind::Float, |f, v| {
+    ///     if let Value::Float(x) = v {
+    ///         write!(f, "{x:.2}")?;
+    ///     }
+    ///     Ok(())
+    /// });
+    ///
+    /// let result = engine
+    ///     .compile("{{ price }} x {{ count }}")?
+    ///     .render(&engine, upon::value! { price: 1.5, count: 3 })
+    ///     .to_string()?;
+    /// assert_eq!(result, "1.50 x 3");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn add_type_formatter<F>(&mut self, kind: ValueKind, f: F) -> bool
+    where
+        F: Fn(&mut fmt::Formatter<'_>, &Value) -> fmt::Result + Sync + Send + 'static,
+    {
+        self.type_formatters.insert(kind, Box::new(f)).is_some()
+    }
+
+    /// Add a formatter to the end of the formatter chain.
+    ///
+    /// When an expression is emitted without an explicit formatter, each
+    /// formatter in the chain is tried in the order they were added until one
+    /// returns [`Handled::Yes`][fmt::Handled::Yes]. If every formatter returns
+    /// [`Handled::No`][fmt::Handled::No] then the type formatter for the kind
+    /// of the value is used if there is one, otherwise the default formatter
+    /// is used. Like type formatters the chain is not used within an
+    /// `{% autoescape off %}` block.
+    ///
+    /// Use [`fmt::always_handled`] to add an existing formatter function to
+    /// the chain.
+    ///
+    /// # Examples
+    ///
+    /// Format maps that look like money and leave other values to the
+    /// default formatter.
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    /// use upon::fmt::Handled;
+    /// use upon::Value;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_chained_formatter(|f, v| {
+    ///     if let (Some(Value::Integer(cents)), Some(Value::String(currency))) =
+    ///         (v.get("cents"), v.get("currency"))
+    ///     {
+    ///         write!(f, "{}.{:02} {currency}", cents / 100, cents % 100)?;
+    ///         return Ok(Handled::Yes);
+    ///     }
+    ///     Ok(Handled::No)
+    /// });
+    ///
+    /// let result = engine
+    ///     .compile("{{ item }}: {{ price }}")?
+    ///     .render(&engine, upon::value! { item: "tea", price: { cents: 350, currency: "EUR" } })
+    ///     .to_string()?;
+    /// assert_eq!(result, "tea: 3.50 EUR");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn add_chained_formatter<F>(&mut self, f: F)
+    where
+        F: Fn(&mut fmt::Formatter<'_>, &Value) -> fmt::ChainResult + Sync + Send + 'static,
+    {
+        self.chained_formatters.push(Box::new(f));
     }
 
     /// Add a new value formatter to the engine.
@@ -414,6 +993,20 @@ impl<'engine> Engine<'engine> {
             .map(|f| f.discriminant())
     }
 
+    /// Add a new value formatter to the engine, failing if the name is taken.
+    ///
+    /// This is the same as [`add_formatter`][Engine::add_formatter] except
+    /// that an existing filter or formatter with the same name is never
+    /// replaced. Instead `Err(_)` with the type of the existing function is
+    /// returned and the engine is left unchanged.
+    pub fn try_add_formatter<N, F>(&mut self, name: N, f: F) -> std::result::Result<(), EngineFn>
+    where
+        N: Into<Cow<'engine, str>>,
+        F: Fn(&mut fmt::Formatter<'_>, &Value) -> fmt::Result + Sync + Send + 'static,
+    {
+        self.try_insert_function(name.into(), || EngineBoxFn::Formatter(Box::new(f)))
+    }
+
     /// Add a new filter to the engine.
     ///
     /// See the [`filters`] module documentation for more information on
@@ -436,65 +1029,535 @@ impl<'engine> Engine<'engine> {
         A: FilterArgs,
     {
         self.functions
-            .insert(name.into(), EngineBoxFn::Filter(filters::new(f)))
+            .insert(name.into(), EngineBoxFn::Filter(filters::new(f), None))
             .map(|f| f.discriminant())
     }
 
-    /// Remove a formatter or filter by name.
+    /// Add a new filter to the engine, failing if the name is taken.
     ///
-    /// # Note
+    /// This is the same as [`add_filter`][Engine::add_filter] except that an
+    /// existing filter or formatter with the same name is never replaced.
+    /// Instead `Err(_)` with the type of the existing function is returned
+    /// and the engine is left unchanged.
     ///
-    /// Formatters and filters share the same namespace. If a filter or
-    /// formatter with name existed in the engine, it is replaced and `Some(_)`
-    /// with the type of function that was replaced is returned, else `None` is
-    /// returned.
-    pub fn remove_function(&mut self, name: &str) -> Option<EngineFn> {
-        self.functions.remove(name).map(|f| f.discriminant())
-    }
-
-    /// Add a template to the engine.
+    /// # Examples
     ///
-    /// The template will be compiled and stored under the given name.
+    /// ```
+    /// use upon::EngineFn;
     ///
-    /// You can either pass a borrowed template ([`&str`]) or owned template
-    /// ([`String`]) to this function. When passing a borrowed template, the
-    /// lifetime needs to be at least as long as the engine lifetime. For
-    /// shorter template lifetimes use [`.compile(..)`][Engine::compile].
-    #[inline]
-    pub fn add_template<N, S>(&mut self, name: N, source: S) -> Result<()>
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter("lower", str::to_lowercase);
+    ///
+    /// assert_eq!(engine.try_add_filter("upper", str::to_uppercase), Ok(()));
+    /// assert_eq!(
+    ///     engine.try_add_filter("lower", str::to_uppercase),
+    ///     Err(EngineFn::Filter)
+    /// );
+    /// ```
+    #[cfg(feature = "filters")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+    pub fn try_add_filter<N, F, R, A>(&mut self, name: N, f: F) -> std::result::Result<(), EngineFn>
     where
         N: Into<Cow<'engine, str>>,
-        S: Into<Cow<'engine, str>>,
+        F: Filter<R, A> + Send + Sync + 'static,
+        R: FilterReturn,
+        A: FilterArgs,
     {
-        match compile::template(self, source.into()) {
-            Ok(template) => {
-                self.templates.insert(name.into(), template);
-                Ok(())
-            }
-            Err(err) => Err(err.with_template_name(name.into().into())),
-        }
+        self.try_insert_function(name.into(), || EngineBoxFn::Filter(filters::new(f), None))
     }
 
-    /// Lookup a template by name.
+    /// Add all the filters in the [`filters::stdlib`] module to the engine.
     ///
-    /// # Panics
+    /// Each filter is registered under the name of its function, for example
+    /// [`upper`][filters::stdlib::upper] is added as `upper`. Any existing
+    /// filter or formatter with the same name is replaced.
     ///
-    /// If the template with the given name does not exist.
+    /// # Examples
     ///
-    #[inline]
-    #[track_caller]
-    pub fn template(&self, name: &str) -> TemplateRef<'_> {
-        match self.get_template(name) {
-            Some(template) => template,
-            None => panic!("template with name '{}' does not exist in engine", name),
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_stdlib_filters();
+    ///
+    /// let result = engine
+    ///     .compile("{{ names | join: \", \" | upper }}")?
+    ///     .render(&engine, upon::value! { names: ["John", "Jane"] })
+    ///     .to_string()?;
+    /// assert_eq!(result, "JOHN, JANE");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[cfg(feature = "stdlib")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "stdlib")))]
+    pub fn add_stdlib_filters(&mut self) {
+        use crate::filters::stdlib;
+
+        self.add_filter("upper", stdlib::upper);
+        self.add_filter("lower", stdlib::lower);
+        self.add_filter("trim", stdlib::trim);
+        self.add_filter("indent", stdlib::indent);
+        self.add_filter("indent_first", stdlib::indent_first);
+        self.add_filter("dedent", stdlib::dedent);
+        self.add_filter("len", stdlib::len);
+        self.add_filter("default", stdlib::default);
+        self.add_filter("join", stdlib::join);
+        self.add_filter("reverse", stdlib::reverse);
+        self.add_filter("first", stdlib::first);
+        self.add_filter("last", stdlib::last);
+        self.add_filter("nth", stdlib::nth);
+        self.add_filter("slice", stdlib::slice);
+        self.add_filter("replace", stdlib::replace);
+        self.add_filter("starts_with", stdlib::starts_with);
+        self.add_filter("ends_with", stdlib::ends_with);
+        self.add_filter("contains", stdlib::contains);
+        self.add_filter("slugify", stdlib::slugify);
+        self.add_filter("timestamp", stdlib::timestamp);
+        self.add_filter("timestamp_fmt", stdlib::timestamp_fmt);
+        #[cfg(all(feature = "json", feature = "serde"))]
+        {
+            self.add_filter("json", stdlib::json);
+            self.add_filter("json_fmt", stdlib::json_fmt);
         }
+        self.add_filter("add", stdlib::add);
+        self.add_filter("sub", stdlib::sub);
+        self.add_filter("mul", stdlib::mul);
+        self.add_filter("div", stdlib::div);
     }
 
-    /// Lookup a template by name, returning `None` if it does not exist.
-    #[inline]
-    pub fn get_template(&self, name: &str) -> Option<TemplateRef<'_>> {
-        self.templates
-            .get_key_value(name)
+    /// Add a new filter to the engine along with its documentation.
+    ///
+    /// This behaves exactly like [`add_filter`][Engine::add_filter] but also
+    /// stores the given metadata which can be retrieved using
+    /// [`filter_meta`][Engine::filter_meta].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::filters::FilterMeta;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter_with_meta(
+    ///     "repeat",
+    ///     |s: &str, n: usize| s.repeat(n),
+    ///     FilterMeta {
+    ///         description: "Repeats the string `n` times.".into(),
+    ///         arg_names: vec!["n".into()],
+    ///     },
+    /// );
+    ///
+    /// let meta = engine.filter_meta("repeat").unwrap();
+    /// assert_eq!(meta.arg_names, ["n"]);
+    /// ```
+    #[cfg(feature = "filters")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+    #[inline]
+    pub fn add_filter_with_meta<N, F, R, A>(
+        &mut self,
+        name: N,
+        f: F,
+        meta: FilterMeta,
+    ) -> Option<EngineFn>
+    where
+        N: Into<Cow<'engine, str>>,
+        F: Filter<R, A> + Send + Sync + 'static,
+        R: FilterReturn,
+        A: FilterArgs,
+    {
+        self.functions
+            .insert(
+                name.into(),
+                EngineBoxFn::Filter(filters::new(f), Some(meta)),
+            )
+            .map(|f| f.discriminant())
+    }
+
+    /// Add a new filter that accepts named arguments to the engine.
+    ///
+    /// The filter receives the piped value, the positional arguments and the
+    /// named arguments. Named arguments are specified using `name = value`
+    /// and must come after any positional arguments.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use upon::Value;
+    ///
+    /// fn pad(v: &Value, _: &[Value], named: &BTreeMap<String, Value>) -> Result<String, String> {
+    ///     let (Value::String(s), Some(Value::Integer(width))) = (v, named.get("width")) else {
+    ///         return Err("expected string value and integer `width`".into());
+    ///     };
+    ///     let fill = match named.get("fill") {
+    ///         Some(Value::String(fill)) => fill.as_str(),
+    ///         _ => " ",
+    ///     };
+    ///     let n = (*width as usize).saturating_sub(s.chars().count());
+    ///     Ok(format!("{}{s}", fill.repeat(n)))
+    /// }
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter_with_named_args("pad", pad);
+    ///
+    /// let result = engine
+    ///     .compile(r#"{{ id | pad: width = 5, fill = "0" }}"#)?
+    ///     .render(&engine, upon::value! { id: "42" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "00042");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Formatters and filters share the same namespace. If a filter or
+    /// formatter with the same name already exists in the engine, it is
+    /// replaced and `Some(_)` with the type of function that was replaced is
+    /// returned, else `None` is returned.
+    #[cfg(feature = "filters")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+    #[inline]
+    pub fn add_filter_with_named_args<N, F, R>(&mut self, name: N, f: F) -> Option<EngineFn>
+    where
+        N: Into<Cow<'engine, str>>,
+        F: Fn(&Value, &[Value], &BTreeMap<String, Value>) -> R + Send + Sync + 'static,
+        R: FilterReturn,
+    {
+        self.functions
+            .insert(
+                name.into(),
+                EngineBoxFn::Filter(filters::new_with_named_args(f), None),
+            )
+            .map(|f| f.discriminant())
+    }
+
+    /// Add a new filter that has access to per-render state to the engine.
+    ///
+    /// The filter receives a [`RenderContext`][filters::RenderContext], the
+    /// piped value and the positional arguments. The context starts out empty
+    /// for every render, so any state stored in it does not leak across
+    /// renders. The context also provides the template source of each
+    /// argument using [`arg_source`][filters::RenderContext::arg_source].
+    ///
+    /// # Examples
+    ///
+    /// A filter that assigns sequential IDs.
+    ///
+    /// ```
+    /// use upon::filters::RenderContext;
+    /// use upon::Value;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter_mut("next_id", |ctx: &mut RenderContext, _: &Value, _: &[Value]| {
+    ///     let id = match ctx.get("next_id") {
+    ///         Some(Value::Integer(id)) => *id,
+    ///         _ => 0,
+    ///     };
+    ///     ctx.insert("next_id", id + 1);
+    ///     format!("id-{id}")
+    /// });
+    ///
+    /// let template = engine.compile("{% for x in xs %}{{ x | next_id }} {% endfor %}")?;
+    /// for _ in 0..2 {
+    ///     let result = template
+    ///         .render(&engine, upon::value! { xs: [1, 2] })
+    ///         .to_string()?;
+    ///     assert_eq!(result, "id-0 id-1 ");
+    /// }
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Formatters and filters share the same namespace. If a filter or
+    /// formatter with the same name already exists in the engine, it is
+    /// replaced and `Some(_)` with the type of function that was replaced is
+    /// returned, else `None` is returned.
+    #[cfg(feature = "filters")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+    #[inline]
+    pub fn add_filter_mut<N, F, R>(&mut self, name: N, f: F) -> Option<EngineFn>
+    where
+        N: Into<Cow<'engine, str>>,
+        F: Fn(&mut filters::RenderContext, &Value, &[Value]) -> R + Send + Sync + 'static,
+        R: FilterReturn,
+    {
+        self.functions
+            .insert(
+                name.into(),
+                EngineBoxFn::Filter(filters::new_with_context(f), None),
+            )
+            .map(|f| f.discriminant())
+    }
+
+    /// Add a new filter that can look up variables to the engine.
+    ///
+    /// The filter receives a [`Context`][filters::Context], the piped value and
+    /// the positional arguments. The context can be used to look up any
+    /// variable that is in scope where the filter is called, for example a
+    /// locale in the render context. If the filter returns an error it is
+    /// reported in the same way as for any other filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::filters::Context;
+    /// use upon::Value;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_filter_with_context("t", |ctx: Context<'_>, key: &Value, _: &[Value]| {
+    ///     let locale = ctx.get("locale").ok_or("no locale set")?;
+    ///     match (&*locale, key) {
+    ///         (Value::String(l), Value::String(k)) if l == "fr" && k == "hello" => Ok("bonjour"),
+    ///         (Value::String(l), Value::String(k)) if l == "en" && k == "hello" => Ok("hello"),
+    ///         _ => Err("no translation"),
+    ///     }
+    /// });
+    ///
+    /// let template = engine.compile(r#"{{ "hello" | t }}"#)?;
+    /// let result = template
+    ///     .render(&engine, upon::value! { locale: "fr" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "bonjour");
+    ///
+    /// let err = template
+    ///     .render(&engine, upon::value! {})
+    ///     .to_string()
+    ///     .unwrap_err();
+    /// assert_eq!(err.to_string(), "filter error: no locale set");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Formatters and filters share the same namespace. If a filter or
+    /// formatter with the same name already exists in the engine, it is
+    /// replaced and `Some(_)` with the type of function that was replaced is
+    /// returned, else `None` is returned.
+    #[cfg(feature = "filters")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+    #[inline]
+    pub fn add_filter_with_context<N, F, R>(&mut self, name: N, f: F) -> Option<EngineFn>
+    where
+        N: Into<Cow<'engine, str>>,
+        F: Fn(filters::Context<'_>, &Value, &[Value]) -> R + Send + Sync + 'static,
+        R: FilterReturn,
+    {
+        self.functions
+            .insert(
+                name.into(),
+                EngineBoxFn::Filter(filters::new_with_lookup(f), None),
+            )
+            .map(|f| f.discriminant())
+    }
+
+    /// Returns the documentation for the filter with the given name.
+    ///
+    /// Returns `None` if the filter does not exist or if it was added without
+    /// any metadata.
+    #[cfg(feature = "filters")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "filters")))]
+    #[inline]
+    pub fn filter_meta(&self, name: &str) -> Option<&FilterMeta> {
+        match self.functions.get(name)? {
+            EngineBoxFn::Filter(_, meta) => meta.as_ref(),
+            EngineBoxFn::Formatter(_) => None,
+        }
+    }
+
+    /// Returns the type of the formatter or filter with the given name.
+    ///
+    /// Returns `None` if no formatter or filter with the name exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::EngineFn;
+    ///
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_formatter("unit", |_, _| Ok(()));
+    ///
+    /// assert_eq!(engine.get_function("unit"), Some(EngineFn::Formatter));
+    /// assert_eq!(engine.get_function("lower"), None);
+    /// ```
+    #[inline]
+    pub fn get_function(&self, name: &str) -> Option<EngineFn> {
+        self.functions.get(name).map(|f| f.discriminant())
+    }
+
+    /// Returns an iterator over the names and types of the formatters and
+    /// filters in the engine.
+    ///
+    /// The names are yielded in sorted order. Type formatters added using
+    /// [`add_type_formatter`][Engine::add_type_formatter] and the default
+    /// formatter are not included.
+    #[inline]
+    pub fn function_names(&self) -> impl Iterator<Item = (&str, EngineFn)> + '_ {
+        self.functions
+            .iter()
+            .map(|(name, f)| (name.as_ref(), f.discriminant()))
+    }
+
+    /// Remove a formatter or filter by name.
+    ///
+    /// # Note
+    ///
+    /// Formatters and filters share the same namespace. If a filter or
+    /// formatter with name existed in the engine, it is replaced and `Some(_)`
+    /// with the type of function that was replaced is returned, else `None` is
+    /// returned.
+    pub fn remove_function(&mut self, name: &str) -> Option<EngineFn> {
+        self.functions.remove(name).map(|f| f.discriminant())
+    }
+
+    /// Remove all formatters and filters.
+    ///
+    /// Type formatters added using
+    /// [`add_type_formatter`][Engine::add_type_formatter] and the default
+    /// formatter are not affected.
+    #[inline]
+    pub fn clear_functions(&mut self) {
+        self.functions.clear();
+    }
+
+    /// Add a template to the engine.
+    ///
+    /// The template will be compiled and stored under the given name.
+    ///
+    /// You can either pass a borrowed template ([`&str`]) or owned template
+    /// ([`String`]) to this function. When passing a borrowed template, the
+    /// lifetime needs to be at least as long as the engine lifetime. For
+    /// shorter template lifetimes use [`.compile(..)`][Engine::compile].
+    #[inline]
+    pub fn add_template<N, S>(&mut self, name: N, source: S) -> Result<()>
+    where
+        N: Into<Cow<'engine, str>>,
+        S: Into<Cow<'engine, str>>,
+    {
+        match compile::template(self, source.into()) {
+            Ok(template) => {
+                self.templates.insert(name.into(), template);
+                Ok(())
+            }
+            Err(err) => Err(err.with_template_name(name.into().into())),
+        }
+    }
+
+    /// Add many templates to the engine.
+    ///
+    /// Each template is compiled and stored under the given name, the same as
+    /// [`add_template(..)`][Engine::add_template]. All templates are compiled
+    /// before any are added, so if a template fails to compile then the
+    /// engine is left unchanged and the error is returned with the name of
+    /// that template.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_templates([("header", "<h1>{{ title }}</h1>"), ("footer", "<p>Bye</p>")])?;
+    /// assert_eq!(engine.len(), 2);
+    ///
+    /// let err = engine
+    ///     .add_templates([("lorem", "{{ ipsum }}"), ("dolor", "{{ sit }")])
+    ///     .unwrap_err();
+    /// assert_eq!(err.template_name(), Some("dolor"));
+    /// assert_eq!(engine.len(), 2);
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn add_templates<I, N, S>(&mut self, templates: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (N, S)>,
+        N: Into<Cow<'engine, str>>,
+        S: Into<Cow<'engine, str>>,
+    {
+        let templates = templates
+            .into_iter()
+            .map(|(name, source)| {
+                let name = name.into();
+                match compile::template(self, source.into()) {
+                    Ok(template) => Ok((name, template)),
+                    Err(err) => Err(err.with_template_name(name.into())),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.templates.extend(templates);
+        Ok(())
+    }
+
+    /// Add a template to the engine and return a reference to it.
+    ///
+    /// This is the same as [`add_template(..)`][Engine::add_template]
+    /// followed by [`template(..)`][Engine::template], but without the extra
+    /// lookup.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// let result = engine
+    ///     .add_template_ref("hello", "Hello {{ user.name }}!")?
+    ///     .render(upon::value! { user: { name: "John Smith" } })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John Smith!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn add_template_ref<N, S>(&mut self, name: N, source: S) -> Result<TemplateRef<'_>>
+    where
+        N: Into<Cow<'engine, str>>,
+        S: Into<Cow<'engine, str>>,
+    {
+        let name = name.into();
+        self.add_template(name.clone(), source)?;
+        let (name, template) = self.templates.get_key_value(&*name).unwrap();
+        Ok(TemplateRef {
+            engine: self,
+            name,
+            template,
+        })
+    }
+
+    /// Add a precompiled template to the engine.
+    ///
+    /// The bytes must have been returned by [`Template::to_bytes`] or
+    /// [`TemplateRef::to_bytes`]. The template is stored under the given name
+    /// without compiling the template source again.
+    ///
+    /// # Errors
+    ///
+    /// If the bytes are corrupted or were created by an incompatible version
+    /// of this crate.
+    #[cfg(feature = "precompile")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "precompile")))]
+    pub fn load_template<N>(&mut self, name: N, bytes: &[u8]) -> Result<()>
+    where
+        N: Into<Cow<'engine, str>>,
+    {
+        let name = name.into();
+        match types::precompile::from_bytes(bytes) {
+            Ok(template) => {
+                self.templates.insert(name, template);
+                Ok(())
+            }
+            Err(err) => Err(err.with_template_name(name.into())),
+        }
+    }
+
+    /// Lookup a template by name.
+    ///
+    /// # Panics
+    ///
+    /// If the template with the given name does not exist.
+    ///
+    #[inline]
+    #[track_caller]
+    pub fn template(&self, name: &str) -> TemplateRef<'_> {
+        match self.get_template(name) {
+            Some(template) => template,
+            None => panic!("template with name '{}' does not exist in engine", name),
+        }
+    }
+
+    /// Lookup a template by name, returning `None` if it does not exist.
+    #[inline]
+    pub fn get_template(&self, name: &str) -> Option<TemplateRef<'_>> {
+        self.lookup_template(name)
             .map(|(name, template)| TemplateRef {
                 engine: self,
                 name,
@@ -502,6 +1565,33 @@ impl<'engine> Engine<'engine> {
             })
     }
 
+    /// Inserts a function unless a function with the same name exists.
+    fn try_insert_function(
+        &mut self,
+        name: Cow<'engine, str>,
+        f: impl FnOnce() -> EngineBoxFn,
+    ) -> std::result::Result<(), EngineFn> {
+        match self.functions.entry(name) {
+            Entry::Occupied(entry) => Err(entry.get().discriminant()),
+            Entry::Vacant(entry) => {
+                entry.insert(f());
+                Ok(())
+            }
+        }
+    }
+
+    /// Lookup a template in the engine and then in the template store.
+    fn lookup_template(&self, name: &str) -> Option<(&str, &program::Template<'_>)> {
+        match self.templates.get_key_value(name) {
+            Some((name, template)) => Some((name, template)),
+            None => self
+                .store
+                .as_ref()?
+                .get_template(name)
+                .map(|(name, template)| (name, &*template.template)),
+        }
+    }
+
     /// Remove a template by name.
     ///
     /// Returns `true` if a template was removed, `false` if there was no
@@ -511,6 +1601,126 @@ impl<'engine> Engine<'engine> {
         self.templates.remove(name).is_some()
     }
 
+    /// Remove all templates.
+    ///
+    /// Templates in the template store are not affected.
+    #[inline]
+    pub fn clear_templates(&mut self) {
+        self.templates.clear();
+    }
+
+    /// Remove all templates, formatters and filters.
+    ///
+    /// This is the same as calling [`clear_templates`][Engine::clear_templates]
+    /// and [`clear_functions`][Engine::clear_functions]. All other settings,
+    /// including the syntax and the default formatter, are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::with_syntax(upon::Syntax::builder().expr("<{", "}>").build());
+    /// engine.add_filter("lower", str::to_lowercase);
+    /// engine.add_template("hello", "Hello <{ name | lower }>!")?;
+    ///
+    /// engine.reset();
+    /// assert!(engine.get_template("hello").is_none());
+    ///
+    /// let result = engine
+    ///     .compile("Hello <{ name }>!")?
+    ///     .render(&engine, upon::value! { name: "John" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn reset(&mut self) {
+        self.clear_templates();
+        self.clear_functions();
+    }
+
+    /// Returns an iterator over the names of the templates in the engine.
+    ///
+    /// The names are yielded in sorted order. Templates that are only
+    /// available through the [template store][Engine::set_store] are not
+    /// included.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_template("hello", "Hello {{ name }}!")?;
+    /// engine.add_template("goodbye", "Goodbye {{ name }}!")?;
+    ///
+    /// let names: Vec<_> = engine.template_names().collect();
+    /// assert_eq!(names, ["goodbye", "hello"]);
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn template_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.templates.keys().map(|name| &**name)
+    }
+
+    /// Returns an iterator over the templates whose names start with the given
+    /// prefix.
+    ///
+    /// The templates are yielded lazily in sorted order by name. Like
+    /// [`template_names`][Engine::template_names] this does not include
+    /// templates that are only available through the template store.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_template("emails/welcome", "Welcome {{ name }}!")?;
+    /// engine.add_template("emails/goodbye", "Goodbye {{ name }}!")?;
+    /// engine.add_template("pages/home", "Home")?;
+    ///
+    /// let mut results = Vec::new();
+    /// for template in engine.templates_with_prefix("emails/") {
+    ///     let result = template
+    ///         .render(upon::value! { name: "John" })
+    ///         .to_string()?;
+    ///     results.push((template.name(), result));
+    /// }
+    /// assert_eq!(
+    ///     results,
+    ///     [
+    ///         ("emails/goodbye", "Goodbye John!".to_owned()),
+    ///         ("emails/welcome", "Welcome John!".to_owned()),
+    ///     ]
+    /// );
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn templates_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = TemplateRef<'a>> + 'a {
+        self.templates
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(name, _)| name.starts_with(prefix))
+            .map(move |(name, template)| TemplateRef {
+                engine: self,
+                name,
+                template,
+            })
+    }
+
+    /// Returns the number of templates in the engine.
+    ///
+    /// Like [`template_names`][Engine::template_names] this does not include
+    /// templates that are only available through the template store.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Returns `true` if there are no templates in the engine.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
     /// Compile a template.
     ///
     /// The template will not be stored in the engine. The advantage over using
@@ -522,7 +1732,109 @@ impl<'engine> Engine<'engine> {
         S: Into<Cow<'source, str>>,
     {
         let template = compile::template(self, source.into())?;
-        Ok(Template { template })
+        Ok(Template {
+            template: Arc::new(template),
+            name: None,
+        })
+    }
+
+    /// Compile a template with the given name.
+    ///
+    /// Like [`compile`][Engine::compile] the template will not be stored in
+    /// the engine. The name is only used in place of `<anonymous>` in errors
+    /// returned when compiling and rendering the template, which is useful
+    /// for telling apart many ad hoc templates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let err = engine
+    ///     .compile_named("greeting", "Hello {{ user.name }}!")?
+    ///     .render(&engine, upon::Value::None)
+    ///     .to_string()
+    ///     .unwrap_err();
+    /// assert_eq!(err.template_name(), Some("greeting"));
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn compile_named<'source, N, S>(&self, name: N, source: S) -> Result<Template<'source>>
+    where
+        N: Into<String>,
+        S: Into<Cow<'source, str>>,
+    {
+        let name = name.into();
+        match compile::template(self, source.into()) {
+            Ok(template) => Ok(Template {
+                template: Arc::new(template),
+                name: Some(name.into()),
+            }),
+            Err(err) => Err(err.with_template_name(name)),
+        }
+    }
+
+    /// Compile a template, reusing a previously compiled template with the
+    /// same source if there is one.
+    ///
+    /// This is useful when the same template sources are compiled over and
+    /// over, for example when they are loaded from a database on each
+    /// request. The first time a source is compiled the template is stored in
+    /// a cache in the engine keyed by a hash of the source, after that a clone
+    /// of the cached [`Template`] is returned, which is cheap. Templates that
+    /// fail to compile are not cached.
+    ///
+    /// # Note
+    ///
+    /// Cached templates are kept until the engine is dropped or the cache is
+    /// cleared using [`clear_compile_cache`][Engine::clear_compile_cache].
+    /// Every distinct source is cached, so the cache can grow without bound if
+    /// many different sources are compiled. The source is copied into the
+    /// cache the first time it is compiled, which is why the returned template
+    /// does not borrow from the source. Changing any setting that affects
+    /// compilation clears the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// for _ in 0..3 {
+    ///     let source = String::from("Hello {{ user.name }}!");
+    ///     let result = engine
+    ///         .compile_cached(&source)?
+    ///         .render(&engine, upon::value! { user: { name: "John Smith" } })
+    ///         .to_string()?;
+    ///     assert_eq!(result, "Hello John Smith!");
+    /// }
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn compile_cached(&self, source: &str) -> Result<Template<'static>> {
+        let key = {
+            let mut hasher = DefaultHasher::new();
+            source.hash(&mut hasher);
+            hasher.finish()
+        };
+        // The lock is not held while compiling so that other threads are not
+        // blocked, at worst the same source is compiled more than once.
+        match self.compile_cache().get(&key) {
+            Some(template) if template.source() == source => return Ok(template.clone()),
+            _ => {}
+        }
+        let template = self.compile(source.to_owned())?;
+        self.compile_cache().insert(key, template.clone());
+        Ok(template)
+    }
+
+    /// Remove all templates cached by
+    /// [`compile_cached`][Engine::compile_cached].
+    pub fn clear_compile_cache(&self) {
+        self.compile_cache().clear();
+    }
+
+    fn compile_cache(&self) -> MutexGuard<'_, HashMap<u64, Template<'static>>> {
+        // A panic can not happen while the lock is held, so the cache is
+        // always in a valid state even if the mutex is poisoned.
+        self.compile_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
     }
 }
 
@@ -530,10 +1842,22 @@ impl std::fmt::Debug for Engine<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Engine")
             .field("searcher", &(..))
+            .field("case_insensitive_keywords", &self.case_insensitive_keywords)
+            .field("trim_blocks", &self.trim_blocks)
+            .field("lstrip_blocks", &self.lstrip_blocks)
+            .field("trim_comments", &self.trim_comments)
+            .field("strict", &self.strict)
             .field("default_formatter", &(..))
+            .field("type_formatters", &self.type_formatters.keys())
+            .field("chained_formatters", &self.chained_formatters.len())
             .field("functions", &self.functions)
             .field("templates", &self.templates)
+            .field("compile_cache", &self.compile_cache().len())
+            .field("store", &self.store.as_ref().map(|_| ..))
             .field("max_include_depth", &self.max_include_depth)
+            .field("max_output_size", &self.max_output_size)
+            .field("missing_var_fn", &self.missing_var_fn.as_ref().map(|_| ..))
+            .field("undefined", &self.undefined)
             .finish()
     }
 }
@@ -542,7 +1866,7 @@ impl EngineBoxFn {
     fn discriminant(&self) -> EngineFn {
         match self {
             #[cfg(feature = "filters")]
-            Self::Filter(_) => EngineFn::Filter,
+            Self::Filter(..) => EngineFn::Filter,
             Self::Formatter(_) => EngineFn::Formatter,
         }
     }
@@ -552,7 +1876,7 @@ impl std::fmt::Debug for EngineBoxFn {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let name = match self {
             #[cfg(feature = "filters")]
-            Self::Filter(_) => "Filter",
+            Self::Filter(..) => "Filter",
             Self::Formatter(_) => "Formatter",
         };
         f.debug_tuple(name).finish()
@@ -572,7 +1896,27 @@ impl<'render> Template<'render> {
     where
         S: serde::Serialize,
     {
-        Renderer::with_serde(engine, &self.template, None, ctx)
+        Renderer::with_serde(engine, &self.template, self.name.as_deref(), ctx)
+    }
+
+    /// Render only the named block of the template using the provided
+    /// [`serde`] value.
+    ///
+    /// This is a shorthand for [`render`][Template::render] followed by
+    /// [`with_block`][crate::Renderer::with_block].
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    #[inline]
+    pub fn render_block<S>(
+        &self,
+        engine: &'render Engine<'render>,
+        name: &'render str,
+        ctx: S,
+    ) -> Renderer<'_>
+    where
+        S: serde::Serialize,
+    {
+        self.render(engine, ctx).with_block(name)
     }
 
     /// Render the template using the provided value.
@@ -586,20 +1930,90 @@ impl<'render> Template<'render> {
         engine: &'render Engine<'render>,
         ctx: &'render Value,
     ) -> Renderer<'_> {
-        Renderer::with_value(engine, &self.template, None, ctx)
+        Renderer::with_value(engine, &self.template, self.name.as_deref(), ctx)
+    }
+
+    /// Render the template using the provided owned or borrowed value.
+    ///
+    /// This accepts anything that implements [`IntoValue`], so both a
+    /// [`Value`] and a `&Value` can be passed. A borrowed value is never
+    /// cloned. Other [`serde`] values can be passed by wrapping them in
+    /// [`Serialized`].
+    ///
+    /// The returned struct must be consumed using
+    /// [`.to_string()`][crate::Renderer::to_string] or
+    /// [`.to_writer(..)`][crate::Renderer::to_writer].
+    #[inline]
+    pub fn render_value<V>(&self, engine: &'render Engine<'render>, ctx: V) -> Renderer<'_>
+    where
+        V: IntoValue<'render>,
+    {
+        Renderer::with_cow(
+            engine,
+            &self.template,
+            self.name.as_deref(),
+            ctx.into_value(),
+        )
     }
 
     /// Render the using the provided value function.
     ///
+    /// The function is called for each variable that is looked up. Since it
+    /// can be [`FnMut`] it may cache values that are expensive to fetch. It
+    /// can return a [`String`] or a [`ValueFnError`] as the error.
+    ///
+    /// The returned struct must be consumed using
+    /// [`.to_string()`][crate::Renderer::to_string] or
+    /// [`.to_writer(..)`][crate::Renderer::to_writer].
+    #[inline]
+    pub fn render_from_fn<F, E>(
+        &self,
+        engine: &'render Engine<'render>,
+        mut value_fn: F,
+    ) -> Renderer<'_>
+    where
+        F: FnMut(&[ValueMember<'_>]) -> std::result::Result<Value, E> + 'render,
+        E: Into<ValueFnError>,
+    {
+        let value_fn = move |path: &[ValueMember<'_>]| match value_fn(path) {
+            Ok(value) => Ok(Cow::Owned(value)),
+            Err(err) => Err(err.into()),
+        };
+        Renderer::with_value_fn(
+            engine,
+            &self.template,
+            self.name.as_deref(),
+            Box::new(value_fn),
+        )
+    }
+
+    /// Render the using the provided value function that can return borrowed
+    /// values.
+    ///
+    /// This is the same as [`render_from_fn`][Template::render_from_fn] except
+    /// that the function returns a [`Cow`], which avoids cloning values that
+    /// can be lent from a longer lived store.
+    ///
     /// The returned struct must be consumed using
     /// [`.to_string()`][crate::Renderer::to_string] or
     /// [`.to_writer(..)`][crate::Renderer::to_writer].
     #[inline]
-    pub fn render_from_fn<F>(&self, engine: &'render Engine<'render>, value_fn: F) -> Renderer<'_>
+    pub fn render_from_cow_fn<F, E>(
+        &self,
+        engine: &'render Engine<'render>,
+        mut value_fn: F,
+    ) -> Renderer<'_>
     where
-        F: Fn(&[ValueMember<'_>]) -> std::result::Result<Value, String> + 'render,
+        F: FnMut(&[ValueMember<'_>]) -> std::result::Result<Cow<'render, Value>, E> + 'render,
+        E: Into<ValueFnError>,
     {
-        Renderer::with_value_fn(engine, &self.template, None, Box::new(value_fn))
+        let value_fn = move |path: &[ValueMember<'_>]| value_fn(path).map_err(Into::into);
+        Renderer::with_value_fn(
+            engine,
+            &self.template,
+            self.name.as_deref(),
+            Box::new(value_fn),
+        )
     }
 
     /// Returns the original template source.
@@ -607,6 +2021,130 @@ impl<'render> Template<'render> {
     pub fn source(&self) -> &str {
         &self.template.source
     }
+
+    /// Returns the name given to [`compile_named`][Engine::compile_named], if
+    /// any.
+    #[inline]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns a human readable listing of the compiled instructions.
+    ///
+    /// This is purely a diagnostic aid for debugging template logic or
+    /// reporting bugs. The output format is not stable and may change in any
+    /// release.
+    #[inline]
+    pub fn disassemble(&self) -> String {
+        self.template.disassemble()
+    }
+
+    /// Converts the compiled template to bytes.
+    ///
+    /// The bytes can be loaded using [`Template::from_bytes`] or
+    /// [`Engine::load_template`] by the same version of this crate.
+    #[cfg(feature = "precompile")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "precompile")))]
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        types::precompile::to_bytes(&self.template)
+    }
+
+    /// Returns the paths of the variables that this template reads from the
+    /// render context.
+    ///
+    /// Each path is only returned once, in the order it first appears in the
+    /// template. Variables that are defined by the template itself, such as
+    /// `for` loop variables, `with` names and the special `loop` variable, are
+    /// not included. Neither are variables used by included templates.
+    #[inline]
+    pub fn variables(&self) -> Vec<Vec<ValueMember<'_>>> {
+        self.template.variables()
+    }
+
+    /// Checks that every variable this template reads from the render context
+    /// is one of the given top-level names.
+    ///
+    /// On failure the unknown top-level names are returned, each only once, in
+    /// the order they first appear in the template. Variables are found in the
+    /// same way as [`variables`][Self::variables], so names defined by the
+    /// template itself are never unknown. This is useful for catching drift
+    /// between templates and the data passed to them in tests or CI.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("{% for item in items %}{{ item }} {{ user.name }}{% endfor %}")?;
+    /// assert_eq!(template.check_against(&["items", "user"]), Ok(()));
+    /// assert_eq!(template.check_against(&["items"]), Err(vec![String::from("user")]));
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn check_against(&self, available: &[&str]) -> std::result::Result<(), Vec<String>> {
+        self.template.check_against(available)
+    }
+
+    /// Returns the number of compiled instructions in this template.
+    ///
+    /// This is a rough measure of the size of the template. See also
+    /// [`stats`][Self::stats].
+    #[inline]
+    pub fn instr_count(&self) -> usize {
+        self.template.instrs.len()
+    }
+
+    /// Returns statistics about this template.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("{% for user in users %}{{ user.name | upper }}{% endfor %}")?;
+    /// let stats = template.stats();
+    /// assert_eq!(stats.loops, 1);
+    /// assert_eq!(stats.exprs, 2);
+    /// assert_eq!(stats.calls, 1);
+    /// assert_eq!(stats.instrs, template.instr_count());
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn stats(&self) -> TemplateStats {
+        self.template.stats()
+    }
+}
+
+#[cfg(feature = "precompile")]
+#[cfg_attr(docsrs, doc(cfg(feature = "precompile")))]
+impl Template<'static> {
+    /// Loads a compiled template from bytes returned by
+    /// [`to_bytes`][Template::to_bytes].
+    ///
+    /// # Errors
+    ///
+    /// If the bytes are corrupted or were created by an incompatible version
+    /// of this crate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let bytes = engine.compile("Hello {{ user.name }}!")?.to_bytes();
+    ///
+    /// let template = upon::Template::from_bytes(&bytes)?;
+    /// let result = template
+    ///     .render(&engine, upon::value!{ user: { name: "John Smith" }})
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John Smith!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let template = types::precompile::from_bytes(bytes)?;
+        Ok(Self {
+            template: Arc::new(template),
+            name: None,
+        })
+    }
 }
 
 impl std::fmt::Debug for Template<'_> {
@@ -614,6 +2152,7 @@ impl std::fmt::Debug for Template<'_> {
         f.debug_struct("Template")
             .field("engine", &(..))
             .field("template", &self.template)
+            .field("name", &self.name)
             .finish()
     }
 }
@@ -634,6 +2173,41 @@ impl<'render> TemplateRef<'render> {
         Renderer::with_serde(self.engine, self.template, Some(self.name), ctx)
     }
 
+    /// Render only the named block of the template using the provided
+    /// [`serde`] value.
+    ///
+    /// This is a shorthand for [`render`][TemplateRef::render] followed by
+    /// [`with_block`][crate::Renderer::with_block].
+    ///
+    /// # Examples
+    ///
+    /// Render the content of a page without the surrounding layout.
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_template("base", "<main>{% block content %}{% endblock %}</main>")?;
+    /// engine.add_template(
+    ///     "page",
+    ///     r#"{% extends "base" %}{% block content %}Hello {{ name }}!{% endblock %}"#,
+    /// )?;
+    ///
+    /// let result = engine
+    ///     .template("page")
+    ///     .render_block("content", upon::value! { name: "John" })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    #[inline]
+    pub fn render_block<S>(&self, name: &'render str, ctx: S) -> Renderer<'_>
+    where
+        S: serde::Serialize,
+    {
+        self.render(ctx).with_block(name)
+    }
+
     /// Render the template using the provided value.
     ///
     /// The returned struct must be consumed using
@@ -644,16 +2218,71 @@ impl<'render> TemplateRef<'render> {
         Renderer::with_value(self.engine, self.template, Some(self.name), ctx)
     }
 
+    /// Render the template using the provided owned or borrowed value.
+    ///
+    /// This accepts anything that implements [`IntoValue`], so both a
+    /// [`Value`] and a `&Value` can be passed. A borrowed value is never
+    /// cloned. Other [`serde`] values can be passed by wrapping them in
+    /// [`Serialized`].
+    ///
+    /// The returned struct must be consumed using
+    /// [`.to_string()`][crate::Renderer::to_string] or
+    /// [`.to_writer(..)`][crate::Renderer::to_writer].
+    #[inline]
+    pub fn render_value<V>(&self, ctx: V) -> Renderer<'render>
+    where
+        V: IntoValue<'render>,
+    {
+        Renderer::with_cow(
+            self.engine,
+            self.template,
+            Some(self.name),
+            ctx.into_value(),
+        )
+    }
+
     /// Render the using the provided value function.
     ///
+    /// See [`Template::render_from_fn`] for more details.
+    ///
+    /// The returned struct must be consumed using
+    /// [`.to_string()`][crate::Renderer::to_string] or
+    /// [`.to_writer(..)`][crate::Renderer::to_writer].
+    #[inline]
+    pub fn render_from_fn<F, E>(&self, mut value_fn: F) -> Renderer<'render>
+    where
+        F: FnMut(&[ValueMember<'_>]) -> std::result::Result<Value, E> + 'render,
+        E: Into<ValueFnError>,
+    {
+        let value_fn = move |path: &[ValueMember<'_>]| match value_fn(path) {
+            Ok(value) => Ok(Cow::Owned(value)),
+            Err(err) => Err(err.into()),
+        };
+        Renderer::with_value_fn(
+            self.engine,
+            self.template,
+            Some(self.name),
+            Box::new(value_fn),
+        )
+    }
+
+    /// Render the using the provided value function that can return borrowed
+    /// values.
+    ///
+    /// This is the same as [`render_from_fn`][TemplateRef::render_from_fn]
+    /// except that the function returns a [`Cow`], which avoids cloning values
+    /// that can be lent from a longer lived store.
+    ///
     /// The returned struct must be consumed using
     /// [`.to_string()`][crate::Renderer::to_string] or
     /// [`.to_writer(..)`][crate::Renderer::to_writer].
     #[inline]
-    pub fn render_from_fn<F>(&self, value_fn: F) -> Renderer<'render>
+    pub fn render_from_cow_fn<F, E>(&self, mut value_fn: F) -> Renderer<'render>
     where
-        F: Fn(&[ValueMember<'_>]) -> std::result::Result<Value, String> + 'render,
+        F: FnMut(&[ValueMember<'_>]) -> std::result::Result<Cow<'render, Value>, E> + 'render,
+        E: Into<ValueFnError>,
     {
+        let value_fn = move |path: &[ValueMember<'_>]| value_fn(path).map_err(Into::into);
         Renderer::with_value_fn(
             self.engine,
             self.template,
@@ -662,11 +2291,101 @@ impl<'render> TemplateRef<'render> {
         )
     }
 
+    /// Returns the name of the template.
+    #[inline]
+    pub fn name(&self) -> &'render str {
+        self.name
+    }
+
     /// Returns the original template source.
     #[inline]
     pub fn source(&self) -> &'render str {
         &self.template.source
     }
+
+    /// Returns a human readable listing of the compiled instructions.
+    ///
+    /// This is purely a diagnostic aid for debugging template logic or
+    /// reporting bugs. The output format is not stable and may change in any
+    /// release.
+    #[inline]
+    pub fn disassemble(&self) -> String {
+        self.template.disassemble()
+    }
+
+    /// Converts the compiled template to bytes.
+    ///
+    /// The bytes can be loaded using [`Template::from_bytes`] or
+    /// [`Engine::load_template`] by the same version of this crate.
+    #[cfg(feature = "precompile")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "precompile")))]
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        types::precompile::to_bytes(self.template)
+    }
+
+    /// Returns the paths of the variables that this template reads from the
+    /// render context.
+    ///
+    /// Each path is only returned once, in the order it first appears in the
+    /// template. Variables that are defined by the template itself, such as
+    /// `for` loop variables, `with` names and the special `loop` variable, are
+    /// not included. Neither are variables used by included templates.
+    #[inline]
+    pub fn variables(&self) -> Vec<Vec<ValueMember<'_>>> {
+        self.template.variables()
+    }
+
+    /// Checks that every variable this template reads from the render context
+    /// is one of the given top-level names.
+    ///
+    /// On failure the unknown top-level names are returned, each only once, in
+    /// the order they first appear in the template. Variables are found in the
+    /// same way as [`variables`][Self::variables], so names defined by the
+    /// template itself are never unknown. This is useful for catching drift
+    /// between templates and the data passed to them in tests or CI.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("{% for item in items %}{{ item }} {{ user.name }}{% endfor %}")?;
+    /// assert_eq!(template.check_against(&["items", "user"]), Ok(()));
+    /// assert_eq!(template.check_against(&["items"]), Err(vec![String::from("user")]));
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn check_against(&self, available: &[&str]) -> std::result::Result<(), Vec<String>> {
+        self.template.check_against(available)
+    }
+
+    /// Returns the number of compiled instructions in this template.
+    ///
+    /// This is a rough measure of the size of the template. See also
+    /// [`stats`][Self::stats].
+    #[inline]
+    pub fn instr_count(&self) -> usize {
+        self.template.instrs.len()
+    }
+
+    /// Returns statistics about this template.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("{% for user in users %}{{ user.name | upper }}{% endfor %}")?;
+    /// let stats = template.stats();
+    /// assert_eq!(stats.loops, 1);
+    /// assert_eq!(stats.exprs, 2);
+    /// assert_eq!(stats.calls, 1);
+    /// assert_eq!(stats.instrs, template.instr_count());
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn stats(&self) -> TemplateStats {
+        self.template.stats()
+    }
 }
 
 impl std::fmt::Debug for TemplateRef<'_> {
@@ -40,13 +40,37 @@
 ///     address: addr,
 /// };
 /// ```
+///
+/// This includes existing [`Value`][crate::Value]s, nested `value!`
+/// invocations and arbitrary expressions.
+///
+/// ```
+/// let user = upon::value!{ name: "John Smith" };
+/// let count = 41;
+///
+/// let v = upon::value!{
+///     user: user,
+///     count: count + 1,
+///     meta: upon::value!{ admin: true },
+/// };
+/// ```
+///
+/// Lists and maps written using `[...]` and `{...}` are always parsed as part
+/// of the macro, and interpolation applies to each of their elements and
+/// values at any level of nesting. An expression that evaluates to a list or
+/// map, like the `names` variable above, is converted as a whole.
+///
+/// # Panics
+///
+/// If converting an interpolated expression to a [`Value`][crate::Value]
+/// fails. Use [`try_value!`][crate::try_value] to handle the error instead.
 #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
 #[macro_export]
 macro_rules! value {
     ( $($tt:tt)+ ) => {
         $crate::Value::Map({
             let mut map = ::std::collections::BTreeMap::new();
-            $crate::_value!(@map map () ($($tt)+) ($($tt)+));
+            $crate::_value!([unwrap] @map map () ($($tt)+) ($($tt)+));
             map
         })
     };
@@ -56,6 +80,55 @@ macro_rules! value {
     }
 }
 
+/// Like [`value!`][crate::value] but returns a
+/// [`Result<Value>`][crate::Result] instead of panicking if converting an
+/// interpolated expression to a [`Value`][crate::Value] fails.
+///
+/// The `?` operator can be used in interpolated expressions, any error is
+/// returned from the macro so it must be convertible into an
+/// [`Error`][crate::Error].
+///
+/// # Examples
+///
+/// ```
+/// fn compute_name() -> upon::Result<String> {
+///     Ok(String::from("John Smith"))
+/// }
+///
+/// # fn main() -> upon::Result<()> {
+/// let items = vec![1, 2, 3];
+///
+/// let v = upon::try_value!{
+///     items: items,
+///     user: { name: compute_name()? },
+/// }?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Conversion fails for values that can't be represented as a
+/// [`Value`][crate::Value], like integers that are out of range.
+///
+/// ```
+/// let result = upon::try_value!{ count: u64::MAX };
+/// assert!(result.is_err());
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+#[macro_export]
+macro_rules! try_value {
+    ( $($tt:tt)+ ) => {
+        (|| -> $crate::Result<$crate::Value> {
+            let mut map = ::std::collections::BTreeMap::new();
+            $crate::_value!([fallible] @map map () ($($tt)+) ($($tt)+));
+            ::std::result::Result::Ok($crate::Value::Map(map))
+        })()
+    };
+
+    () => {
+        $crate::Result::Ok($crate::Value::Map(::std::collections::BTreeMap::new()))
+    }
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _value {
@@ -63,51 +136,51 @@ macro_rules! _value {
     // TT muncher for parsing the inside of a list [...]. Produces a List of
     // the elements.
     //
-    // Must be invoked as: value!(@list [] $($tt)*)
+    // Must be invoked as: _value!([$mode] @list [] $($tt)*)
     //////////////////////////////////////////////////////////////////////////
 
     // Done with trailing comma.
-    (@list [$($elems:expr,)*]) => {
+    ([$mode:ident] @list [$($elems:expr,)*]) => {
         $crate::_vec![$($elems,)*]
     };
 
     // Done without trailing comma.
-    (@list [$($elems:expr),*]) => {
+    ([$mode:ident] @list [$($elems:expr),*]) => {
         $crate::_vec![$($elems),*]
     };
 
     // Next element is `None`.
-    (@list [$($elems:expr,)*] None $($rest:tt)*) => {
-        $crate::_value!(@list [$($elems,)* $crate::_value!(None)] $($rest)*)
+    ([$mode:ident] @list [$($elems:expr,)*] None $($rest:tt)*) => {
+        $crate::_value!([$mode] @list [$($elems,)* $crate::_value!([$mode] None)] $($rest)*)
     };
 
     // Next element is an array.
-    (@list [$($elems:expr,)*] [$($array:tt)*] $($rest:tt)*) => {
-        $crate::_value!(@list [$($elems,)* $crate::_value!([$($array)*])] $($rest)*)
+    ([$mode:ident] @list [$($elems:expr,)*] [$($array:tt)*] $($rest:tt)*) => {
+        $crate::_value!([$mode] @list [$($elems,)* $crate::_value!([$mode] [$($array)*])] $($rest)*)
     };
 
     // Next element is a map.
-    (@list [$($elems:expr,)*] {$($map:tt)*} $($rest:tt)*) => {
-        $crate::_value!(@list [$($elems,)* $crate::_value!({$($map)*})] $($rest)*)
+    ([$mode:ident] @list [$($elems:expr,)*] {$($map:tt)*} $($rest:tt)*) => {
+        $crate::_value!([$mode] @list [$($elems,)* $crate::_value!([$mode] {$($map)*})] $($rest)*)
     };
 
     // Next element is an expression followed by comma.
-    (@list [$($elems:expr,)*] $next:expr, $($rest:tt)*) => {
-        $crate::_value!(@list [$($elems,)* $crate::_value!($next),] $($rest)*)
+    ([$mode:ident] @list [$($elems:expr,)*] $next:expr, $($rest:tt)*) => {
+        $crate::_value!([$mode] @list [$($elems,)* $crate::_value!([$mode] $next),] $($rest)*)
     };
 
     // Last element is an expression with no trailing comma.
-    (@list [$($elems:expr,)*] $last:expr) => {
-        $crate::_value!(@list [$($elems,)* $crate::_value!($last)])
+    ([$mode:ident] @list [$($elems:expr,)*] $last:expr) => {
+        $crate::_value!([$mode] @list [$($elems,)* $crate::_value!([$mode] $last)])
     };
 
     // Comma after the most recent element.
-    (@list [$($elems:expr),*] , $($rest:tt)*) => {
-        $crate::_value!(@list [$($elems,)*] $($rest)*)
+    ([$mode:ident] @list [$($elems:expr),*] , $($rest:tt)*) => {
+        $crate::_value!([$mode] @list [$($elems,)*] $($rest)*)
     };
 
     // Unexpected token after most recent element.
-    (@list [$($elems:expr),*] $unexpected:tt $($rest:tt)*) => {
+    ([$mode:ident] @list [$($elems:expr),*] $unexpected:tt $($rest:tt)*) => {
         $crate::_value_unexpected!($unexpected)
     };
 
@@ -115,122 +188,129 @@ macro_rules! _value {
     // TT muncher for parsing the inside of a map {...}. Each entry is
     // inserted into the given map variable.
     //
-    // Must be invoked as: value!(@map $map () ($($tt)*) ($($tt)*))
+    // Must be invoked as: _value!([$mode] @map $map () ($($tt)*) ($($tt)*))
     //
     // We require two copies of the input tokens so that we can match on one
     // copy and trigger errors on the other copy.
     //////////////////////////////////////////////////////////////////////////
 
     // Done.
-    (@map $map:ident () () ()) => {};
+    ([$mode:ident] @map $map:ident () () ()) => {};
 
     // Insert the current entry followed by trailing comma.
-    (@map $map:ident [$key:ident] ($value:expr) , $($rest:tt)*) => {
+    ([$mode:ident] @map $map:ident [$key:ident] ($value:expr) , $($rest:tt)*) => {
         let _ = $map.insert(stringify!($key).into(), $value);
-        $crate::_value!(@map $map () ($($rest)*) ($($rest)*));
+        $crate::_value!([$mode] @map $map () ($($rest)*) ($($rest)*));
     };
 
     // Current entry followed by unexpected token.
-    (@map $map:ident [$key:ident] ($value:expr) $unexpected:tt $($rest:tt)*) => {
+    ([$mode:ident] @map $map:ident [$key:ident] ($value:expr) $unexpected:tt $($rest:tt)*) => {
         $crate::_value_unexpected!($unexpected);
     };
 
     // Insert the last entry without trailing comma.
-    (@map $map:ident [$key:ident] ($value:expr)) => {
+    ([$mode:ident] @map $map:ident [$key:ident] ($value:expr)) => {
         let _ = $map.insert(stringify!($key).into(), $value);
     };
 
     // Next value is `None`.
-    (@map $map:ident ($key:ident) (: None $($rest:tt)*) $copy:tt) => {
-        $crate::_value!(@map $map [$key] ($crate::_value!(None)) $($rest)*);
+    ([$mode:ident] @map $map:ident ($key:ident) (: None $($rest:tt)*) $copy:tt) => {
+        $crate::_value!([$mode] @map $map [$key] ($crate::_value!([$mode] None)) $($rest)*);
     };
 
     // Next value is an array.
-    (@map $map:ident ($key:ident) (: [$($array:tt)*] $($rest:tt)*) $copy:tt) => {
-        $crate::_value!(@map $map [$key] ($crate::_value!([$($array)*])) $($rest)*);
+    ([$mode:ident] @map $map:ident ($key:ident) (: [$($array:tt)*] $($rest:tt)*) $copy:tt) => {
+        $crate::_value!([$mode] @map $map [$key] ($crate::_value!([$mode] [$($array)*])) $($rest)*);
     };
 
     // Next value is a map.
-    (@map $map:ident ($key:ident) (: {$($mapping:tt)*} $($rest:tt)*) $copy:tt) => {
-        $crate::_value!(@map $map [$key] ($crate::_value!({$($mapping)*})) $($rest)*);
+    ([$mode:ident] @map $map:ident ($key:ident) (: {$($mapping:tt)*} $($rest:tt)*) $copy:tt) => {
+        $crate::_value!([$mode] @map $map [$key] ($crate::_value!([$mode] {$($mapping)*})) $($rest)*);
     };
 
     // Next value is an ident followed by comma.
-    (@map $map:ident ($key:ident) (: $value:expr , $($rest:tt)*) $copy:tt) => {
-        $crate::_value!(@map $map [$key] ($crate::_value!($value)) , $($rest)*);
+    ([$mode:ident] @map $map:ident ($key:ident) (: $value:expr , $($rest:tt)*) $copy:tt) => {
+        $crate::_value!([$mode] @map $map [$key] ($crate::_value!([$mode] $value)) , $($rest)*);
     };
 
     // Last value is an ident with no trailing comma.
-    (@map $map:ident ($key:ident) (: $value:expr) $copy:tt) => {
-        $crate::_value!(@map $map [$key] ($crate::_value!($value)));
+    ([$mode:ident] @map $map:ident ($key:ident) (: $value:expr) $copy:tt) => {
+        $crate::_value!([$mode] @map $map [$key] ($crate::_value!([$mode] $value)));
     };
 
     // Missing value for last entry.
     // Trigger a reasonable error message.
-    (@map $map:ident ($key:ident) (:) $copy:tt) => {
+    ([$mode:ident] @map $map:ident ($key:ident) (:) $copy:tt) => {
         // "unexpected end of macro invocation"
         $crate::_value!();
     };
 
     // Missing colon and value for last entry.
     // Trigger a reasonable error message.
-    (@map $map:ident ($key:ident) () $copy:tt) => {
+    ([$mode:ident] @map $map:ident ($key:ident) () $copy:tt) => {
         // "unexpected end of macro invocation"
         $crate::_value!();
     };
 
     // Misplaced colon. Trigger a reasonable error message.
-    (@map $map:ident () (: $($rest:tt)*) ($colon:tt $($copy:tt)*)) => {
+    ([$mode:ident] @map $map:ident () (: $($rest:tt)*) ($colon:tt $($copy:tt)*)) => {
         // Takes no arguments so "no rules expected the token `:`".
         $crate::_value_unexpected!($colon);
     };
 
     // Take an ident for the current key.
-    (@map $map:ident () ($key:ident $($rest:tt)*) $copy:tt) => {
-        $crate::_value!(@map $map ($key) ($($rest)*) ($($rest)*));
+    ([$mode:ident] @map $map:ident () ($key:ident $($rest:tt)*) $copy:tt) => {
+        $crate::_value!([$mode] @map $map ($key) ($($rest)*) ($($rest)*));
     };
 
     //////////////////////////////////////////////////////////////////////////
     // The main implementation.
     //
-    // Must be invoked as: value!($($value)+)
+    // Must be invoked as: _value!([$mode] $($value)+)
+    //
+    // The mode is either `unwrap` or `fallible` and determines how errors
+    // converting interpolated expressions are handled.
     //////////////////////////////////////////////////////////////////////////
-    (None) => {
+    ([$mode:ident] None) => {
         $crate::Value::None
     };
 
-    (false) => {
+    ([$mode:ident] false) => {
         $crate::Value::Bool(false)
     };
 
-    (true) => {
-        $crate::Value::Bool(false)
+    ([$mode:ident] true) => {
+        $crate::Value::Bool(true)
     };
 
-    ([]) => {
+    ([$mode:ident] []) => {
         $crate::Value::List($crate::_vec![])
     };
 
-    ([ $($tt:tt)+ ]) => {
-        $crate::Value::List($crate::_value!(@list [] $($tt)+))
+    ([$mode:ident] [ $($tt:tt)+ ]) => {
+        $crate::Value::List($crate::_value!([$mode] @list [] $($tt)+))
     };
 
-    ({}) => {
+    ([$mode:ident] {}) => {
         $crate::Value::Map(::std::collections::BTreeMap::new())
     };
 
-    ({ $($tt:tt)+ }) => {
+    ([$mode:ident] { $($tt:tt)+ }) => {
         $crate::Value::Map({
             let mut map = ::std::collections::BTreeMap::new();
-            $crate::_value!(@map map () ($($tt)+) ($($tt)+));
+            $crate::_value!([$mode] @map map () ($($tt)+) ($($tt)+));
             map
         })
     };
 
     // Default to `Serialize` implementation.
-    ($other:expr) => {
+    ([unwrap] $other:expr) => {
         $crate::to_value($other).unwrap()
     };
+
+    ([fallible] $other:expr) => {
+        $crate::to_value($other)?
+    };
 }
 
 // The `value!` macro above cannot invoke vec directly because it uses
@@ -1,18 +1,37 @@
+use std::borrow::Cow;
 use std::fmt::Write;
+use std::mem;
+use std::rc::Rc;
+#[cfg(feature = "filters")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 
-use crate::fmt::Formatter;
+#[cfg(feature = "filters")]
+use crate::filters::RenderContext;
+use crate::fmt::{FormatFn, Formatter, Handled};
 use crate::render::iter::LoopState;
+#[cfg(feature = "filters")]
+use crate::render::iter::ValueIter;
 use crate::render::stack::{Stack, State};
 use crate::render::RendererInner;
 use crate::types::ast;
 use crate::types::program::{Instr, Template};
+use crate::types::span::Span;
 use crate::value::ValueCow;
-use crate::{EngineBoxFn, Error, Result};
+use crate::{EngineBoxFn, Error, Result, Undefined, Value};
 
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct RendererImpl<'render, 'stack> {
     pub(crate) inner: RendererInner<'render>,
     pub(crate) stack: Stack<'stack>,
+    /// The escaping mode set by each enclosing `autoescape` block.
+    pub(crate) autoescape: Vec<bool>,
+    /// The scratch space shared by all filters during this render.
+    #[cfg(feature = "filters")]
+    pub(crate) context: RenderContext,
+    /// Whether to hand control back to the caller after each instruction that
+    /// emits output, used when rendering in chunks.
+    pub(crate) chunked: bool,
 }
 
 #[cfg(feature = "filters")]
@@ -22,89 +41,322 @@ pub struct FilterState<'a> {
     pub source: &'a str,
     pub filter: &'a ast::Ident,
     pub value: &'a mut ValueCow<'a>,
+    /// The span of the value the filter is applied to.
+    pub span: Span,
     pub args: &'a [ast::BaseExpr],
+    pub named_args: &'a [ast::NamedArg],
+    pub context: &'a mut RenderContext,
+}
+
+/// The result of applying a filter.
+#[cfg(feature = "filters")]
+#[cfg_attr(internal_debug, derive(Debug))]
+pub enum FilterOutput {
+    Value(Value),
+    Iter(ValueIter),
+}
+
+#[cfg(feature = "filters")]
+impl FilterOutput {
+    /// Converts the output to a value, collecting any iterator into a list.
+    ///
+    /// An iterator that reports that it never ends is an error, any other
+    /// iterator is collected while checking the cancel flag so that a very
+    /// long one can still be stopped.
+    fn into_value(self, cancel: Option<&AtomicBool>, source: &str, span: Span) -> Result<Value> {
+        let iter = match self {
+            Self::Value(value) => return Ok(value),
+            Self::Iter(iter) => iter,
+        };
+        if iter.size_hint().0 == usize::MAX {
+            return Err(Error::render(
+                "cannot collect an unbounded lazy iterator into a list",
+                source,
+                span,
+            ));
+        }
+        let mut list = Vec::new();
+        for item in iter {
+            if cancel.map_or(false, |c| c.load(Ordering::Relaxed)) {
+                return Err(Error::cancelled());
+            }
+            list.push(item);
+        }
+        Ok(Value::List(list))
+    }
+}
+
+/// A template, or a block in a template, that is currently being rendered.
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Frame<'render, 'stack> {
+    template: &'render Template<'render>,
+    /// The name of the template, used to enrich errors.
+    name: Option<Cow<'stack, str>>,
+    /// The index of the next instruction.
+    pc: usize,
+    /// The index of the instruction to stop at, this is the end of the block
+    /// when rendering an overridden block.
+    end: usize,
+    /// Whether a scope was pushed to the stack for this template.
+    has_scope: bool,
+    /// The templates that extend this one, most derived first.
+    children: Rc<[Child<'render, 'stack>]>,
 }
 
+/// A template that extends another along with its name.
+type Child<'render, 'stack> = (&'render Template<'render>, Option<Cow<'stack, str>>);
+
 #[cfg_attr(internal_debug, derive(Debug))]
 enum RenderState<'render, 'stack> {
     Done,
+    Yield,
+    Block {
+        template: &'render Template<'render>,
+        name: Option<Cow<'stack, str>>,
+        pc: usize,
+        end: usize,
+    },
     Include {
-        template_name: &'render ast::String,
+        template_name: Cow<'stack, str>,
+        span: Span,
+        ignore_missing: bool,
     },
     IncludeWith {
-        template_name: &'render ast::String,
+        template_name: Cow<'stack, str>,
+        span: Span,
+        ignore_missing: bool,
         globals: ValueCow<'stack>,
     },
 }
 
+impl Frame<'_, '_> {
+    /// Adds the name of the template to the error, if it has one.
+    fn enrich(&self, err: Error) -> Error {
+        match &self.name {
+            Some(s) => err.with_template_name(s.to_string()),
+            None => err,
+        }
+    }
+}
+
 impl<'render, 'stack> RendererImpl<'render, 'stack>
 where
     'render: 'stack,
 {
     pub(crate) fn render(mut self, f: &mut Formatter<'_>) -> Result<()> {
-        let mut templates = vec![(self.inner.template, self.inner.template_name, 0, false)];
-
-        let max_include_depth = self
-            .inner
-            .max_include_depth
-            .unwrap_or(self.inner.engine.max_include_depth);
+        let mut templates = self.start()?;
+        while !templates.is_empty() {
+            self.step(f, &mut templates)?;
+        }
+        Ok(())
+    }
 
-        while let Some((t, tname, pc, has_scope)) = templates.last_mut() {
-            let state = self.render_one(f, t, pc).map_err(|e| match tname {
+    /// Returns the initial stack of templates to render.
+    pub(crate) fn start(&mut self) -> Result<Vec<Frame<'render, 'stack>>> {
+        let template = self.inner.template;
+        let name = self.inner.template_name.map(Cow::Borrowed);
+        let mut frame = self.frame(template, name, 0, self.max_include_depth())?;
+        if let Some(block) = self.inner.block {
+            select_block(&mut frame, block).map_err(|e| match self.inner.template_name {
                 Some(s) => e.with_template_name(s.to_owned()),
                 None => e,
             })?;
-            match state {
-                RenderState::Done => {
-                    if *has_scope {
-                        self.stack.pop_scope();
-                        self.stack.pop_boundary();
-                    }
-                    templates.pop();
-                }
-                RenderState::Include { template_name } => {
-                    let template =
-                        self.get_template(&t.source, template_name)
-                            .map_err(|e| match tname {
-                                Some(s) => e.with_template_name(s.to_owned()),
-                                None => e,
-                            })?;
-                    templates.push((template, Some(template_name.as_str()), 0, false));
-                }
-                RenderState::IncludeWith {
-                    template_name,
-                    globals,
-                } => {
-                    let template =
-                        self.get_template(&t.source, template_name)
-                            .map_err(|e| match tname {
-                                Some(s) => e.with_template_name(s.to_owned()),
-                                None => e,
-                            })?;
-                    self.stack.push(State::Boundary);
-                    self.stack.push(State::Scope(globals));
-                    templates.push((template, Some(template_name.as_str()), 0, true));
+        }
+        let templates = vec![frame];
+        self.update_template_vars(&templates);
+        Ok(templates)
+    }
+
+    /// Updates the variables that describe the templates being rendered, if
+    /// they are enabled.
+    fn update_template_vars(&mut self, templates: &[Frame<'render, 'stack>]) {
+        if !self.inner.template_vars {
+            return;
+        }
+        let names: Vec<Value> = templates
+            .iter()
+            .map(|frame| match &frame.name {
+                Some(name) => Value::from(&**name),
+                None => Value::None,
+            })
+            .collect();
+        let current = names.last().cloned().unwrap_or_default();
+        self.stack.set_template_vars(Value::from([
+            ("__template__", current),
+            ("__template_stack__", Value::List(names)),
+        ]));
+    }
+
+    /// Renders the last template on the stack until it is done, hands over to
+    /// another template, or yields after emitting output.
+    pub(crate) fn step(
+        &mut self,
+        f: &mut Formatter<'_>,
+        templates: &mut Vec<Frame<'render, 'stack>>,
+    ) -> Result<()> {
+        let max_include_depth = self.max_include_depth();
+
+        let frame = match templates.last_mut() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        let state = self.render_one(f, frame).map_err(|e| frame.enrich(e))?;
+        match state {
+            RenderState::Done => {
+                if frame.has_scope {
+                    self.stack.pop_scope();
+                    self.stack.pop_boundary();
                 }
+                templates.pop();
+                self.update_template_vars(templates);
             }
-            if templates.len() > max_include_depth {
-                return Err(Error::max_include_depth(max_include_depth));
+            RenderState::Yield => {}
+            RenderState::Block {
+                template,
+                name,
+                pc,
+                end,
+            } => {
+                let children = frame.children.clone();
+                templates.push(Frame {
+                    template,
+                    name,
+                    pc,
+                    end,
+                    has_scope: false,
+                    children,
+                });
+                self.update_template_vars(templates);
+            }
+            RenderState::Include {
+                template_name,
+                span,
+                ignore_missing,
+            } => {
+                let start = self.inner.profile_start();
+                let template =
+                    match self.find_template(&frame.template.source, &template_name, span) {
+                        Ok(Some(template)) => template,
+                        Ok(None) if ignore_missing => return Ok(()),
+                        Ok(None) => {
+                            let err =
+                                Error::render("unknown template", &frame.template.source, span);
+                            return Err(frame.enrich(err));
+                        }
+                        Err(err) => return Err(frame.enrich(err)),
+                    };
+                self.inner.profile_include(&template_name, start);
+                let depth = templates.len();
+                let frame = self.frame(template, Some(template_name), depth, max_include_depth)?;
+                templates.push(frame);
+                self.update_template_vars(templates);
+            }
+            RenderState::IncludeWith {
+                template_name,
+                span,
+                ignore_missing,
+                globals,
+            } => {
+                let start = self.inner.profile_start();
+                let template =
+                    match self.find_template(&frame.template.source, &template_name, span) {
+                        Ok(Some(template)) => template,
+                        Ok(None) if ignore_missing => return Ok(()),
+                        Ok(None) => {
+                            let err =
+                                Error::render("unknown template", &frame.template.source, span);
+                            return Err(frame.enrich(err));
+                        }
+                        Err(err) => return Err(frame.enrich(err)),
+                    };
+                self.inner.profile_include(&template_name, start);
+                let depth = templates.len();
+                let mut frame =
+                    self.frame(template, Some(template_name), depth, max_include_depth)?;
+                frame.has_scope = true;
+                self.stack.push(State::Boundary);
+                self.stack.push(State::Scope(globals));
+                templates.push(frame);
+                self.update_template_vars(templates);
             }
         }
-
+        if templates.len() > max_include_depth {
+            return Err(Error::max_include_depth(max_include_depth));
+        }
         Ok(())
     }
 
+    fn max_include_depth(&self) -> usize {
+        self.inner
+            .max_include_depth
+            .unwrap_or(self.inner.engine.max_include_depth)
+    }
+
+    /// Returns a frame that renders the given template.
+    ///
+    /// If the template extends another template then the base template is
+    /// rendered instead and the chain of templates that extend it is used to
+    /// look up overridden blocks. Each template in the chain counts towards
+    /// the maximum include depth.
+    fn frame(
+        &mut self,
+        mut template: &'render Template<'render>,
+        mut name: Option<Cow<'stack, str>>,
+        depth: usize,
+        max_include_depth: usize,
+    ) -> Result<Frame<'render, 'stack>> {
+        let mut children = Vec::new();
+        while let Some(parent) = &template.extends {
+            let base = self
+                .get_template(&template.source, parent.as_str(), parent.span)
+                .map_err(|e| match &name {
+                    Some(s) => e.with_template_name(s.to_string()),
+                    None => e,
+                })?;
+            children.push((template, name));
+            if depth + children.len() >= max_include_depth {
+                return Err(Error::max_include_depth(max_include_depth));
+            }
+            template = base;
+            name = Some(Cow::Borrowed(parent.as_str()));
+        }
+        Ok(Frame {
+            template,
+            name,
+            pc: 0,
+            end: template.instrs.len(),
+            has_scope: false,
+            children: children.into(),
+        })
+    }
+
     fn render_one(
         &mut self,
         f: &mut Formatter<'_>,
-        t: &'render Template<'render>,
-        pc: &mut usize,
+        frame: &mut Frame<'render, 'stack>,
     ) -> Result<RenderState<'render, 'stack>> {
+        let t = frame.template;
+        let pc = &mut frame.pc;
+
         // An expression that we are building
         let mut expr: Option<ValueCow<'stack>> = None;
+        // The left hand side operands of comparisons
+        let mut operands: Vec<ValueCow<'stack>> = Vec::new();
+        // A lazy iterator returned from a filter that is about to be looped
+        // over, the expression is left as `None` in this case
+        #[cfg(feature = "filters")]
+        let mut lazy: Option<ValueIter> = None;
+        // Whether the expression is an optional access that was not found
+        let mut missing = false;
 
-        while let Some(instr) = t.instrs.get(*pc) {
-            match instr {
+        while *pc < frame.end {
+            if let Some(cancel) = self.inner.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Error::cancelled());
+                }
+            }
+
+            match &t.instrs[*pc] {
                 Instr::Jump(j) => {
                     *pc = *j;
                     continue;
@@ -126,15 +378,23 @@ where
 
                 Instr::Emit(span) => {
                     let value = expr.take().unwrap();
-                    (self.inner.engine.default_formatter)(f, &value)
-                        .map_err(|err| Error::format(err, &t.source, *span))?;
+                    let missing = mem::take(&mut missing) && matches!(*value, Value::None);
+                    match self.inner.missing_placeholder {
+                        Some(placeholder) if missing => f.write_str(placeholder)?,
+                        _ => self
+                            .format(f, &value)
+                            .map_err(|err| Error::format(err, &t.source, *span))?,
+                    }
                 }
 
                 Instr::EmitRaw(span) => {
-                    let raw = &t.source[*span];
                     // We don't need to enrich this error because it can only
                     // fail because of an IO error.
-                    f.write_str(raw)?;
+                    if self.inner.whitespace_trace {
+                        emit_raw_traced(f, t, *span)?;
+                    } else {
+                        f.write_str(&t.source[*span])?;
+                    }
                 }
 
                 Instr::EmitWith(name, _span) => {
@@ -144,18 +404,26 @@ where
                         // it and then emit the value using the default
                         // formatter.
                         #[cfg(feature = "filters")]
-                        Some(EngineBoxFn::Filter(filter)) => {
+                        Some(EngineBoxFn::Filter(filter, _)) => {
                             let mut value = expr.take().unwrap();
+                            let start = self.inner.profile_start();
                             let result = filter(FilterState {
                                 stack: &self.stack,
                                 source: &t.source,
                                 filter: name,
                                 value: &mut value,
+                                span: *_span,
                                 args: &[],
-                            })
-                            .map_err(|err| err.enrich(&t.source, name.span))?;
-                            (self.inner.engine.default_formatter)(f, &result)
-                                .map_err(|err| Error::format(err, &t.source, *_span))?;
+                                named_args: &[],
+                                context: &mut self.context,
+                            });
+                            self.inner.profile_filter(name_raw, start);
+                            let result = result
+                                .map_err(|err| err.enrich(&t.source, name.span))?
+                                .into_value(self.inner.cancel, &t.source, name.span)?;
+                            self.format(f, &result).map_err(|err| {
+                                Error::format(err, &t.source, _span.combine(name.span))
+                            })?;
                         }
                         // The referenced function is a formatter so we simply
                         // emit the value with it.
@@ -175,75 +443,265 @@ where
                     }
                 }
 
-                Instr::LoopStart(vars, span) => {
+                Instr::LoopStart(vars, span, reversed) => {
                     let iterable = expr.take().unwrap();
-                    self.stack.push(State::Loop(LoopState::new(
-                        &t.source, vars, iterable, *span,
+                    #[cfg(feature = "filters")]
+                    let state = match lazy.take() {
+                        Some(iter) => LoopState::lazy(&t.source, vars, iter)?,
+                        None => LoopState::new(&t.source, vars, iterable, *span, *reversed)?,
+                    };
+                    #[cfg(not(feature = "filters"))]
+                    let state = LoopState::new(&t.source, vars, iterable, *span, *reversed)?;
+                    self.stack.push(State::Loop(state));
+                }
+
+                Instr::LoopStartRange(vars, inclusive, spans, reversed) => {
+                    let start = operands.pop().unwrap();
+                    let end = expr.take().unwrap();
+                    self.stack.push(State::Loop(LoopState::range(
+                        &t.source, vars, &start, &end, *inclusive, *spans, *reversed,
                     )?));
                 }
 
-                Instr::LoopNext(j) => {
-                    if self.stack.last_loop_state_mut().iterate().is_none() {
+                Instr::LoopNext(j, k) => {
+                    let state = self.stack.last_loop_state_mut();
+                    let empty = !state.is_started();
+                    if state.iterate(&t.source)?.is_none() {
                         self.stack.pop_loop_state();
-                        *pc = *j;
+                        *pc = match k {
+                            Some(k) if empty => *k,
+                            _ => *j,
+                        };
                         continue;
                     }
                 }
 
+                Instr::LoopBreak(j) => {
+                    self.stack.pop_to_loop_state();
+                    self.stack.pop_loop_state();
+                    *pc = *j;
+                    continue;
+                }
+
+                Instr::LoopContinue(j) => {
+                    self.stack.pop_to_loop_state();
+                    *pc = *j;
+                    continue;
+                }
+
                 Instr::WithStart(name) => {
                     let value = expr.take().unwrap();
-                    self.stack.push(State::Var(name, value))
+                    self.stack.push(State::Var(&t.source[name.span], value))
                 }
 
                 Instr::WithEnd => {
                     self.stack.pop_var();
                 }
 
-                Instr::Include(template_name) => {
+                Instr::AutoescapeStart(enabled) => {
+                    self.autoescape.push(*enabled);
+                }
+
+                Instr::AutoescapeEnd => {
+                    self.autoescape.pop().unwrap();
+                }
+
+                Instr::Include(name, ignore_missing) => {
                     *pc += 1;
-                    return Ok(RenderState::Include { template_name });
+                    let template_name = self.template_name(&t.source, name)?;
+                    return Ok(RenderState::Include {
+                        template_name,
+                        span: name.span(),
+                        ignore_missing: *ignore_missing,
+                    });
                 }
 
-                Instr::IncludeWith(template_name) => {
+                Instr::IncludeWith(name, ignore_missing) => {
                     *pc += 1;
+                    let template_name = self.template_name(&t.source, name)?;
                     let globals = expr.take().unwrap();
                     return Ok(RenderState::IncludeWith {
                         template_name,
+                        span: name.span(),
+                        ignore_missing: *ignore_missing,
                         globals,
                     });
                 }
 
+                Instr::BlockStart(name, j) => {
+                    let name = &t.source[name.span];
+                    let child = frame.children.iter().find_map(|(child, child_name)| {
+                        let (start, end) = child.block(name)?;
+                        Some((*child, child_name, start, end))
+                    });
+                    // Render the most derived override of the block, unless
+                    // that is the block that we are already rendering.
+                    if let Some((child, child_name, start, end)) = child {
+                        if !(std::ptr::eq(child, t) && start == *pc + 1) {
+                            *pc = *j;
+                            return Ok(RenderState::Block {
+                                template: child,
+                                name: child_name.clone(),
+                                pc: start,
+                                end,
+                            });
+                        }
+                    }
+                }
+
+                Instr::IncludeRaw(name) => {
+                    let raw = self.get_raw(&t.source, name)?;
+                    // We don't need to enrich this error because it can only
+                    // fail because of an IO error.
+                    f.write_str(&raw)?;
+                }
+
                 Instr::ExprStart(var) => {
-                    let value = self.stack.lookup_var(&t.source, var)?;
-                    let prev = expr.replace(value);
-                    debug_assert!(prev.is_none());
+                    let value = match self.stack.lookup_var_maybe(&t.source, var)? {
+                        Some(value) => Some(value),
+                        None => match (self.stack.undefined(), t.tag(var.span())) {
+                            // Skip the rest of the expression and emit the tag
+                            // as it was written instead.
+                            (Undefined::Keep, Some(tag)) => {
+                                // A compiled template always emits the
+                                // expression in a tag but a precompiled one
+                                // might not, so don't search past the end.
+                                let emit = t.instrs[*pc..frame.end].iter().position(|instr| {
+                                    matches!(instr, Instr::Emit(_) | Instr::EmitWith(..))
+                                });
+                                match emit {
+                                    Some(i) => *pc += i,
+                                    None => {
+                                        return Err(Error::render(
+                                            "expected expression in tag to be emitted",
+                                            &t.source,
+                                            var.span(),
+                                        ));
+                                    }
+                                }
+                                // Discard any operands from earlier in the
+                                // expression, e.g. `a` in `{{ a ~ b }}`.
+                                operands.clear();
+                                f.write_str(&t.source[tag])?;
+                                None
+                            }
+                            _ => Some(self.stack.undefined_var(&t.source, var)?),
+                        },
+                    };
+                    if let Some(value) = value {
+                        missing = var.is_optional() && matches!(*value, Value::None);
+                        let prev = expr.replace(value);
+                        debug_assert!(prev.is_none());
+                    }
                 }
 
                 Instr::ExprStartLit(value) => {
+                    missing = false;
                     let prev = expr.replace(ValueCow::Owned(value.clone()));
                     debug_assert!(prev.is_none());
                 }
 
-                Instr::Apply(name, _, _args) => {
+                Instr::ExprStartMaybe(var, j) => {
+                    match self.stack.lookup_var_maybe(&t.source, var) {
+                        Ok(Some(value)) => {
+                            missing = false;
+                            let prev = expr.replace(value);
+                            debug_assert!(prev.is_none());
+                        }
+                        Ok(None) | Err(_) => {
+                            *pc = *j;
+                            continue;
+                        }
+                    }
+                }
+
+                Instr::JumpIfNotNone(j) => {
+                    if !matches!(expr.as_deref(), Some(Value::None)) {
+                        *pc = *j;
+                        continue;
+                    }
+                    expr = None;
+                }
+
+                Instr::Test(predicate, span) => {
+                    let value = expr.take().unwrap();
+                    let result = match predicate {
+                        ast::Predicate::Empty => is_empty(&t.source, &value, *span)?,
+                        ast::Predicate::Defined => true,
+                        ast::Predicate::None => matches!(*value, Value::None),
+                        ast::Predicate::String => matches!(*value, Value::String(_)),
+                        ast::Predicate::Number => {
+                            matches!(*value, Value::Integer(_) | Value::Float(_))
+                        }
+                        ast::Predicate::List => matches!(*value, Value::List(_)),
+                        ast::Predicate::Map => matches!(*value, Value::Map(_)),
+                        ast::Predicate::Bool => matches!(*value, Value::Bool(_)),
+                    };
+                    expr.replace(ValueCow::Owned(Value::Bool(result)));
+                }
+
+                Instr::ExprPush => {
+                    operands.push(expr.take().unwrap());
+                }
+
+                Instr::Compare(op, span) => {
+                    let lhs = operands.pop().unwrap();
+                    let rhs = expr.take().unwrap();
+                    let result = compare(&t.source, &lhs, *op, &rhs, *span)?;
+                    expr.replace(ValueCow::Owned(Value::Bool(result)));
+                }
+
+                Instr::Concat(lhs_span, rhs_span) => {
+                    let lhs = operands.pop().unwrap();
+                    let rhs = expr.take().unwrap();
+                    let result = concat(&t.source, lhs, *lhs_span, &rhs, *rhs_span)?;
+                    expr.replace(ValueCow::Owned(Value::String(result)));
+                }
+
+                Instr::Apply(name, _span, _args) => {
                     let name_raw = &t.source[name.span];
                     match self.inner.engine.functions.get(name_raw) {
                         // The referenced function is a filter, so we apply it.
                         #[cfg(feature = "filters")]
-                        Some(EngineBoxFn::Filter(filter)) => {
+                        Some(EngineBoxFn::Filter(filter, _)) => {
+                            missing = false;
                             let mut value = expr.take().unwrap();
-                            let args = _args
+                            let (args, named_args) = _args
                                 .as_ref()
-                                .map(|args| args.values.as_slice())
-                                .unwrap_or(&[]);
+                                .map(|args| (args.values.as_slice(), args.named.as_slice()))
+                                .unwrap_or((&[], &[]));
+                            let start = self.inner.profile_start();
                             let result = filter(FilterState {
                                 stack: &self.stack,
                                 source: &t.source,
                                 filter: name,
                                 value: &mut value,
+                                span: *_span,
                                 args,
-                            })
-                            .map_err(|e| e.enrich(&t.source, name.span))?;
-                            expr.replace(ValueCow::Owned(result));
+                                named_args,
+                                context: &mut self.context,
+                            });
+                            self.inner.profile_filter(name_raw, start);
+                            let result = result.map_err(|e| e.enrich(&t.source, name.span))?;
+                            let value = match result {
+                                // A lazy iterator is only kept if it is
+                                // looped over straight away and not in
+                                // reverse, otherwise it is collected into a
+                                // list.
+                                FilterOutput::Iter(iter)
+                                    if matches!(
+                                        t.instrs.get(*pc + 1),
+                                        Some(Instr::LoopStart(_, _, false))
+                                    ) =>
+                                {
+                                    lazy = Some(iter);
+                                    Value::None
+                                }
+                                result => {
+                                    result.into_value(self.inner.cancel, &t.source, name.span)?
+                                }
+                            };
+                            expr.replace(ValueCow::Owned(value));
                         }
                         // The referenced function is a formatter which is not valid
                         // in the middle of an expression.
@@ -261,28 +719,262 @@ where
                     }
                 }
             }
+            let emitted = matches!(
+                t.instrs[*pc],
+                Instr::Emit(_) | Instr::EmitRaw(_) | Instr::EmitWith(..) | Instr::IncludeRaw(_)
+            );
             *pc += 1;
+            // No expression is being built after emitting, so it is safe to
+            // return here and continue from the next instruction later.
+            if emitted && self.chunked {
+                return Ok(RenderState::Yield);
+            }
         }
 
-        assert!(*pc == t.instrs.len());
+        assert!(*pc == frame.end);
         Ok(RenderState::Done)
     }
 
+    /// Formats a value for an expression that doesn't specify a formatter.
+    ///
+    /// Each formatter in the engine formatter chain is tried first, unless
+    /// within an `{% autoescape off %}` block.
+    fn format(&self, f: &mut Formatter<'_>, value: &Value) -> crate::fmt::Result {
+        if self.autoescape.last() != Some(&false) {
+            for formatter in &self.inner.engine.chained_formatters {
+                if formatter(f, value)? == Handled::Yes {
+                    return Ok(());
+                }
+            }
+        }
+        (self.default_formatter(value))(f, value)
+    }
+
+    /// Returns the formatter used to emit expressions that don't specify one.
+    ///
+    /// Within an `{% autoescape off %}` block this is always [`fmt::default`],
+    /// otherwise it is the formatter configured on the engine for the kind of
+    /// value, falling back to the engine default formatter.
+    ///
+    /// [`fmt::default`]: crate::fmt::default
+    fn default_formatter(&self, value: &Value) -> &'render FormatFn {
+        let engine = self.inner.engine;
+        match self.autoescape.last() {
+            Some(false) => &crate::fmt::default,
+            Some(true) | None => match engine.type_formatters.get(&value.kind()) {
+                Some(f) => &**f,
+                None => engine.default_formatter(),
+            },
+        }
+    }
+
+    /// Evaluates the name of an included template.
+    fn template_name(
+        &self,
+        source: &str,
+        name: &'render ast::BaseExpr,
+    ) -> Result<Cow<'stack, str>> {
+        let value = match name {
+            ast::BaseExpr::Literal(lit) => ValueCow::Borrowed(&lit.value),
+            ast::BaseExpr::Var(var) => self.stack.lookup_var(source, var)?,
+        };
+        match value {
+            ValueCow::Borrowed(Value::String(s)) => Ok(Cow::Borrowed(s)),
+            ValueCow::Owned(Value::String(s)) => Ok(Cow::Owned(s)),
+            value => Err(Error::render(
+                format!(
+                    "expected string, but expression evaluated to {}",
+                    value.human()
+                ),
+                source,
+                name.span(),
+            )),
+        }
+    }
+
     fn get_template(
         &mut self,
         source: &str,
-        name: &ast::String,
+        name: &str,
+        span: Span,
     ) -> Result<&'render Template<'render>> {
-        if let Some(template_fn) = &mut self.inner.template_fn {
-            template_fn(name.as_str())
-                .map(|t| &t.template)
-                .map_err(|e| Error::render(e, source, name.span))
-        } else {
-            self.inner
+        self.find_template(source, name, span)?
+            .ok_or_else(|| Error::render("unknown template", source, span))
+    }
+
+    /// Looks up a template, returning `None` if the engine has no template
+    /// with the given name.
+    ///
+    /// Any error returned by a custom template function is passed through
+    /// because it cannot be told apart from a template that is missing.
+    fn find_template(
+        &mut self,
+        source: &str,
+        name: &str,
+        span: Span,
+    ) -> Result<Option<&'render Template<'render>>> {
+        match &mut self.inner.template_fn {
+            Some(template_fn) => template_fn(name)
+                .map(|t| Some(&*t.template))
+                .map_err(|e| Error::render(e, source, span)),
+            None => Ok(self
+                .inner
                 .engine
-                .templates
-                .get(name.as_str())
-                .ok_or_else(|| Error::render("unknown template", source, name.span))
+                .lookup_template(name)
+                .map(|(_, template)| template)),
+        }
+    }
+
+    fn get_raw(&mut self, source: &str, name: &ast::String) -> Result<String> {
+        match &mut self.inner.include_raw_fn {
+            Some(include_raw_fn) => {
+                include_raw_fn(name.as_str()).map_err(|e| Error::render(e, source, name.span))
+            }
+            None => Err(Error::render(
+                "no raw include function set",
+                source,
+                name.span,
+            )),
+        }
+    }
+}
+
+/// Compares two values using the given operator.
+///
+/// Integers and floats can be compared with each other, otherwise both values
+/// must be the same type. Only numbers and strings can be ordered.
+fn compare(source: &str, lhs: &Value, op: ast::CompareOp, rhs: &Value, span: Span) -> Result<bool> {
+    use std::cmp::Ordering;
+
+    let err = || {
+        Error::render(
+            format!(
+                "cannot compare {} with {} using `{}`",
+                lhs.human(),
+                rhs.human(),
+                op.human()
+            ),
+            source,
+            span,
+        )
+    };
+
+    let ord = match (lhs, rhs) {
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Integer(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::None, Value::None)
+        | (Value::Bool(_), Value::Bool(_))
+        | (Value::List(_), Value::List(_))
+        | (Value::Map(_), Value::Map(_)) => {
+            return match op {
+                ast::CompareOp::Eq => Ok(lhs == rhs),
+                ast::CompareOp::Ne => Ok(lhs != rhs),
+                _ => Err(err()),
+            };
+        }
+        _ => return Err(err()),
+    };
+
+    let result = match op {
+        ast::CompareOp::Eq => ord == Some(Ordering::Equal),
+        ast::CompareOp::Ne => ord != Some(Ordering::Equal),
+        ast::CompareOp::Lt => ord == Some(Ordering::Less),
+        ast::CompareOp::Le => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+        ast::CompareOp::Gt => ord == Some(Ordering::Greater),
+        ast::CompareOp::Ge => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+    };
+    Ok(result)
+}
+
+/// Formats both values using the default formatter and joins them together.
+fn concat(
+    source: &str,
+    lhs: ValueCow<'_>,
+    lhs_span: Span,
+    rhs: &Value,
+    rhs_span: Span,
+) -> Result<String> {
+    let mut s = match lhs {
+        ValueCow::Owned(Value::String(s)) => s,
+        lhs => {
+            let mut s = String::new();
+            crate::fmt::default(&mut Formatter::with_string(&mut s), &lhs)
+                .map_err(|err| Error::format(err, source, lhs_span))?;
+            s
+        }
+    };
+    crate::fmt::default(&mut Formatter::with_string(&mut s), rhs)
+        .map_err(|err| Error::format(err, source, rhs_span))?;
+    Ok(s)
+}
+
+/// Returns whether the value is an empty string, list, or map, or `None`.
+fn is_empty(source: &str, value: &Value, span: Span) -> Result<bool> {
+    match value {
+        Value::None => Ok(true),
+        Value::String(s) => Ok(s.is_empty()),
+        Value::List(list) => Ok(list.is_empty()),
+        Value::Map(map) => Ok(map.is_empty()),
+        value => Err(Error::render(
+            format!(
+                "expected string, list, or map, but expression evaluated to {}",
+                value.human()
+            ),
+            source,
+            span,
+        )),
+    }
+}
+
+/// Emits a raw span along with the whitespace that was trimmed around it.
+fn emit_raw_traced(f: &mut Formatter<'_>, t: &Template<'_>, span: Span) -> std::fmt::Result {
+    let emit_trim = |f: &mut Formatter<'_>, trim: Option<&Span>| match trim {
+        Some(trim) => write!(f, "«{}»", t.source[*trim].escape_debug()),
+        None => Ok(()),
+    };
+    let left = t.trims.iter().find(|trim| trim.n == span.m);
+    let right = t.trims.iter().find(|trim| trim.m == span.n);
+    emit_trim(f, left)?;
+    f.write_str(&t.source[span])?;
+    emit_trim(f, right)
+}
+
+/// Restricts the frame to rendering only the most derived definition of the
+/// block with the given name.
+fn select_block(frame: &mut Frame<'_, '_>, name: &str) -> Result<()> {
+    let found = frame
+        .children
+        .iter()
+        .find_map(|(child, child_name)| {
+            let (start, end) = child.block(name)?;
+            Some((*child, child_name.clone(), start, end))
+        })
+        .or_else(|| {
+            let (start, end) = frame.template.block(name)?;
+            Some((frame.template, frame.name.clone(), start, end))
+        });
+    match found {
+        Some((template, name, start, end)) => {
+            frame.template = template;
+            frame.name = name;
+            frame.pc = start;
+            frame.end = end;
+            Ok(())
+        }
+        None => {
+            let mut names: Vec<_> = frame
+                .children
+                .iter()
+                .map(|(child, _)| *child)
+                .chain(Some(frame.template))
+                .flat_map(|t| t.block_names())
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            Err(Error::unknown_block(name, &names))
         }
     }
 }
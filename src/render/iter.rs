@@ -1,5 +1,8 @@
 use std::collections::btree_map as map;
+#[cfg(all(feature = "filters", internal_debug))]
+use std::fmt;
 use std::iter::Enumerate;
+use std::ops::RangeInclusive;
 use std::slice;
 use std::vec as list;
 
@@ -14,17 +17,21 @@ use crate::{Error, Result, Value};
 pub enum LoopState<'a> {
     /// An iterator over a borrowed list and the last item yielded
     ListBorrowed {
-        /// The name of the loop variable
-        i: &'a str,
+        /// The loop variable(s)
+        vars: ItemVars<'a>,
         iter: Enumerate<slice::Iter<'a, Value>>,
+        /// Whether to iterate in reverse
+        rev: bool,
         value: Option<(usize, &'a Value)>,
     },
 
     /// An iterator over an owned list and the last item yielded
     ListOwned {
-        /// The name of the loop variable
-        i: &'a str,
+        /// The loop variable(s)
+        vars: ItemVars<'a>,
         iter: Enumerate<list::IntoIter<Value>>,
+        /// Whether to iterate in reverse
+        rev: bool,
         value: Option<(usize, Value)>,
     },
 
@@ -47,6 +54,112 @@ pub enum LoopState<'a> {
         iter: Enumerate<map::IntoIter<String, Value>>,
         value: Option<(usize, (String, Value))>,
     },
+
+    /// An iterator over a range of integers and the last integer yielded
+    Range {
+        /// The name of the loop variable
+        i: &'a str,
+        iter: RangeInclusive<i64>,
+        /// Whether to iterate in reverse
+        rev: bool,
+        value: Option<(usize, i64)>,
+    },
+
+    /// A lazy iterator returned from a filter, the last item yielded and the
+    /// item that will be yielded next
+    #[cfg(feature = "filters")]
+    Lazy {
+        /// The name of the loop variable
+        i: &'a str,
+        iter: ValueIter,
+        value: Option<(usize, Value)>,
+        next: Option<Value>,
+    },
+}
+
+/// The loop variables that each list item is bound to.
+#[cfg_attr(internal_debug, derive(Debug))]
+pub enum ItemVars<'a> {
+    /// The name of the loop variable
+    One(&'a str),
+    /// The names of the loop variables that each item is unpacked into and
+    /// the span of all of them
+    Many(Vec<&'a str>, Span),
+}
+
+impl ItemVars<'_> {
+    /// Returns the value bound to the given name, if any.
+    ///
+    /// The item must already have been checked using [`ItemVars::check`].
+    fn get<'v>(&self, name: &str, item: &'v Value) -> Option<&'v Value> {
+        match (self, item) {
+            (Self::One(i), _) if name == *i => Some(item),
+            (Self::Many(names, _), Value::List(list)) => {
+                let j = names.iter().position(|n| *n == name)?;
+                list.get(j)
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks that the item can be unpacked into the loop variables.
+    fn check(&self, source: &str, item: &Value) -> Result<()> {
+        let (n, span) = match self {
+            Self::One(_) => return Ok(()),
+            Self::Many(names, span) => (names.len(), *span),
+        };
+        match item {
+            Value::List(list) if list.len() == n => Ok(()),
+            Value::List(list) => Err(Error::render(
+                format!(
+                    "cannot unpack list item of length {} into {n} variables",
+                    list.len()
+                ),
+                source,
+                span,
+            )),
+            item => Err(Error::render(
+                format!("cannot unpack {} into {n} variables", item.human()),
+                source,
+                span,
+            )),
+        }
+    }
+}
+
+/// A lazy iterator of values returned from a filter.
+#[cfg(feature = "filters")]
+pub struct ValueIter(Box<dyn Iterator<Item = Value>>);
+
+#[cfg(feature = "filters")]
+impl ValueIter {
+    pub fn new<I>(iter: I) -> Self
+    where
+        I: Iterator + 'static,
+        I::Item: Into<Value>,
+    {
+        Self(Box::new(iter.map(Into::into)))
+    }
+}
+
+#[cfg(feature = "filters")]
+impl Iterator for ValueIter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[cfg(all(feature = "filters", internal_debug))]
+impl fmt::Debug for ValueIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueIter").finish_non_exhaustive()
+    }
 }
 
 impl<'a> LoopState<'a> {
@@ -56,6 +169,7 @@ impl<'a> LoopState<'a> {
         vars: &'a ast::LoopVars,
         iterable: ValueCow<'a>,
         span: Span,
+        rev: bool,
     ) -> Result<Self> {
         let human = iterable.human();
         let err = || {
@@ -67,12 +181,24 @@ impl<'a> LoopState<'a> {
         };
 
         let unpack_list_item = |vars: &'a ast::LoopVars| match vars {
-            ast::LoopVars::Item(item) => Ok(item),
+            ast::LoopVars::Item(item) => Ok(ItemVars::One(&source[item.span])),
             ast::LoopVars::KeyValue(kv) => Err(Error::render(
                 "cannot unpack list item into two variables",
                 source,
                 kv.span,
             )),
+            ast::LoopVars::Tuple(tuple) => {
+                let names = tuple.vars.iter().map(|var| &source[var.span]).collect();
+                Ok(ItemVars::Many(names, tuple.span))
+            }
+        };
+
+        let err_rev_map = || {
+            Error::render(
+                "cannot iterate over a map in reverse, maps are always iterated in key order",
+                source,
+                span,
+            )
         };
 
         let unpack_map_item = |vars: &'a ast::LoopVars| match vars {
@@ -82,19 +208,26 @@ impl<'a> LoopState<'a> {
                 item.span,
             )),
             ast::LoopVars::KeyValue(kv) => Ok(kv),
+            ast::LoopVars::Tuple(tuple) => Err(Error::render(
+                format!("cannot unpack map item into {} variables", tuple.vars.len()),
+                source,
+                tuple.span,
+            )),
         };
 
         match iterable {
             ValueCow::Borrowed(v) => match v {
                 Value::List(list) => {
-                    let item = unpack_list_item(vars)?;
+                    let vars = unpack_list_item(vars)?;
                     Ok(Self::ListBorrowed {
-                        i: &source[item.span],
+                        vars,
                         iter: list.iter().enumerate(),
+                        rev,
                         value: None,
                     })
                 }
 
+                Value::Map(_) if rev => Err(err_rev_map()),
                 Value::Map(map) => {
                     let kv = unpack_map_item(vars)?;
                     Ok(Self::MapBorrowed {
@@ -109,14 +242,16 @@ impl<'a> LoopState<'a> {
 
             ValueCow::Owned(v) => match v {
                 Value::List(list) => {
-                    let item = unpack_list_item(vars)?;
+                    let vars = unpack_list_item(vars)?;
                     Ok(Self::ListOwned {
-                        i: &source[item.span],
+                        vars,
                         iter: list.into_iter().enumerate(),
+                        rev,
                         value: None,
                     })
                 }
 
+                Value::Map(_) if rev => Err(err_rev_map()),
                 Value::Map(map) => {
                     let kv = unpack_map_item(vars)?;
                     Ok(Self::MapOwned {
@@ -131,22 +266,176 @@ impl<'a> LoopState<'a> {
         }
     }
 
-    pub fn iterate(&mut self) -> Option<()> {
-        match self {
-            Self::ListBorrowed { iter, value, .. } => {
-                *value = Some(iter.next()?);
+    /// Constructs the initial loop state for a range of integers.
+    ///
+    /// A range where the end is before the start is empty.
+    pub fn range(
+        source: &'a str,
+        vars: &'a ast::LoopVars,
+        start: &Value,
+        end: &Value,
+        inclusive: bool,
+        [start_span, end_span]: [Span; 2],
+        rev: bool,
+    ) -> Result<Self> {
+        let int = |value: &Value, span| match value {
+            Value::Integer(i) => Ok(*i),
+            value => Err(Error::render(
+                format!(
+                    "expected integer, but expression evaluated to {}",
+                    value.human()
+                ),
+                source,
+                span,
+            )),
+        };
+        let start = int(start, start_span)?;
+        let end = int(end, end_span)?;
+
+        let item = match vars {
+            ast::LoopVars::Item(item) => item,
+            ast::LoopVars::KeyValue(kv) => {
+                return Err(Error::render(
+                    "cannot unpack range item into two variables",
+                    source,
+                    kv.span,
+                ))
+            }
+            ast::LoopVars::Tuple(tuple) => {
+                return Err(Error::render(
+                    format!(
+                        "cannot unpack range item into {} variables",
+                        tuple.vars.len()
+                    ),
+                    source,
+                    tuple.span,
+                ))
             }
-            Self::ListOwned { iter, value, .. } => {
-                *value = Some(iter.next()?);
+        };
+
+        // An exclusive range is converted to an inclusive one, if the end is
+        // the smallest integer the range is empty anyway.
+        #[allow(clippy::reversed_empty_ranges)]
+        let range = match (inclusive, end.checked_sub(1)) {
+            (true, _) => start..=end,
+            (false, Some(end)) => start..=end,
+            (false, None) => 1..=0,
+        };
+
+        Ok(Self::Range {
+            i: &source[item.span],
+            iter: range,
+            rev,
+            value: None,
+        })
+    }
+
+    /// Constructs the initial loop state for a lazy iterator returned from a
+    /// filter.
+    ///
+    /// The iterator is always one item ahead of the loop so that `loop.last`
+    /// is known.
+    #[cfg(feature = "filters")]
+    pub fn lazy(source: &'a str, vars: &'a ast::LoopVars, mut iter: ValueIter) -> Result<Self> {
+        let item = match vars {
+            ast::LoopVars::Item(item) => item,
+            ast::LoopVars::KeyValue(kv) => {
+                return Err(Error::render(
+                    "cannot unpack iterator item into two variables",
+                    source,
+                    kv.span,
+                ))
             }
-            Self::MapBorrowed { iter, value, .. } => {
-                *value = Some(iter.next()?);
+            ast::LoopVars::Tuple(tuple) => {
+                return Err(Error::render(
+                    format!(
+                        "cannot unpack iterator item into {} variables",
+                        tuple.vars.len()
+                    ),
+                    source,
+                    tuple.span,
+                ))
             }
-            Self::MapOwned { iter, value, .. } => {
-                *value = Some(iter.next()?);
+        };
+        let next = iter.next();
+        Ok(Self::Lazy {
+            i: &source[item.span],
+            iter,
+            value: None,
+            next,
+        })
+    }
+
+    /// Advances to the next item, returning `None` if the loop is finished.
+    ///
+    /// List items are checked here so that an item that cannot be unpacked
+    /// into the loop variables is an error even if none of them are used.
+    pub fn iterate(&mut self, source: &str) -> Result<Option<()>> {
+        match self {
+            Self::ListBorrowed {
+                vars,
+                iter,
+                rev,
+                value,
+            } => match next(iter, *rev) {
+                Some((_, item)) => {
+                    vars.check(source, item)?;
+                    *value = Some((next_index(value), item));
+                }
+                None => return Ok(None),
+            },
+            Self::ListOwned {
+                vars,
+                iter,
+                rev,
+                value,
+            } => match next(iter, *rev) {
+                Some((_, item)) => {
+                    vars.check(source, &item)?;
+                    *value = Some((next_index(value), item));
+                }
+                None => return Ok(None),
+            },
+            Self::MapBorrowed { iter, value, .. } => match iter.next() {
+                Some(next) => *value = Some(next),
+                None => return Ok(None),
+            },
+            Self::MapOwned { iter, value, .. } => match iter.next() {
+                Some(next) => *value = Some(next),
+                None => return Ok(None),
+            },
+            Self::Range {
+                iter, rev, value, ..
+            } => match next(iter, *rev) {
+                Some(n) => *value = Some((next_index(value), n)),
+                None => return Ok(None),
+            },
+            #[cfg(feature = "filters")]
+            Self::Lazy {
+                iter, value, next, ..
+            } => {
+                let i = next_index(value);
+                match next.take() {
+                    Some(item) => *value = Some((i, item)),
+                    None => return Ok(None),
+                }
+                *next = iter.next();
             }
         }
-        Some(())
+        Ok(Some(()))
+    }
+
+    /// Returns whether the loop has yielded any items.
+    pub fn is_started(&self) -> bool {
+        match self {
+            Self::ListBorrowed { value, .. } => value.is_some(),
+            Self::ListOwned { value, .. } => value.is_some(),
+            Self::MapBorrowed { value, .. } => value.is_some(),
+            Self::MapOwned { value, .. } => value.is_some(),
+            Self::Range { value, .. } => value.is_some(),
+            #[cfg(feature = "filters")]
+            Self::Lazy { value, .. } => value.is_some(),
+        }
     }
 
     pub fn lookup_var(&self, source: &str, var: &ast::Var) -> Result<Option<ValueCow<'a>>> {
@@ -176,22 +465,28 @@ impl<'a> LoopState<'a> {
 
         match self {
             Self::ListBorrowed {
-                i,
+                vars,
                 value: Some((_, value)),
                 ..
-            } if name == *i => {
-                let v = resolve!(*value);
-                Ok(Some(ValueCow::Borrowed(v)))
-            }
+            } => match vars.get(name, value) {
+                Some(v) => {
+                    let v = resolve!(v);
+                    Ok(Some(ValueCow::Borrowed(v)))
+                }
+                None => Ok(None),
+            },
 
             Self::ListOwned {
-                i,
+                vars,
                 value: Some((_, value)),
                 ..
-            } if name == *i => {
-                let v = resolve!(value);
-                Ok(Some(ValueCow::Owned(v.clone())))
-            }
+            } => match vars.get(name, value) {
+                Some(v) => {
+                    let v = resolve!(v);
+                    Ok(Some(ValueCow::Owned(v.clone())))
+                }
+                None => Ok(None),
+            },
 
             Self::MapBorrowed {
                 k,
@@ -233,6 +528,27 @@ impl<'a> LoopState<'a> {
                 Ok(Some(ValueCow::Owned(v.clone())))
             }
 
+            Self::Range {
+                i,
+                value: Some((_, value)),
+                ..
+            } if name == *i => {
+                if let [m, ..] = var.rest() {
+                    return Err(Error::render("cannot index into integer", source, m.span));
+                }
+                Ok(Some(ValueCow::Owned(Value::Integer(*value))))
+            }
+
+            #[cfg(feature = "filters")]
+            Self::Lazy {
+                i,
+                value: Some((_, value)),
+                ..
+            } if name == *i => {
+                let v = resolve!(value);
+                Ok(Some(ValueCow::Owned(v.clone())))
+            }
+
             _ => Ok(None),
         }
     }
@@ -243,11 +559,23 @@ impl<'a> LoopState<'a> {
             None => return Ok(None),
         };
 
+        // The total length is the number of items already yielded plus the
+        // number remaining, which is unknown for some lazy iterators and may
+        // not fit in an `i64` for integer ranges.
+        let len = match rem.and_then(|rem| i64::try_from(i as u128 + 1 + rem).ok()) {
+            Some(len) => Value::Integer(len),
+            None => Value::None,
+        };
+        let last = rem == Some(0);
+
         if path.len() == 1 {
             return Ok(Some(ValueCow::Owned(Value::from([
                 ("index", Value::Integer(i as i64)),
+                ("index0", Value::Integer(i as i64)),
+                ("index1", Value::Integer(i as i64 + 1)),
+                ("length", len),
                 ("first", Value::Bool(i == 0)),
-                ("last", Value::Bool(rem == 0)),
+                ("last", Value::Bool(last)),
             ]))));
         }
 
@@ -265,9 +593,11 @@ impl<'a> LoopState<'a> {
         };
 
         let v = match (&member.op, name) {
-            (_, "index") => Value::Integer(i as i64),
+            (_, "index" | "index0") => Value::Integer(i as i64),
+            (_, "index1") => Value::Integer(i as i64 + 1),
+            (_, "length") => len,
             (_, "first") => Value::Bool(i == 0),
-            (_, "last") => Value::Bool(rem == 0),
+            (_, "last") => Value::Bool(last),
             (ast::AccessOp::Optional, _) => Value::None,
             (ast::AccessOp::Direct, _) => {
                 return Err(Error::render("not found in map", source, member.span))
@@ -285,29 +615,73 @@ impl<'a> LoopState<'a> {
         Ok(Some(ValueCow::Owned(v)))
     }
 
-    fn current_index_and_rem(&self) -> Option<(usize, usize)> {
+    /// Returns the index of the current item and the number of items
+    /// remaining, if it is known.
+    fn current_index_and_rem(&self) -> Option<(usize, Option<u128>)> {
         match self {
             LoopState::ListBorrowed {
                 iter,
                 value: Some((i, _)),
                 ..
-            } => Some((*i, iter.len())),
+            } => Some((*i, Some(iter.len() as u128))),
             LoopState::ListOwned {
                 iter,
                 value: Some((i, _)),
                 ..
-            } => Some((*i, iter.len())),
+            } => Some((*i, Some(iter.len() as u128))),
             LoopState::MapBorrowed {
                 iter,
                 value: Some((i, _)),
                 ..
-            } => Some((*i, iter.len())),
+            } => Some((*i, Some(iter.len() as u128))),
             LoopState::MapOwned {
                 iter,
                 value: Some((i, _)),
                 ..
-            } => Some((*i, iter.len())),
+            } => Some((*i, Some(iter.len() as u128))),
+            // Integer ranges are not exact size iterators and the size hint
+            // saturates, so the remaining length is computed from the bounds.
+            LoopState::Range {
+                iter,
+                value: Some((i, _)),
+                ..
+            } => {
+                let rem = match iter.is_empty() {
+                    true => 0,
+                    false => (*iter.end() as i128 - *iter.start() as i128 + 1) as u128,
+                };
+                Some((*i, Some(rem)))
+            }
+            // The remaining length of a lazy iterator is only known if there
+            // is no next item or if the iterator has an exact size hint.
+            #[cfg(feature = "filters")]
+            LoopState::Lazy {
+                iter,
+                value: Some((i, _)),
+                next,
+                ..
+            } => {
+                let rem = match (next, iter.size_hint()) {
+                    (None, _) => Some(0),
+                    (Some(_), (lo, Some(hi))) if lo == hi => Some(lo as u128 + 1),
+                    (Some(_), _) => None,
+                };
+                Some((*i, rem))
+            }
             _ => None,
         }
     }
 }
+
+/// Returns the next item from the front or the back of the iterator.
+fn next<I: DoubleEndedIterator>(iter: &mut I, rev: bool) -> Option<I::Item> {
+    match rev {
+        true => iter.next_back(),
+        false => iter.next(),
+    }
+}
+
+/// Returns the loop index of the item after the last item yielded.
+fn next_index<T>(value: &Option<(usize, T)>) -> usize {
+    value.as_ref().map_or(0, |(i, _)| i + 1)
+}
@@ -5,36 +5,133 @@ mod iter;
 mod stack;
 mod value;
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
 use std::io;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 
-use crate::fmt::{Formatter, Writer};
 #[cfg(feature = "filters")]
-pub use crate::render::core::FilterState;
-use crate::render::core::RendererImpl;
+use crate::filters::RenderContext;
+use crate::fmt::{FmtWriter, Formatter, Limited, Writer};
+#[cfg(feature = "filters")]
+pub use crate::render::core::{FilterOutput, FilterState};
+use crate::render::core::{Frame, RendererImpl};
+#[cfg(feature = "filters")]
+pub use crate::render::iter::ValueIter;
 pub use crate::render::stack::Stack;
 use crate::types::program::Template;
-use crate::{Engine, Error, Result, Value, ValueFn};
+use crate::{Engine, Error, Result, Undefined, Value, ValueFn};
 
 fn to_string(inner: RendererInner<'_>, stack: Stack<'_>) -> Result<String> {
+    let trim_trailing_newline = inner.trim_trailing_newline;
     let mut s = String::with_capacity(inner.template.source.len());
-    let mut f = Formatter::with_string(&mut s);
-    RendererImpl { inner, stack }.render(&mut f)?;
+    render(inner, stack, &mut s)?;
+    if trim_trailing_newline {
+        trim_newline(&mut s);
+    }
     Ok(s)
 }
 
-fn to_writer<W>(inner: RendererInner<'_>, stack: Stack<'_>, writer: W) -> Result<()>
+fn to_writer<W>(inner: RendererInner<'_>, stack: Stack<'_>, mut writer: W) -> Result<()>
 where
     W: io::Write,
 {
+    if inner.trim_trailing_newline {
+        // We can't take back bytes that have already been written, so the
+        // output has to be buffered in order to trim it.
+        let s = to_string(inner, stack)?;
+        writer.write_all(s.as_bytes())?;
+        return Ok(());
+    }
     let mut w = Writer::new(writer);
-    let mut f = Formatter::with_writer(&mut w);
-    RendererImpl { inner, stack }
-        .render(&mut f)
-        .map_err(|err| w.take_err().map(Error::from).unwrap_or(err))
+    render(inner, stack, &mut w).map_err(|err| w.take_err().map(Error::from).unwrap_or(err))
+}
+
+fn to_fmt_writer<W>(inner: RendererInner<'_>, stack: Stack<'_>, mut writer: W) -> Result<()>
+where
+    W: fmt::Write,
+{
+    if inner.trim_trailing_newline {
+        let s = to_string(inner, stack)?;
+        writer.write_str(&s)?;
+        return Ok(());
+    }
+    // The writer doesn't give any details when it fails, so the error is
+    // replaced with a plain format error instead of one that points at the
+    // part of the template that was being rendered.
+    let mut w = FmtWriter::new(writer);
+    render(inner, stack, &mut w).map_err(|err| {
+        if w.failed() {
+            Error::from(fmt::Error)
+        } else {
+            err
+        }
+    })
+}
+
+fn render(inner: RendererInner<'_>, stack: Stack<'_>, buf: &mut dyn fmt::Write) -> Result<()> {
+    let max_output_size = inner.max_output_size();
+    let stack = stack.with_undefined(inner.undefined());
+    let renderer = RendererImpl {
+        inner,
+        stack,
+        autoescape: Vec::new(),
+        #[cfg(feature = "filters")]
+        context: RenderContext::default(),
+        chunked: false,
+    };
+    let mut buf = Limited::new(buf, max_output_size.unwrap_or(usize::MAX));
+    let result = renderer.render(&mut Formatter::with_limited(&mut buf));
+    // A formatter might ignore the error caused by the limit, so the limit is
+    // checked even if rendering succeeded.
+    match (max_output_size, buf.exceeded()) {
+        (Some(max), true) => Err(Error::max_output_size(max)),
+        _ => result,
+    }
+}
+
+fn to_chunks<'render>(inner: RendererInner<'render>, stack: Stack<'render>) -> Chunks<'render> {
+    let trim_trailing_newline = inner.trim_trailing_newline;
+    let max_output_size = inner.max_output_size();
+    let stack = stack.with_undefined(inner.undefined());
+    let mut renderer = RendererImpl {
+        inner,
+        stack,
+        autoescape: Vec::new(),
+        #[cfg(feature = "filters")]
+        context: RenderContext::default(),
+        chunked: true,
+    };
+    let state = match renderer.start() {
+        Ok(templates) => ChunksState::Rendering {
+            renderer: Box::new(renderer),
+            templates,
+            trim_trailing_newline,
+            newline: String::new(),
+            max_output_size,
+            remaining: max_output_size.unwrap_or(usize::MAX),
+        },
+        Err(err) => ChunksState::Err(err),
+    };
+    Chunks { state }
+}
+
+/// Removes a single trailing `\n` or `\r\n` from the string.
+fn trim_newline(s: &mut String) {
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
 }
 
 type TemplateFn<'a> = dyn FnMut(&str) -> std::result::Result<&'a crate::Template<'a>, String> + 'a;
 
+type IncludeRawFn<'a> = dyn FnMut(&str) -> std::result::Result<String, String> + 'a;
+
 /// A renderer that interprets a compiled [`Template`][crate::Template] or
 /// [`TemplateRef`][crate::TemplateRef].
 ///
@@ -42,12 +139,242 @@ type TemplateFn<'a> = dyn FnMut(&str) -> std::result::Result<&'a crate::Template
 /// - [`Template{,Ref}::render`][crate::Template::render]
 /// - [`Template{,Ref}::render_from`][crate::Template::render_from]
 /// - [`Template{,Ref}::render_from_fn`][crate::Template::render_from_fn]
-#[must_use = "must call `.to_string()` or `.to_writer(..)` on the renderer"]
+/// - [`Template{,Ref}::render_from_cow_fn`][crate::Template::render_from_cow_fn]
+#[must_use = "must call `.to_string()`, `.to_vec()`, `.to_writer(..)`, `.buffered_to_writer(..)` or `.into_chunks()` on the renderer"]
 pub struct Renderer<'render> {
     globals: Globals<'render>,
+    fallback: Option<&'render Value>,
     inner: RendererInner<'render>,
 }
 
+/// An iterator over the rendered output of a template in chunks.
+///
+/// This struct is created by [`Renderer::into_chunks`]. Each item is the
+/// output emitted by a single part of the template, for example some raw
+/// template text or the value of an expression. Empty chunks are never
+/// yielded. If rendering fails the error is yielded and then the iterator is
+/// finished.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Chunks<'render> {
+    state: ChunksState<'render>,
+}
+
+enum ChunksState<'render> {
+    Rendering {
+        renderer: Box<RendererImpl<'render, 'render>>,
+        templates: Vec<Frame<'render, 'render>>,
+        trim_trailing_newline: bool,
+        /// A trailing newline held back from the last chunk, it is only
+        /// yielded if more output follows it.
+        newline: String,
+        max_output_size: Option<usize>,
+        /// The number of bytes that can still be output.
+        remaining: usize,
+    },
+    Err(Error),
+    Done,
+}
+
+impl Iterator for Chunks<'_> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (renderer, templates, trim_trailing_newline, newline, max_output_size, remaining) =
+            match &mut self.state {
+                ChunksState::Rendering {
+                    renderer,
+                    templates,
+                    trim_trailing_newline,
+                    newline,
+                    max_output_size,
+                    remaining,
+                } => (
+                    renderer,
+                    templates,
+                    *trim_trailing_newline,
+                    newline,
+                    *max_output_size,
+                    remaining,
+                ),
+                ChunksState::Err(_) => {
+                    match std::mem::replace(&mut self.state, ChunksState::Done) {
+                        ChunksState::Err(err) => return Some(Err(err)),
+                        _ => unreachable!(),
+                    }
+                }
+                ChunksState::Done => return None,
+            };
+
+        while !templates.is_empty() {
+            let mut s = std::mem::take(newline);
+            let mut buf = Limited::new(&mut s, *remaining);
+            let result = renderer.step(&mut Formatter::with_limited(&mut buf), templates);
+            let result = match (max_output_size, buf.exceeded()) {
+                (Some(max), true) => Err(Error::max_output_size(max)),
+                _ => result,
+            };
+            *remaining = buf.remaining();
+            if let Err(err) = result {
+                self.state = ChunksState::Done;
+                return Some(Err(err));
+            }
+            if trim_trailing_newline {
+                *newline = split_newline(&mut s);
+            }
+            if !s.is_empty() {
+                return Some(Ok(s));
+            }
+        }
+
+        // A lone carriage return is not a newline so it is not trimmed.
+        let rest = std::mem::take(newline);
+        self.state = ChunksState::Done;
+        match rest.as_str() {
+            "\r" => Some(Ok(rest)),
+            _ => None,
+        }
+    }
+}
+
+/// Splits off a trailing `\n`, `\r\n` or `\r` from the string.
+///
+/// A trailing `\r` is split off because it might be followed by a `\n` in the
+/// next chunk.
+fn split_newline(s: &mut String) -> String {
+    let n = if s.ends_with("\r\n") {
+        2
+    } else if s.ends_with('\n') || s.ends_with('\r') {
+        1
+    } else {
+        0
+    };
+    s.split_off(s.len() - n)
+}
+
+/// Receives timings of filters and includes while rendering.
+///
+/// Attach a profiler to a render using [`Renderer::with_profiler`]. Both
+/// methods do nothing by default, so only the events of interest need to be
+/// implemented. Nothing is timed when no profiler is attached.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use std::time::Duration;
+///
+/// #[derive(Default)]
+/// struct Stats {
+///     filters: BTreeMap<String, (usize, Duration)>,
+/// }
+///
+/// impl upon::Profiler for Stats {
+///     fn filter(&mut self, name: &str, duration: Duration) {
+///         let (count, total) = self.filters.entry(name.to_owned()).or_default();
+///         *count += 1;
+///         *total += duration;
+///     }
+/// }
+///
+/// let mut engine = upon::Engine::new();
+/// engine.add_filter("upper", str::to_uppercase);
+///
+/// let mut stats = Stats::default();
+/// let result = engine
+///     .compile("{% for name in names %}{{ name | upper }} {% endfor %}")?
+///     .render(&engine, upon::value! { names: ["John", "Jane"] })
+///     .with_profiler(&mut stats)
+///     .to_string()?;
+/// assert_eq!(result, "JOHN JANE ");
+/// assert_eq!(stats.filters["upper"].0, 2);
+/// # Ok::<(), upon::Error>(())
+/// ```
+pub trait Profiler {
+    /// Called after a filter with the given name is applied.
+    fn filter(&mut self, name: &str, duration: Duration) {
+        let _ = (name, duration);
+    }
+
+    /// Called after the template with the given name is resolved for an
+    /// `{% include %}` statement.
+    ///
+    /// The duration is the time taken to look up the template, not the time
+    /// taken to render it.
+    fn include(&mut self, name: &str, duration: Duration) {
+        let _ = (name, duration);
+    }
+}
+
+/// A reusable set of options that can be applied to a [`Renderer`].
+///
+/// This is useful when rendering many templates with the same settings.
+///
+/// # Examples
+///
+/// ```
+/// use upon::{Engine, RenderOptions};
+///
+/// let mut engine = Engine::new();
+/// engine.add_template("hello", "Hello {{ user.name }}!")?;
+///
+/// let options = RenderOptions::new().with_max_include_depth(4);
+///
+/// let result = engine
+///     .template("hello")
+///     .render(upon::value!{ user: { name: "John Smith" }})
+///     .with_options(&options)
+///     .to_string()?;
+/// assert_eq!(result, "Hello John Smith!");
+/// # Ok::<(), upon::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    max_include_depth: Option<usize>,
+    max_output_size: Option<usize>,
+    trim_trailing_newline: bool,
+    undefined: Option<Undefined>,
+}
+
+impl RenderOptions {
+    /// Construct a new set of render options with nothing set.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum length of the template render stack.
+    ///
+    /// See [`Renderer::with_max_include_depth`].
+    pub fn with_max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = Some(depth);
+        self
+    }
+
+    /// Set the maximum number of bytes that may be output.
+    ///
+    /// See [`Renderer::with_max_output_size`].
+    pub fn with_max_output_size(mut self, bytes: usize) -> Self {
+        self.max_output_size = Some(bytes);
+        self
+    }
+
+    /// Remove a single trailing newline from the rendered output.
+    ///
+    /// See [`Renderer::trim_trailing_newline`].
+    pub fn trim_trailing_newline(mut self) -> Self {
+        self.trim_trailing_newline = true;
+        self
+    }
+
+    /// Set what happens when a variable is not found.
+    ///
+    /// See [`Renderer::with_undefined_behavior`].
+    pub fn with_undefined_behavior(mut self, undefined: Undefined) -> Self {
+        self.undefined = Some(undefined);
+        self
+    }
+}
+
 enum Globals<'render> {
     Owned(Result<Value>),
     Borrowed(&'render Value),
@@ -58,7 +385,23 @@ pub(crate) struct RendererInner<'render> {
     template: &'render Template<'render>,
     template_name: Option<&'render str>,
     max_include_depth: Option<usize>,
+    max_output_size: Option<usize>,
+    trim_trailing_newline: bool,
+    whitespace_trace: bool,
+    /// Whether the `__template__` and `__template_stack__` variables are
+    /// defined.
+    template_vars: bool,
+    undefined: Option<Undefined>,
+    /// A flag that cancels rendering when it is set.
+    cancel: Option<&'render AtomicBool>,
+    /// The text emitted for optional accesses that are not found.
+    missing_placeholder: Option<&'render str>,
+    /// Receives timings of filters and includes, if any.
+    profiler: Option<&'render mut dyn Profiler>,
+    /// The name of the only block to render, if any.
+    block: Option<&'render str>,
     template_fn: Option<Box<TemplateFn<'render>>>,
+    include_raw_fn: Option<Box<IncludeRawFn<'render>>>,
 }
 
 #[cfg(internal_debug)]
@@ -68,10 +411,52 @@ impl std::fmt::Debug for RendererInner<'_> {
             .field("engine", &self.engine)
             .field("template", &self.template)
             .field("max_include_depth", &self.max_include_depth)
+            .field("max_output_size", &self.max_output_size)
+            .field("trim_trailing_newline", &self.trim_trailing_newline)
+            .field("whitespace_trace", &self.whitespace_trace)
+            .field("template_vars", &self.template_vars)
+            .field("undefined", &self.undefined)
+            .field("cancel", &self.cancel)
+            .field("missing_placeholder", &self.missing_placeholder)
+            .field("profiler", &self.profiler.is_some())
+            .field("block", &self.block)
             .finish_non_exhaustive()
     }
 }
 
+impl RendererInner<'_> {
+    /// Returns the maximum output size, falling back to the engine setting.
+    fn max_output_size(&self) -> Option<usize> {
+        self.max_output_size.or(self.engine.max_output_size)
+    }
+
+    /// Returns the undefined variable behavior, falling back to the engine
+    /// setting.
+    fn undefined(&self) -> Undefined {
+        self.undefined.unwrap_or(self.engine.undefined)
+    }
+
+    /// Returns the current time if a profiler is attached.
+    fn profile_start(&self) -> Option<Instant> {
+        self.profiler.as_ref().map(|_| Instant::now())
+    }
+
+    /// Reports the time taken by a filter to the profiler, if any.
+    #[cfg(feature = "filters")]
+    fn profile_filter(&mut self, name: &str, start: Option<Instant>) {
+        if let (Some(profiler), Some(start)) = (self.profiler.as_deref_mut(), start) {
+            profiler.filter(name, start.elapsed());
+        }
+    }
+
+    /// Reports the time taken to resolve an include to the profiler, if any.
+    fn profile_include(&mut self, name: &str, start: Option<Instant>) {
+        if let (Some(profiler), Some(start)) = (self.profiler.as_deref_mut(), start) {
+            profiler.include(name, start.elapsed());
+        }
+    }
+}
+
 impl<'render> Renderer<'render> {
     fn new(
         engine: &'render Engine<'render>,
@@ -81,12 +466,23 @@ impl<'render> Renderer<'render> {
     ) -> Self {
         Self {
             globals,
+            fallback: None,
             inner: RendererInner {
                 engine,
                 template,
                 template_name,
                 max_include_depth: None,
+                max_output_size: None,
+                trim_trailing_newline: false,
+                whitespace_trace: false,
+                template_vars: false,
+                undefined: None,
+                cancel: None,
+                missing_placeholder: None,
+                profiler: None,
+                block: None,
                 template_fn: None,
+                include_raw_fn: None,
             },
         }
     }
@@ -118,6 +514,20 @@ impl<'render> Renderer<'render> {
         Self::new(engine, template, template_name, Globals::Borrowed(globals))
     }
 
+    pub(crate) fn with_cow(
+        engine: &'render Engine<'render>,
+        template: &'render Template<'render>,
+        template_name: Option<&'render str>,
+        globals: Result<Cow<'render, Value>>,
+    ) -> Self {
+        let globals = match globals {
+            Ok(Cow::Borrowed(value)) => Globals::Borrowed(value),
+            Ok(Cow::Owned(value)) => Globals::Owned(Ok(value)),
+            Err(err) => Globals::Owned(Err(err)),
+        };
+        Self::new(engine, template, template_name, globals)
+    }
+
     pub(crate) fn with_value_fn(
         engine: &'render Engine<'render>,
         template: &'render Template<'render>,
@@ -133,6 +543,9 @@ impl<'render> Renderer<'render> {
     /// default is to look for the template with the exact matching name in the
     /// engine, i.e. the same as
     /// [`Engine::get_template`][crate::Engine::get_template].
+    ///
+    /// Errors returned by the function are always reported, even if the
+    /// include statement uses `ignore missing`.
     pub fn with_template_fn<F>(mut self, template_fn: F) -> Self
     where
         F: FnMut(&str) -> std::result::Result<&'render crate::Template<'render>, String> + 'render,
@@ -141,6 +554,110 @@ impl<'render> Renderer<'render> {
         self
     }
 
+    /// Set a function that is called when raw content is included.
+    ///
+    /// This resolves `{% include_raw "name" %}` statements. The returned string
+    /// is emitted verbatim, it is not parsed as a template and it is not passed
+    /// to any value formatter. There is no default, rendering a template that
+    /// uses `include_raw` without setting this function is an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let result = engine
+    ///     .compile("<style>{% include_raw \"main.css\" %}</style>")?
+    ///     .render_from(&engine, &upon::Value::None)
+    ///     .with_include_raw_fn(|name| match name {
+    ///         "main.css" => Ok(String::from("p { color: red; }")),
+    ///         _ => Err(format!("unknown asset `{name}`")),
+    ///     })
+    ///     .to_string()?;
+    /// assert_eq!(result, "<style>p { color: red; }</style>");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_include_raw_fn<F>(mut self, include_raw_fn: F) -> Self
+    where
+        F: FnMut(&str) -> std::result::Result<String, String> + 'render,
+    {
+        self.inner.include_raw_fn = Some(Box::new(include_raw_fn));
+        self
+    }
+
+    /// Set additional global variables that are used when a variable is not
+    /// found.
+    ///
+    /// This is useful for values that are shared between many renders, for
+    /// example the site name or build version, so that they don't need to be
+    /// merged into every context. The context always takes precedence, the
+    /// given value is only consulted when a variable is not found in the
+    /// context or in any variables bound by the template. When rendering using
+    /// a value function the given value is consulted when the function returns
+    /// an error.
+    ///
+    /// Unlike the context, these variables are also visible from templates
+    /// included using `{% include ... with ... %}`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let globals = upon::value! { site: "Example", title: "Home" };
+    /// let result = engine
+    ///     .compile("{{ title }} | {{ site }}")?
+    ///     .render(&engine, upon::value! { title: "About" })
+    ///     .with_globals(&globals)
+    ///     .to_string()?;
+    /// assert_eq!(result, "About | Example");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_globals(mut self, globals: &'render Value) -> Self {
+        self.fallback = Some(globals);
+        self
+    }
+
+    /// Render only the block with the given name.
+    ///
+    /// This is useful for rendering a fragment of a page, for example to
+    /// update part of it, without rendering the surrounding layout. If the
+    /// template extends another template, the most derived definition of the
+    /// block is rendered, so the block may be defined in any template in the
+    /// chain. Any blocks nested inside the block are rendered as usual.
+    ///
+    /// Only the block itself is rendered, so variables bound outside of it,
+    /// for example by an enclosing `for` loop or a `set` statement before it,
+    /// are not available. Rendering fails if no template in the chain defines
+    /// the block, the error lists the blocks that do exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile(
+    ///     "<h1>{{ title }}</h1>{% block content %}<p>{{ body }}</p>{% endblock %}",
+    /// )?;
+    /// let result = template
+    ///     .render(&engine, upon::value! { title: "Home", body: "Welcome" })
+    ///     .with_block("content")
+    ///     .to_string()?;
+    /// assert_eq!(result, "<p>Welcome</p>");
+    ///
+    /// let err = template
+    ///     .render(&engine, upon::Value::None)
+    ///     .with_block("sidebar")
+    ///     .to_string()
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "render error: unknown block `sidebar`, available blocks: `content`"
+    /// );
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_block(mut self, name: &'render str) -> Self {
+        self.inner.block = Some(name);
+        self
+    }
+
     /// Set the maximum length of the template render stack.
     ///
     /// This is the maximum number of nested `{% include ... %}` statements that
@@ -152,48 +669,450 @@ impl<'render> Renderer<'render> {
         self
     }
 
+    /// Set the maximum number of bytes that may be output.
+    ///
+    /// Rendering fails with an [`ErrorKind::MaxOutputSize`] error as soon as
+    /// any more output would be written, so the output is never larger than
+    /// the limit. When the output is trimmed using
+    /// [`trim_trailing_newline()`][Renderer::trim_trailing_newline] the limit
+    /// applies to the untrimmed output.
+    ///
+    /// Defaults to the engine setting.
+    ///
+    /// [`ErrorKind::MaxOutputSize`]: crate::ErrorKind::MaxOutputSize
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let err = engine
+    ///     .compile("{% for i in 0..100 %}{{ i }}{% endfor %}")?
+    ///     .render(&engine, upon::Value::None)
+    ///     .with_max_output_size(16)
+    ///     .to_string()
+    ///     .unwrap_err();
+    /// assert_eq!(err.to_string(), "render error: reached maximum output size (16 bytes)");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_max_output_size(mut self, bytes: usize) -> Self {
+        self.inner.max_output_size = Some(bytes);
+        self
+    }
+
+    /// Set a flag that cancels rendering when it is set.
+    ///
+    /// The flag is checked before every instruction, so rendering stops
+    /// promptly with an [`ErrorKind::Cancelled`] error even in long running
+    /// loops or deeply nested includes. This can be set from another thread,
+    /// for example by a watchdog that enforces a deadline. A single filter
+    /// call that never returns can not be cancelled.
+    ///
+    /// [`ErrorKind::Cancelled`]: crate::ErrorKind::Cancelled
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomic::AtomicBool;
+    ///
+    /// let engine = upon::Engine::new();
+    /// let cancel = AtomicBool::new(true);
+    /// let err = engine
+    ///     .compile("{% for i in 0..100 %}{{ i }}{% endfor %}")?
+    ///     .render(&engine, upon::Value::None)
+    ///     .with_cancel(&cancel)
+    ///     .to_string()
+    ///     .unwrap_err();
+    /// assert_eq!(err.kind(), upon::ErrorKind::Cancelled);
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_cancel(mut self, cancel: &'render AtomicBool) -> Self {
+        self.inner.cancel = Some(cancel);
+        self
+    }
+
+    /// Set the name of the template used in errors.
+    ///
+    /// This replaces the name of a template rendered from the engine, or the
+    /// `<anonymous>` name of a template that was compiled without one.
+    /// Included templates are always named by the name they were included
+    /// with.
+    pub fn with_template_name(mut self, name: &'render str) -> Self {
+        self.inner.template_name = Some(name);
+        self
+    }
+
+    /// Set the text that is emitted in place of a missing optional access.
+    ///
+    /// When an expression that uses optional access, like `{{ user?.name }}`,
+    /// evaluates to `None` the placeholder is written as is instead of
+    /// nothing. The placeholder is not used if a filter is applied to the
+    /// value or if the expression specifies a formatter, and direct accesses
+    /// that are not found are still an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let result = engine
+    ///     .compile("{{ user?.name }} ({{ user?.email }})")?
+    ///     .render(&engine, upon::value! { user: { name: "John Smith" } })
+    ///     .with_missing_placeholder("—")
+    ///     .to_string()?;
+    /// assert_eq!(result, "John Smith (—)");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_missing_placeholder(mut self, placeholder: &'render str) -> Self {
+        self.inner.missing_placeholder = Some(placeholder);
+        self
+    }
+
+    /// Set a profiler that receives the time taken by each filter and
+    /// include.
+    ///
+    /// See [`Profiler`] for an example.
+    pub fn with_profiler(mut self, profiler: &'render mut dyn Profiler) -> Self {
+        self.inner.profiler = Some(profiler);
+        self
+    }
+
+    /// Apply the given render options.
+    ///
+    /// Any option that is set in the [`RenderOptions`] overrides the
+    /// corresponding renderer setting, options that are not set are left
+    /// unchanged.
+    pub fn with_options(mut self, options: &RenderOptions) -> Self {
+        let RenderOptions {
+            max_include_depth,
+            max_output_size,
+            trim_trailing_newline,
+            undefined,
+        } = *options;
+        if let Some(depth) = max_include_depth {
+            self.inner.max_include_depth = Some(depth);
+        }
+        if let Some(bytes) = max_output_size {
+            self.inner.max_output_size = Some(bytes);
+        }
+        if trim_trailing_newline {
+            self.inner.trim_trailing_newline = true;
+        }
+        if let Some(undefined) = undefined {
+            self.inner.undefined = Some(undefined);
+        }
+        self
+    }
+
+    /// Set what happens when a variable is not found.
+    ///
+    /// See [`Undefined`] for the available behaviors.
+    ///
+    /// Defaults to the engine setting.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Undefined;
+    ///
+    /// let engine = upon::Engine::new();
+    /// let result = engine
+    ///     .compile("Hello {{ user.name }}{% if title %} ({{ title }}){% endif %}!")?
+    ///     .render(&engine, upon::value! { user: { name: "John Smith" } })
+    ///     .with_undefined_behavior(Undefined::Empty)
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John Smith!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_undefined_behavior(mut self, undefined: Undefined) -> Self {
+        self.inner.undefined = Some(undefined);
+        self
+    }
+
+    /// Remove a single trailing newline from the rendered output.
+    ///
+    /// This is useful when generating files from templates that were authored
+    /// with a trailing newline. Both `\n` and `\r\n` are removed, but only
+    /// once, so any additional blank lines are preserved.
+    ///
+    /// When rendering using [`to_writer(..)`][Renderer::to_writer] the entire
+    /// output is buffered in memory before being written.
+    pub fn trim_trailing_newline(mut self) -> Self {
+        self.inner.trim_trailing_newline = true;
+        self
+    }
+
+    /// Mark whitespace that was removed by trim markers in the rendered output.
+    ///
+    /// This is a debugging aid for templates that use many `{{-`, `-}}`, `{%-`
+    /// and `-%}` markers. Instead of being removed, any trimmed whitespace is
+    /// emitted surrounded by `«` and `»` with newlines and tabs escaped. The
+    /// output is not meant to be used for anything other than inspecting the
+    /// template layout.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let result = engine
+    ///     .compile("{% for n in nums -%}\n  {{ n }}\n{%- endfor %}")?
+    ///     .render(&engine, upon::value! { nums: [1, 2] })
+    ///     .with_whitespace_trace()
+    ///     .to_string()?;
+    /// assert_eq!(result, "«\\n  »1«\\n»«\\n  »2«\\n»");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_whitespace_trace(mut self) -> Self {
+        self.inner.whitespace_trace = true;
+        self
+    }
+
+    /// Define variables that describe the template being rendered.
+    ///
+    /// This is a debugging aid for finding out which template produced some
+    /// output. The following variables are defined in every template, taking
+    /// precedence over any variables with the same name.
+    ///
+    /// - `__template__` is the name of the current template.
+    /// - `__template_stack__` is a list of the names of the templates being
+    ///   rendered, starting with the template that was rendered and ending
+    ///   with the current template. Templates that are included, and
+    ///   templates that are extended, are added to the end of the list.
+    ///
+    /// The name of a template that was not registered in the engine is
+    /// `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut engine = upon::Engine::new();
+    /// engine.add_template("nested", "{{ __template__ }} in {{ __template_stack__.0 }}")?;
+    /// engine.add_template("main", "{% include \"nested\" %}")?;
+    /// let result = engine
+    ///     .template("main")
+    ///     .render(upon::Value::None)
+    ///     .with_template_vars()
+    ///     .to_string()?;
+    /// assert_eq!(result, "nested in main");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn with_template_vars(mut self) -> Self {
+        self.inner.template_vars = true;
+        self
+    }
+
     /// Render the template to a string.
     pub fn to_string(self) -> Result<String> {
-        let Self { globals, inner } = self;
+        let Self {
+            globals,
+            fallback,
+            inner,
+        } = self;
+        let missing_var_fn = inner.engine.missing_var_fn.as_deref();
         match globals {
             Globals::Owned(result) => {
                 let value = result?;
-                let stack = Stack::new(&value);
+                let stack = Stack::new(&value).with_fallback(fallback, missing_var_fn);
                 let x = to_string(inner, stack);
                 drop(value);
                 x
             }
             Globals::Borrowed(value) => {
-                let stack = Stack::new(value);
+                let stack = Stack::new(value).with_fallback(fallback, missing_var_fn);
                 to_string(inner, stack)
             }
             Globals::Fn(value_fn) => {
-                let stack = Stack::with_value_fn(&value_fn);
+                let value_fn = RefCell::new(value_fn);
+                let stack = Stack::with_value_fn(&value_fn).with_fallback(fallback, missing_var_fn);
                 to_string(inner, stack)
             }
         }
     }
 
+    /// Render the template to a byte vector.
+    ///
+    /// This is the same as [`to_string()`][Renderer::to_string] but returns
+    /// the UTF-8 bytes of the output, which is useful for callers that feed
+    /// byte sinks. The buffer is taken from the rendered string, so this does
+    /// not copy the output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let bytes = engine
+    ///     .compile("Hello {{ user.name }}!")?
+    ///     .render(&engine, upon::value! { user: { name: "John Smith" }})
+    ///     .to_vec()?;
+    /// assert_eq!(bytes, b"Hello John Smith!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn to_vec(self) -> Result<Vec<u8>> {
+        self.to_string().map(String::into_bytes)
+    }
+
     /// Render the template to the given writer.
+    ///
+    /// The output is written as the template is rendered, so if rendering
+    /// fails then any output rendered before the error will already have been
+    /// written. Use [`buffered_to_writer(..)`][Renderer::buffered_to_writer]
+    /// if this is not acceptable.
     pub fn to_writer<W>(self, w: W) -> Result<()>
     where
         W: io::Write,
     {
-        let Self { globals, inner } = self;
+        let Self {
+            globals,
+            fallback,
+            inner,
+        } = self;
+        let missing_var_fn = inner.engine.missing_var_fn.as_deref();
         match globals {
             Globals::Owned(result) => {
                 let value = result?;
-                let stack = Stack::new(&value);
+                let stack = Stack::new(&value).with_fallback(fallback, missing_var_fn);
                 to_writer(inner, stack, w)
             }
             Globals::Borrowed(value) => {
-                let stack = Stack::new(value);
+                let stack = Stack::new(value).with_fallback(fallback, missing_var_fn);
                 to_writer(inner, stack, w)
             }
             Globals::Fn(value_fn) => {
-                let stack = Stack::with_value_fn(&value_fn);
+                let value_fn = RefCell::new(value_fn);
+                let stack = Stack::with_value_fn(&value_fn).with_fallback(fallback, missing_var_fn);
                 to_writer(inner, stack, w)
             }
         }
     }
+
+    /// Render the template to the given [`fmt::Write`] writer.
+    ///
+    /// This is the same as [`to_writer(..)`][Renderer::to_writer] but for
+    /// writers like [`String`] that implement [`fmt::Write`] instead of
+    /// [`io::Write`]. If the writer fails then a format error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    ///
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("Hello {{ user.name }}!")?;
+    ///
+    /// let mut buf = String::new();
+    /// writeln!(buf, "Greeting:")?;
+    /// template
+    ///     .render(&engine, upon::value! { user: { name: "John Smith" }})
+    ///     .to_fmt_writer(&mut buf)?;
+    /// assert_eq!(buf, "Greeting:\nHello John Smith!");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn to_fmt_writer<W>(self, w: W) -> Result<()>
+    where
+        W: fmt::Write,
+    {
+        let Self {
+            globals,
+            fallback,
+            inner,
+        } = self;
+        let missing_var_fn = inner.engine.missing_var_fn.as_deref();
+        match globals {
+            Globals::Owned(result) => {
+                let value = result?;
+                let stack = Stack::new(&value).with_fallback(fallback, missing_var_fn);
+                to_fmt_writer(inner, stack, w)
+            }
+            Globals::Borrowed(value) => {
+                let stack = Stack::new(value).with_fallback(fallback, missing_var_fn);
+                to_fmt_writer(inner, stack, w)
+            }
+            Globals::Fn(value_fn) => {
+                let value_fn = RefCell::new(value_fn);
+                let stack = Stack::with_value_fn(&value_fn).with_fallback(fallback, missing_var_fn);
+                to_fmt_writer(inner, stack, w)
+            }
+        }
+    }
+
+    /// Render the template to an internal buffer and then write it to the
+    /// given writer.
+    ///
+    /// Unlike [`to_writer(..)`][Renderer::to_writer] nothing is written if
+    /// rendering fails. If rendering succeeds then the entire output is
+    /// written and the writer is flushed. Since the entire output is buffered
+    /// in memory this is not suitable for streaming large output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("Hello {{ user.name }}!")?;
+    ///
+    /// let mut buf = Vec::new();
+    /// let result = template
+    ///     .render(&engine, upon::value! { user: {} })
+    ///     .buffered_to_writer(&mut buf);
+    /// assert!(result.is_err());
+    /// assert!(buf.is_empty());
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn buffered_to_writer<W>(self, mut w: W) -> Result<()>
+    where
+        W: io::Write,
+    {
+        let s = self.to_string()?;
+        w.write_all(s.as_bytes())?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Render the template lazily, returning an iterator over the output.
+    ///
+    /// Unlike [`to_string()`][Renderer::to_string] and
+    /// [`to_writer(..)`][Renderer::to_writer] the template is only rendered as
+    /// the iterator is advanced, each item is the output emitted by a single
+    /// part of the template. This is useful for streaming large output without
+    /// buffering all of it or blocking on a writer.
+    ///
+    /// Since the iterator owns the globals, values are cloned when they are
+    /// looked up unless they were borrowed using
+    /// [`render_from`][crate::Template::render_from].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let engine = upon::Engine::new();
+    /// let template = engine.compile("Hello {{ user.name }}!")?;
+    /// let chunks = template
+    ///     .render(&engine, upon::value! { user: { name: "John Smith" }})
+    ///     .into_chunks()
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(chunks, ["Hello ", "John Smith", "!"]);
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    pub fn into_chunks(self) -> Chunks<'render> {
+        let Self {
+            globals,
+            fallback,
+            inner,
+        } = self;
+        let missing_var_fn = inner.engine.missing_var_fn.as_deref();
+        match globals {
+            Globals::Owned(Ok(value)) => to_chunks(
+                inner,
+                Stack::with_owned(value).with_fallback(fallback, missing_var_fn),
+            ),
+            Globals::Owned(Err(err)) => Chunks {
+                state: ChunksState::Err(err),
+            },
+            Globals::Borrowed(value) => to_chunks(
+                inner,
+                Stack::new(value).with_fallback(fallback, missing_var_fn),
+            ),
+            Globals::Fn(value_fn) => to_chunks(
+                inner,
+                Stack::with_owned_value_fn(Box::new(RefCell::new(value_fn)))
+                    .with_fallback(fallback, missing_var_fn),
+            ),
+        }
+    }
 }
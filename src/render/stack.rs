@@ -1,23 +1,42 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+
 use crate::render::iter::LoopState;
 use crate::render::value::{lookup_path, lookup_path_maybe};
 use crate::types::ast;
 use crate::value::ValueCow;
-use crate::{Error, Result, Value, ValueAccess, ValueAccessOp, ValueFn, ValueMember};
+use crate::{Error, MissingVarFn, Result, Undefined, Value, ValueFn, ValueFnError, ValueMember};
 
-#[cfg_attr(internal_debug, derive(Debug))]
 pub struct Stack<'a> {
     stack: Vec<State<'a>>,
+    /// Variables that are used when a variable is not found on the stack.
+    fallback: Option<&'a Value>,
+    /// A function that is called when a variable is not found anywhere else.
+    missing_var_fn: Option<&'a MissingVarFn>,
+    /// What to do when a variable is not found anywhere.
+    undefined: Undefined,
+    /// Variables describing the template being rendered, these are looked up
+    /// before anything else.
+    template_vars: Option<Value>,
 }
 
 pub enum State<'a> {
     /// A function for fetching values.
-    ValueFn(&'a ValueFn<'a>),
+    ValueFn(&'a (dyn LookupFn + 'a)),
+
+    /// A function for fetching values that is owned by the stack, values
+    /// returned from it are always cloned.
+    OwnedValueFn(Box<dyn LookupFn + 'a>),
 
     /// An entire scope of variables, always a map
     Scope(ValueCow<'a>),
 
-    /// A single variable.
-    Var(&'a ast::Ident, ValueCow<'a>),
+    /// A single variable and its name.
+    ///
+    /// The name is stored as a string rather than a span because the
+    /// variable may be looked up from another template, e.g. in a block
+    /// override, or using a path that is not from a template at all.
+    Var(&'a str, ValueCow<'a>),
 
     /// The current state of a loop iteration
     Loop(LoopState<'a>),
@@ -26,11 +45,48 @@ pub enum State<'a> {
     Boundary,
 }
 
+/// A value function that can be stored on the stack.
+///
+/// [`ValueFn`] is invariant over its lifetime because the lifetime appears in
+/// the return type. This trait ties the returned value to the borrow of the
+/// function instead so that the stack remains covariant.
+pub trait LookupFn {
+    fn lookup<'a>(
+        &'a self,
+        path: &[ValueMember<'_>],
+    ) -> std::result::Result<Cow<'a, Value>, ValueFnError>;
+}
+
+/// The value function is [`FnMut`] so it is wrapped in a [`RefCell`]. It is
+/// never called reentrantly so the borrow never fails.
+impl LookupFn for RefCell<Box<ValueFn<'_>>> {
+    fn lookup<'a>(
+        &'a self,
+        path: &[ValueMember<'_>],
+    ) -> std::result::Result<Cow<'a, Value>, ValueFnError> {
+        (self.borrow_mut())(path)
+    }
+}
+
+#[cfg(internal_debug)]
+impl std::fmt::Debug for Stack<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stack")
+            .field("stack", &self.stack)
+            .field("fallback", &self.fallback)
+            .field("missing_var_fn", &self.missing_var_fn.map(|_| ..))
+            .field("undefined", &self.undefined)
+            .field("template_vars", &self.template_vars)
+            .finish()
+    }
+}
+
 #[cfg(internal_debug)]
 impl std::fmt::Debug for State<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::ValueFn(_) => f.debug_tuple("ValueFn").field(&(..)).finish(),
+            Self::OwnedValueFn(_) => f.debug_tuple("OwnedValueFn").field(&(..)).finish(),
             Self::Scope(scope) => f.debug_tuple("Scope").field(scope).finish(),
             Self::Var(ident, value) => f.debug_tuple("Var").field(ident).field(value).finish(),
             Self::Loop(state) => f.debug_tuple("Loop").field(state).finish(),
@@ -43,52 +99,120 @@ impl<'a> Stack<'a> {
     pub fn new(globals: &'a Value) -> Self {
         Self {
             stack: vec![State::Scope(ValueCow::Borrowed(globals))],
+            fallback: None,
+            missing_var_fn: None,
+            undefined: Undefined::Error,
+            template_vars: None,
         }
     }
 
-    pub fn with_value_fn(f: &'a ValueFn<'a>) -> Self {
+    pub fn with_value_fn(f: &'a (dyn LookupFn + 'a)) -> Self {
         Self {
             stack: vec![State::ValueFn(f)],
+            fallback: None,
+            missing_var_fn: None,
+            undefined: Undefined::Error,
+            template_vars: None,
         }
     }
 
-    /// Resolves a path to a variable on the stack.
+    pub fn with_owned(globals: Value) -> Self {
+        Self {
+            stack: vec![State::Scope(ValueCow::Owned(globals))],
+            fallback: None,
+            missing_var_fn: None,
+            undefined: Undefined::Error,
+            template_vars: None,
+        }
+    }
+
+    pub fn with_owned_value_fn(f: Box<dyn LookupFn + 'a>) -> Self {
+        Self {
+            stack: vec![State::OwnedValueFn(f)],
+            fallback: None,
+            missing_var_fn: None,
+            undefined: Undefined::Error,
+            template_vars: None,
+        }
+    }
+
+    /// Set the variables and the function that are used when a variable is
+    /// not found on the stack. These are visible from every template,
+    /// including templates included using `with`.
+    pub fn with_fallback(
+        mut self,
+        fallback: Option<&'a Value>,
+        missing_var_fn: Option<&'a MissingVarFn>,
+    ) -> Self {
+        self.fallback = fallback;
+        self.missing_var_fn = missing_var_fn;
+        self
+    }
+
+    /// Set what to do when a variable is not found anywhere.
+    pub fn with_undefined(mut self, undefined: Undefined) -> Self {
+        self.undefined = undefined;
+        self
+    }
+
+    pub fn undefined(&self) -> Undefined {
+        self.undefined
+    }
+
+    /// Set the variables that describe the template being rendered.
+    pub fn set_template_vars(&mut self, vars: Value) {
+        self.template_vars = Some(vars);
+    }
+
+    /// Resolves a path to a variable on the stack, applying the undefined
+    /// variable behavior if it is not found.
     pub fn lookup_var(&self, source: &str, v: &ast::Var) -> Result<ValueCow<'a>> {
+        match self.lookup_var_maybe(source, v)? {
+            Some(value) => Ok(value),
+            None => self.undefined_var(source, v),
+        }
+    }
+
+    /// Resolves a path to a variable on the stack, returning `None` if the
+    /// variable is not found.
+    pub fn lookup_var_maybe(&self, source: &str, v: &ast::Var) -> Result<Option<ValueCow<'a>>> {
+        if let Some(vars) = &self.template_vars {
+            let vars = ValueCow::Borrowed(vars);
+            if let Some(mut value) = lookup_path_maybe(source, &vars, &v.path)? {
+                return Ok(Some(ValueCow::Owned(value.take())));
+            }
+        }
         for state in self.stack.iter().rev() {
             match state {
                 State::ValueFn(value_fn) => {
-                    let path: Vec<_> = v
-                        .path
-                        .iter()
-                        .map(|member| {
-                            let op = match member.op {
-                                ast::AccessOp::Direct => ValueAccessOp::Direct,
-                                ast::AccessOp::Optional => ValueAccessOp::Optional,
-                            };
-                            let access = match member.access {
-                                ast::Access::Index(a) => ValueAccess::Index(a.value),
-                                ast::Access::Key(a) => ValueAccess::Key(&source[a.span]),
-                            };
-                            ValueMember { op, access }
-                        })
-                        .collect();
-                    return value_fn(&path)
-                        .map(ValueCow::Owned)
-                        .map_err(|reason| Error::render(reason, source, v.span()));
+                    let path = v.to_value_path(source);
+                    return match value_fn.lookup(&path) {
+                        Ok(Cow::Borrowed(value)) => Ok(Some(ValueCow::Borrowed(value))),
+                        Ok(Cow::Owned(value)) => Ok(Some(ValueCow::Owned(value))),
+                        Err(err) => self.lookup_fallback_or(source, v, err).map(Some),
+                    };
+                }
+
+                State::OwnedValueFn(value_fn) => {
+                    let path = v.to_value_path(source);
+                    return match value_fn.lookup(&path) {
+                        Ok(value) => Ok(Some(ValueCow::Owned(value.into_owned()))),
+                        Err(err) => self.lookup_fallback_or(source, v, err).map(Some),
+                    };
                 }
 
                 State::Scope(scope) => match lookup_path_maybe(source, scope, &v.path)? {
-                    Some(value) => return Ok(value),
+                    Some(value) => return Ok(Some(value)),
                     None => continue,
                 },
 
-                State::Var(name, var) if source[v.first().access.span()] == source[name.span] => {
-                    return lookup_path(source, var, v.rest());
+                State::Var(name, var) if source[v.first().access.span()] == **name => {
+                    return lookup_path(source, var, v.rest()).map(Some);
                 }
 
                 State::Loop(loop_state) => {
                     if let Some(value) = loop_state.lookup_var(source, v)? {
-                        return Ok(value);
+                        return Ok(Some(value));
                     }
                 }
 
@@ -100,11 +224,55 @@ impl<'a> Stack<'a> {
                 _ => {}
             }
         }
-        Err(Error::render(
-            "not found in this scope",
-            source,
-            v.first().span,
-        ))
+        self.lookup_fallback(source, v)
+    }
+
+    /// Returns the value of a variable that was not found, or an error,
+    /// depending on the undefined variable behavior.
+    pub fn undefined_var(&self, source: &str, v: &ast::Var) -> Result<ValueCow<'a>> {
+        match self.undefined {
+            Undefined::Error => Err(Error::render(
+                "not found in this scope",
+                source,
+                v.first().span,
+            )),
+            Undefined::Empty | Undefined::Keep => Ok(ValueCow::Owned(Value::None)),
+        }
+    }
+
+    /// Resolves a path using the fallback variables and then the missing
+    /// variable function, returning `None` if neither has a value.
+    fn lookup_fallback(&self, source: &str, v: &ast::Var) -> Result<Option<ValueCow<'a>>> {
+        if let Some(fallback) = self.fallback {
+            let fallback = ValueCow::Borrowed(fallback);
+            if let Some(value) = lookup_path_maybe(source, &fallback, &v.path)? {
+                return Ok(Some(value));
+            }
+        }
+        match self.missing_var_fn {
+            Some(f) => Ok(f(&v.to_value_path(source)).map(ValueCow::Owned)),
+            None => Ok(None),
+        }
+    }
+
+    /// Resolves a path using the fallback variables after a value function
+    /// failed, returning the value function error if it is not found.
+    fn lookup_fallback_or(
+        &self,
+        source: &str,
+        v: &ast::Var,
+        err: ValueFnError,
+    ) -> Result<ValueCow<'a>> {
+        match self.lookup_fallback(source, v)? {
+            Some(value) => Ok(value),
+            None => {
+                let span = match err.member.and_then(|i| v.path.get(i)) {
+                    Some(member) => member.span,
+                    None => v.span(),
+                };
+                Err(Error::render(err.reason, source, span))
+            }
+        }
     }
 
     pub fn push(&mut self, state: State<'a>) {
@@ -125,13 +293,20 @@ impl<'a> Stack<'a> {
         }
     }
 
-    pub fn pop_var(&mut self) -> (&'a ast::Ident, ValueCow<'a>) {
+    pub fn pop_var(&mut self) -> (&'a str, ValueCow<'a>) {
         match self.stack.pop().unwrap() {
             State::Var(name, value) => (name, value),
             _ => panic!("expected variable"),
         }
     }
 
+    /// Removes any variables added since the last loop state.
+    pub fn pop_to_loop_state(&mut self) {
+        while !matches!(self.stack.last().unwrap(), State::Loop(_)) {
+            self.pop_var();
+        }
+    }
+
     pub fn pop_loop_state(&mut self) -> LoopState<'a> {
         match self.stack.pop().unwrap() {
             State::Loop(state) => state,
@@ -99,8 +99,14 @@ pub fn lookup<'a>(
 ) -> Result<Option<&'a Value>> {
     match (value, &member.access) {
         (Value::List(list), ast::Access::Index(index)) => {
-            let ast::Index { value: i, .. } = index;
-            match (&member.op, list.get(*i)) {
+            let ast::Index {
+                value: i, negative, ..
+            } = *index;
+            let item = match negative {
+                true => list.len().checked_sub(i).and_then(|i| list.get(i)),
+                false => list.get(i),
+            };
+            match (&member.op, item) {
                 (_, Some(value)) => Ok(Some(value)),
                 (ast::AccessOp::Optional, _) => Ok(None),
                 (ast::AccessOp::Direct, _) => {
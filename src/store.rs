@@ -0,0 +1,98 @@
+//! Defines the [`TemplateStore`] trait.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::Template;
+
+/// A source of compiled templates that can back an [`Engine`][crate::Engine].
+///
+/// When a template is looked up using
+/// [`Engine::template`][crate::Engine::template],
+/// [`Engine::get_template`][crate::Engine::get_template] or an
+/// `{% include .. %}` statement, the engine first checks the templates added
+/// with [`Engine::add_template`][crate::Engine::add_template] and then falls
+/// back to the store set with [`Engine::set_store`][crate::Engine::set_store].
+///
+/// Compiled templates do not depend on the syntax of the engine that compiled
+/// them, so a store can be shared by multiple engines, for example by wrapping
+/// it in an [`Arc`].
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use std::sync::Arc;
+///
+/// use upon::{Engine, Syntax};
+///
+/// let mut templates = BTreeMap::new();
+/// let engine = Engine::new();
+/// templates.insert(String::from("footer"), engine.compile("Goodbye!")?);
+/// let store = Arc::new(templates);
+///
+/// let syntax = Syntax::builder().block("<%", "%>").build();
+/// let mut custom = Engine::with_syntax(syntax);
+/// custom.set_store(store.clone());
+/// custom.add_template("hello", r#"Hello! <% include "footer" %>"#)?;
+///
+/// let result = custom.template("hello").render(upon::value!{}).to_string()?;
+/// assert_eq!(result, "Hello! Goodbye!");
+/// # Ok::<(), upon::Error>(())
+/// ```
+pub trait TemplateStore: Send + Sync {
+    /// Returns the template with the given name and the name that it is stored
+    /// under, or `None` if it does not exist.
+    ///
+    /// The returned name is used in error messages and should be equal to the
+    /// requested name.
+    fn get_template(&self, name: &str) -> Option<(&str, &Template<'_>)>;
+}
+
+impl<T> TemplateStore for &T
+where
+    T: TemplateStore + ?Sized,
+{
+    #[inline]
+    fn get_template(&self, name: &str) -> Option<(&str, &Template<'_>)> {
+        (**self).get_template(name)
+    }
+}
+
+impl<T> TemplateStore for Box<T>
+where
+    T: TemplateStore + ?Sized,
+{
+    #[inline]
+    fn get_template(&self, name: &str) -> Option<(&str, &Template<'_>)> {
+        (**self).get_template(name)
+    }
+}
+
+impl<T> TemplateStore for Arc<T>
+where
+    T: TemplateStore + ?Sized,
+{
+    #[inline]
+    fn get_template(&self, name: &str) -> Option<(&str, &Template<'_>)> {
+        (**self).get_template(name)
+    }
+}
+
+impl TemplateStore for BTreeMap<String, Template<'_>> {
+    #[inline]
+    fn get_template(&self, name: &str) -> Option<(&str, &Template<'_>)> {
+        self.get_key_value(name).map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+impl<S> TemplateStore for HashMap<String, Template<'_>, S>
+where
+    S: BuildHasher + Send + Sync,
+{
+    #[inline]
+    fn get_template(&self, name: &str) -> Option<(&str, &Template<'_>)> {
+        self.get_key_value(name).map(|(k, v)| (k.as_str(), v))
+    }
+}
@@ -22,7 +22,8 @@
 //! - Integers: `42`, `0o52`, `-0x2a`
 //! - Floats: `0.123`, `-3.14`, `5.23e10`
 //! - Strings: `"Hello World!"`, escape characters are supported: `\r`, `\n`,
-//!   `\t`, `\\`, `\"`
+//!   `\t`, `\0`, `\\`, `\"` and unicode escapes with up to six hexadecimal
+//!   digits like `\u{1F600}`
 //!
 //! ## Values
 //!
@@ -52,6 +53,23 @@
 //! And also hello {{ users.2.name }}!
 //! ```
 //!
+//! A negative index counts from the end of the list, so `-1` selects the last
+//! item, `-2` the second to last item, and so on.
+//!
+//! ```text
+//! And finally hello {{ users.-1.name }}!
+//! ```
+//!
+//! Indices can be written using the same binary, octal and hexadecimal
+//! prefixes as integer literals, for example `users.0x1f`.
+//!
+//! Any keyword can be used as a field name in a dotted path, for example
+//! `user.if`. Only `if`, `not`, `else`, `endif`, `for`, `in`, `endfor`,
+//! `with`, `as`, `endwith`, `include`, `true` and `false` are reserved
+//! everywhere else. The other keywords are only keywords where they are used,
+//! at the start of a block like `{% set ... %}` or as an operator like `and`,
+//! so variables can be named `set`, `block` or `raw`.
+//!
 //! The dotted path syntax will raise an error when the field or index is not
 //! found. If you want to try lookup a field and return [`Value::None`] when it
 //! is not found then you can use the optional dotted path syntax. The following
@@ -62,7 +80,40 @@
 //! Hello {{ user.name }} {{ user?.surname }}!
 //! ```
 //!
+//! The `??` operator can be used to fall back to another expression when a
+//! value is not found or is [`Value::None`]. The operands are evaluated from
+//! left to right and the first value that is found and is not
+//! [`Value::None`] is used. Filters can be applied to each operand. If the
+//! last operand is not found then an error is raised as usual.
+//!
+//! ```text
+//! Hello {{ user.nickname ?? user.name ?? "stranger" }}!
+//! ```
+//!
+//! The `~` operator formats its operands and joins them together into a
+//! string. The operands are evaluated from left to right and are formatted
+//! as they would be by [`fmt::default`], so lists and maps can not be joined.
+//! Filters and the `??` operator are applied to each operand before they are
+//! joined.
+//!
+//! ```html
+//! <a href="{{ base_url ~ "/users/" ~ user.id }}">{{ user.name }}</a>
+//! ```
+//!
+//! Parentheses can be used to group part of an expression so that filters
+//! and operators apply to the whole group.
+//!
+//! ```html
+//! {{ (user.first_name ~ " " ~ user.last_name) | upper }}
+//! ```
+//!
+//! By default it is an error if a variable itself is not found. This can be
+//! changed using [`Engine::set_undefined_behavior`] so that such variables are
+//! rendered as empty or the tag is left in the output as written.
+//!
 //! [`Value::None`]: crate::Value::None
+//! [`fmt::default`]: crate::fmt::default
+//! [`Engine::set_undefined_behavior`]: crate::Engine::set_undefined_behavior
 //!
 //! ## Filters
 //!
@@ -84,6 +135,17 @@
 //! {{ page.path | append: ".html" }}
 //! ```
 //!
+//! Some filters also accept named arguments which are specified using
+//! `name = value` after any positional arguments.
+//!
+//! ```html
+//! {{ order.id | pad: width = 10, fill = "0" }}
+//! ```
+//!
+//! A single trailing comma is allowed after the last argument of the last
+//! filter in an expression or block, which can make generating templates
+//! easier.
+//!
 //! See the [`filters`][crate::filters] module documentation for more
 //! information on filters.
 //!
@@ -94,13 +156,13 @@
 //! ## Conditionals
 //!
 //! Conditionals are marked using an opening `if` block and a closing `endif`
-//! block. It can also have zero or more optional `else if` clauses and an
-//! optional `else` clause. A conditional renders the contents of the block
-//! based on the specified condition which can be any
-//! [**expression**](#expressions). An expression can be negated by applying the
-//! prefix `not`. The conditional evaluates the expression based on it's
-//! truthiness. The following values are considered falsy, every other value is
-//! truthy and will pass the condition:
+//! block. It can also have zero or more optional `else if` clauses, which can
+//! also be written as `elif`, and an optional `else` clause. A conditional
+//! renders the contents of the block based on the specified condition which can
+//! be any [**expression**](#expressions). An expression can be negated by
+//! applying the prefix `not`. The conditional evaluates the expression based on
+//! it's truthiness. The following values are considered falsy, every other
+//! value is truthy and will pass the condition:
 //! - `None`
 //! - Boolean `false`
 //! - An integer with value `0`
@@ -125,11 +187,62 @@
 //! {% endif %}
 //! ```
 //!
+//! A condition can also be a test using `is` or `is not` followed by the name
+//! of the test. This makes the intent clearer than relying on truthiness. The
+//! following tests are available.
+//!
+//! - `defined` passes if the variable is found. Unlike other expressions, a
+//!   variable that is not found is not an error.
+//! - `empty` passes if the value is `None` or an empty string, list, or map.
+//! - `none` passes if the value is `None`.
+//! - `string`, `number`, `list`, `map`, and `bool` pass if the value is of
+//!   that type. Both integers and floats are numbers.
+//!
+//! ```html
+//! {% if user.roles is empty %}
+//!     <p>User has no roles</p>
+//! {% else if user.groups is not empty %}
+//!     <p>User is in {{ user.groups | len }} groups</p>
+//! {% endif %}
+//!
+//! {% if user.avatar is defined %}
+//!     <img src="{{ user.avatar }}">
+//! {% endif %}
+//! ```
+//!
+//! Two expressions can be compared using one of the comparison operators `==`,
+//! `!=`, `<`, `<=`, `>`, or `>=`. Integers and floats can be compared with
+//! each other, otherwise both values must be the same type. Only numbers and
+//! strings can be compared using the ordering operators. Comparing any other
+//! values results in a render error.
+//!
+//! ```html
+//! {% if user.age >= 18 %}
+//!     <p>Welcome</p>
+//! {% else if user.status == "pending" %}
+//!     <p>Your account is pending</p>
+//! {% endif %}
+//! ```
+//!
+//! Conditions can be combined using `and` and `or`, where `and` binds tighter
+//! than `or`. Evaluation stops as soon as the result is known, so in the
+//! following example `user.profile.name` is only looked up if `user.profile`
+//! is truthy. The `not` prefix only applies to the operand that directly
+//! follows it.
+//!
+//! ```html
+//! {% if user.profile and user.profile.name == "admin" or user.is_owner %}
+//!     <p>Welcome back</p>
+//! {% else if not user.is_enabled or user.roles is empty %}
+//!     <p>Access denied</p>
+//! {% endif %}
+//! ```
+//!
 //! ## Loops
 //!
 //! Loops are marked using an opening `for` block and a closing `endfor` block.
 //! A loop renders the contents of the block once for each item in the specified
-//! sequence. This is done by unpacking each item into one or two variables.
+//! sequence. This is done by unpacking each item into one or more variables.
 //! These variables are added to the scope within the loop block and shadow any
 //! variables with the same name in the outer scope. The specified sequence can
 //! be any [**expression**](#expressions) but it must resolve to a list or map.
@@ -156,20 +269,87 @@
 //! {% endfor %}
 //! ```
 //!
-//! Additionally, there are three special values available within loops.
+//! Maps are always iterated in sorted key order, not in the order that the
+//! keys were inserted or the fields of a struct were declared. If the order
+//! matters then use a list of maps instead, for example
+//! `[{ id: "b", .. }, { id: "a", .. }]`, and iterate over the list.
+//!
+//! If each item in a list is itself a list then it can be unpacked into three
+//! or more loop variables. Every item must have exactly as many elements as
+//! there are loop variables, otherwise rendering fails.
+//!
+//! ```html
+//! {% for id, label, count in rows %}
+//!     <tr><td>{{ id }}</td><td>{{ label }}</td><td>{{ count }}</td></tr>
+//! {% endfor %}
+//! ```
+//!
+//! Loops can also iterate over a range of integers using `start..end` or
+//! `start..=end` to include the end. The bounds can be any variable or literal
+//! that resolves to an integer. If the end is before the start then the loop
+//! body is not rendered at all.
+//!
+//! ```html
+//! {% for page in 1..=page_count %}
+//!     <a href="/page/{{ page }}">{{ page }}</a>
+//! {% endfor %}
+//! ```
+//!
+//! Lists and ranges can be iterated in reverse by adding the `reversed`
+//! modifier after the iterable. The list is not copied, and the special loop
+//! values below reflect the reversed order, so `loop.first` is `true` for the
+//! last item in the list. Maps are always iterated in key order, so trying to
+//! iterate over a map in reverse fails.
+//!
+//! ```html
+//! {% for post in posts reversed %}
+//!     <h2>{{ post.title }}</h2>
+//! {% endfor %}
+//! ```
+//!
+//! Additionally, there are some special values available within loops.
 //!
 //! - `loop.index`: a zero-based index of the current value in the iterable
+//! - `loop.index0`: the same as `loop.index`
+//! - `loop.index1`: a one-based index of the current value in the iterable
+//! - `loop.length`: the total number of items in the iterable, or `None` if
+//!   the length of a [lazy][crate::filters::Lazy] iterator is unknown or the
+//!   length of a range is too large to be an integer
 //! - `loop.first`: `true` if this is the first iteration of the loop
 //! - `loop.last`: `true` if this is the last iteration of the loop
 //!
 //! ```html
 //! <ul>
 //! {% for user in users %}
-//!     <li>{{ loop.index }}. {{ user.name }}</li>
+//!     <li>{{ loop.index1 }} of {{ loop.length }}. {{ user.name }}</li>
 //! {% endfor %}
 //! </ul>
 //! ```
 //!
+//! Use a `break` block to exit a loop early and a `continue` block to skip to
+//! the next iteration. These are only valid inside a `for` block.
+//!
+//! ```html
+//! {% for user in users %}
+//!     {% if user.is_hidden %}{% continue %}{% endif %}
+//!     {% if user.is_last %}{% break %}{% endif %}
+//!     <p>{{ user.name }}</p>
+//! {% endfor %}
+//! ```
+//!
+//! A loop can have an `else` clause which is rendered instead of the loop body
+//! if the sequence has no items, for example an empty list, an empty map or an
+//! empty range. The loop variables are not available within the `else`
+//! clause, and `break` and `continue` can't be used in it to refer to the loop.
+//!
+//! ```html
+//! {% for user in users %}
+//!     <p>{{ user.name }}</p>
+//! {% else %}
+//!     <p>No users found.</p>
+//! {% endfor %}
+//! ```
+//!
 //! ## With
 //!
 //! "With" blocks can be used to create a variable from an
@@ -182,6 +362,57 @@
 //! {% endwith %}
 //! ```
 //!
+//! The expression is evaluated in the enclosing scope, so nested blocks can
+//! derive new variables from outer ones.
+//!
+//! ```html
+//! {% with user.name as name %}
+//!     {% with name | upper as shout %}
+//!         {{ name }} {{ shout }}
+//!     {% endwith %}
+//! {% endwith %}
+//! ```
+//!
+//! Multiple variables can be created at once by separating them with commas.
+//! They are created in order, so each expression can use the variables created
+//! before it in the same block. All of them go out of scope at the end of the
+//! block.
+//!
+//! ```html
+//! {% with user.profile.settings as settings, settings.theme as theme %}
+//!     {{ theme }} {{ settings.lang }}
+//! {% endwith %}
+//! ```
+//!
+//! ## Set
+//!
+//! A "set" block binds the result of an [**expression**](#expressions) to a
+//! name without a closing block. The name is valid until the end of the
+//! enclosing scope, for example the end of the template or the end of the
+//! current `if`, `for`, or `with` block. Setting the same name again shadows
+//! the previous binding.
+//!
+//! ```html
+//! {% set fullname = user.names | join: " " %}
+//! Hello {{ fullname }}!
+//! ```
+//!
+//! ## Autoescape
+//!
+//! "Autoescape" blocks can be used to toggle escaping for a region of the
+//! template. Within an `autoescape off` block expressions are emitted using
+//! [`fmt::default`][crate::fmt::default] instead of the engine's default
+//! formatter, which is useful when the content is trusted. An `autoescape on`
+//! block restores the engine's default formatter. Expressions that specify a
+//! formatter explicitly, like `{{ user.name | escape_html }}`, are not
+//! affected.
+//!
+//! ```html
+//! {% autoescape off %}
+//!     {{ trusted_html }}
+//! {% endautoescape %}
+//! ```
+//!
 //! ## Include
 //!
 //! "Include" blocks can be used to render nested templates. The nested template
@@ -213,10 +444,103 @@
 //! </body>
 //! ```
 //!
+//! The template name can also be given as a variable, in which case the name is
+//! looked up when the template is rendered. The variable must evaluate to a
+//! string.
+//!
+//! ```html
+//! <body>
+//!     ...
+//!
+//!     {% include page.footer with path.to.footer.info %}
+//!
+//! </body>
+//! ```
+//!
+//! By default it is an error if the nested template is not found. Adding
+//! `ignore missing` after the name renders nothing instead. Errors raised while
+//! rendering a nested template that is found are still returned, and so are
+//! errors returned by a custom template function set using
+//! [`with_template_fn`][crate::Renderer::with_template_fn].
+//!
+//! ```html
+//! <body>
+//!     ...
+//!
+//!     {% include "plugins/footer" ignore missing with path.to.footer.info %}
+//!
+//! </body>
+//! ```
+//!
 //! Self-referential templates and include cycles are allowed but the maximum
 //! include depth is restricted by the engine setting
 //! [`set_max_include_depth`][crate::Engine::set_max_include_depth].
 //!
+//! ## Extends
+//!
+//! "Extends" and "block" blocks can be used to share a layout between
+//! templates. A base template defines named blocks, and a child template that
+//! extends the base template can override any of them. For example, assuming
+//! the following template has been registered in the engine as "base".
+//!
+//! ```html
+//! <head>
+//!     <title>{% block title %}My site{% endblock %}</title>
+//! </head>
+//! <body>
+//!     {% block content %}{% endblock %}
+//! </body>
+//! ```
+//!
+//! Then the following child template would render the "base" template
+//! with its own `content` block in place of the one in the base template. The
+//! `title` block is not overridden so the one in the base template is used.
+//!
+//! ```html
+//! {% extends "base" %}
+//!
+//! {% block content %}
+//!     <p>Hello {{ user.name }}!</p>
+//! {% endblock %}
+//! ```
+//!
+//! The `extends` block must be at the top level of the template and any
+//! content outside of blocks in the child template is ignored. Blocks can be
+//! nested and a base template can itself extend another template, in which
+//! case the most derived override of each block is rendered. Overridden
+//! blocks are rendered with the variables of the base template in scope. Each
+//! extended template counts towards the maximum include depth.
+//!
+//! A single block can also be rendered on its own, without the rest of the
+//! layout, using [`with_block`][crate::Renderer::with_block].
+//!
+//! ## Include raw
+//!
+//! "Include raw" blocks can be used to inline static content, like a CSS file,
+//! without treating it as a template. The content is resolved at render time
+//! using the function set with
+//! [`with_include_raw_fn`][crate::Renderer::with_include_raw_fn] and is
+//! emitted verbatim, so any delimiters in it are not interpreted.
+//!
+//! ```html
+//! <style>
+//!     {% include_raw "main.css" %}
+//! </style>
+//! ```
+//!
+//! ## Raw
+//!
+//! "Raw" blocks can be used to emit template syntax literally. Everything
+//! between `{% raw %}` and the first `{% endraw %}` is emitted verbatim,
+//! expressions, blocks, and comments within it are not interpreted. Raw blocks
+//! cannot be nested.
+//!
+//! ```text
+//! {% raw %}
+//!     Use {{ user.name }} to render the user's name.
+//! {% endraw %}
+//! ```
+//!
 //! # Whitespace control
 //!
 //! If an expression or block includes a hyphen `-` character, like `{{-`,
@@ -250,3 +574,6 @@
 //! ```text
 //! Hello, and welcome, John!
 //! ```
+//!
+//! To see exactly which whitespace was removed, render the template using
+//! [`Renderer::with_whitespace_trace`][crate::Renderer::with_whitespace_trace].
@@ -1,10 +1,13 @@
 //! AST representing a template.
 
 use crate::types::span::Span;
+use crate::{ValueAccess, ValueAccessOp, ValueMember};
 
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct Template {
     pub scope: Scope,
+    pub trims: Vec<Span>,
+    pub extends: Option<String>,
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
@@ -17,9 +20,15 @@ pub enum Stmt {
     Raw(Span),
     InlineExpr(InlineExpr),
     Include(Include),
+    IncludeRaw(String),
     IfElse(IfElse),
     ForLoop(ForLoop),
     With(With),
+    Autoescape(Autoescape),
+    Break,
+    Continue,
+    Set(Set),
+    Block(Block),
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
@@ -30,8 +39,15 @@ pub struct InlineExpr {
 
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct Include {
-    pub name: String,
+    pub name: BaseExpr,
     pub globals: Option<Expr>,
+    pub ignore_missing: bool,
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Block {
+    pub name: Ident,
+    pub body: Scope,
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
@@ -42,23 +58,87 @@ pub struct String {
 
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct IfElse {
-    pub not: bool,
-    pub cond: Expr,
+    pub cond: Cond,
     pub then_branch: Scope,
     pub else_branch: Option<Scope>,
 }
 
+#[cfg_attr(internal_debug, derive(Debug))]
+pub enum Cond {
+    Expr(Expr),
+    Test(Test),
+    Compare(Compare),
+    Not(Box<Cond>),
+    And(Vec<Cond>),
+    Or(Vec<Cond>),
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Test {
+    pub expr: Expr,
+    pub not: bool,
+    pub predicate: Predicate,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(internal_debug, derive(Debug))]
+pub enum Predicate {
+    Empty,
+    Defined,
+    None,
+    String,
+    Number,
+    List,
+    Map,
+    Bool,
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Compare {
+    pub lhs: Expr,
+    pub op: CompareOp,
+    pub rhs: Expr,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(internal_debug, derive(Debug))]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct ForLoop {
     pub vars: LoopVars,
-    pub iterable: Expr,
+    pub iterable: Iterable,
+    pub reversed: bool,
     pub body: Scope,
+    pub else_branch: Option<Scope>,
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub enum Iterable {
+    Expr(Expr),
+    Range(Range),
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Range {
+    pub start: BaseExpr,
+    pub end: BaseExpr,
+    pub inclusive: bool,
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
 pub enum LoopVars {
     Item(Ident),
     KeyValue(KeyValue),
+    Tuple(Tuple),
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
@@ -68,10 +148,28 @@ pub struct KeyValue {
     pub span: Span,
 }
 
+/// Three or more loop variables that each list item is unpacked into.
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Tuple {
+    pub vars: Vec<Ident>,
+    pub span: Span,
+}
+
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct With {
-    pub expr: Expr,
+    pub bindings: Vec<(Expr, Ident)>,
+    pub body: Scope,
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Set {
     pub name: Ident,
+    pub expr: Expr,
+}
+
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Autoescape {
+    pub enabled: bool,
     pub body: Scope,
 }
 
@@ -79,6 +177,24 @@ pub struct With {
 pub enum Expr {
     Base(BaseExpr),
     Call(Call),
+    Coalesce(Coalesce),
+    Concat(Concat),
+}
+
+/// Two or more expressions separated by `??`, the first one that is found and
+/// is not `None` is used.
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Coalesce {
+    pub exprs: Vec<Expr>,
+    pub span: Span,
+}
+
+/// Two or more expressions separated by `~`, the values are formatted and
+/// joined together into a string.
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct Concat {
+    pub exprs: Vec<Expr>,
+    pub span: Span,
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
@@ -92,9 +208,16 @@ pub struct Call {
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct Args {
     pub values: Vec<BaseExpr>,
+    pub named: Vec<NamedArg>,
     pub span: Span,
 }
 
+#[cfg_attr(internal_debug, derive(Debug))]
+pub struct NamedArg {
+    pub name: Ident,
+    pub value: BaseExpr,
+}
+
 #[cfg_attr(internal_debug, derive(Debug))]
 pub enum BaseExpr {
     Var(Var),
@@ -130,6 +253,8 @@ pub enum Access {
 #[cfg_attr(internal_debug, derive(Debug))]
 pub struct Index {
     pub value: usize,
+    /// Whether the index counts from the end of the list.
+    pub negative: bool,
     pub span: Span,
 }
 
@@ -157,11 +282,41 @@ impl String {
     }
 }
 
+impl CompareOp {
+    pub const fn human(&self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+impl Predicate {
+    pub const fn human(&self) -> &'static str {
+        match self {
+            Self::Empty => "empty",
+            Self::Defined => "defined",
+            Self::None => "none",
+            Self::String => "string",
+            Self::Number => "number",
+            Self::List => "list",
+            Self::Map => "map",
+            Self::Bool => "bool",
+        }
+    }
+}
+
 impl Expr {
     pub fn span(&self) -> Span {
         match self {
             Self::Base(base) => base.span(),
             Self::Call(call) => call.span,
+            Self::Coalesce(coalesce) => coalesce.span,
+            Self::Concat(concat) => concat.span,
         }
     }
 }
@@ -191,6 +346,33 @@ impl Var {
     pub fn rest(&self) -> &[Member] {
         &self.path[1..]
     }
+
+    /// Returns whether any member of the path uses optional access.
+    pub fn is_optional(&self) -> bool {
+        self.path
+            .iter()
+            .any(|member| matches!(member.op, AccessOp::Optional))
+    }
+
+    /// Converts the path to the public representation passed to value
+    /// functions.
+    pub fn to_value_path<'a>(&self, source: &'a str) -> Vec<ValueMember<'a>> {
+        self.path
+            .iter()
+            .map(|member| {
+                let op = match member.op {
+                    AccessOp::Direct => ValueAccessOp::Direct,
+                    AccessOp::Optional => ValueAccessOp::Optional,
+                };
+                let access = match member.access {
+                    Access::Index(a) if a.negative => ValueAccess::NegativeIndex(a.value),
+                    Access::Index(a) => ValueAccess::Index(a.value),
+                    Access::Key(a) => ValueAccess::Key(&source[a.span]),
+                };
+                ValueMember { op, access }
+            })
+            .collect()
+    }
 }
 
 impl Access {
@@ -1,4 +1,6 @@
 pub mod ast;
+#[cfg(feature = "precompile")]
+pub mod precompile;
 pub mod program;
 pub mod span;
 pub mod syntax;
@@ -0,0 +1,1175 @@
+//! Converts a compiled [`Template`] to and from bytes so that it can be stored
+//! and later loaded without compiling the template source again.
+//!
+//! The format is a header and version, followed by the template source,
+//! instructions, trimmed whitespace and extended template name, followed by a
+//! checksum of all the preceding bytes. Integers are encoded using LEB128.
+//!
+//! Loaded templates are verified so that malformed instructions result in an
+//! error when loading instead of a panic when rendering.
+
+use std::borrow::Cow;
+
+use crate::types::ast;
+use crate::types::program::{Instr, Template};
+use crate::types::span::Span;
+use crate::{Error, Result, Value};
+
+const MAGIC: &[u8] = b"UPON";
+const VERSION: u8 = 2;
+
+/// Converts a compiled template to bytes.
+pub fn to_bytes(template: &Template<'_>) -> Vec<u8> {
+    let mut w = Writer {
+        buf: MAGIC.to_vec(),
+    };
+    w.u8(VERSION);
+    w.str(&template.source);
+    w.len(template.instrs.len());
+    for instr in &template.instrs {
+        w.instr(instr);
+    }
+    w.len(template.trims.len());
+    for span in &template.trims {
+        w.span(*span);
+    }
+    w.len(template.tags.len());
+    for span in &template.tags {
+        w.span(*span);
+    }
+    match &template.extends {
+        Some(name) => {
+            w.u8(1);
+            w.string(name);
+        }
+        None => w.u8(0),
+    }
+    let checksum = fnv1a(&w.buf);
+    w.buf.extend(checksum.to_le_bytes());
+    w.buf
+}
+
+/// Loads a compiled template from bytes that were returned by [`to_bytes`].
+pub fn from_bytes(bytes: &[u8]) -> Result<Template<'static>> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(Error::precompile("missing header"));
+    }
+    let i = bytes
+        .len()
+        .checked_sub(8)
+        .filter(|&i| i >= MAGIC.len())
+        .ok_or_else(err_eof)?;
+    let (data, checksum) = bytes.split_at(i);
+    if fnv1a(data).to_le_bytes() != checksum {
+        return Err(Error::precompile("checksum mismatch"));
+    }
+
+    let mut r = Reader {
+        bytes: &data[MAGIC.len()..],
+    };
+    let version = r.u8()?;
+    if version != VERSION {
+        return Err(Error::precompile(format!(
+            "unsupported version `{version}`"
+        )));
+    }
+    let source = r.str()?.to_owned();
+    let mut instrs = Vec::new();
+    for _ in 0..r.len()? {
+        instrs.push(r.instr()?);
+    }
+    let mut trims = Vec::new();
+    for _ in 0..r.len()? {
+        trims.push(r.span()?);
+    }
+    let mut tags = Vec::new();
+    for _ in 0..r.len()? {
+        tags.push(r.span()?);
+    }
+    let extends = match r.u8()? {
+        0 => None,
+        1 => Some(r.string()?),
+        _ => return Err(err_invalid()),
+    };
+    if !r.bytes.is_empty() {
+        return Err(Error::precompile("unexpected trailing bytes"));
+    }
+
+    let template = Template {
+        source: Cow::Owned(source),
+        instrs,
+        trims,
+        tags,
+        extends,
+    };
+    verify(&template)?;
+    Ok(template)
+}
+
+/// A 64-bit FNV-1a hash of the bytes.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn err_eof() -> Error {
+    Error::precompile("unexpected end of input")
+}
+
+fn err_invalid() -> Error {
+    Error::precompile("invalid instruction")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Writer
+////////////////////////////////////////////////////////////////////////////////
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn bool(&mut self, v: bool) {
+        self.u8(u8::from(v));
+    }
+
+    fn u64(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.u8((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.u8(v as u8);
+    }
+
+    fn len(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.buf.extend(s.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.len(span.m);
+        self.len(span.n);
+    }
+
+    fn ident(&mut self, ident: &ast::Ident) {
+        self.span(ident.span);
+    }
+
+    fn string(&mut self, s: &ast::String) {
+        self.str(&s.name);
+        self.span(s.span);
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::None => self.u8(0),
+            Value::Bool(b) => {
+                self.u8(1);
+                self.bool(*b);
+            }
+            Value::Integer(i) => {
+                self.u8(2);
+                self.buf.extend(i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                self.u8(3);
+                self.buf.extend(f.to_bits().to_le_bytes());
+            }
+            Value::String(s) => {
+                self.u8(4);
+                self.str(s);
+            }
+            Value::List(_) | Value::Map(_) => {
+                unreachable!("compiled template literals are always scalars")
+            }
+        }
+    }
+
+    fn var(&mut self, var: &ast::Var) {
+        self.len(var.path.len());
+        for member in &var.path {
+            self.bool(matches!(member.op, ast::AccessOp::Optional));
+            match member.access {
+                ast::Access::Index(index) => {
+                    self.u8(0);
+                    self.len(index.value);
+                    self.bool(index.negative);
+                    self.span(index.span);
+                }
+                ast::Access::Key(key) => {
+                    self.u8(1);
+                    self.ident(&key);
+                }
+            }
+            self.span(member.span);
+        }
+    }
+
+    fn base_expr(&mut self, base_expr: &ast::BaseExpr) {
+        match base_expr {
+            ast::BaseExpr::Var(var) => {
+                self.u8(0);
+                self.var(var);
+            }
+            ast::BaseExpr::Literal(lit) => {
+                self.u8(1);
+                self.value(&lit.value);
+                self.span(lit.span);
+            }
+        }
+    }
+
+    fn loop_vars(&mut self, vars: &ast::LoopVars) {
+        match vars {
+            ast::LoopVars::Item(item) => {
+                self.u8(0);
+                self.ident(item);
+            }
+            ast::LoopVars::KeyValue(kv) => {
+                self.u8(1);
+                self.ident(&kv.key);
+                self.ident(&kv.value);
+                self.span(kv.span);
+            }
+            ast::LoopVars::Tuple(tuple) => {
+                self.u8(2);
+                self.len(tuple.vars.len());
+                for var in &tuple.vars {
+                    self.ident(var);
+                }
+                self.span(tuple.span);
+            }
+        }
+    }
+
+    fn args(&mut self, args: &ast::Args) {
+        self.len(args.values.len());
+        for value in &args.values {
+            self.base_expr(value);
+        }
+        self.len(args.named.len());
+        for arg in &args.named {
+            self.ident(&arg.name);
+            self.base_expr(&arg.value);
+        }
+        self.span(args.span);
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match instr {
+            Instr::Jump(j) => {
+                self.u8(0);
+                self.len(*j);
+            }
+            Instr::JumpIfTrue(j) => {
+                self.u8(1);
+                self.len(*j);
+            }
+            Instr::JumpIfFalse(j) => {
+                self.u8(2);
+                self.len(*j);
+            }
+            Instr::Emit(span) => {
+                self.u8(3);
+                self.span(*span);
+            }
+            Instr::EmitRaw(span) => {
+                self.u8(4);
+                self.span(*span);
+            }
+            Instr::EmitWith(name, span) => {
+                self.u8(5);
+                self.ident(name);
+                self.span(*span);
+            }
+            Instr::LoopStart(vars, span, reversed) => {
+                self.u8(6);
+                self.loop_vars(vars);
+                self.span(*span);
+                self.bool(*reversed);
+            }
+            Instr::LoopStartRange(vars, inclusive, [start, end], reversed) => {
+                self.u8(7);
+                self.loop_vars(vars);
+                self.bool(*inclusive);
+                self.span(*start);
+                self.span(*end);
+                self.bool(*reversed);
+            }
+            Instr::LoopNext(j, k) => {
+                self.u8(8);
+                self.len(*j);
+                match k {
+                    Some(k) => {
+                        self.u8(1);
+                        self.len(*k);
+                    }
+                    None => self.u8(0),
+                }
+            }
+            Instr::LoopBreak(j) => {
+                self.u8(9);
+                self.len(*j);
+            }
+            Instr::LoopContinue(j) => {
+                self.u8(10);
+                self.len(*j);
+            }
+            Instr::WithStart(name) => {
+                self.u8(11);
+                self.ident(name);
+            }
+            Instr::WithEnd => self.u8(12),
+            Instr::AutoescapeStart(enabled) => {
+                self.u8(13);
+                self.bool(*enabled);
+            }
+            Instr::AutoescapeEnd => self.u8(14),
+            Instr::Include(name, ignore_missing) => {
+                self.u8(15);
+                self.base_expr(name);
+                self.bool(*ignore_missing);
+            }
+            Instr::IncludeWith(name, ignore_missing) => {
+                self.u8(16);
+                self.base_expr(name);
+                self.bool(*ignore_missing);
+            }
+            Instr::BlockStart(name, j) => {
+                self.u8(17);
+                self.ident(name);
+                self.len(*j);
+            }
+            Instr::IncludeRaw(name) => {
+                self.u8(18);
+                self.string(name);
+            }
+            Instr::ExprStart(var) => {
+                self.u8(19);
+                self.var(var);
+            }
+            Instr::ExprStartLit(value) => {
+                self.u8(20);
+                self.value(value);
+            }
+            Instr::Test(predicate, span) => {
+                self.u8(21);
+                match predicate {
+                    ast::Predicate::Empty => self.u8(0),
+                    ast::Predicate::Defined => self.u8(1),
+                    ast::Predicate::None => self.u8(2),
+                    ast::Predicate::String => self.u8(3),
+                    ast::Predicate::Number => self.u8(4),
+                    ast::Predicate::List => self.u8(5),
+                    ast::Predicate::Map => self.u8(6),
+                    ast::Predicate::Bool => self.u8(7),
+                }
+                self.span(*span);
+            }
+            Instr::ExprPush => self.u8(22),
+            Instr::Compare(op, span) => {
+                self.u8(23);
+                self.u8(match op {
+                    ast::CompareOp::Eq => 0,
+                    ast::CompareOp::Ne => 1,
+                    ast::CompareOp::Lt => 2,
+                    ast::CompareOp::Le => 3,
+                    ast::CompareOp::Gt => 4,
+                    ast::CompareOp::Ge => 5,
+                });
+                self.span(*span);
+            }
+            Instr::Apply(name, span, args) => {
+                self.u8(24);
+                self.ident(name);
+                self.span(*span);
+                match args {
+                    Some(args) => {
+                        self.u8(1);
+                        self.args(args);
+                    }
+                    None => self.u8(0),
+                }
+            }
+            Instr::ExprStartMaybe(var, j) => {
+                self.u8(25);
+                self.var(var);
+                self.len(*j);
+            }
+            Instr::JumpIfNotNone(j) => {
+                self.u8(26);
+                self.len(*j);
+            }
+            Instr::Concat(lhs, rhs) => {
+                self.u8(27);
+                self.span(*lhs);
+                self.span(*rhs);
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Reader
+////////////////////////////////////////////////////////////////////////////////
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.bytes.len() {
+            return Err(err_eof());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(err_invalid()),
+        }
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(Error::precompile("integer overflow"))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| Error::precompile("integer overflow"))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        let n = self.len()?;
+        std::str::from_utf8(self.take(n)?).map_err(|_| Error::precompile("invalid UTF-8"))
+    }
+
+    fn span(&mut self) -> Result<Span> {
+        let m = self.len()?;
+        let n = self.len()?;
+        Ok(Span { m, n })
+    }
+
+    fn ident(&mut self) -> Result<ast::Ident> {
+        Ok(ast::Ident { span: self.span()? })
+    }
+
+    fn string(&mut self) -> Result<ast::String> {
+        let name = self.str()?.to_owned();
+        let span = self.span()?;
+        Ok(ast::String { name, span })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.u8()? {
+            0 => Ok(Value::None),
+            1 => Ok(Value::Bool(self.bool()?)),
+            2 => Ok(Value::Integer(i64::from_le_bytes(self.array()?))),
+            3 => Ok(Value::Float(f64::from_bits(u64::from_le_bytes(
+                self.array()?,
+            )))),
+            4 => Ok(Value::String(self.str()?.to_owned())),
+            _ => Err(err_invalid()),
+        }
+    }
+
+    fn var(&mut self) -> Result<ast::Var> {
+        let n = self.len()?;
+        if n == 0 {
+            return Err(err_invalid());
+        }
+        let mut path = Vec::new();
+        for _ in 0..n {
+            let op = match self.bool()? {
+                true => ast::AccessOp::Optional,
+                false => ast::AccessOp::Direct,
+            };
+            let access = match self.u8()? {
+                0 => {
+                    let value = self.len()?;
+                    let negative = self.bool()?;
+                    let span = self.span()?;
+                    ast::Access::Index(ast::Index {
+                        value,
+                        negative,
+                        span,
+                    })
+                }
+                1 => ast::Access::Key(self.ident()?),
+                _ => return Err(err_invalid()),
+            };
+            let span = self.span()?;
+            path.push(ast::Member { op, access, span });
+        }
+        Ok(ast::Var { path })
+    }
+
+    fn base_expr(&mut self) -> Result<ast::BaseExpr> {
+        match self.u8()? {
+            0 => Ok(ast::BaseExpr::Var(self.var()?)),
+            1 => {
+                let value = self.value()?;
+                let span = self.span()?;
+                Ok(ast::BaseExpr::Literal(ast::Literal { value, span }))
+            }
+            _ => Err(err_invalid()),
+        }
+    }
+
+    fn loop_vars(&mut self) -> Result<ast::LoopVars> {
+        match self.u8()? {
+            0 => Ok(ast::LoopVars::Item(self.ident()?)),
+            1 => {
+                let key = self.ident()?;
+                let value = self.ident()?;
+                let span = self.span()?;
+                Ok(ast::LoopVars::KeyValue(ast::KeyValue { key, value, span }))
+            }
+            2 => {
+                let n = self.len()?;
+                if n < 3 {
+                    return Err(err_invalid());
+                }
+                let mut vars = Vec::new();
+                for _ in 0..n {
+                    vars.push(self.ident()?);
+                }
+                let span = self.span()?;
+                Ok(ast::LoopVars::Tuple(ast::Tuple { vars, span }))
+            }
+            _ => Err(err_invalid()),
+        }
+    }
+
+    fn args(&mut self) -> Result<ast::Args> {
+        let mut values = Vec::new();
+        for _ in 0..self.len()? {
+            values.push(self.base_expr()?);
+        }
+        let mut named = Vec::new();
+        for _ in 0..self.len()? {
+            let name = self.ident()?;
+            let value = self.base_expr()?;
+            named.push(ast::NamedArg { name, value });
+        }
+        let span = self.span()?;
+        Ok(ast::Args {
+            values,
+            named,
+            span,
+        })
+    }
+
+    fn instr(&mut self) -> Result<Instr> {
+        let instr = match self.u8()? {
+            0 => Instr::Jump(self.len()?),
+            1 => Instr::JumpIfTrue(self.len()?),
+            2 => Instr::JumpIfFalse(self.len()?),
+            3 => Instr::Emit(self.span()?),
+            4 => Instr::EmitRaw(self.span()?),
+            5 => Instr::EmitWith(self.ident()?, self.span()?),
+            6 => Instr::LoopStart(self.loop_vars()?, self.span()?, self.bool()?),
+            7 => Instr::LoopStartRange(
+                self.loop_vars()?,
+                self.bool()?,
+                [self.span()?, self.span()?],
+                self.bool()?,
+            ),
+            8 => {
+                let j = self.len()?;
+                let k = match self.u8()? {
+                    0 => None,
+                    1 => Some(self.len()?),
+                    _ => return Err(err_invalid()),
+                };
+                Instr::LoopNext(j, k)
+            }
+            9 => Instr::LoopBreak(self.len()?),
+            10 => Instr::LoopContinue(self.len()?),
+            11 => Instr::WithStart(self.ident()?),
+            12 => Instr::WithEnd,
+            13 => Instr::AutoescapeStart(self.bool()?),
+            14 => Instr::AutoescapeEnd,
+            15 => Instr::Include(self.base_expr()?, self.bool()?),
+            16 => Instr::IncludeWith(self.base_expr()?, self.bool()?),
+            17 => Instr::BlockStart(self.ident()?, self.len()?),
+            18 => Instr::IncludeRaw(self.string()?),
+            19 => Instr::ExprStart(self.var()?),
+            20 => Instr::ExprStartLit(self.value()?),
+            21 => {
+                let predicate = match self.u8()? {
+                    0 => ast::Predicate::Empty,
+                    1 => ast::Predicate::Defined,
+                    2 => ast::Predicate::None,
+                    3 => ast::Predicate::String,
+                    4 => ast::Predicate::Number,
+                    5 => ast::Predicate::List,
+                    6 => ast::Predicate::Map,
+                    7 => ast::Predicate::Bool,
+                    _ => return Err(err_invalid()),
+                };
+                Instr::Test(predicate, self.span()?)
+            }
+            22 => Instr::ExprPush,
+            23 => {
+                let op = match self.u8()? {
+                    0 => ast::CompareOp::Eq,
+                    1 => ast::CompareOp::Ne,
+                    2 => ast::CompareOp::Lt,
+                    3 => ast::CompareOp::Le,
+                    4 => ast::CompareOp::Gt,
+                    5 => ast::CompareOp::Ge,
+                    _ => return Err(err_invalid()),
+                };
+                Instr::Compare(op, self.span()?)
+            }
+            24 => {
+                let name = self.ident()?;
+                let span = self.span()?;
+                let args = match self.u8()? {
+                    0 => None,
+                    1 => Some(self.args()?),
+                    _ => return Err(err_invalid()),
+                };
+                Instr::Apply(name, span, args)
+            }
+            25 => Instr::ExprStartMaybe(self.var()?, self.len()?),
+            26 => Instr::JumpIfNotNone(self.len()?),
+            27 => Instr::Concat(self.span()?, self.span()?),
+            _ => return Err(err_invalid()),
+        };
+        Ok(instr)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Verification
+////////////////////////////////////////////////////////////////////////////////
+
+/// The state of the renderer before executing an instruction.
+#[derive(Clone, Default, PartialEq)]
+struct Machine {
+    /// Whether there is a current expression.
+    expr: bool,
+    /// The number of values on the operand stack.
+    operands: usize,
+    /// The loop states and variables on the stack.
+    stack: Vec<Frame>,
+    /// The number of enclosing `autoescape` blocks.
+    autoescapes: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Frame {
+    /// A loop state along with the index of its `LoopNext` instruction.
+    Loop(usize),
+    Var,
+}
+
+/// A `block` along with the state at the start of it. The block may be
+/// rendered in place of a block in another template, so instructions inside
+/// it may not jump outside of it or remove anything from the stack that was
+/// added outside of it.
+#[derive(Clone, Copy)]
+struct Block {
+    start: usize,
+    end: usize,
+    stack: usize,
+    autoescapes: usize,
+}
+
+/// Checks that all the spans in the template are valid and that rendering the
+/// instructions can never panic or loop forever.
+///
+/// This simulates the renderer state through the instructions, a state is
+/// recorded for each instruction and any jump to the instruction must have
+/// the same state. The only backwards jumps allowed are to the `LoopNext`
+/// instruction of the innermost loop, which advances the loop iterator.
+fn verify(t: &Template<'_>) -> Result<()> {
+    let err = |i: usize| Error::precompile(format!("invalid instruction at {i}"));
+
+    let source = &*t.source;
+    let spans_ok = t.trims.iter().all(|s| span_ok(source, *s))
+        && t.tags.iter().all(|s| span_ok(source, *s))
+        && t.tags.windows(2).all(|w| w[0].n <= w[1].m)
+        && t.extends.iter().all(|s| span_ok(source, s.span));
+    if !spans_ok {
+        return Err(Error::precompile("invalid span"));
+    }
+
+    let n = t.instrs.len();
+    let mut states: Vec<Option<Machine>> = vec![None; n + 1];
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut next = Some(Machine::default());
+
+    for (i, instr) in t.instrs.iter().enumerate() {
+        if !instr_spans_ok(source, instr) {
+            return Err(err(i));
+        }
+        while matches!(blocks.last(), Some(b) if b.end <= i) {
+            blocks.pop();
+        }
+
+        let mut m = match (next.take(), states[i].take()) {
+            (Some(a), Some(b)) if a != b => return Err(err(i)),
+            (Some(m), _) | (None, Some(m)) => m,
+            // This instruction is unreachable.
+            (None, None) => continue,
+        };
+        states[i] = Some(m.clone());
+
+        let floor = blocks.last().copied();
+        let loop_next = matches!(t.instrs.get(i + 1), Some(Instr::LoopNext(..)));
+        let mut jumps = Vec::new();
+
+        let ok = match instr {
+            Instr::Jump(j) => {
+                jumps.push((*j, m));
+                true
+            }
+            Instr::JumpIfTrue(j) | Instr::JumpIfFalse(j) => {
+                let ok = std::mem::take(&mut m.expr);
+                jumps.push((*j, m.clone()));
+                next = Some(m);
+                ok
+            }
+            Instr::Emit(_) | Instr::EmitWith(_, _) | Instr::IncludeWith(..) => {
+                let ok = std::mem::take(&mut m.expr);
+                next = Some(m);
+                ok
+            }
+            Instr::WithStart(_) => {
+                let ok = std::mem::take(&mut m.expr);
+                m.stack.push(Frame::Var);
+                next = Some(m);
+                ok
+            }
+            Instr::LoopStart(_, _, _) => {
+                let ok = std::mem::take(&mut m.expr) && loop_next;
+                m.stack.push(Frame::Loop(i + 1));
+                next = Some(m);
+                ok
+            }
+            Instr::LoopStartRange(_, _, _, _) => {
+                let ok = std::mem::take(&mut m.expr) && m.operands > 0 && loop_next;
+                m.operands = m.operands.saturating_sub(1);
+                m.stack.push(Frame::Loop(i + 1));
+                next = Some(m);
+                ok
+            }
+            Instr::LoopNext(j, k) => {
+                let ok = !m.expr && m.stack.last() == Some(&Frame::Loop(i));
+                let mut exit = m.clone();
+                exit.stack.pop();
+                if let Some(k) = k {
+                    jumps.push((*k, exit.clone()));
+                }
+                jumps.push((*j, exit));
+                next = Some(m);
+                ok
+            }
+            Instr::LoopBreak(j) | Instr::LoopContinue(j) => {
+                match m.stack.iter().rposition(|f| matches!(f, Frame::Loop(_))) {
+                    Some(k) if floor.map_or(true, |b| k >= b.stack) => {
+                        let keep = if let Instr::LoopBreak(_) = instr {
+                            k
+                        } else {
+                            k + 1
+                        };
+                        m.stack.truncate(keep);
+                        jumps.push((*j, m));
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Instr::WithEnd => {
+                let ok = m.stack.last() == Some(&Frame::Var)
+                    && floor.map_or(true, |b| m.stack.len() > b.stack);
+                m.stack.pop();
+                next = Some(m);
+                ok
+            }
+            Instr::AutoescapeStart(_) => {
+                m.autoescapes += 1;
+                next = Some(m);
+                true
+            }
+            Instr::AutoescapeEnd => {
+                let ok = m.autoescapes > floor.map_or(0, |b| b.autoescapes);
+                m.autoescapes = m.autoescapes.saturating_sub(1);
+                next = Some(m);
+                ok
+            }
+            Instr::BlockStart(_, j) => {
+                let ok = !m.expr && m.operands == 0 && *j > i;
+                blocks.push(Block {
+                    start: i,
+                    end: *j,
+                    stack: m.stack.len(),
+                    autoescapes: m.autoescapes,
+                });
+                jumps.push((*j, m.clone()));
+                next = Some(m);
+                ok
+            }
+            Instr::EmitRaw(_) | Instr::Include(..) | Instr::IncludeRaw(_) => {
+                let ok = !m.expr;
+                next = Some(m);
+                ok
+            }
+            Instr::ExprStart(_) | Instr::ExprStartLit(_) => {
+                let ok = !std::mem::replace(&mut m.expr, true);
+                next = Some(m);
+                ok
+            }
+            Instr::ExprStartMaybe(_, j) => {
+                let ok = !m.expr;
+                jumps.push((*j, m.clone()));
+                m.expr = true;
+                next = Some(m);
+                ok
+            }
+            Instr::JumpIfNotNone(j) => {
+                let ok = m.expr;
+                jumps.push((*j, m.clone()));
+                m.expr = false;
+                next = Some(m);
+                ok
+            }
+            Instr::Test(_, _) | Instr::Apply(_, _, _) => {
+                let ok = m.expr;
+                next = Some(m);
+                ok
+            }
+            Instr::ExprPush => {
+                let ok = std::mem::take(&mut m.expr);
+                m.operands += 1;
+                next = Some(m);
+                ok
+            }
+            Instr::Compare(_, _) | Instr::Concat(_, _) => {
+                let ok = m.expr && m.operands > 0;
+                m.operands = m.operands.saturating_sub(1);
+                next = Some(m);
+                ok
+            }
+        };
+        if !ok {
+            return Err(err(i));
+        }
+
+        // A jump must stay inside the current block and must agree with the
+        // state already recorded for the target. A backwards jump must be to
+        // the `LoopNext` of the innermost loop, otherwise it could jump to
+        // itself or to an earlier instruction forever.
+        let floor = blocks.iter().rev().find(|b| b.start < i);
+        for (j, m) in jumps {
+            let inside = floor.map_or(j <= n, |b| b.start < j && j <= b.end);
+            if !inside || (j <= i && m.stack.last() != Some(&Frame::Loop(j))) {
+                return Err(err(i));
+            }
+            match &mut states[j] {
+                Some(state) if *state != m => return Err(err(i)),
+                Some(_) => {}
+                // Backwards jumps must target an instruction that was
+                // already verified.
+                None if j <= i => return Err(err(i)),
+                state @ None => *state = Some(m),
+            }
+        }
+    }
+
+    match (next, states[n].take()) {
+        (Some(a), Some(b)) if a != b => Err(err(n)),
+        (Some(m), _) | (None, Some(m)) if m != Machine::default() => Err(err(n)),
+        _ => Ok(()),
+    }
+}
+
+fn span_ok(source: &str, span: Span) -> bool {
+    span.m <= span.n && source.is_char_boundary(span.m) && source.is_char_boundary(span.n)
+}
+
+fn instr_spans_ok(source: &str, instr: &Instr) -> bool {
+    let ok = |span: Span| span_ok(source, span);
+    let var_ok = |var: &ast::Var| var.path.iter().all(|m| ok(m.span) && ok(m.access.span()));
+    let base_expr_ok = |e: &ast::BaseExpr| match e {
+        ast::BaseExpr::Var(var) => var_ok(var),
+        ast::BaseExpr::Literal(lit) => ok(lit.span),
+    };
+    let loop_vars_ok = |vars: &ast::LoopVars| match vars {
+        ast::LoopVars::Item(item) => ok(item.span),
+        ast::LoopVars::KeyValue(kv) => ok(kv.key.span) && ok(kv.value.span) && ok(kv.span),
+        ast::LoopVars::Tuple(tuple) => tuple.vars.iter().all(|v| ok(v.span)) && ok(tuple.span),
+    };
+    match instr {
+        Instr::Jump(_)
+        | Instr::JumpIfTrue(_)
+        | Instr::JumpIfFalse(_)
+        | Instr::JumpIfNotNone(_)
+        | Instr::LoopNext(..)
+        | Instr::LoopBreak(_)
+        | Instr::LoopContinue(_)
+        | Instr::WithEnd
+        | Instr::AutoescapeStart(_)
+        | Instr::AutoescapeEnd
+        | Instr::ExprStartLit(_)
+        | Instr::ExprPush => true,
+        Instr::Emit(span)
+        | Instr::EmitRaw(span)
+        | Instr::Test(_, span)
+        | Instr::Compare(_, span) => ok(*span),
+        Instr::Concat(lhs, rhs) => ok(*lhs) && ok(*rhs),
+        Instr::EmitWith(name, span) => ok(name.span) && ok(*span),
+        Instr::LoopStart(vars, span, _) => loop_vars_ok(vars) && ok(*span),
+        Instr::LoopStartRange(vars, _, [start, end], _) => {
+            loop_vars_ok(vars) && ok(*start) && ok(*end)
+        }
+        Instr::WithStart(name) | Instr::BlockStart(name, _) => ok(name.span),
+        Instr::Include(name, _) | Instr::IncludeWith(name, _) => base_expr_ok(name),
+        Instr::IncludeRaw(name) => ok(name.span),
+        Instr::ExprStart(var) | Instr::ExprStartMaybe(var, _) => var_ok(var),
+        Instr::Apply(name, span, args) => {
+            ok(name.span)
+                && ok(*span)
+                && args.iter().all(|args| {
+                    ok(args.span)
+                        && args.values.iter().all(base_expr_ok)
+                        && args
+                            .named
+                            .iter()
+                            .all(|arg| ok(arg.name.span) && base_expr_ok(&arg.value))
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(source: &str, instrs: Vec<Instr>) -> Result<Template<'static>> {
+        let template = Template {
+            source: Cow::Owned(source.to_owned()),
+            instrs,
+            trims: Vec::new(),
+            tags: Vec::new(),
+            extends: None,
+        };
+        from_bytes(&to_bytes(&template))
+    }
+
+    fn var(m: usize, n: usize) -> ast::Var {
+        let span = Span { m, n };
+        ast::Var {
+            path: vec![ast::Member {
+                op: ast::AccessOp::Direct,
+                access: ast::Access::Key(ast::Ident { span }),
+                span,
+            }],
+        }
+    }
+
+    fn item(m: usize, n: usize) -> ast::LoopVars {
+        ast::LoopVars::Item(ast::Ident {
+            span: Span { m, n },
+        })
+    }
+
+    #[track_caller]
+    fn assert_invalid(source: &str, instrs: Vec<Instr>, reason: &str) {
+        let err = load(source, instrs).unwrap_err();
+        assert_eq!(err.reason(), Some(reason));
+    }
+
+    #[test]
+    fn verify_ok() {
+        // {% for x in xs %}{% if x %}{% break %}{% endif %}{{ x }}{% endfor %}
+        let instrs = vec![
+            Instr::ExprStart(var(0, 2)),
+            Instr::LoopStart(item(3, 4), Span { m: 0, n: 2 }, false),
+            Instr::LoopNext(9, None),
+            Instr::ExprStart(var(3, 4)),
+            Instr::JumpIfFalse(6),
+            Instr::LoopBreak(9),
+            Instr::ExprStart(var(3, 4)),
+            Instr::Emit(Span { m: 3, n: 4 }),
+            Instr::Jump(2),
+        ];
+        load("xs x", instrs).unwrap();
+    }
+
+    #[test]
+    fn verify_err_span() {
+        let instrs = vec![Instr::EmitRaw(Span { m: 0, n: 3 })];
+        assert_invalid("é", instrs, "invalid instruction at 0");
+        let instrs = vec![Instr::EmitRaw(Span { m: 0, n: 5 })];
+        assert_invalid(
+            "lorem",
+            vec![Instr::EmitRaw(Span { m: 2, n: 1 })],
+            "invalid instruction at 0",
+        );
+        assert_invalid("lore", instrs, "invalid instruction at 0");
+    }
+
+    #[test]
+    fn verify_err_expr() {
+        let instrs = vec![Instr::Emit(Span { m: 0, n: 1 })];
+        assert_invalid("x", instrs, "invalid instruction at 0");
+        let instrs = vec![Instr::ExprStart(var(0, 1))];
+        assert_invalid("x", instrs, "invalid instruction at 1");
+        let instrs = vec![
+            Instr::ExprStart(var(0, 1)),
+            Instr::Compare(ast::CompareOp::Eq, Span { m: 0, n: 1 }),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 1");
+    }
+
+    #[test]
+    fn verify_err_jump() {
+        assert_invalid("", vec![Instr::Jump(2)], "invalid instruction at 0");
+        // A backwards jump to an instruction that was never reached.
+        let instrs = vec![Instr::Jump(2), Instr::WithEnd, Instr::Jump(1)];
+        assert_invalid("", instrs, "invalid instruction at 2");
+        // The states at the jump target don't match.
+        let instrs = vec![
+            Instr::ExprStart(var(0, 1)),
+            Instr::JumpIfTrue(3),
+            Instr::ExprStart(var(0, 1)),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 3");
+    }
+
+    #[test]
+    fn verify_err_jump_backwards() {
+        // A jump to itself.
+        assert_invalid("", vec![Instr::Jump(0)], "invalid instruction at 0");
+        // A jump to an earlier instruction with the same state.
+        let instrs = vec![Instr::EmitRaw(Span { m: 0, n: 1 }), Instr::Jump(0)];
+        assert_invalid("x", instrs, "invalid instruction at 1");
+        // A jump inside a loop that is not to the `LoopNext`.
+        let instrs = vec![
+            Instr::ExprStart(var(0, 1)),
+            Instr::LoopStart(item(0, 1), Span { m: 0, n: 1 }, false),
+            Instr::LoopNext(5, None),
+            Instr::EmitRaw(Span { m: 0, n: 1 }),
+            Instr::Jump(3),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 4");
+    }
+
+    #[test]
+    fn verify_err_stack() {
+        assert_invalid("", vec![Instr::WithEnd], "invalid instruction at 0");
+        assert_invalid("", vec![Instr::AutoescapeEnd], "invalid instruction at 0");
+        assert_invalid("", vec![Instr::LoopBreak(1)], "invalid instruction at 0");
+        let instrs = vec![
+            Instr::ExprStart(var(0, 1)),
+            Instr::LoopStart(item(0, 1), Span { m: 0, n: 1 }, false),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 1");
+        let instrs = vec![
+            Instr::ExprStart(var(0, 1)),
+            Instr::WithStart(ast::Ident {
+                span: Span { m: 0, n: 1 },
+            }),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 2");
+    }
+
+    #[test]
+    fn verify_err_block() {
+        // A `block` inside a loop that breaks out of the loop.
+        let instrs = vec![
+            Instr::ExprStart(var(0, 1)),
+            Instr::LoopStart(item(0, 1), Span { m: 0, n: 1 }, false),
+            Instr::LoopNext(6, None),
+            Instr::BlockStart(
+                ast::Ident {
+                    span: Span { m: 0, n: 1 },
+                },
+                5,
+            ),
+            Instr::LoopBreak(6),
+            Instr::Jump(2),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 4");
+        // A jump out of a `block`.
+        let instrs = vec![
+            Instr::BlockStart(
+                ast::Ident {
+                    span: Span { m: 0, n: 1 },
+                },
+                2,
+            ),
+            Instr::Jump(3),
+            Instr::EmitRaw(Span { m: 0, n: 1 }),
+        ];
+        assert_invalid("x", instrs, "invalid instruction at 1");
+    }
+
+    #[test]
+    fn render_keep_err_no_emit() {
+        // A tag whose expression is never emitted.
+        let template = Template {
+            source: Cow::Borrowed("{{ x }}"),
+            instrs: vec![
+                Instr::ExprStart(var(3, 4)),
+                Instr::JumpIfFalse(2),
+                Instr::EmitRaw(Span { m: 0, n: 2 }),
+            ],
+            trims: Vec::new(),
+            tags: vec![Span { m: 0, n: 7 }],
+            extends: None,
+        };
+        let template = crate::Template::from_bytes(&to_bytes(&template)).unwrap();
+        let engine = crate::Engine::new();
+        let err = template
+            .render(&engine, crate::Value::None)
+            .with_undefined_behavior(crate::Undefined::Keep)
+            .to_string()
+            .unwrap_err();
+        assert_eq!(
+            err.reason(),
+            Some("expected expression in tag to be emitted")
+        );
+    }
+
+    #[test]
+    fn from_bytes_err_version() {
+        let mut bytes = b"UPON\x03".to_vec();
+        bytes.extend(fnv1a(&bytes).to_le_bytes());
+        let err = from_bytes(&bytes).unwrap_err();
+        assert_eq!(err.reason(), Some("unsupported version `3`"));
+    }
+}
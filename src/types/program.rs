@@ -5,7 +5,7 @@ use std::borrow::Cow;
 
 use crate::types::ast;
 use crate::types::span::Span;
-use crate::Value;
+use crate::{TemplateStats, Value, ValueAccess, ValueMember};
 
 pub const FIXME: usize = !0;
 
@@ -13,6 +13,12 @@ pub const FIXME: usize = !0;
 pub struct Template<'source> {
     pub source: Cow<'source, str>,
     pub instrs: Vec<Instr>,
+    /// The whitespace that was trimmed from the source, in source order.
+    pub trims: Vec<Span>,
+    /// The spans of the `{{ ... }}` tags, in source order.
+    pub tags: Vec<Span>,
+    /// The name of the template that this template extends.
+    pub extends: Option<ast::String>,
 }
 
 #[cfg_attr(internal_debug, derive(Debug))]
@@ -32,14 +38,33 @@ pub enum Instr {
     /// Emit raw template
     EmitRaw(Span),
 
-    /// Apply the filter or value formatter to the current expression and emit
+    /// Apply the filter or value formatter to the current expression and emit,
+    /// the span is the span of the expression without the filter
     EmitWith(ast::Ident, Span),
 
-    /// Start a loop over the current expression
-    LoopStart(ast::LoopVars, Span),
+    /// Start a loop over the current expression, the flag is whether to
+    /// iterate in reverse
+    LoopStart(ast::LoopVars, Span, bool),
 
-    /// Advance and jump to the start of the loop
-    LoopNext(usize),
+    /// Start a loop over a range of integers, the start of the range is
+    /// popped from the operand stack and the end is the current expression.
+    /// The first flag is whether the range is inclusive, the spans are those
+    /// of the start and end, and the last flag is whether to iterate in
+    /// reverse.
+    LoopStartRange(ast::LoopVars, bool, [Span; 2], bool),
+
+    /// Advance to the next iteration of the loop, if the loop is finished
+    /// then remove the loop state and jump to the first instruction, or to the
+    /// second instruction if the loop did not yield any items
+    LoopNext(usize, Option<usize>),
+
+    /// Remove any variables added inside the loop and the loop state and jump
+    /// out of the loop
+    LoopBreak(usize),
+
+    /// Remove any variables added inside the loop and jump to the next
+    /// iteration of the loop
+    LoopContinue(usize),
 
     /// Push the current expression to the stack as a variable
     WithStart(ast::Ident),
@@ -47,11 +72,26 @@ pub enum Instr {
     /// Remove a previously added variable from the stack
     WithEnd,
 
-    /// Render a template
-    Include(ast::String),
+    /// Enable or disable escaping using the default formatter
+    AutoescapeStart(bool),
+
+    /// Restore the escaping mode from before the previous `AutoescapeStart`
+    AutoescapeEnd,
+
+    /// Render a template, the name is a string literal or a variable. If the
+    /// flag is set then nothing is rendered if the template is not found.
+    Include(ast::BaseExpr, bool),
 
     /// Render a template with the current expression
-    IncludeWith(ast::String),
+    IncludeWith(ast::BaseExpr, bool),
+
+    /// Start a block that ends at the given instruction. If a template that
+    /// extends this one overrides the block then the override is rendered
+    /// instead and we jump to the end of the block.
+    BlockStart(ast::Ident, usize),
+
+    /// Emit the raw contents returned by the raw include function
+    IncludeRaw(ast::String),
 
     /// Lookup a variable and start building an expression
     ExprStart(ast::Var),
@@ -59,10 +99,303 @@ pub enum Instr {
     /// Start building an expression using a literal
     ExprStartLit(Value),
 
-    /// Apply the filter to the value at the top of the stack
+    /// Lookup a variable and start building an expression, if the variable
+    /// cannot be looked up then jump to the instruction instead
+    ExprStartMaybe(ast::Var, usize),
+
+    /// Jump to the instruction if the current expression is not `None`,
+    /// otherwise discard the current expression
+    JumpIfNotNone(usize),
+
+    /// Replace the current expression with the result of the test
+    Test(ast::Predicate, Span),
+
+    /// Move the current expression to the operand stack
+    ExprPush,
+
+    /// Replace the current expression with the result of comparing the value
+    /// popped from the operand stack with it
+    Compare(ast::CompareOp, Span),
+
+    /// Replace the current expression with the value popped from the operand
+    /// stack and the current expression formatted and joined together, the
+    /// spans are the spans of the two operands
+    Concat(Span, Span),
+
+    /// Apply the filter to the value at the top of the stack, the span is the
+    /// span of the expression the filter is applied to
     Apply(ast::Ident, Span, Option<ast::Args>),
 }
 
+impl Template<'_> {
+    /// Returns the range of instructions that make up the body of the block
+    /// with the given name, if this template defines it.
+    pub fn block(&self, name: &str) -> Option<(usize, usize)> {
+        self.instrs
+            .iter()
+            .enumerate()
+            .find_map(|(i, instr)| match instr {
+                Instr::BlockStart(ident, j) if self.source[ident.span] == *name => {
+                    Some((i + 1, *j))
+                }
+                _ => None,
+            })
+    }
+
+    /// Returns the span of the `{{ ... }}` tag that contains the span, if any.
+    pub fn tag(&self, span: Span) -> Option<Span> {
+        let i = self.tags.partition_point(|tag| tag.n <= span.m);
+        self.tags
+            .get(i)
+            .copied()
+            .filter(|tag| tag.m <= span.m && span.n <= tag.n)
+    }
+
+    /// Returns the names of the blocks that this template defines.
+    pub fn block_names(&self) -> impl Iterator<Item = &str> {
+        self.instrs.iter().filter_map(|instr| match instr {
+            Instr::BlockStart(ident, _) => Some(&self.source[ident.span]),
+            _ => None,
+        })
+    }
+
+    /// Returns statistics about the instructions.
+    pub fn stats(&self) -> TemplateStats {
+        let mut stats = TemplateStats {
+            instrs: self.instrs.len(),
+            ..TemplateStats::default()
+        };
+        for instr in &self.instrs {
+            match instr {
+                Instr::LoopStart(..) | Instr::LoopStartRange(..) => stats.loops += 1,
+                Instr::Include(..) | Instr::IncludeWith(..) | Instr::IncludeRaw(_) => {
+                    stats.includes += 1
+                }
+                Instr::ExprStart(_) | Instr::ExprStartLit(_) | Instr::ExprStartMaybe(..) => {
+                    stats.exprs += 1
+                }
+                Instr::Apply(..) | Instr::EmitWith(..) => stats.calls += 1,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Returns a human readable listing of the instructions.
+    pub fn disassemble(&self) -> std::string::String {
+        let mut out = std::string::String::new();
+        for (i, instr) in self.instrs.iter().enumerate() {
+            let (opcode, operand, span) = self.describe(instr);
+            let line = match span {
+                Some(span) => format!("{i:04}  {opcode:<16}{operand:<24} @ {}..{}", span.m, span.n),
+                None => format!("{i:04}  {opcode:<16}{operand}"),
+            };
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Returns the deduplicated paths of all variables that are looked up from
+    /// the render context.
+    ///
+    /// Variables bound inside the template by `for` and `with` blocks, as well
+    /// as the special `loop` variable, are excluded.
+    pub fn variables(&self) -> Vec<Vec<ValueMember<'_>>> {
+        let src = &*self.source;
+
+        // The names bound in the current scope along with the instruction
+        // at which they go out of scope, if known.
+        let mut locals: Vec<(&str, usize)> = Vec::new();
+        let mut vars: Vec<Vec<ValueMember<'_>>> = Vec::new();
+
+        for (i, instr) in self.instrs.iter().enumerate() {
+            while matches!(locals.last(), Some((_, end)) if *end <= i) {
+                locals.pop();
+            }
+
+            let mut add = |var: &ast::Var| {
+                if let ast::Access::Key(key) = var.first().access {
+                    let name = &src[key.span];
+                    if locals.iter().any(|(n, _)| *n == name) {
+                        return;
+                    }
+                }
+                let path = var.to_value_path(src);
+                if !vars.contains(&path) {
+                    vars.push(path);
+                }
+            };
+
+            match instr {
+                Instr::ExprStart(var)
+                | Instr::ExprStartMaybe(var, _)
+                | Instr::Include(ast::BaseExpr::Var(var), _)
+                | Instr::IncludeWith(ast::BaseExpr::Var(var), _) => add(var),
+                Instr::Apply(_, _, Some(args)) => {
+                    let named = args.named.iter().map(|arg| &arg.value);
+                    for arg in args.values.iter().chain(named) {
+                        if let ast::BaseExpr::Var(var) = arg {
+                            add(var);
+                        }
+                    }
+                }
+                Instr::LoopStart(loop_vars, _, _) | Instr::LoopStartRange(loop_vars, _, _, _) => {
+                    let end = match self.instrs.get(i + 1) {
+                        Some(Instr::LoopNext(j, _)) => *j,
+                        _ => panic!("expected loop next instr"),
+                    };
+                    locals.push(("loop", end));
+                    match loop_vars {
+                        ast::LoopVars::Item(item) => locals.push((&src[item.span], end)),
+                        ast::LoopVars::KeyValue(kv) => {
+                            locals.push((&src[kv.key.span], end));
+                            locals.push((&src[kv.value.span], end));
+                        }
+                        ast::LoopVars::Tuple(tuple) => {
+                            for var in &tuple.vars {
+                                locals.push((&src[var.span], end));
+                            }
+                        }
+                    }
+                }
+                Instr::WithStart(name) => locals.push((&src[name.span], usize::MAX)),
+                Instr::WithEnd => {
+                    locals.pop();
+                }
+                _ => {}
+            }
+        }
+
+        vars
+    }
+
+    /// Returns the deduplicated top-level names of the variables that are
+    /// looked up from the render context but are not in `available`.
+    pub fn check_against(&self, available: &[&str]) -> Result<(), Vec<std::string::String>> {
+        let mut unknown: Vec<std::string::String> = Vec::new();
+        for path in self.variables() {
+            if let Some(ValueMember {
+                access: ValueAccess::Key(name),
+                ..
+            }) = path.first()
+            {
+                if !available.contains(name) && !unknown.iter().any(|n| n == name) {
+                    unknown.push((*name).to_owned());
+                }
+            }
+        }
+        match unknown.is_empty() {
+            true => Ok(()),
+            false => Err(unknown),
+        }
+    }
+
+    fn describe(&self, instr: &Instr) -> (&'static str, std::string::String, Option<Span>) {
+        let src = &*self.source;
+        match instr {
+            Instr::Jump(j) => ("Jump", format!("-> {j:04}"), None),
+            Instr::JumpIfTrue(j) => ("JumpIfTrue", format!("-> {j:04}"), None),
+            Instr::JumpIfFalse(j) => ("JumpIfFalse", format!("-> {j:04}"), None),
+            Instr::Emit(span) => ("Emit", std::string::String::new(), Some(*span)),
+            Instr::EmitRaw(span) => ("EmitRaw", format!("{:?}", &src[*span]), Some(*span)),
+            Instr::EmitWith(name, span) => (
+                "EmitWith",
+                src[name.span].to_owned(),
+                Some(span.combine(name.span)),
+            ),
+            Instr::LoopStart(vars, span, reversed) => {
+                let mut operand = fmt_loop_vars(src, vars);
+                if *reversed {
+                    operand.push_str(" reversed");
+                }
+                ("LoopStart", operand, Some(*span))
+            }
+            Instr::LoopStartRange(vars, inclusive, [start, end], reversed) => {
+                let op = if *inclusive { "..=" } else { ".." };
+                let mut operand = format!("{} {op}", fmt_loop_vars(src, vars));
+                if *reversed {
+                    operand.push_str(" reversed");
+                }
+                ("LoopStartRange", operand, Some(start.combine(*end)))
+            }
+            Instr::LoopNext(j, None) => ("LoopNext", format!("-> {j:04}"), None),
+            Instr::LoopNext(j, Some(k)) => ("LoopNext", format!("-> {j:04} else {k:04}"), None),
+            Instr::LoopBreak(j) => ("LoopBreak", format!("-> {j:04}"), None),
+            Instr::LoopContinue(j) => ("LoopContinue", format!("-> {j:04}"), None),
+            Instr::WithStart(name) => ("WithStart", src[name.span].to_owned(), Some(name.span)),
+            Instr::WithEnd => ("WithEnd", std::string::String::new(), None),
+            Instr::AutoescapeStart(enabled) => {
+                let mode = if *enabled { "on" } else { "off" };
+                ("AutoescapeStart", mode.to_owned(), None)
+            }
+            Instr::AutoescapeEnd => ("AutoescapeEnd", std::string::String::new(), None),
+            Instr::Include(name, ignore_missing) => {
+                let operand = include_operand(src, name, *ignore_missing);
+                ("Include", operand, Some(name.span()))
+            }
+            Instr::IncludeWith(name, ignore_missing) => {
+                let operand = include_operand(src, name, *ignore_missing);
+                ("IncludeWith", operand, Some(name.span()))
+            }
+            Instr::BlockStart(name, j) => {
+                let operand = format!("{} -> {j:04}", &src[name.span]);
+                ("BlockStart", operand, Some(name.span))
+            }
+            Instr::IncludeRaw(name) => {
+                let operand = format!("{:?}", name.as_str());
+                ("IncludeRaw", operand, Some(name.span))
+            }
+            Instr::ExprStart(var) => ("ExprStart", src[var.span()].to_owned(), Some(var.span())),
+            Instr::ExprStartLit(value) => ("ExprStartLit", format!("{value:?}"), None),
+            Instr::ExprStartMaybe(var, j) => {
+                let operand = format!("{} -> {j:04}", &src[var.span()]);
+                ("ExprStartMaybe", operand, Some(var.span()))
+            }
+            Instr::JumpIfNotNone(j) => ("JumpIfNotNone", format!("-> {j:04}"), None),
+            Instr::Test(predicate, span) => ("Test", predicate.human().to_owned(), Some(*span)),
+            Instr::ExprPush => ("ExprPush", std::string::String::new(), None),
+            Instr::Compare(op, span) => ("Compare", op.human().to_owned(), Some(*span)),
+            Instr::Concat(lhs, rhs) => (
+                "Concat",
+                std::string::String::new(),
+                Some(lhs.combine(*rhs)),
+            ),
+            Instr::Apply(name, span, args) => {
+                let (operand, end) = match args {
+                    Some(args) => (
+                        format!("{}: {}", &src[name.span], &src[args.span]),
+                        args.span,
+                    ),
+                    None => (src[name.span].to_owned(), name.span),
+                };
+                ("Apply", operand, Some(span.combine(end)))
+            }
+        }
+    }
+}
+
+fn fmt_loop_vars(src: &str, vars: &ast::LoopVars) -> std::string::String {
+    match vars {
+        ast::LoopVars::Item(item) => src[item.span].to_owned(),
+        ast::LoopVars::KeyValue(kv) => format!("{}, {}", &src[kv.key.span], &src[kv.value.span]),
+        ast::LoopVars::Tuple(tuple) => tuple
+            .vars
+            .iter()
+            .map(|var| &src[var.span])
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+fn include_operand(src: &str, name: &ast::BaseExpr, ignore_missing: bool) -> std::string::String {
+    let name = &src[name.span()];
+    match ignore_missing {
+        true => format!("{name} ignore missing"),
+        false => name.to_owned(),
+    }
+}
+
 #[cfg(not(internal_debug))]
 impl std::fmt::Debug for Template<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -1,5 +1,7 @@
 use std::marker::PhantomData;
 
+use crate::{Error, Result};
+
 /// The template syntax configuration.
 ///
 /// Use [`Syntax::default()`] to get the default syntax configuration and
@@ -7,17 +9,41 @@ use std::marker::PhantomData;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Syntax<'a> {
     pub(crate) patterns: Vec<(Kind, String)>,
+    pub(crate) case_insensitive_keywords: bool,
     _marker: PhantomData<&'a ()>,
 }
 
 /// A builder for the syntax configuration.
 ///
 /// This struct is typically created using [`Syntax::builder()`].
+///
+/// # Delimiters
+///
+/// Each begin delimiter also has a whitespace trimming variant with `-`
+/// appended, and each end delimiter has one with `-` prepended, for example
+/// `{{-` and `-}}`. All of these delimiters are searched for at the same time
+/// using the following rules:
+///
+/// - The delimiter that starts earliest in the source wins.
+/// - If several delimiters start at the same position the longest one wins.
+///
+/// This means delimiters may overlap as long as they are not identical. For
+/// example with `expr("{", "}")` and `block("{{", "}}")` the source `{{` is
+/// always a begin block delimiter and `{ {` is a begin expression followed by
+/// a `{` character.
+///
+/// Within a tag, delimiters are checked for before any other syntax. For
+/// example with `expr("<", ">")` a `>` always ends the expression, so it can't
+/// be used as a comparison operator.
+///
+/// Delimiters that are identical, including the trimming variants, can't be
+/// told apart and are rejected by [`try_build`][SyntaxBuilder::try_build].
 #[derive(Debug, Clone)]
 pub struct SyntaxBuilder<'a> {
     expr: Option<(&'a str, &'a str)>,
     block: Option<(&'a str, &'a str)>,
     comment: Option<(&'a str, &'a str)>,
+    case_insensitive_keywords: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -83,6 +109,57 @@ impl<'a> Syntax<'a> {
     pub fn builder() -> SyntaxBuilder<'a> {
         SyntaxBuilder::new()
     }
+
+    /// Returns the begin and end expression delimiters, if set.
+    ///
+    /// The whitespace trimming variants are not included, see the
+    /// [delimiter rules](SyntaxBuilder#delimiters).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let syntax = upon::Syntax::builder().expr("<{", "}>").build();
+    /// assert_eq!(syntax.expr(), Some(("<{", "}>")));
+    /// assert_eq!(syntax.block(), None);
+    /// ```
+    #[inline]
+    pub fn expr(&self) -> Option<(&str, &str)> {
+        self.delimiters(Kind::BeginExpr, Kind::EndExpr)
+    }
+
+    /// Returns the begin and end block delimiters, if set.
+    ///
+    /// The whitespace trimming variants are not included.
+    #[inline]
+    pub fn block(&self) -> Option<(&str, &str)> {
+        self.delimiters(Kind::BeginBlock, Kind::EndBlock)
+    }
+
+    /// Returns the begin and end comment delimiters, if set.
+    ///
+    /// The whitespace trimming variants are not included.
+    #[inline]
+    pub fn comment(&self) -> Option<(&str, &str)> {
+        self.delimiters(Kind::BeginComment, Kind::EndComment)
+    }
+
+    /// Returns whether keywords are matched regardless of case.
+    ///
+    /// See [`SyntaxBuilder::case_insensitive_keywords`].
+    #[inline]
+    pub fn case_insensitive_keywords(&self) -> bool {
+        self.case_insensitive_keywords
+    }
+
+    fn delimiters(&self, begin: Kind, end: Kind) -> Option<(&str, &str)> {
+        let find = |kind: Kind| {
+            self.patterns
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, p)| p.as_str())
+        };
+        Some((find(begin)?, find(end)?))
+    }
 }
 
 impl<'a> SyntaxBuilder<'a> {
@@ -94,6 +171,7 @@ impl<'a> SyntaxBuilder<'a> {
             expr: None,
             block: None,
             comment: None,
+            case_insensitive_keywords: false,
         }
     }
 
@@ -139,8 +217,68 @@ impl<'a> SyntaxBuilder<'a> {
         self
     }
 
+    /// Set whether keywords are matched regardless of case.
+    ///
+    /// By default keywords must be lowercase, so `{% IF cond %}` is an error
+    /// and `If` can be used as a variable name. When enabled, `if`, `If`, and
+    /// `IF` are all the same keyword and all casings of keywords are reserved,
+    /// so they can no longer be used as variable names.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let syntax = upon::Syntax::builder()
+    ///     .expr("{{", "}}")
+    ///     .block("{%", "%}")
+    ///     .case_insensitive_keywords(true)
+    ///     .build();
+    /// let engine = upon::Engine::with_syntax(syntax);
+    /// let result = engine
+    ///     .compile("{% IF user.enabled %}Hello {{ user.name }}!{% EndIf %}")?
+    ///     .render(&engine, upon::value! { user: { enabled: true, name: "John" } })
+    ///     .to_string()?;
+    /// assert_eq!(result, "Hello John!");
+    /// # Ok::<(), upon::Error>(())
+    /// ```
+    #[inline]
+    pub fn case_insensitive_keywords(&mut self, yes: bool) -> &mut Self {
+        self.case_insensitive_keywords = yes;
+        self
+    }
+
     /// Builds the syntax configuration.
+    ///
+    /// # Panics
+    ///
+    /// If any of the delimiters are identical, see
+    /// [`try_build`][SyntaxBuilder::try_build].
+    #[track_caller]
     pub fn build(&self) -> Syntax<'a> {
+        match self.try_build() {
+            Ok(syntax) => syntax,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Builds the syntax configuration, returning an error if any of the
+    /// delimiters are identical.
+    ///
+    /// See the [delimiter rules](SyntaxBuilder#delimiters) for how
+    /// overlapping delimiters are matched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let syntax = upon::Syntax::builder()
+    ///     .expr("{", "}")
+    ///     .block("{-", "-}")
+    ///     .try_build();
+    /// assert_eq!(
+    ///     syntax.unwrap_err().to_string(),
+    ///     "invalid syntax: ambiguous delimiters, begin expression trim and begin block are both `{-`"
+    /// );
+    /// ```
+    pub fn try_build(&self) -> Result<Syntax<'a>> {
         let mut patterns = Vec::new();
         if let Some((begin, end)) = self.expr {
             patterns.push((Kind::BeginExpr, begin.into()));
@@ -160,10 +298,20 @@ impl<'a> SyntaxBuilder<'a> {
             patterns.push((Kind::BeginCommentTrim, format!("{begin}-")));
             patterns.push((Kind::EndCommentTrim, format!("-{end}")));
         }
-        Syntax {
+        for (i, (k1, p1)) in patterns.iter().enumerate() {
+            if let Some((k2, _)) = patterns[i + 1..].iter().find(|(_, p2)| p1 == p2) {
+                return Err(Error::ambiguous_syntax(format!(
+                    "ambiguous delimiters, {} and {} are both `{p1}`",
+                    k1.human(),
+                    k2.human()
+                )));
+            }
+        }
+        Ok(Syntax {
             patterns,
+            case_insensitive_keywords: self.case_insensitive_keywords,
             _marker: PhantomData,
-        }
+        })
     }
 }
 
@@ -185,6 +333,23 @@ impl Kind {
             _ => unreachable!(),
         }
     }
+
+    fn human(&self) -> &'static str {
+        match self {
+            Self::BeginExpr => "begin expression",
+            Self::EndExpr => "end expression",
+            Self::BeginExprTrim => "begin expression trim",
+            Self::EndExprTrim => "end expression trim",
+            Self::BeginBlock => "begin block",
+            Self::EndBlock => "end block",
+            Self::BeginBlockTrim => "begin block trim",
+            Self::EndBlockTrim => "end block trim",
+            Self::BeginComment => "begin comment",
+            Self::EndComment => "end comment",
+            Self::BeginCommentTrim => "begin comment trim",
+            Self::EndCommentTrim => "end comment trim",
+        }
+    }
 }
 
 impl From<Kind> for usize {
@@ -22,7 +22,6 @@ impl Deref for ValueCow<'_> {
 }
 
 impl<'a> ValueCow<'a> {
-    #[cfg(feature = "filters")]
     pub fn take(&mut self) -> Value {
         match self {
             Self::Borrowed(v) => v.clone(),
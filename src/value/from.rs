@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 
+use crate::value::MapBuilder;
 use crate::Value;
 
 impl From<()> for Value {
@@ -107,6 +108,12 @@ where
     }
 }
 
+impl From<MapBuilder> for Value {
+    fn from(builder: MapBuilder) -> Self {
+        builder.build()
+    }
+}
+
 impl<V> From<Option<V>> for Value
 where
     V: Into<Value>,
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+
+use crate::Value;
+
+/// Converts a JSON value into a [`Value`] without serializing it again.
+///
+/// JSON integers become [`Value::Integer`], any integer larger than
+/// [`i64::MAX`] is clamped to [`i64::MAX`]. All other numbers become
+/// [`Value::Float`].
+///
+/// # Examples
+///
+/// ```
+/// let json = serde_json::json!({ "user": { "name": "John Smith" } });
+///
+/// let engine = upon::Engine::new();
+/// let result = engine
+///     .compile("Hello {{ user.name }}!")?
+///     .render_from(&engine, &upon::Value::from(json))
+///     .to_string()?;
+/// assert_eq!(result, "Hello John Smith!");
+/// # Ok::<(), upon::Error>(())
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Self::None,
+            serde_json::Value::Bool(b) => Self::Bool(b),
+            serde_json::Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+                (Some(i), _) => Self::Integer(i),
+                (None, Some(_)) => Self::Integer(i64::MAX),
+                (None, None) => Self::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Self::String(s),
+            serde_json::Value::Array(list) => {
+                Self::List(list.into_iter().map(Self::from).collect())
+            }
+            serde_json::Value::Object(map) => Self::Map(
+                map.into_iter()
+                    .map(|(k, v)| (k, Self::from(v)))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+        }
+    }
+}
@@ -2,18 +2,53 @@
 
 mod cow;
 mod from;
+#[cfg(feature = "json")]
+mod json;
 #[cfg(feature = "serde")]
 mod ser;
 
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
 
 pub(crate) use crate::value::cow::ValueCow;
 #[cfg(feature = "serde")]
 #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
 pub use crate::value::ser::to_value;
+use crate::Result;
 
 /// Data to be rendered represented as a recursive enum.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// # Equality and hashing
+///
+/// [`Value`] implements [`Eq`] and [`Hash`] so that it can be used as a key in
+/// a map or stored in a set. To make this possible floats are compared a bit
+/// differently to [`f64`]:
+///
+/// - `NaN` is equal to `NaN`, regardless of its sign or payload.
+/// - `-0.0` is equal to `0.0`.
+///
+/// All other floats are compared as usual. Values of different variants are
+/// never equal, so `Value::Integer(1)` is not equal to `Value::Float(1.0)`.
+///
+/// # Map ordering
+///
+/// A [`Value::Map`] is a [`BTreeMap`] so its entries are always sorted by key.
+/// The order that keys were inserted in is not preserved, this includes maps
+/// and structs that are converted using [`to_value`][crate::to_value]. This
+/// means that `{% for k, v in map %}` iterates in sorted key order. If the
+/// order matters use a [`Value::List`] of maps instead.
+///
+/// ```
+/// let value = upon::value! { b: 1, a: 2 };
+/// let keys: Vec<_> = match &value {
+///     upon::Value::Map(map) => map.keys().collect(),
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(keys, ["a", "b"]);
+/// ```
+#[derive(Debug, Clone)]
 pub enum Value {
     None,
     Bool(bool),
@@ -24,6 +59,490 @@ pub enum Value {
     Map(BTreeMap<String, Value>),
 }
 
+/// The kind of a [`Value`], i.e. its variant without any data.
+///
+/// This is used to register a formatter for a particular type of value using
+/// [`Engine::add_type_formatter`][crate::Engine::add_type_formatter].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValueKind {
+    None,
+    Bool,
+    Integer,
+    Float,
+    String,
+    List,
+    Map,
+}
+
+/// A builder for a [`Value::Map`], returned from [`Value::map`].
+///
+/// The builder converts into a [`Value`] using [`build`][MapBuilder::build] or
+/// [`From`], so builders can be nested without calling `build`.
+#[derive(Debug, Clone, Default)]
+pub struct MapBuilder {
+    map: BTreeMap<String, Value>,
+}
+
+impl MapBuilder {
+    /// Inserts an entry into the map, replacing any existing value for the
+    /// key.
+    pub fn insert<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        self.map.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the built map.
+    pub fn build(self) -> Value {
+        Value::Map(self.map)
+    }
+}
+
+/// A conversion into a [`Value`] that can be rendered.
+///
+/// This is implemented for anything that implements `Into<Value>`, which
+/// includes [`Value`] itself, as well as for `&Value`. Borrowed values are
+/// used as is and are never cloned. Any other type that implements
+/// [`serde::Serialize`] can be converted by wrapping it in [`Serialized`].
+pub trait IntoValue<'a> {
+    /// Converts this type into an owned or borrowed value.
+    ///
+    /// If this fails then the error is returned when rendering.
+    fn into_value(self) -> Result<Cow<'a, Value>>;
+}
+
+impl<'a, T> IntoValue<'a> for T
+where
+    T: Into<Value>,
+{
+    #[inline]
+    fn into_value(self) -> Result<Cow<'a, Value>> {
+        Ok(Cow::Owned(self.into()))
+    }
+}
+
+impl<'a> IntoValue<'a> for &'a Value {
+    #[inline]
+    fn into_value(self) -> Result<Cow<'a, Value>> {
+        Ok(Cow::Borrowed(self))
+    }
+}
+
+/// Wraps a [`serde`] value so that it implements [`IntoValue`].
+///
+/// The value is converted using [`to_value`] and any error is returned when
+/// rendering.
+///
+/// # Examples
+///
+/// ```
+/// #[derive(serde::Serialize)]
+/// struct User {
+///     name: &'static str,
+/// }
+///
+/// let user = User { name: "John Smith" };
+///
+/// let engine = upon::Engine::new();
+/// let result = engine
+///     .compile("Hello {{ name }}!")?
+///     .render_value(&engine, upon::Serialized(&user))
+///     .to_string()?;
+/// assert_eq!(result, "Hello John Smith!");
+/// # Ok::<(), upon::Error>(())
+/// ```
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+#[derive(Debug, Clone, Copy)]
+pub struct Serialized<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<'a, T> IntoValue<'a> for Serialized<T>
+where
+    T: serde::Serialize,
+{
+    #[inline]
+    fn into_value(self) -> Result<Cow<'a, Value>> {
+        to_value(self.0).map(Cow::Owned)
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::None, Self::None) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => (a.is_nan() && b.is_nan()) || a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Map(a), Self::Map(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::None => {}
+            Self::Bool(b) => b.hash(state),
+            Self::Integer(i) => i.hash(state),
+            Self::Float(f) => {
+                // Equal floats must hash the same, so all `NaN`s and both
+                // zeros are normalized before hashing the bit pattern.
+                let f = if f.is_nan() {
+                    f64::NAN
+                } else if *f == 0.0 {
+                    0.0
+                } else {
+                    *f
+                };
+                f.to_bits().hash(state)
+            }
+            Self::String(s) => s.hash(state),
+            Self::List(list) => list.hash(state),
+            Self::Map(map) => map.hash(state),
+        }
+    }
+}
+
+impl Value {
+    /// Returns a builder for a map.
+    ///
+    /// This is an alternative to the [`value!`][crate::value] macro that does
+    /// not require the `serde` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// let value = Value::map()
+    ///     .insert("name", "John Smith")
+    ///     .insert("emails", Value::list(["john@example.com"]))
+    ///     .insert("address", Value::map().insert("city", "Sydney"))
+    ///     .build();
+    /// assert_eq!(value.get("address.city"), Some(&Value::from("Sydney")));
+    /// ```
+    pub fn map() -> MapBuilder {
+        MapBuilder::default()
+    }
+
+    /// Returns a list of the given items.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// let value = Value::list((1..=3).map(|i| i * 2));
+    /// assert_eq!(value, Value::from([2, 4, 6]));
+    /// ```
+    pub fn list<I, V>(items: I) -> Self
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Self::List(items.into_iter().map(Into::into).collect())
+    }
+
+    /// Returns the kind of this value.
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Self::None => ValueKind::None,
+            Self::Bool(_) => ValueKind::Bool,
+            Self::Integer(_) => ValueKind::Integer,
+            Self::Float(_) => ValueKind::Float,
+            Self::String(_) => ValueKind::String,
+            Self::List(_) => ValueKind::List,
+            Self::Map(_) => ValueKind::Map,
+        }
+    }
+
+    /// Returns the boolean if this value is a boolean.
+    ///
+    /// Other values are not converted using their truthiness.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// assert_eq!(Value::from(true).as_bool(), Some(true));
+    /// assert_eq!(Value::from(1).as_bool(), None);
+    /// ```
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer if this value is an integer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// assert_eq!(Value::from(42).as_i64(), Some(42));
+    /// assert_eq!(Value::from(4.2).as_i64(), None);
+    /// ```
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the number as a float if this value is an integer or a float.
+    ///
+    /// Integers are converted using `as`, so very large integers may lose
+    /// precision.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// assert_eq!(Value::from(42).as_f64(), Some(42.0));
+    /// assert_eq!(Value::from(4.2).as_f64(), Some(4.2));
+    /// assert_eq!(Value::from("42").as_f64(), None);
+    /// ```
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Integer(n) => Some(*n as f64),
+            Self::Float(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the string slice if this value is a string.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// assert_eq!(Value::from("lorem").as_str(), Some("lorem"));
+    /// assert_eq!(Value::from(42).as_str(), None);
+    /// ```
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the items if this value is a list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// assert_eq!(Value::from([1, 2]).as_list(), Some(&[Value::from(1), Value::from(2)][..]));
+    /// assert_eq!(Value::from("lorem").as_list(), None);
+    /// ```
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Self::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries if this value is a map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// let value = upon::value! { lorem: 1 };
+    /// assert_eq!(value.as_map().map(|map| map.len()), Some(1));
+    /// assert_eq!(Value::from([1, 2]).as_map(), None);
+    /// ```
+    pub fn as_map(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Self::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Returns the owned string if this value is a string.
+    ///
+    /// This is like [`Value::as_str`] but consumes the value, so the string is
+    /// not cloned.
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the owned items if this value is a list.
+    ///
+    /// This is like [`Value::as_list`] but consumes the value, so the items
+    /// are not cloned.
+    pub fn into_list(self) -> Option<Vec<Value>> {
+        match self {
+            Self::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Returns the owned entries if this value is a map.
+    ///
+    /// This is like [`Value::as_map`] but consumes the value, so the entries
+    /// are not cloned.
+    pub fn into_map(self) -> Option<BTreeMap<String, Value>> {
+        match self {
+            Self::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Takes the value out, leaving [`Value::None`] in its place.
+    ///
+    /// This is useful for moving a nested value out of a map or list without
+    /// cloning it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// let mut value = upon::value! { user: { name: "John" } };
+    /// let name = value.get_mut("user.name").unwrap().take();
+    /// assert_eq!(name.into_string().as_deref(), Some("John"));
+    /// assert_eq!(value, upon::value! { user: { name: None } });
+    /// ```
+    pub fn take(&mut self) -> Value {
+        std::mem::take(self)
+    }
+
+    /// Returns a reference to the nested value at the given path.
+    ///
+    /// The path is split on `.` and each segment is looked up in turn. A
+    /// segment looks up a key in a map, or if it is an integer, an index in a
+    /// list. Like in templates, a negative index like `-1` counts from the end
+    /// of the list. A numeric segment on a map falls back to looking up the
+    /// segment as a string key, so `"0"` is found in `{ "0": true }`. An empty
+    /// path returns this value.
+    ///
+    /// Returns `None` if any segment is not found or if a value along the path
+    /// is not a list or map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use upon::Value;
+    ///
+    /// let value = Value::from([("users", Value::from([("john", 1), ("jane", 2)]))]);
+    /// assert_eq!(value.get("users.jane"), Some(&Value::from(2)));
+    /// assert_eq!(value.get("users.bob"), None);
+    ///
+    /// let value = Value::from([("items", Value::from(["a", "b", "c"]))]);
+    /// assert_eq!(value.get("items.0"), Some(&Value::from("a")));
+    /// assert_eq!(value.get("items.-1"), Some(&Value::from("c")));
+    /// ```
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('.')
+            .try_fold(self, |value, segment| match value {
+                Self::List(list) => list.get(list_index(list.len(), segment)?),
+                Self::Map(map) => map.get(segment),
+                _ => None,
+            })
+    }
+
+    /// Returns a mutable reference to the nested value at the given path.
+    ///
+    /// The path is looked up in the same way as [`Value::get`].
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Value> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('.')
+            .try_fold(self, |value, segment| match value {
+                Self::List(list) => {
+                    let i = list_index(list.len(), segment)?;
+                    list.get_mut(i)
+                }
+                Self::Map(map) => map.get_mut(segment),
+                _ => None,
+            })
+    }
+
+    /// Deep merges the other value into this value.
+    ///
+    /// If both values are maps then each entry in the other map is merged
+    /// into the entry with the same key in this map, or inserted if there is
+    /// no such entry. Otherwise, the other value replaces this value, this
+    /// includes lists which are replaced rather than concatenated. This is
+    /// useful for layering a render context from defaults and overrides.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ctx = upon::value! {
+    ///     site: { title: "Blog", theme: { color: "blue", font: "serif" } },
+    ///     tags: ["a", "b"],
+    /// };
+    /// ctx.merge(upon::value! {
+    ///     site: { theme: { color: "red" } },
+    ///     tags: ["c"],
+    /// });
+    /// assert_eq!(
+    ///     ctx,
+    ///     upon::value! {
+    ///         site: { title: "Blog", theme: { color: "red", font: "serif" } },
+    ///         tags: ["c"],
+    ///     }
+    /// );
+    /// ```
+    pub fn merge(&mut self, other: Value) {
+        match (self, other) {
+            (Self::Map(map), Self::Map(other)) => {
+                for (key, value) in other {
+                    match map.entry(key) {
+                        Entry::Occupied(mut entry) => entry.get_mut().merge(value),
+                        Entry::Vacant(entry) => {
+                            entry.insert(value);
+                        }
+                    }
+                }
+            }
+            (this, other) => *this = other,
+        }
+    }
+}
+
+/// Parses a path segment as an index into a list of the given length.
+fn list_index(len: usize, segment: &str) -> Option<usize> {
+    let (digits, negative) = match segment.strip_prefix('-') {
+        Some(digits) => (digits, true),
+        None => (segment, false),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let i: usize = digits.parse().ok()?;
+    match negative && i != 0 {
+        true => len.checked_sub(i),
+        false => Some(i),
+    }
+}
+
 impl Default for Value {
     fn default() -> Self {
         Self::None
@@ -12,6 +12,9 @@ use crate::value::ser::variants::{SerializeStructVariant, SerializeTupleVariant}
 use crate::{Error, Result, Value};
 
 /// Convert a `T` to a `Value`.
+///
+/// Maps and structs are converted to a [`Value::Map`] which is sorted by key,
+/// so the order of the entries or fields is not preserved.
 #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
 pub fn to_value<T>(value: T) -> Result<Value>
 where
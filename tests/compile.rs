@@ -1,4 +1,4 @@
-use upon::{Engine, Error};
+use upon::{Engine, Error, ErrorKind, Syntax};
 
 #[test]
 fn compile_empty() {
@@ -10,6 +10,30 @@ fn compile_raw() {
     Engine::new().compile("lorem ipsum dolor sit amet").unwrap();
 }
 
+#[test]
+fn compile_err_case_insensitive_keyword_reserved() {
+    let syntax = Syntax::builder()
+        .expr("{{", "}}")
+        .block("{%", "%}")
+        .case_insensitive_keywords(true)
+        .build();
+    let err = Engine::with_syntax(syntax)
+        .compile("lorem {{ If }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected keyword `If`",
+        "
+  --> <anonymous>:1:10
+   |
+ 1 | lorem {{ If }}
+   |          ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
 #[test]
 fn compile_comment() {
     Engine::new()
@@ -92,6 +116,248 @@ fn compile_inline_expr_filter_args() {
         .unwrap();
 }
 
+#[test]
+fn compile_inline_expr_filter_named_args() {
+    Engine::new()
+        .compile(r#"{{ lorem | ipsum: true, dolor = 3.14, sit=amet.consectetur }}"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_inline_expr_filter_args_trailing_comma() {
+    let engine = Engine::new();
+    for source in [
+        "{{ lorem | ipsum: true, }}",
+        "{{ lorem | ipsum: true, 3.14, -}}",
+        "{{ lorem | ipsum: true, dolor = 1, }}",
+        "{% for x in lorem | ipsum: 1, %}{% endfor %}",
+        "{% if lorem | ipsum: 1, %}{% endif %}",
+    ] {
+        engine.compile(source).unwrap();
+    }
+}
+
+#[test]
+fn compile_inline_expr_err_filter_args_leading_comma() {
+    let err = Engine::new()
+        .compile("{{ lorem | ipsum: , dolor }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found comma",
+        "
+  --> <anonymous>:1:19
+   |
+ 1 | {{ lorem | ipsum: , dolor }}
+   |                   ^--
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_filter_args_doubled_comma() {
+    let err = Engine::new()
+        .compile("{{ lorem | ipsum: dolor,, }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found comma",
+        "
+  --> <anonymous>:1:25
+   |
+ 1 | {{ lorem | ipsum: dolor,, }}
+   |                         ^--
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_filter_args_trailing_comma_before_filter() {
+    let err = Engine::new()
+        .compile("{{ lorem | ipsum: dolor, | sit }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found pipe",
+        "
+  --> <anonymous>:1:26
+   |
+ 1 | {{ lorem | ipsum: dolor, | sit }}
+   |                          ^--
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_coalesce() {
+    Engine::new()
+        .compile(r#"{{ lorem?.ipsum ?? dolor | sit: 1 ?? "amet" }}"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_inline_expr_err_coalesce_missing_operand() {
+    let err = Engine::new().compile("{{ lorem ?? }}").unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found end expression",
+        "
+  --> <anonymous>:1:13
+   |
+ 1 | {{ lorem ?? }}
+   |             ^^-
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_concat() {
+    Engine::new()
+        .compile(r#"{{ lorem ~ "/" ~ ipsum | dolor: 1 ~ sit ?? "amet" }}"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_inline_expr_err_concat_missing_operand() {
+    let err = Engine::new().compile("{{ lorem ~ }}").unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found end expression",
+        "
+  --> <anonymous>:1:12
+   |
+ 1 | {{ lorem ~ }}
+   |            ^^-
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_group_deeply_nested() {
+    let source = format!("{{{{ {}lorem{} }}}}", "(".repeat(1000), ")".repeat(1000));
+    Engine::new().compile(source).unwrap();
+}
+
+#[test]
+fn compile_inline_expr_err_group_unclosed() {
+    let err = Engine::new()
+        .compile("{{ (lorem ~ (ipsum | dolor) }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unclosed parenthesis",
+        "
+  --> <anonymous>:1:4
+   |
+ 1 | {{ (lorem ~ (ipsum | dolor) }}
+   |    ^--
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_group_unexpected_close() {
+    let err = Engine::new().compile("{{ lorem) }}").unwrap_err();
+    assert_err(
+        &err,
+        "expected end expression, found close parenthesis",
+        "
+  --> <anonymous>:1:9
+   |
+ 1 | {{ lorem) }}
+   |         ^--
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_group_expected_close() {
+    let err = Engine::new().compile("{{ (lorem ipsum) }}").unwrap_err();
+    assert_err(
+        &err,
+        "expected close parenthesis, found identifier",
+        "
+  --> <anonymous>:1:11
+   |
+ 1 | {{ (lorem ipsum) }}
+   |           ^^^^^
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_filter_positional_after_named_arg() {
+    let err = Engine::new()
+        .compile("{{ lorem | ipsum: dolor = 1, sit }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "positional argument after named argument",
+        "
+  --> <anonymous>:1:30
+   |
+ 1 | {{ lorem | ipsum: dolor = 1, sit }}
+   |                              ^^^
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_filter_duplicate_named_arg() {
+    let err = Engine::new()
+        .compile("{{ lorem | ipsum: dolor = 1, dolor = 2 }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "duplicate named argument `dolor`",
+        "
+  --> <anonymous>:1:30
+   |
+ 1 | {{ lorem | ipsum: dolor = 1, dolor = 2 }}
+   |                              ^^^^^
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_filter_named_arg_not_identifier() {
+    let err = Engine::new()
+        .compile("{{ lorem | ipsum: dolor.sit = 1 }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected identifier for named argument",
+        "
+  --> <anonymous>:1:19
+   |
+ 1 | {{ lorem | ipsum: dolor.sit = 1 }}
+   |                   ^^^^^^^^^
+   |
+   = reason: REASON
+",
+    )
+}
+
 #[test]
 fn compile_inline_expr_err_eof() {
     let err = Engine::new().compile("lorem {{ ipsum.dolor |").unwrap_err();
@@ -185,6 +451,44 @@ fn compile_inline_expr_err_index_overflow() {
     )
 }
 
+#[test]
+fn compile_inline_expr_err_index_overflow_radix() {
+    let err = Engine::new()
+        .compile("lorem {{ ipsum.-0x1ffffffffffffffff }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "base 16 literal out of range for unsigned 64-bit integer",
+        "
+  --> <anonymous>:1:16
+   |
+ 1 | lorem {{ ipsum.-0x1ffffffffffffffff }}
+   |                ^^^^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_inline_expr_err_index_invalid_digit() {
+    let err = Engine::new()
+        .compile("lorem {{ ipsum.-0o18 }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "invalid digit for base 8 literal",
+        "
+  --> <anonymous>:1:20
+   |
+ 1 | lorem {{ ipsum.-0o18 }}
+   |                    ^--
+   |
+   = reason: REASON
+",
+    )
+}
+
 #[test]
 fn compile_inline_expr_err_integer_overflow() {
     let err = Engine::new()
@@ -242,6 +546,53 @@ fn compile_inline_expr_err_unknown_escape_character() {
     )
 }
 
+#[test]
+fn compile_inline_expr_err_invalid_unicode_escape() {
+    let tests = [
+        (r#"{{ "\u41" }}"#, "invalid unicode escape", "   |      ^--"),
+        (r#"{{ "\u{}" }}"#, "invalid unicode escape", "   |      ^^^"),
+        (
+            r#"{{ "\u{4g}" }}"#,
+            "invalid unicode escape",
+            "   |      ^^^^",
+        ),
+        (
+            r#"{{ "\u{41" }}"#,
+            "invalid unicode escape",
+            "   |      ^^^^",
+        ),
+        (
+            r#"{{ "\u{1000000}" }}"#,
+            "invalid unicode escape",
+            "   |      ^^^^^^^^^",
+        ),
+        (
+            r#"{{ "\u{D800}" }}"#,
+            "unicode escape must not be a surrogate",
+            "   |      ^^^^^^^",
+        ),
+        (
+            r#"{{ "\u{110000}" }}"#,
+            "unicode escape must be at most 10FFFF",
+            "   |      ^^^^^^^^^",
+        ),
+    ];
+    for (source, reason, marker) in tests {
+        let err = Engine::new().compile(source).unwrap_err();
+        let pretty = format!(
+            "
+  --> <anonymous>:1:6
+   |
+ 1 | {source}
+{marker}
+   |
+   = reason: REASON
+"
+        );
+        assert_err(&err, reason, &pretty);
+    }
+}
+
 #[test]
 fn compile_inline_expr_err_unexpected_comma_token() {
     let err = Engine::new()
@@ -399,34 +750,27 @@ fn compile_if_else_if_else_statement() {
 }
 
 #[test]
-fn compile_if_else_statement() {
-    Engine::new()
-        .compile("lorem {% if ipsum %} dolor {% else %} sit {% endif %} amet")
-        .unwrap();
-}
-
-#[test]
-fn compile_if_statement_nested() {
+fn compile_if_elif_statement() {
     Engine::new()
         .compile(
-            "lorem {% if ipsum %} dolor {% else %} {% if sit %} amet {% endif %}, consectetur {% endif %}",
+            "lorem {% if ipsum %} dolor {% elif sit %} amet {% elif consectetur %} {% else %} adipiscing {% endif %}",
         )
         .unwrap();
 }
 
 #[test]
-fn compile_if_statement_err_expected_keyword() {
+fn compile_if_elif_statement_err_unexpected() {
     let err = Engine::new()
-        .compile("lorem {% fi ipsum %} dolor {% endif %} sit")
+        .compile("lorem {% elif cond %} {% endif %} ipsum")
         .unwrap_err();
     assert_err(
         &err,
-        "expected keyword, found identifier",
+        "unexpected `else if` block",
         "
-  --> <anonymous>:1:10
+  --> <anonymous>:1:7
    |
- 1 | lorem {% fi ipsum %} dolor {% endif %} sit
-   |          ^^-
+ 1 | lorem {% elif cond %} {% endif %} ipsum
+   |       ^^^^^^^^^^^^^^^
    |
    = reason: REASON
 ",
@@ -434,18 +778,18 @@ fn compile_if_statement_err_expected_keyword() {
 }
 
 #[test]
-fn compile_if_statement_err_unexpected_keyword() {
+fn compile_if_elif_statement_err_after_else() {
     let err = Engine::new()
-        .compile("lorem {% in ipsum %} dolor {% endif %} sit")
+        .compile("lorem {% if ipsum %}{% else %}{% elif cond %} {% endif %} ipsum")
         .unwrap_err();
     assert_err(
         &err,
-        "unexpected keyword `in`",
+        "unexpected `else if` block",
         "
-  --> <anonymous>:1:10
+  --> <anonymous>:1:31
    |
- 1 | lorem {% in ipsum %} dolor {% endif %} sit
-   |          ^^-
+ 1 | lorem {% if ipsum %}{% else %}{% elif cond %} {% endif %} ipsum
+   |                               ^^^^^^^^^^^^^^^
    |
    = reason: REASON
 ",
@@ -453,18 +797,18 @@ fn compile_if_statement_err_unexpected_keyword() {
 }
 
 #[test]
-fn compile_if_statement_err_unexpected_endif_block() {
+fn compile_if_elif_statement_err_unclosed_if_block() {
     let err = Engine::new()
-        .compile("lorem {% endif %} ipsum")
+        .compile("lorem {% if ipsum %} dolor {% elif sit %}")
         .unwrap_err();
     assert_err(
         &err,
-        "unexpected `endif` block",
+        "unclosed `if` block",
         "
   --> <anonymous>:1:7
    |
- 1 | lorem {% endif %} ipsum
-   |       ^^^^^^^^^^^
+ 1 | lorem {% if ipsum %} dolor {% elif sit %}
+   |       ^^^^^^^^^^^^^^
    |
    = reason: REASON
 ",
@@ -472,16 +816,89 @@ fn compile_if_statement_err_unexpected_endif_block() {
 }
 
 #[test]
-fn compile_if_statement_err_unexpected_else_if_block() {
-    let err = Engine::new()
-        .compile("lorem {% else if cond %} {% endif %} ipsum")
-        .unwrap_err();
-    assert_err(
-        &err,
-        "unexpected `else if` block",
-        "
-  --> <anonymous>:1:7
-   |
+fn compile_if_else_statement() {
+    Engine::new()
+        .compile("lorem {% if ipsum %} dolor {% else %} sit {% endif %} amet")
+        .unwrap();
+}
+
+#[test]
+fn compile_if_statement_nested() {
+    Engine::new()
+        .compile(
+            "lorem {% if ipsum %} dolor {% else %} {% if sit %} amet {% endif %}, consectetur {% endif %}",
+        )
+        .unwrap();
+}
+
+#[test]
+fn compile_if_statement_err_expected_keyword() {
+    let err = Engine::new()
+        .compile("lorem {% fi ipsum %} dolor {% endif %} sit")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected keyword, found identifier",
+        "
+  --> <anonymous>:1:10
+   |
+ 1 | lorem {% fi ipsum %} dolor {% endif %} sit
+   |          ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_if_statement_err_unexpected_keyword() {
+    let err = Engine::new()
+        .compile("lorem {% in ipsum %} dolor {% endif %} sit")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected keyword `in`",
+        "
+  --> <anonymous>:1:10
+   |
+ 1 | lorem {% in ipsum %} dolor {% endif %} sit
+   |          ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_if_statement_err_unexpected_endif_block() {
+    let err = Engine::new()
+        .compile("lorem {% endif %} ipsum")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `endif` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% endif %} ipsum
+   |       ^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_if_statement_err_unexpected_else_if_block() {
+    let err = Engine::new()
+        .compile("lorem {% else if cond %} {% endif %} ipsum")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `else if` block",
+        "
+  --> <anonymous>:1:7
+   |
  1 | lorem {% else if cond %} {% endif %} ipsum
    |       ^^^^^^^^^^^^^^^^^^
    |
@@ -566,6 +983,122 @@ fn compile_if_statement_err_unclosed_if_block() {
     );
 }
 
+#[test]
+fn compile_if_statement_test() {
+    Engine::new()
+        .compile("{% if ipsum is empty %}{% else if not dolor.sit is not empty %}{% endif %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_if_statement_test_err_unknown_test() {
+    let err = Engine::new()
+        .compile("lorem {% if ipsum is dolor %} sit {% endif %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unknown test",
+        "
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% if ipsum is dolor %} sit {% endif %}
+   |                      ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_if_statement_test_err_expected_identifier() {
+    let err = Engine::new()
+        .compile("lorem {% if ipsum is %} sit {% endif %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected identifier, found end block",
+        "
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% if ipsum is %} sit {% endif %}
+   |                      ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_if_statement_compare() {
+    Engine::new()
+        .compile(r#"{% if ipsum == "a" %}{% else if not dolor.sit >= -1.5 %}{% else if a | len < b.0 %}{% endif %}"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_if_statement_compare_err_missing_rhs() {
+    let err = Engine::new()
+        .compile("lorem {% if ipsum == %} sit {% endif %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found end block",
+        "
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% if ipsum == %} sit {% endif %}
+   |                      ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_inline_expr_err_compare() {
+    let err = Engine::new()
+        .compile("lorem {{ ipsum == dolor }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected end expression, found equality operator",
+        "
+  --> <anonymous>:1:16
+   |
+ 1 | lorem {{ ipsum == dolor }}
+   |                ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_if_statement_and_or() {
+    Engine::new()
+        .compile("{% if not ipsum and dolor or sit is empty and amet > 1 %}{% else if a or b %}{% endif %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_if_statement_and_err_missing_operand() {
+    let err = Engine::new()
+        .compile("lorem {% if ipsum and %} sit {% endif %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found end block",
+        "
+  --> <anonymous>:1:23
+   |
+ 1 | lorem {% if ipsum and %} sit {% endif %}
+   |                       ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
 #[test]
 fn compile_for_statement_item() {
     Engine::new()
@@ -580,6 +1113,91 @@ fn compile_for_statement_key_value() {
         .unwrap();
 }
 
+#[test]
+fn compile_for_statement_many_vars() {
+    Engine::new()
+        .compile("lorem {% for ipsum, dolor, sit in amet %} {{ sit }} {% endfor %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_for_statement_reversed() {
+    Engine::new()
+        .compile("{% for ipsum in dolor | sit: 1 reversed %}{% endfor %}{% for i in 0..=amet reversed %}{% endfor %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_for_else_statement() {
+    Engine::new()
+        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% else %}sit{% endfor %} amet")
+        .unwrap();
+}
+
+#[test]
+fn compile_for_else_statement_err_break_in_else() {
+    let err = Engine::new()
+        .compile("lorem {% for ipsum in dolor %}{% else %}{% break %}{% endfor %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `break` outside of loop",
+        "
+  --> <anonymous>:1:44
+   |
+ 1 | lorem {% for ipsum in dolor %}{% else %}{% break %}{% endfor %}
+   |                                            ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_for_else_statement_break_in_else_outer_loop() {
+    Engine::new()
+        .compile("{% for ipsum in dolor %}{% for sit in ipsum %}{% else %}{% break %}{% endfor %}{% endfor %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_for_statement_err_reversed_unknown_modifier() {
+    let err = Engine::new()
+        .compile("{% for ipsum in dolor backwards %}{% endfor %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected end block, found identifier",
+        "
+  --> <anonymous>:1:23
+   |
+ 1 | {% for ipsum in dolor backwards %}{% endfor %}
+   |                       ^^^^^^^^^
+   |
+   = reason: REASON
+",
+    )
+}
+
+#[test]
+fn compile_for_statement_err_many_vars_trailing_comma() {
+    let err = Engine::new()
+        .compile("lorem {% for ipsum, dolor, in sit %} amet")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected identifier, found keyword",
+        "
+  --> <anonymous>:1:28
+   |
+ 1 | lorem {% for ipsum, dolor, in sit %} amet
+   |                            ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
 #[test]
 fn compile_for_statement_err_trailing_comma() {
     let err = Engine::new()
@@ -659,16 +1277,16 @@ fn compile_for_statement_err_unexpected_endfor_block() {
 #[test]
 fn compile_for_statement_err_unexpected_else_block() {
     let err = Engine::new()
-        .compile("lorem {% for _, ipsum in dolor %} {% else %} {% endif %}")
+        .compile("lorem {% for _, ipsum in dolor %} {% else %} {% else %} {% endfor %}")
         .unwrap_err();
     assert_err(
         &err,
         "unexpected `else` block",
         "
-  --> <anonymous>:1:35
+  --> <anonymous>:1:46
    |
- 1 | lorem {% for _, ipsum in dolor %} {% else %} {% endif %}
-   |                                   ^^^^^^^^^^
+ 1 | lorem {% for _, ipsum in dolor %} {% else %} {% else %} {% endfor %}
+   |                                              ^^^^^^^^^^
    |
    = reason: REASON
 ",
@@ -733,25 +1351,25 @@ fn compile_for_statement_err_unclosed_for_block() {
 }
 
 #[test]
-fn compile_with_statement() {
+fn compile_for_statement_break_and_continue() {
     Engine::new()
-        .compile("lorem {% with ipsum as dolor %} {{ dolor }} {% endwith %} sit")
+        .compile("{% for ipsum in dolor %}{% if sit %}{% break %}{% else %}{% continue %}{% endif %}{% endfor %}")
         .unwrap();
 }
 
 #[test]
-fn compile_with_statement_err_unclosed_with_block() {
+fn compile_for_statement_err_break_outside_loop() {
     let err = Engine::new()
-        .compile("lorem {% with ipsum as dolor %} sit")
+        .compile("lorem {% if ipsum %}{% break %}{% endif %}")
         .unwrap_err();
     assert_err(
         &err,
-        "unclosed `with` block",
+        "unexpected `break` outside of loop",
         "
-  --> <anonymous>:1:7
+  --> <anonymous>:1:24
    |
- 1 | lorem {% with ipsum as dolor %} sit
-   |       ^^^^^^^^^^^^^^^^^^^^^^^^^
+ 1 | lorem {% if ipsum %}{% break %}{% endif %}
+   |                        ^^^^^
    |
    = reason: REASON
 ",
@@ -759,18 +1377,18 @@ fn compile_with_statement_err_unclosed_with_block() {
 }
 
 #[test]
-fn compile_with_statement_err_unexpected_endwith_block() {
+fn compile_for_statement_err_continue_outside_loop() {
     let err = Engine::new()
-        .compile("lorem {% with ipsum as dolor %} sit {% else %} {% endif %}")
+        .compile("lorem {% for ipsum in dolor %}{% endfor %}{% continue %}")
         .unwrap_err();
     assert_err(
         &err,
-        "unexpected `else` block",
+        "unexpected `continue` outside of loop",
         "
-  --> <anonymous>:1:37
+  --> <anonymous>:1:46
    |
- 1 | lorem {% with ipsum as dolor %} sit {% else %} {% endif %}
-   |                                     ^^^^^^^^^^
+ 1 | lorem {% for ipsum in dolor %}{% endfor %}{% continue %}
+   |                                              ^^^^^^^^
    |
    = reason: REASON
 ",
@@ -778,13 +1396,129 @@ fn compile_with_statement_err_unexpected_endwith_block() {
 }
 
 #[test]
-fn compile_with_statement_err_unexpected_else_block() {
+fn compile_set_statement() {
+    Engine::new()
+        .compile("lorem {% set ipsum = dolor.0 | sit: amet %} {{ ipsum }}")
+        .unwrap();
+}
+
+#[test]
+fn compile_set_statement_err_expected_assign() {
     let err = Engine::new()
-        .compile("lorem {% endwith %} ipsum")
+        .compile("lorem {% set ipsum dolor %}")
         .unwrap_err();
     assert_err(
         &err,
-        "unexpected `endwith` block",
+        "expected assignment operator, found identifier",
+        "
+  --> <anonymous>:1:20
+   |
+ 1 | lorem {% set ipsum dolor %}
+   |                    ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_set_statement_err_unexpected_keyword() {
+    let err = Engine::new()
+        .compile("lorem {% set for = dolor %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected identifier, found keyword",
+        "
+  --> <anonymous>:1:14
+   |
+ 1 | lorem {% set for = dolor %}
+   |              ^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_with_statement() {
+    Engine::new()
+        .compile("lorem {% with ipsum as dolor %} {{ dolor }} {% endwith %} sit")
+        .unwrap();
+}
+
+#[test]
+fn compile_with_statement_multiple() {
+    Engine::new()
+        .compile("lorem {% with ipsum as dolor, dolor.sit as amet %} {{ amet }} {% endwith %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_with_statement_err_trailing_comma() {
+    let err = Engine::new()
+        .compile("lorem {% with ipsum as dolor, %} sit {% endwith %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected expression, found end block",
+        "
+  --> <anonymous>:1:31
+   |
+ 1 | lorem {% with ipsum as dolor, %} sit {% endwith %}
+   |                               ^^-
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_with_statement_err_unclosed_with_block() {
+    let err = Engine::new()
+        .compile("lorem {% with ipsum as dolor %} sit")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unclosed `with` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% with ipsum as dolor %} sit
+   |       ^^^^^^^^^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_with_statement_err_unexpected_endwith_block() {
+    let err = Engine::new()
+        .compile("lorem {% with ipsum as dolor %} sit {% else %} {% endif %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `else` block",
+        "
+  --> <anonymous>:1:37
+   |
+ 1 | lorem {% with ipsum as dolor %} sit {% else %} {% endif %}
+   |                                     ^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_with_statement_err_unexpected_else_block() {
+    let err = Engine::new()
+        .compile("lorem {% endwith %} ipsum")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `endwith` block",
         "
   --> <anonymous>:1:7
    |
@@ -796,6 +1530,130 @@ fn compile_with_statement_err_unexpected_else_block() {
     );
 }
 
+#[test]
+fn compile_autoescape_statement() {
+    Engine::new()
+        .compile("lorem {% autoescape off %} ipsum {% endautoescape %} dolor")
+        .unwrap();
+}
+
+#[test]
+fn compile_autoescape_statement_err_expected_on_or_off() {
+    let err = Engine::new()
+        .compile("lorem {% autoescape ipsum %}{% endautoescape %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected `on` or `off`",
+        "
+  --> <anonymous>:1:21
+   |
+ 1 | lorem {% autoescape ipsum %}{% endautoescape %}
+   |                     ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_autoescape_statement_err_unclosed_autoescape_block() {
+    let err = Engine::new()
+        .compile("lorem {% autoescape on %} sit")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unclosed `autoescape` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% autoescape on %} sit
+   |       ^^^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_autoescape_statement_err_unexpected_endautoescape_block() {
+    let err = Engine::new()
+        .compile("lorem {% with ipsum as dolor %}{% endautoescape %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `endautoescape` block",
+        "
+  --> <anonymous>:1:32
+   |
+ 1 | lorem {% with ipsum as dolor %}{% endautoescape %}
+   |                                ^^^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_err_accessors() {
+    let err = Engine::new()
+        .add_template("lorem", "ipsum {{ dolor }")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Syntax);
+    assert_eq!(err.template_name(), Some("lorem"));
+    assert_eq!(err.span(), Some(15..16));
+    assert_eq!(err.line_column(), Some((1, 16)));
+
+    let err = Engine::new().compile("ipsum {{ dolor }").unwrap_err();
+    assert_eq!(err.template_name(), None);
+}
+
+#[test]
+fn compile_err_display_with_context() {
+    let source = "1\n2\n3\n4\n5\n6\n7\n8\n{{ lorem }\n\n11\n12";
+    let mut engine = Engine::new();
+    let err = engine.add_template("ipsum", source).unwrap_err();
+    assert_eq!(
+        err.display_with_context(2).to_string(),
+        "invalid syntax
+
+   --> ipsum:9:10
+    |
+  7 | 7
+  8 | 8
+  9 | {{ lorem }
+    |          ^--
+ 10 | 
+ 11 | 11
+    |
+    = reason: unexpected character
+"
+    );
+    assert_eq!(err.display_with_context(0).to_string(), format!("{err:#}"));
+    assert_eq!(
+        err.display_with_context(100).to_string().lines().count(),
+        19
+    );
+
+    let err = Engine::new().compile("{{ lorem }").unwrap_err();
+    assert_eq!(err.display_with_context(3).to_string(), format!("{err:#}"));
+
+    // At most ten lines are displayed on each side.
+    let source = format!("{}{{{{ lorem }}{}", "\n".repeat(20), "\n".repeat(20));
+    let err = Engine::new().compile(&source).unwrap_err();
+    let display = err.display_with_context(100).to_string();
+    assert_eq!(display.lines().count(), 28);
+    assert!(display.contains("\n 11 | \n"));
+    assert!(display.contains("\n 31 | \n"));
+    assert!(!display.contains("\n 10 | \n"));
+
+    let err = Syntax::builder().expr("|", "|").try_build().unwrap_err();
+    assert_eq!(
+        err.display_with_context(3).to_string(),
+        "invalid syntax: ambiguous delimiters, begin expression and end expression are both `|`"
+    );
+}
+
 #[test]
 fn compile_include_statement() {
     Engine::new()
@@ -817,6 +1675,321 @@ fn compile_include_with_statement_filters() {
         .unwrap();
 }
 
+#[test]
+fn compile_include_statement_var() {
+    Engine::new()
+        .compile(r#"lorem {% include ipsum.name with dolor %} sit"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_include_statement_ignore_missing() {
+    Engine::new()
+        .compile(r#"lorem {% include "ipsum" ignore missing with dolor %} sit"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_include_statement_err_expected_missing() {
+    let err = Engine::new()
+        .compile(r#"lorem {% include "ipsum" ignore dolor %}"#)
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected `missing`",
+        r#"
+  --> <anonymous>:1:33
+   |
+ 1 | lorem {% include "ipsum" ignore dolor %}
+   |                                 ^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn compile_include_statement_err_expected_string() {
+    let err = Engine::new()
+        .compile("lorem {% include 123 %} dolor")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected string or variable",
+        "
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {% include 123 %} dolor
+   |                  ^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_extends_statement() {
+    Engine::new()
+        .compile(
+            r#"{% extends "base" %}{% block lorem %}{% block ipsum %}{% endblock %}{% endblock %}"#,
+        )
+        .unwrap();
+}
+
+#[test]
+fn compile_extends_statement_err_not_top_level() {
+    let err = Engine::new()
+        .compile(r#"{% block lorem %}{% extends "base" %}{% endblock %}"#)
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `extends` block",
+        r#"
+  --> <anonymous>:1:18
+   |
+ 1 | {% block lorem %}{% extends "base" %}{% endblock %}
+   |                  ^^^^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn compile_block_statement_err_duplicate() {
+    let err = Engine::new()
+        .compile("{% block lorem %}{% endblock %}{% block lorem %}{% endblock %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "duplicate block `lorem`",
+        "
+  --> <anonymous>:1:41
+   |
+ 1 | {% block lorem %}{% endblock %}{% block lorem %}{% endblock %}
+   |                                         ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_block_statement_err_unclosed() {
+    let err = Engine::new()
+        .compile("lorem {% block ipsum %} dolor")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unclosed `block` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% block ipsum %} dolor
+   |       ^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_block_statement_err_break_outside_block() {
+    let err = Engine::new()
+        .compile("{% for x in xs %}{% block lorem %}{% break %}{% endblock %}{% endfor %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `break` outside of loop",
+        "
+  --> <anonymous>:1:38
+   |
+ 1 | {% for x in xs %}{% block lorem %}{% break %}{% endblock %}{% endfor %}
+   |                                      ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_include_raw_statement() {
+    Engine::new()
+        .compile(r#"lorem {% include_raw "ipsum" %} dolor"#)
+        .unwrap();
+}
+
+#[test]
+fn compile_include_raw_statement_err_expected_string() {
+    let err = Engine::new()
+        .compile("lorem {% include_raw ipsum %} dolor")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected string, found identifier",
+        "
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% include_raw ipsum %} dolor
+   |                      ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_raw_statement() {
+    Engine::new()
+        .compile("lorem {% raw %}{{ ipsum }}{% if %}{% endraw %} dolor")
+        .unwrap();
+}
+
+#[test]
+fn compile_raw_statement_err_unclosed_raw_block() {
+    let err = Engine::new()
+        .compile("lorem {% raw %} {{ ipsum }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unclosed `raw` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% raw %} {{ ipsum }}
+   |       ^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_raw_statement_err_unclosed_raw_block_eof() {
+    let err = Engine::new().compile("lorem {% raw %}").unwrap_err();
+    assert_err(
+        &err,
+        "unclosed `raw` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% raw %}
+   |       ^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_raw_statement_err_unexpected_endraw_block() {
+    let err = Engine::new().compile("lorem {% endraw %}").unwrap_err();
+    assert_err(
+        &err,
+        "unexpected `endraw` block",
+        "
+  --> <anonymous>:1:7
+   |
+ 1 | lorem {% endraw %}
+   |       ^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_strict() {
+    let mut engine = Engine::new();
+    engine.set_strict(true);
+    let tests = [
+        "{% with lorem as ipsum %}{{ ipsum }}{% endwith %}",
+        "{% with lorem as ipsum, ipsum.dolor as sit %}{{ sit }}{% endwith %}",
+        "{% with lorem as ipsum %}{% if ipsum %}{% endif %}{% endwith %}",
+        "{% with lorem as ipsum %}{{ dolor | sit: ipsum.amet }}{% endwith %}",
+        "{% with lorem as ipsum %}{% include \"dolor\" %}{% endwith %}",
+        "{% with lorem as ipsum %}{% for x in ipsum %}{% endfor %}{% endwith %}",
+        "{% set ipsum = lorem %}{% for x in xs %}{{ x ~ ipsum }}{% endfor %}",
+        "{% set ipsum = lorem %}{% set ipsum = ipsum ~ dolor %}{{ ipsum }}",
+        "{% for x in xs %}{% endfor %}",
+        "{% set title = lorem %}{% block body %}{% endblock %}",
+        "{% with 1 as x %}{% block b %}{% endblock %}{% endwith %}",
+    ];
+    for source in tests {
+        engine.compile(source).unwrap();
+    }
+}
+
+#[test]
+fn compile_strict_disabled() {
+    Engine::new()
+        .compile("{% with lorem as ipsum %}{% endwith %}{% set dolor = sit %}")
+        .unwrap();
+}
+
+#[test]
+fn compile_strict_err_unused_with() {
+    let mut engine = Engine::new();
+    engine.set_strict(true);
+    let err = engine
+        .compile("{% with lorem as ipsum, dolor as sit %}{{ ipsum }}{% endwith %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unused binding `sit`",
+        "
+  --> <anonymous>:1:34
+   |
+ 1 | {% with lorem as ipsum, dolor as sit %}{{ ipsum }}{% endwith %}
+   |                                  ^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_strict_err_unused_set() {
+    let mut engine = Engine::new();
+    engine.set_strict(true);
+    let err = engine
+        .compile("{% if lorem %}{% set ipsum = dolor %}{% endif %}{{ ipsum }}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unused binding `ipsum`",
+        "
+  --> <anonymous>:1:22
+   |
+ 1 | {% if lorem %}{% set ipsum = dolor %}{% endif %}{{ ipsum }}
+   |                      ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn compile_strict_err_shadowed() {
+    let mut engine = Engine::new();
+    engine.set_strict(true);
+    let err = engine
+        .compile("{% with lorem as x %}{% for x in xs %}{{ x }}{% endfor %}{% endwith %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unused binding `x`",
+        "
+  --> <anonymous>:1:18
+   |
+ 1 | {% with lorem as x %}{% for x in xs %}{{ x }}{% endfor %}{% endwith %}
+   |                  ^--
+   |
+   = reason: REASON
+",
+    );
+}
+
 #[track_caller]
 fn assert_err(err: &Error, reason: &str, pretty: &str) {
     let display = format!("invalid syntax: {reason}");
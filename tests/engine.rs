@@ -1,8 +1,12 @@
 #![cfg(feature = "serde")]
 
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use std::thread;
 
-use upon::{value, Engine};
+use upon::{
+    value, Engine, Syntax, TemplateStore, Undefined, Value, ValueAccess, ValueAccessOp, ValueMember,
+};
 
 #[test]
 fn engine_debug() {
@@ -35,6 +39,91 @@ fn engine_compile_borrowed_source_non_static() -> upon::Result<()> {
     Ok(())
 }
 
+#[test]
+fn engine_compile_cached() -> upon::Result<()> {
+    let engine = Engine::new();
+    for lorem in ["ipsum", "dolor"] {
+        let source = String::from("{{ lorem }}");
+        let template = engine.compile_cached(&source)?;
+        drop(source);
+        let result = template
+            .render(&engine, value! { lorem: lorem })
+            .to_string()?;
+        assert_eq!(result, lorem);
+    }
+    let result = engine
+        .compile_cached("{{ ipsum }}")?
+        .render(&engine, value! { ipsum: "sit" })
+        .to_string()?;
+    assert_eq!(result, "sit");
+    Ok(())
+}
+
+#[test]
+fn engine_compile_cached_err() {
+    let engine = Engine::new();
+    for _ in 0..2 {
+        let err = engine.compile_cached("{{ lorem }").unwrap_err();
+        assert_eq!(err.to_string(), "invalid syntax: unexpected character");
+    }
+}
+
+#[test]
+fn engine_compile_cached_cleared_by_settings() -> upon::Result<()> {
+    let mut engine = Engine::new();
+    let source = "{% if lorem %}\nipsum{% endif %}";
+    let result = engine
+        .compile_cached(source)?
+        .render(&engine, value! { lorem: true })
+        .to_string()?;
+    assert_eq!(result, "\nipsum");
+    engine.set_trim_blocks(true);
+    let result = engine
+        .compile_cached(source)?
+        .render(&engine, value! { lorem: true })
+        .to_string()?;
+    assert_eq!(result, "ipsum");
+    Ok(())
+}
+
+#[test]
+fn engine_compile_named() -> upon::Result<()> {
+    let engine = Engine::new();
+    let template = engine.compile_named("lorem", "{{ ipsum }}")?;
+    assert_eq!(template.name(), Some("lorem"));
+    assert_eq!(engine.compile("{{ ipsum }}")?.name(), None);
+    let err = template
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.template_name(), Some("lorem"));
+    assert!(format!("{err:#}").contains("--> lorem:1:4"));
+    let err = engine.compile_named("lorem", "{{ ipsum }").unwrap_err();
+    assert_eq!(err.template_name(), Some("lorem"));
+    Ok(())
+}
+
+#[test]
+fn engine_renderer_with_template_name() -> upon::Result<()> {
+    let mut engine = Engine::new();
+    engine.add_template("lorem", "{{ ipsum }}")?;
+    let err = engine
+        .template("lorem")
+        .render(Value::None)
+        .with_template_name("dolor")
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.template_name(), Some("dolor"));
+    let err = engine
+        .compile("{{ ipsum }}")?
+        .render(&engine, Value::None)
+        .with_template_name("sit")
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.template_name(), Some("sit"));
+    Ok(())
+}
+
 #[test]
 fn engine_add_template_borrowed_source_non_static() -> upon::Result<()> {
     let mut engine = Engine::new();
@@ -61,6 +150,319 @@ fn engine_add_template_owned_source() -> upon::Result<()> {
     Ok(())
 }
 
+#[test]
+fn engine_add_templates() -> upon::Result<()> {
+    let mut engine = Engine::new();
+    engine.add_templates(vec![
+        ("lorem", String::from("{{ ipsum }}")),
+        ("dolor", String::from("{% include \"lorem\" %}")),
+    ])?;
+    assert_eq!(
+        engine.template_names().collect::<Vec<_>>(),
+        ["dolor", "lorem"]
+    );
+    let result = engine
+        .template("dolor")
+        .render(value! { ipsum: "sit" })
+        .to_string()?;
+    assert_eq!(result, "sit");
+    Ok(())
+}
+
+#[test]
+fn engine_add_templates_err() {
+    let mut engine = Engine::new();
+    let err = engine
+        .add_templates([
+            ("lorem", "{{ ipsum }}"),
+            ("dolor", "{{ sit }"),
+            ("amet", "{{"),
+        ])
+        .unwrap_err();
+    assert_eq!(err.template_name(), Some("dolor"));
+    assert!(engine.is_empty());
+}
+
+#[test]
+fn engine_add_template_ref() -> upon::Result<()> {
+    let mut engine = Engine::new();
+    engine.add_template("test", "{{ ipsum }}")?;
+    let template = engine.add_template_ref("test", String::from("{{ lorem }}"))?;
+    assert_eq!(template.source(), "{{ lorem }}");
+    let result = template.render(value! { lorem: "ipsum" }).to_string()?;
+    assert_eq!(result, "ipsum");
+    assert_eq!(engine.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn engine_add_template_ref_err() {
+    let mut engine = Engine::new();
+    let err = engine.add_template_ref("test", "{{ lorem }").unwrap_err();
+    assert_eq!(err.template_name(), Some("test"));
+    assert!(engine.get_template("test").is_none());
+}
+
+#[test]
+fn engine_template_names() {
+    let mut engine = Engine::new();
+    assert!(engine.is_empty());
+    assert_eq!(engine.template_names().count(), 0);
+
+    engine.add_template("lorem", "").unwrap();
+    engine.add_template("ipsum", "").unwrap();
+    engine.add_template("dolor", "").unwrap();
+    assert!(!engine.is_empty());
+    assert_eq!(engine.len(), 3);
+    assert_eq!(
+        engine.template_names().collect::<Vec<_>>(),
+        ["dolor", "ipsum", "lorem"]
+    );
+
+    engine.remove_template("ipsum");
+    assert_eq!(engine.len(), 2);
+    assert_eq!(
+        engine.template_names().collect::<Vec<_>>(),
+        ["dolor", "lorem"]
+    );
+}
+
+#[test]
+fn engine_templates_with_prefix() {
+    let mut engine = Engine::new();
+    engine.add_template("emails", "").unwrap();
+    engine.add_template("emails/welcome", "").unwrap();
+    engine.add_template("emails/goodbye", "").unwrap();
+    engine.add_template("emailsx", "").unwrap();
+    engine.add_template("pages/home", "").unwrap();
+    assert_eq!(
+        engine
+            .templates_with_prefix("emails/")
+            .map(|t| t.name())
+            .collect::<Vec<_>>(),
+        ["emails/goodbye", "emails/welcome"]
+    );
+    assert_eq!(engine.templates_with_prefix("").count(), 5);
+    assert_eq!(engine.templates_with_prefix("lorem").count(), 0);
+}
+
+#[test]
+fn engine_clear_templates() {
+    let mut engine = Engine::new();
+    engine.add_template("lorem", "").unwrap();
+    engine.add_template("ipsum", "").unwrap();
+    engine.clear_templates();
+    assert!(engine.is_empty());
+    assert!(engine.get_template("lorem").is_none());
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn engine_clear_functions() {
+    let mut engine = Engine::new();
+    engine.add_formatter("lorem", |_, _| Ok(()));
+    engine.add_filter("ipsum", |v: Value| v);
+    engine.clear_functions();
+    assert!(engine.remove_function("lorem").is_none());
+    let err = engine
+        .compile("{{ dolor | ipsum }}")
+        .unwrap()
+        .render(&engine, value! { dolor: 1 })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: unknown filter or formatter");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn engine_get_function() {
+    use upon::EngineFn;
+
+    let mut engine = Engine::new();
+    engine.add_formatter("lorem", |_, _| Ok(()));
+    engine.add_filter("ipsum", |v: Value| v);
+    assert_eq!(engine.get_function("lorem"), Some(EngineFn::Formatter));
+    assert_eq!(engine.get_function("ipsum"), Some(EngineFn::Filter));
+    assert_eq!(engine.get_function("dolor"), None);
+    let names: Vec<_> = engine.function_names().collect();
+    assert_eq!(
+        names,
+        [("ipsum", EngineFn::Filter), ("lorem", EngineFn::Formatter)]
+    );
+    engine.remove_function("ipsum");
+    assert_eq!(engine.get_function("ipsum"), None);
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn engine_reset_keeps_syntax() {
+    let mut engine = Engine::with_syntax(Syntax::builder().expr("<{", "}>").build());
+    engine.add_template("lorem", "<{ ipsum }>").unwrap();
+    engine.add_filter("dolor", |v: Value| v);
+    engine.reset();
+    assert!(engine.is_empty());
+    assert!(engine.add_filter("dolor", |v: Value| v).is_none());
+    let result = engine
+        .compile("<{ ipsum }>")
+        .unwrap()
+        .render(&engine, value! { ipsum: "sit" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "sit");
+}
+
+#[test]
+fn engine_missing_var_fn() {
+    let mut engine = Engine::new();
+    engine.set_missing_var_fn(|path| match path {
+        [ValueMember {
+            access: ValueAccess::Key("lorem"),
+            ..
+        }] => Some(Value::from("ipsum")),
+        [ValueMember {
+            access: ValueAccess::Key("dolor"),
+            ..
+        }, ValueMember {
+            op: ValueAccessOp::Optional,
+            access: ValueAccess::Index(1),
+        }] => Some(Value::from("sit")),
+        _ => None,
+    });
+    let result = engine
+        .compile("{{ lorem }} {{ dolor?.1 }} {{ amet ?? 0 }} {{ ctx }}")
+        .unwrap()
+        .render(&engine, value! { ctx: "x" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "ipsum sit 0 x");
+}
+
+#[test]
+fn engine_missing_var_fn_precedence() {
+    let mut engine = Engine::new();
+    engine.set_missing_var_fn(|_| Some(Value::from("fn")));
+    let globals = value! { ipsum: "globals" };
+    let result = engine
+        .compile("{{ lorem }} {{ ipsum }} {% set dolor = 1 %}{{ dolor }} {{ sit }}")
+        .unwrap()
+        .render(&engine, value! { lorem: "ctx" })
+        .with_globals(&globals)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "ctx globals 1 fn");
+}
+
+#[test]
+fn engine_missing_var_fn_err_none() {
+    let mut engine = Engine::new();
+    engine.set_missing_var_fn(|_| None);
+    let err = engine
+        .compile("{{ lorem }}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found in this scope");
+}
+
+#[test]
+fn engine_undefined_behavior() {
+    let mut engine = Engine::new();
+    engine.set_undefined_behavior(Undefined::Empty);
+    engine.add_template("nested", "[{{ ipsum }}]").unwrap();
+    let template = engine
+        .compile(r#"{{ lorem }}{% include "nested" with dolor %}"#)
+        .unwrap();
+    let result = template
+        .render(&engine, value! { dolor: {} })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "[]");
+    let err = template
+        .render(&engine, value! { dolor: {} })
+        .with_undefined_behavior(Undefined::Error)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found in this scope");
+}
+
+#[test]
+fn engine_template_store() {
+    let compiler = Engine::new();
+    let mut store = BTreeMap::new();
+    store.insert(
+        String::from("lorem"),
+        compiler.compile("store {{ x }}").unwrap(),
+    );
+    store.insert(String::from("ipsum"), compiler.compile("store").unwrap());
+
+    let mut engine = Engine::with_store(store);
+    engine.add_template("ipsum", "engine").unwrap();
+
+    let template = engine.template("lorem");
+    assert_eq!(template.source(), "store {{ x }}");
+    let result = template.render(value! { x: 1 }).to_string().unwrap();
+    assert_eq!(result, "store 1");
+
+    // the engine takes precedence over the store
+    assert_eq!(engine.template("ipsum").source(), "engine");
+    assert!(engine.get_template("dolor").is_none());
+}
+
+#[test]
+fn engine_template_store_include() {
+    let compiler = Engine::new();
+    let mut store = HashMap::new();
+    store.insert(String::from("nested"), compiler.compile("{{ x }}").unwrap());
+    let store = Arc::new(store);
+
+    let mut a = Engine::with_store(store.clone());
+    a.add_template("main", r#"a {% include "nested" %}"#)
+        .unwrap();
+
+    let mut b = Engine::with_syntax(Syntax::builder().block("<%", "%>").build());
+    b.set_store(store);
+    b.add_template("main", r#"b <% include "nested" %>"#)
+        .unwrap();
+
+    let result = a
+        .template("main")
+        .render(value! { x: 1 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "a 1");
+    let result = b
+        .template("main")
+        .render(value! { x: 2 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "b 2");
+}
+
+#[test]
+fn engine_template_store_custom() {
+    struct Store(upon::Template<'static>);
+
+    impl TemplateStore for Store {
+        fn get_template(&self, name: &str) -> Option<(&str, &upon::Template<'_>)> {
+            (name == "lorem").then(|| ("lorem", &self.0))
+        }
+    }
+
+    let template = Engine::new().compile("{{ ipsum }}").unwrap();
+    let engine = Engine::with_store(Store(template));
+    let err = engine
+        .template("lorem")
+        .render(Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found in this scope");
+    assert_eq!(
+        format!("{err:#}").lines().nth(2).unwrap(),
+        "  --> lorem:1:4"
+    );
+}
+
 #[cfg(feature = "filters")]
 #[test]
 fn engine_add_filter_nested() {
@@ -85,3 +487,226 @@ fn engine_add_filter_nested() {
     let mut engine = Wrapper::default();
     engine.add_filter("lower", str::to_lowercase);
 }
+
+#[cfg(feature = "filters")]
+#[test]
+fn engine_try_add_filter() {
+    use upon::EngineFn;
+
+    let mut engine = Engine::new();
+    assert_eq!(engine.try_add_filter("lorem", |v: Value| v), Ok(()));
+    assert_eq!(
+        engine.try_add_filter("lorem", str::to_uppercase),
+        Err(EngineFn::Filter)
+    );
+    assert_eq!(engine.try_add_formatter("ipsum", |_, _| Ok(())), Ok(()));
+    assert_eq!(
+        engine.try_add_filter("ipsum", |v: Value| v),
+        Err(EngineFn::Formatter)
+    );
+    assert_eq!(
+        engine.try_add_formatter("lorem", |_, _| Ok(())),
+        Err(EngineFn::Filter)
+    );
+    let result = engine
+        .compile("{{ dolor | lorem }}")
+        .unwrap()
+        .render(&engine, value! { dolor: "sit" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "sit");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn engine_filter_meta() {
+    use upon::filters::FilterMeta;
+
+    let mut engine = Engine::new();
+    let meta = FilterMeta {
+        description: String::from("Repeats the string."),
+        arg_names: vec![String::from("n")],
+    };
+    engine.add_filter_with_meta("repeat", |s: &str, n: usize| s.repeat(n), meta.clone());
+    engine.add_filter("lower", str::to_lowercase);
+    engine.add_formatter("escape", upon::fmt::default);
+
+    assert_eq!(engine.filter_meta("repeat"), Some(&meta));
+    assert_eq!(engine.filter_meta("lower"), None);
+    assert_eq!(engine.filter_meta("escape"), None);
+    assert_eq!(engine.filter_meta("unknown"), None);
+
+    let result = engine
+        .compile(r#"{{ lorem | repeat: 2 }}"#)
+        .unwrap()
+        .render(&engine, value! { lorem: "ipsum" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "ipsumipsum");
+
+    engine.add_filter("repeat", |s: &str| s.repeat(3));
+    assert_eq!(engine.filter_meta("repeat"), None);
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn template_variables() {
+    let mut engine = Engine::new();
+    engine.add_filter("prepend", |s: String, p: String| format!("{p}{s}"));
+    let template = engine
+        .compile(
+            "{{ lorem.ipsum }} {{ lorem?.dolor }} {{ lorem.ipsum }} \
+             {% for item in items.0 %}{{ items.-1 }}{{ item }}{{ loop.index }}{{ sit }}{% endfor %} \
+             {% with amet as sit %}{{ sit }}{% endwith %}{{ sit | prepend: consectetur }}",
+        )
+        .unwrap();
+
+    let key = |k| ValueMember {
+        op: ValueAccessOp::Direct,
+        access: ValueAccess::Key(k),
+    };
+    let optional_key = |k| ValueMember {
+        op: ValueAccessOp::Optional,
+        access: ValueAccess::Key(k),
+    };
+    let index = |i| ValueMember {
+        op: ValueAccessOp::Direct,
+        access: ValueAccess::Index(i),
+    };
+    let negative_index = |i| ValueMember {
+        op: ValueAccessOp::Direct,
+        access: ValueAccess::NegativeIndex(i),
+    };
+    assert_eq!(
+        template.variables(),
+        [
+            vec![key("lorem"), key("ipsum")],
+            vec![key("lorem"), optional_key("dolor")],
+            vec![key("items"), index(0)],
+            vec![key("items"), negative_index(1)],
+            vec![key("sit")],
+            vec![key("amet")],
+            vec![key("consectetur")],
+        ]
+    );
+}
+
+#[test]
+fn template_variables_range() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% for i in start..=end %}{{ i }}{{ loop.index }}{{ lorem }}{% endfor %}")
+        .unwrap();
+    let key = |k| ValueMember {
+        op: ValueAccessOp::Direct,
+        access: ValueAccess::Key(k),
+    };
+    assert_eq!(
+        template.variables(),
+        [vec![key("start")], vec![key("end")], vec![key("lorem")]]
+    );
+}
+
+#[test]
+fn template_variables_break_inside_with() {
+    let engine = Engine::new();
+    let template = engine
+        .compile(
+            "{% for item in items %}{% with item as lorem %}\
+             {% if lorem %}{% break %}{% endif %}{{ lorem }}{% endwith %}{{ ipsum }}{% endfor %}",
+        )
+        .unwrap();
+    let key = |k| ValueMember {
+        op: ValueAccessOp::Direct,
+        access: ValueAccess::Key(k),
+    };
+    assert_eq!(
+        template.variables(),
+        [vec![key("items")], vec![key("ipsum")]]
+    );
+}
+
+#[test]
+fn template_variables_set() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% if ipsum %}{% set lorem = ipsum %}{{ lorem }}{% endif %}{{ lorem }}")
+        .unwrap();
+    let key = |k| ValueMember {
+        op: ValueAccessOp::Direct,
+        access: ValueAccess::Key(k),
+    };
+    assert_eq!(
+        template.variables(),
+        [vec![key("ipsum")], vec![key("lorem")]]
+    );
+}
+
+#[test]
+fn template_check_against() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "nested",
+            "{% for item in items %}{{ item }}{{ lorem.ipsum }}{% endfor %}\
+             {% with dolor as sit %}{{ sit }}{% endwith %}{{ amet }}{{ lorem?.sit }}{{ amet }}",
+        )
+        .unwrap();
+    let template = engine.template("nested");
+    assert_eq!(
+        template.check_against(&["items", "lorem", "dolor", "amet"]),
+        Ok(())
+    );
+    assert_eq!(
+        template.check_against(&["items", "sit"]),
+        Err(vec![
+            String::from("lorem"),
+            String::from("dolor"),
+            String::from("amet")
+        ])
+    );
+}
+
+#[test]
+fn template_disassemble() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("lorem {% if ipsum %}{{ dolor.sit | upper }}{% endif %}")
+        .unwrap();
+    assert_eq!(
+        template.disassemble(),
+        r#"0000  EmitRaw         "lorem "                 @ 0..6
+0001  ExprStart       ipsum                    @ 12..17
+0002  JumpIfFalse     -> 0005
+0003  ExprStart       dolor.sit                @ 23..32
+0004  EmitWith        upper                    @ 23..40
+"#
+    );
+}
+
+#[test]
+fn template_stats() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ lorem }}").unwrap();
+    let template = engine
+        .compile(
+            r#"{% for i in 0..n %}{% if i == ipsum | len %}{% include "nested" %}{% endif %}{% endfor %}
+{% for x in dolor %}{{ x | sit: 1, 2 }}{% endfor %}{% include_raw "amet" %}"#,
+        )
+        .unwrap();
+    let stats = template.stats();
+    assert_eq!(stats.instrs, template.instr_count());
+    assert_eq!(stats.loops, 2);
+    assert_eq!(stats.includes, 2);
+    assert_eq!(stats.exprs, 6);
+    assert_eq!(stats.calls, 2);
+
+    let template = engine.get_template("nested").unwrap();
+    let stats = template.stats();
+    assert_eq!(stats.instrs, 2);
+    assert_eq!(template.instr_count(), 2);
+    assert_eq!(
+        (stats.loops, stats.includes, stats.exprs, stats.calls),
+        (0, 0, 1, 0)
+    );
+}
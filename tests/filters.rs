@@ -2,7 +2,10 @@
 #![cfg(feature = "serde")]
 
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 
+use upon::filters::{self, Lazy};
 use upon::{value, Engine, Error, Value};
 
 #[test]
@@ -443,6 +446,693 @@ fn render_filter_err_custom() {
     );
 }
 
+#[test]
+fn render_filter_err_custom_at_value() {
+    let mut engine = Engine::new();
+    engine.add_filter("test", |_: &Value| {
+        Err::<bool, _>(filters::Error::at_value("test error"))
+    });
+    engine.add_filter("lower", str::to_lowercase);
+    let tests = [
+        (
+            "{{ user.name | test | lower }}",
+            "
+  --> <anonymous>:1:4
+   |
+ 1 | {{ user.name | test | lower }}
+   |    ^^^^^^^^^
+   |
+   = reason: REASON
+",
+        ),
+        (
+            "{{ user.name | lower | test }}",
+            "
+  --> <anonymous>:1:4
+   |
+ 1 | {{ user.name | lower | test }}
+   |    ^^^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+        ),
+    ];
+    for (source, pretty) in tests {
+        let err = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, upon::value! { user: { name: "John Smith" } })
+            .to_string()
+            .unwrap_err();
+        assert_filter_err(&err, "test error", pretty);
+    }
+}
+
+#[test]
+fn render_filter_err_custom_at_arg() {
+    let mut engine = Engine::new();
+    engine.add_filter("test", |_: &Value, _: i64, n: i64| {
+        Err::<bool, _>(filters::Error::at_arg(n as usize, "test error"))
+    });
+    let tests = [
+        (
+            "{{ name | test: 1, 0 }}",
+            "
+  --> <anonymous>:1:17
+   |
+ 1 | {{ name | test: 1, 0 }}
+   |                 ^--
+   |
+   = reason: REASON
+",
+        ),
+        (
+            "{{ name | test: 1, 1 }}",
+            "
+  --> <anonymous>:1:20
+   |
+ 1 | {{ name | test: 1, 1 }}
+   |                    ^--
+   |
+   = reason: REASON
+",
+        ),
+        // There is no such argument so the error points at the filter.
+        (
+            "{{ name | test: 1, 2 }}",
+            "
+  --> <anonymous>:1:11
+   |
+ 1 | {{ name | test: 1, 2 }}
+   |           ^^^^
+   |
+   = reason: REASON
+",
+        ),
+    ];
+    for (source, pretty) in tests {
+        let err = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, upon::value! { name: "John Smith" })
+            .to_string()
+            .unwrap_err();
+        assert_filter_err(&err, "test error", pretty);
+    }
+}
+
+#[test]
+fn render_filter_err_custom_loop() {
+    let mut engine = Engine::new();
+    engine.add_filter("test", |_: &Value, at: &str| {
+        Err::<Vec<Value>, _>(match at {
+            "value" => filters::Error::at_value("test error"),
+            "arg" => filters::Error::at_arg(0, "test error"),
+            _ => filters::Error::new("test error"),
+        })
+    });
+    engine.add_filter("rev", |mut list: Vec<Value>| {
+        list.reverse();
+        list
+    });
+    let tests = [
+        (
+            r#"{% for x in xs | rev | test: "filter" %}{% endfor %}"#,
+            r#"
+  --> <anonymous>:1:24
+   |
+ 1 | {% for x in xs | rev | test: "filter" %}{% endfor %}
+   |                        ^^^^
+   |
+   = reason: REASON
+"#,
+        ),
+        (
+            r#"{% for x in xs | rev | test: "value" | rev %}{% endfor %}"#,
+            r#"
+  --> <anonymous>:1:13
+   |
+ 1 | {% for x in xs | rev | test: "value" | rev %}{% endfor %}
+   |             ^^^^^^^^
+   |
+   = reason: REASON
+"#,
+        ),
+        (
+            r#"{% for x in xs | rev | test: "arg" %}{% endfor %}"#,
+            r#"
+  --> <anonymous>:1:30
+   |
+ 1 | {% for x in xs | rev | test: "arg" %}{% endfor %}
+   |                              ^^^^^
+   |
+   = reason: REASON
+"#,
+        ),
+    ];
+    for (source, pretty) in tests {
+        let err = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, upon::value! { xs: [1, 2, 3] })
+            .to_string()
+            .unwrap_err();
+        assert_filter_err(&err, "test error", pretty);
+    }
+}
+
+#[test]
+fn render_filter_err_custom_at_value_named_args() {
+    let mut engine = Engine::new();
+    engine.add_filter_with_named_args("test", |_, _, _| {
+        Err::<bool, _>(filters::Error::at_value("test error"))
+    });
+    let err = engine
+        .compile("{{ name | test: width = 10 }}")
+        .unwrap()
+        .render(&engine, upon::value! { name: "John Smith" })
+        .to_string()
+        .unwrap_err();
+    assert_filter_err(
+        &err,
+        "test error",
+        "
+  --> <anonymous>:1:4
+   |
+ 1 | {{ name | test: width = 10 }}
+   |    ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_named_args() {
+    let mut engine = Engine::new();
+    engine.add_filter_with_named_args(
+        "test",
+        |v: &Value, args: &[Value], named: &BTreeMap<String, Value>| {
+            format!("{v:?} {args:?} {named:?}")
+        },
+    );
+    let result = engine
+        .compile(r#"{{ name | test: 1, x, width = 10, fill = y }}"#)
+        .unwrap()
+        .render(&engine, value! { name: "John", x: true, y: "0" })
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        r#"String("John") [Integer(1), Bool(true)] {"fill": String("0"), "width": Integer(10)}"#
+    );
+}
+
+#[test]
+fn render_filter_named_args_none() {
+    let mut engine = Engine::new();
+    engine.add_filter_with_named_args(
+        "test",
+        |_: &Value, args: &[Value], named: &BTreeMap<String, Value>| {
+            (args.len() + named.len()) as i64
+        },
+    );
+    let result = engine
+        .compile("{{ name | test }} {{ name | test: 1, 2 }}")
+        .unwrap()
+        .render(&engine, value! { name: "John" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "0 2");
+}
+
+#[test]
+fn render_filter_err_named_args_not_accepted() {
+    let mut engine = Engine::new();
+    engine.add_filter("test", |v: Value, _: i64| v);
+    let err = engine
+        .compile("{{ name | test: width = 10 }}")
+        .unwrap()
+        .render(&engine, value! { name: "John Smith" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "filter does not accept named arguments",
+        "
+  --> <anonymous>:1:17
+   |
+ 1 | {{ name | test: width = 10 }}
+   |                 ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_stateful_atomic() {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    let mut engine = Engine::new();
+    let counter = AtomicI64::new(0);
+    engine.add_filter("counter", move |_: &Value| {
+        counter.fetch_add(1, Ordering::Relaxed)
+    });
+    let template = engine
+        .compile("{{ x | counter }}{{ x | counter }}")
+        .unwrap();
+    let result = template
+        .render(&engine, value! { x: None })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "01");
+    // The state is shared by all renders.
+    let result = template
+        .render(&engine, value! { x: None })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "23");
+}
+
+#[test]
+fn render_filter_mut() {
+    let mut engine = Engine::new();
+    engine.add_filter_mut(
+        "counter",
+        |ctx: &mut upon::filters::RenderContext, _: &Value, args: &[Value]| {
+            let step = match args {
+                [Value::Integer(step)] => *step,
+                _ => 1,
+            };
+            let n = match ctx.get("n") {
+                Some(Value::Integer(n)) => *n,
+                _ => 0,
+            };
+            ctx.insert("n", n + step);
+            n
+        },
+    );
+    engine
+        .add_template("nested", "{{ x | counter: 10 }}")
+        .unwrap();
+    let template = engine
+        .compile(r#"{{ x | counter }} {{ x | counter }} {% include "nested" %} {{ x | counter }}"#)
+        .unwrap();
+    // The state is shared with included templates but reset for every render.
+    for _ in 0..2 {
+        let result = template
+            .render(&engine, value! { x: None })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "0 1 2 12");
+    }
+}
+
+#[test]
+fn render_filter_mut_arg_source() {
+    let mut engine = Engine::new();
+    engine.add_filter_mut(
+        "sources",
+        |ctx: &mut upon::filters::RenderContext, _: &Value, args: &[Value]| {
+            let sources: Result<Vec<_>, _> = (0..args.len()).map(|i| ctx.arg_source(i)).collect();
+            sources.map(|s| s.join(" "))
+        },
+    );
+    let result = engine
+        .compile(r#"{{ x | sources: "a\u{41}",  0x1F, x.y }}|{{ x | sources }}"#)
+        .unwrap()
+        .render(&engine, value! { x: { y: 1 } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, r#""a\u{41}" 0x1F x.y|"#);
+}
+
+#[test]
+fn render_filter_mut_err_arg_source_out_of_bounds() {
+    let mut engine = Engine::new();
+    engine.add_filter_mut(
+        "test",
+        |ctx: &mut upon::filters::RenderContext, _: &Value, _: &[Value]| {
+            ctx.arg_source(1).map(str::to_owned)
+        },
+    );
+    let err = engine
+        .compile("{{ name | test: 1 }}")
+        .unwrap()
+        .render(&engine, value! { name: "John Smith" })
+        .to_string()
+        .unwrap_err();
+    assert_filter_err(
+        &err,
+        "filter has no positional argument at index 1",
+        "
+  --> <anonymous>:1:11
+   |
+ 1 | {{ name | test: 1 }}
+   |           ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_mut_err_named_args() {
+    let mut engine = Engine::new();
+    engine.add_filter_mut(
+        "test",
+        |_: &mut upon::filters::RenderContext, v: &Value, _: &[Value]| v.clone(),
+    );
+    let err = engine
+        .compile("{{ name | test: width = 10 }}")
+        .unwrap()
+        .render(&engine, value! { name: "John Smith" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "filter does not accept named arguments",
+        "
+  --> <anonymous>:1:17
+   |
+ 1 | {{ name | test: width = 10 }}
+   |                 ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_with_context() {
+    let mut engine = Engine::new();
+    engine.add_filter_with_context(
+        "get",
+        |ctx: filters::Context<'_>, path: &Value, _: &[Value]| match path {
+            Value::String(path) => ctx.get(path).map(|v| v.into_owned()),
+            _ => None,
+        },
+    );
+    let globals = value! { site: "example.com" };
+    let template = engine
+        .compile(
+            r#"{{ "user.name" | get }} {{ "users.-1" | get }} {{ "users.0" | get }} {{ "site" | get }} {{ "user.age" | get }}{{ "missing.x" | get }}{{ "user." | get }}{% for user in users %} {{ "user" | get }}{% endfor %}"#,
+        )
+        .unwrap();
+    let result = template
+        .render(
+            &engine,
+            value! { user: { name: "John" }, users: ["Ann", "Bob"] },
+        )
+        .with_globals(&globals)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "John Bob Ann example.com  Ann Bob");
+}
+
+#[test]
+fn render_filter_with_context_with_and_set() {
+    let mut engine = Engine::new();
+    engine.add_filter_with_context("t", |ctx: filters::Context<'_>, _: &Value, _: &[Value]| {
+        ctx.get("locale").map(|v| v.into_owned())
+    });
+    let result = engine
+        .compile(
+            r#"{% with l as locale %}{{ "lorem" | t }}{% endwith %} {% set locale = m %}{{ "ipsum" | t }}"#,
+        )
+        .unwrap()
+        .render(&engine, value! { l: "en", m: "fr" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "en fr");
+}
+
+#[test]
+fn render_filter_with_context_err() {
+    let mut engine = Engine::new();
+    engine.add_filter_with_context("t", |ctx: filters::Context<'_>, _: &Value, _: &[Value]| {
+        ctx.get("locale")
+            .map(|v| v.into_owned())
+            .ok_or_else(|| filters::Error::at_value("no locale set"))
+    });
+    let err = engine
+        .compile(r#"{{ "hello" | t }}"#)
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap_err();
+    assert_filter_err(
+        &err,
+        "no locale set",
+        r#"
+  --> <anonymous>:1:4
+   |
+ 1 | {{ "hello" | t }}
+   |    ^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+
+    let err = engine
+        .compile("{{ x | t: n = 1 }}")
+        .unwrap()
+        .render(&engine, value! { x: 1, locale: "en" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "filter does not accept named arguments",
+        "
+  --> <anonymous>:1:11
+   |
+ 1 | {{ x | t: n = 1 }}
+   |           ^--
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_lazy_loop() {
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let mut engine = Engine::new();
+    engine.add_filter("count_from", {
+        let pulled = pulled.clone();
+        move |start: i64| {
+            let pulled = pulled.clone();
+            Lazy((start..).inspect(move |_| {
+                pulled.fetch_add(1, Ordering::Relaxed);
+            }))
+        }
+    });
+    let result = engine
+        .compile(
+            "{% for i in 1 | count_from %}{{ i }}{% if i == 3 %}{% break %}{% endif %}{% endfor %}",
+        )
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "123");
+    // The loop only looks one item ahead.
+    assert_eq!(pulled.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn render_filter_lazy_loop_fields() {
+    let mut engine = Engine::new();
+    engine.add_filter("exact", |list: Vec<Value>| Lazy(list.into_iter()));
+    engine.add_filter("odd", |list: Vec<Value>| {
+        Lazy(
+            list.into_iter()
+                .filter(|v| matches!(v, Value::Integer(i) if i % 2 == 1)),
+        )
+    });
+    let source = "{% for x in xs | FILTER %}{{ loop.index }}:{{ x }}/{{ loop.length }}{% if loop.last %}!{% endif %} {% endfor %}";
+
+    // The length is known from the exact size hint.
+    let result = engine
+        .compile(source.replace("FILTER", "exact"))
+        .unwrap()
+        .render(&engine, value! { xs: [1, 2, 3] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "0:1/3 1:2/3 2:3/3! ");
+
+    // The length is only known once there are no more items.
+    let result = engine
+        .compile(source.replace("FILTER", "odd"))
+        .unwrap()
+        .render(&engine, value! { xs: [1, 2, 3, 4] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "0:1/ 1:3/2! ");
+}
+
+#[test]
+fn render_filter_lazy_loop_reversed() {
+    let mut engine = Engine::new();
+    engine.add_filter("upto", |n: i64| Lazy(0..n));
+    let result = engine
+        .compile("{% for x in 3 | upto reversed %}{{ x }}{{ loop.length }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "23 13 03 ");
+}
+
+#[test]
+fn render_filter_lazy_loop_else() {
+    let mut engine = Engine::new();
+    engine.add_filter("upto", |n: i64| Lazy(0..n));
+    let template = engine
+        .compile("{% for x in n | upto %}{{ x }}{% else %}none{% endfor %}")
+        .unwrap();
+    let result = template
+        .render(&engine, value! { n: 0 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "none");
+    let result = template
+        .render(&engine, value! { n: 3 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "012");
+}
+
+#[test]
+fn render_filter_lazy_collected() {
+    let mut engine = Engine::new();
+    engine.add_filter("upto", |n: i64| Lazy(0..n));
+    engine.add_filter("len", |list: &[Value]| list.len() as i64);
+    let result = engine
+        .compile("{{ 3 | upto | len }} {% set xs = 4 | upto %}{% for x in xs %}{{ x }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "3 0123");
+}
+
+#[test]
+fn render_filter_lazy_err_custom() {
+    let mut engine = Engine::new();
+    engine.add_filter("upto", |n: i64| match n {
+        n if n < 0 => Err("negative"),
+        n => Ok(Lazy(0..n)),
+    });
+    let err = engine
+        .compile("{% for x in -1 | upto %}{{ x }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap_err();
+    assert_filter_err(
+        &err,
+        "negative",
+        "
+  --> <anonymous>:1:18
+   |
+ 1 | {% for x in -1 | upto %}{{ x }}{% endfor %}
+   |                  ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_lazy_err_unbounded() {
+    let mut engine = Engine::new();
+    engine.add_filter("count_from", |start: i64| Lazy(start..));
+    engine.add_filter("len", |list: &[Value]| list.len() as i64);
+    for source in [
+        "{{ 1 | count_from }}",
+        "{{ 1 | count_from | len }}",
+        "{% for i in 1 | count_from reversed %}{% endfor %}",
+    ] {
+        let err = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, value! {})
+            .to_string()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "render error: cannot collect an unbounded lazy iterator into a list"
+        );
+    }
+    let err = engine
+        .compile("{{ 1 | count_from | len }}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot collect an unbounded lazy iterator into a list",
+        "
+  --> <anonymous>:1:8
+   |
+ 1 | {{ 1 | count_from | len }}
+   |        ^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_filter_lazy_collected_cancel() {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut engine = Engine::new();
+    engine.add_filter("evens", {
+        let cancel = cancel.clone();
+        move |start: i64| {
+            let cancel = cancel.clone();
+            Lazy((start..).filter(|i| i % 2 == 0).inspect(move |_| {
+                cancel.store(true, Ordering::Relaxed);
+            }))
+        }
+    });
+    let err = engine
+        .compile("{{ 0 | evens }}")
+        .unwrap()
+        .render(&engine, value! {})
+        .with_cancel(&cancel)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: rendering was cancelled");
+}
+
+#[test]
+fn render_filter_lazy_err_unpack() {
+    let mut engine = Engine::new();
+    engine.add_filter("upto", |n: i64| Lazy(0..n));
+    let err = engine
+        .compile("{% for k, v in 3 | upto %}{{ k }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack iterator item into two variables",
+        "
+  --> <anonymous>:1:8
+   |
+ 1 | {% for k, v in 3 | upto %}{{ k }}{% endfor %}
+   |        ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
 #[track_caller]
 fn assert_filter_err(err: &Error, reason: &str, pretty: &str) {
     let display = format!("filter error: {reason}");
@@ -458,3 +1148,44 @@ fn assert_err(err: &Error, reason: &str, pretty: &str) {
     assert_eq!(err.to_string(), display);
     assert_eq!(format!("{err:#}"), display_alt);
 }
+
+#[test]
+fn render_filter_owned_list_unpack_many_vars() {
+    let mut engine = Engine::new();
+    engine.add_filter("chunks", |v: Vec<Value>, n: i64| {
+        v.chunks(n as usize)
+            .map(|c| Value::from(c.to_vec()))
+            .collect::<Vec<_>>()
+    });
+    let result = engine
+        .compile("{% for a, b, c in nums | chunks: 3 %}{{ c }}{{ b }}{{ a }};{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { nums: [1, 2, 3, 4, 5, 6] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "321;654;");
+}
+
+#[test]
+fn render_filter_lazy_err_unpack_many_vars() {
+    let mut engine = Engine::new();
+    engine.add_filter("upto", |n: i64| Lazy(0..n));
+    let err = engine
+        .compile("{% for a, b, c in 3 | upto %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! {})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack iterator item into 3 variables",
+        "
+  --> <anonymous>:1:8
+   |
+ 1 | {% for a, b, c in 3 | upto %}{% endfor %}
+   |        ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
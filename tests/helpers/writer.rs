@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use std::fmt;
 use std::io;
 
 #[derive(Default)]
@@ -46,3 +47,14 @@ impl io::Write for Writer {
         Ok(())
     }
 }
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.count += 1;
+        if self.count > self.max {
+            return Err(fmt::Error);
+        }
+        self.buf.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use upon::{Engine, Error, Syntax};
+use upon::{Engine, Error, ErrorKind, Syntax};
 
 #[test]
 fn lex_while_eof() {
@@ -48,6 +48,92 @@ fn lex_syntax_precedence() {
         .unwrap();
 }
 
+#[test]
+fn lex_syntax_delimiter_precedence() {
+    let syntax = Syntax::builder().expr("<", ">").build();
+    let engine = Engine::with_syntax(syntax);
+    engine.compile("lorem <ipsum>").unwrap();
+    let err = engine.compile("lorem < a > b >").unwrap_err();
+    assert_err(
+        &err,
+        "unexpected end expression",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem < a > b >
+   |               ^--
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn lex_syntax_err_ambiguous() {
+    let tests = [
+        (
+            Syntax::builder()
+                .expr("{", "}")
+                .block("{", "%}")
+                .try_build(),
+            "begin expression and begin block are both `{`",
+        ),
+        (
+            Syntax::builder().expr("|", "|").try_build(),
+            "begin expression and end expression are both `|`",
+        ),
+        (
+            Syntax::builder()
+                .expr("{", "}")
+                .block("{-", "-}")
+                .try_build(),
+            "begin expression trim and begin block are both `{-`",
+        ),
+        (
+            Syntax::builder()
+                .block("<%", "%>")
+                .comment("<#", "<%")
+                .try_build(),
+            "begin block and end comment are both `<%`",
+        ),
+    ];
+    for (result, reason) in tests {
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Syntax);
+        assert_eq!(
+            err.to_string(),
+            format!("invalid syntax: ambiguous delimiters, {reason}")
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "ambiguous delimiters, end block and end comment are both `%}`")]
+fn lex_syntax_build_panics_ambiguous() {
+    Syntax::builder()
+        .block("{%", "%}")
+        .comment("{#", "%}")
+        .build();
+}
+
+#[test]
+fn lex_syntax_accessors() {
+    let syntax = Syntax::default();
+    assert_eq!(syntax.expr(), Some(("{{", "}}")));
+    assert_eq!(syntax.block(), Some(("{%", "%}")));
+    assert_eq!(syntax.comment(), Some(("{#", "#}")));
+    assert!(!syntax.case_insensitive_keywords());
+
+    let syntax = Syntax::builder()
+        .block("<-", "->")
+        .case_insensitive_keywords(true)
+        .build();
+    assert_eq!(syntax.expr(), None);
+    assert_eq!(syntax.block(), Some(("<-", "->")));
+    assert_eq!(syntax.comment(), None);
+    assert!(syntax.case_insensitive_keywords());
+}
+
 #[test]
 fn lex_err_unexpected_end_expr() {
     let err = Engine::new()
@@ -182,6 +268,25 @@ fn lex_err_unexpected_character() {
     );
 }
 
+#[test]
+fn lex_err_unexpected_character_bang() {
+    let err = Engine::new()
+        .compile("lorem {% if ipsum ! dolor %}")
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unexpected character",
+        "
+  --> <anonymous>:1:19
+   |
+ 1 | lorem {% if ipsum ! dolor %}
+   |                   ^--
+   |
+   = reason: REASON
+",
+    );
+}
+
 #[test]
 fn lex_err_unclosed_begin_comment() {
     let err = Engine::new()
@@ -2,7 +2,7 @@
 
 use std::collections::BTreeMap;
 
-use upon::{value, Value};
+use upon::{try_value, value, Value};
 
 #[test]
 fn value_empty() {
@@ -28,6 +28,11 @@ fn value_literal() {
     }
 }
 
+#[test]
+fn value_literal_true() {
+    assert_eq!(upon::_value!([unwrap] true), Value::Bool(true));
+}
+
 #[test]
 fn value_list() {
     // empty list
@@ -124,6 +129,95 @@ fn value_map() {
     assert_eq!(v, exp);
 }
 
+#[test]
+fn value_interpolate() {
+    // variable
+    let names = vec!["John", "James"];
+    let v = value! { names: names };
+    let exp = Value::from([("names", Value::from(["John", "James"]))]);
+    assert_eq!(v, exp);
+
+    // existing value
+    let user = Value::from([("name", "John Smith")]);
+    let v = value! { user: user.clone(), users: [user.clone(), &user] };
+    let exp = Value::from([
+        ("user", user.clone()),
+        ("users", Value::from([user.clone(), user])),
+    ]);
+    assert_eq!(v, exp);
+
+    // nested macro invocation
+    let v = value! { user: value! { name: "John Smith", age: 36 } };
+    let exp = Value::from([(
+        "user",
+        Value::from([
+            ("age", Value::from(36)),
+            ("name", Value::from("John Smith")),
+        ]),
+    )]);
+    assert_eq!(v, exp);
+
+    // expressions
+    let count = 41;
+    let name = "john";
+    let v = value! {
+        count: count + 1,
+        name: name.to_uppercase(),
+        list: [count * 2, (count)],
+        cond: count > 40,
+    };
+    let exp = Value::from([
+        ("count", Value::from(42)),
+        ("name", Value::from("JOHN")),
+        ("list", Value::from([82, 41])),
+        ("cond", Value::from(true)),
+    ]);
+    assert_eq!(v, exp);
+}
+
+#[test]
+fn try_value_interpolate() -> upon::Result<()> {
+    fn name() -> upon::Result<String> {
+        Ok(String::from("John"))
+    }
+
+    let v = try_value! {}?;
+    assert_eq!(v, Value::Map(BTreeMap::new()));
+
+    let count = 41;
+    let v = try_value! {
+        count: count + 1,
+        user: { name: name()?, tags: [count, None] },
+    }?;
+    let exp = Value::from([
+        ("count", Value::from(42)),
+        (
+            "user",
+            Value::from([
+                ("name", Value::from("John")),
+                ("tags", Value::from([Value::from(41), Value::None])),
+            ]),
+        ),
+    ]);
+    assert_eq!(v, exp);
+    Ok(())
+}
+
+#[test]
+fn try_value_err() {
+    let err = try_value! { nested: { list: [u64::MAX] } }.unwrap_err();
+    assert_eq!(err.kind(), upon::ErrorKind::Serialize);
+
+    fn fails() -> upon::Result<i64> {
+        upon::to_value(u64::MAX).map(|_| 0)
+    }
+    let err = try_value! { x: fails()? }.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "serialize error: out of range integral type conversion attempted"
+    );
+}
+
 #[test]
 fn value_compile_fail() {
     // let _ = value! { field: {,} };
@@ -0,0 +1,172 @@
+#![cfg(all(feature = "precompile", feature = "serde", feature = "filters"))]
+
+use upon::{value, Engine, ErrorKind, Template, Undefined};
+
+const SOURCE: &str = r#"
+{%- set title = page.title | upper -%}
+<h1>{{ title }}</h1>
+{% for i in 1..=page.count %}{{ i }}{% if loop.last %}!{% endif %}{% endfor %}
+{% for i in 1..=page.count reversed %}{{ i }}{% endfor %}
+{% for key, value in page.meta -%}
+    {%- if key == "skip" or value is empty %}{% continue %}{% endif -%}
+    {{ key }}={{ value | default: fallback = "none" }};
+{%- endfor %}
+{% with page.count as n %}{% if n is number and page.nope is not defined %}{% autoescape off %}{{ n }}{% endautoescape %}{% endif %}{% endwith %}
+{% block footer %}{% include "footer" with page %}{% include "nope" ignore missing %}{% endblock %}
+{{ page?.missing?.0 }} {{ -1.5 }} {{ true }} {{ page.nope ?? page.title | upper ?? 0 }} {{ page.title ~ "-" ~ page.count }}"#;
+
+fn engine() -> Engine<'static> {
+    let mut engine = Engine::new();
+    engine.add_filter("upper", str::to_uppercase);
+    engine.add_filter_with_named_args("default", |v, _, named| match v {
+        upon::Value::None => named["fallback"].clone(),
+        v => v.clone(),
+    });
+    engine.add_template("footer", "({{ title }})").unwrap();
+    engine
+}
+
+#[test]
+fn template_to_bytes_round_trip() {
+    let engine = engine();
+    let template = engine.compile(SOURCE).unwrap();
+    let bytes = template.to_bytes();
+    let loaded = Template::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.source(), template.source());
+    assert_eq!(loaded.disassemble(), template.disassemble());
+    assert_eq!(loaded.to_bytes(), bytes);
+
+    let ctx = value! {
+        page: { title: "Lorem", count: 3, meta: { a: "x", skip: "y", b: None } },
+    };
+    let exp = template.render(&engine, &ctx).to_string().unwrap();
+    let result = loaded.render(&engine, &ctx).to_string().unwrap();
+    assert_eq!(result, exp);
+    assert_eq!(
+        result,
+        "<h1>LOREM</h1>\n123!\n321\na=x;\n3\n(Lorem)\n -1.5 true LOREM Lorem-3"
+    );
+}
+
+#[test]
+fn template_to_bytes_round_trip_many_loop_vars() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% for a, b, c in rows %}{{ c }}{{ b }}{{ a }}{% endfor %}")
+        .unwrap();
+    let loaded = Template::from_bytes(&template.to_bytes()).unwrap();
+    assert_eq!(loaded.disassemble(), template.disassemble());
+    let result = loaded
+        .render(&engine, value! { rows: [[1, 2, 3]] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "321");
+}
+
+#[test]
+fn template_to_bytes_round_trip_for_else() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% for x in xs %}{{ x }}{% break %}{% else %}none{% endfor %}")
+        .unwrap();
+    let loaded = Template::from_bytes(&template.to_bytes()).unwrap();
+    assert_eq!(loaded.disassemble(), template.disassemble());
+    let result = loaded
+        .render(&engine, value! { xs: [] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "none");
+    let result = loaded
+        .render(&engine, value! { xs: [1, 2] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "1");
+}
+
+#[test]
+fn template_to_bytes_round_trip_nested_loops() {
+    let engine = Engine::new();
+    let template = engine
+        .compile(
+            "{% for xs in rows %}{% for x in xs %}{% with x as y %}\
+             {% if y == 2 %}{% continue %}{% endif %}{% if y == 4 %}{% break %}{% endif %}{{ y }}\
+             {% endwith %}{% endfor %};{% endfor %}",
+        )
+        .unwrap();
+    let loaded = Template::from_bytes(&template.to_bytes()).unwrap();
+    assert_eq!(loaded.disassemble(), template.disassemble());
+    let result = loaded
+        .render(&engine, value! { rows: [[1, 2, 3], [4, 5]] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "13;;");
+}
+
+#[test]
+fn template_to_bytes_round_trip_undefined_keep() {
+    let engine = Engine::new();
+    let template = engine.compile("{{ lorem }} {{- ipsum }}").unwrap();
+    let loaded = Template::from_bytes(&template.to_bytes()).unwrap();
+    let result = loaded
+        .render(&engine, value! { ipsum: "x" })
+        .with_undefined_behavior(Undefined::Keep)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "{{ lorem }}x");
+}
+
+#[test]
+fn engine_load_template() {
+    let mut engine = engine();
+    engine
+        .add_template("base", "<{% block body %}{% endblock %}>")
+        .unwrap();
+    let bytes = engine
+        .compile(r#"{% extends "base" %}{% block body %}{{ lorem }}{% endblock %}"#)
+        .unwrap()
+        .to_bytes();
+    engine.load_template("child", &bytes).unwrap();
+    let result = engine
+        .template("child")
+        .render(value! { lorem: "ipsum" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<ipsum>");
+}
+
+#[test]
+fn engine_load_template_err_corrupted() {
+    let mut engine = Engine::new();
+    let bytes = engine.compile("lorem {{ ipsum }}").unwrap().to_bytes();
+
+    let mut corrupted = bytes.clone();
+    corrupted[10] ^= 0xff;
+    let err = engine.load_template("lorem", &corrupted).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Precompile);
+    assert_eq!(err.template_name(), Some("lorem"));
+    assert_eq!(
+        err.to_string(),
+        "invalid precompiled template: checksum mismatch"
+    );
+
+    let err = engine
+        .load_template("lorem", &bytes[..bytes.len() - 1])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid precompiled template: checksum mismatch"
+    );
+
+    let err = engine.load_template("lorem", b"UPON").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid precompiled template: unexpected end of input"
+    );
+
+    let err = engine.load_template("lorem", b"lorem ipsum").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid precompiled template: missing header"
+    );
+    assert!(engine.get_template("lorem").is_none());
+}
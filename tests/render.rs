@@ -6,9 +6,11 @@ use std::collections::BTreeMap;
 use std::error::Error as _;
 use std::fmt::Write;
 use std::iter::zip;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use upon::fmt;
-use upon::{value, Engine, Error, Value};
+use upon::{value, Engine, Error, ErrorKind, RenderOptions, Syntax, Undefined, Value, ValueKind};
 
 use crate::helpers::Writer;
 
@@ -106,6 +108,18 @@ fn render_inline_expr_literal_string_escaped() {
     assert_eq!(result, "lorem escaped \n \r \t \\ \"");
 }
 
+#[test]
+fn render_inline_expr_literal_string_unicode_escaped() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"lorem {{ "\u{1F600} \u{e9}\u{0} \0 \u{10FFFF}" }}"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem \u{1F600} \u{e9}\0 \0 \u{10FFFF}");
+}
+
 #[cfg(feature = "filters")]
 #[test]
 fn render_inline_expr_literal_with_filter() {
@@ -156,6 +170,66 @@ fn render_inline_expr_map_optional_key_chain() {
     assert_eq!(result, "lorem ");
 }
 
+#[test]
+fn render_inline_expr_optional_missing_placeholder() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(
+            "{{ ipsum?.dolor }} {{ ipsum?.sit.amet }} {{ ipsum.consectetur }} {{ ipsum?.lorem }}",
+        )
+        .unwrap()
+        .render(
+            &engine,
+            value! { ipsum: { lorem: "adipiscing", consectetur: None } },
+        )
+        .with_missing_placeholder("-")
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "- -  adipiscing");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_inline_expr_optional_missing_placeholder_with_filter() {
+    let mut engine = Engine::new();
+    engine.add_filter("default", |v: Value, d: String| match v {
+        Value::None => Value::String(d),
+        v => v,
+    });
+    let result = engine
+        .compile(r#"{{ ipsum?.dolor | default: "sit" }} {{ ipsum?.dolor ?? "amet" }}"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: {} })
+        .with_missing_placeholder("-")
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "sit amet");
+}
+
+#[test]
+fn render_inline_expr_optional_missing_placeholder_err_direct() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: {} })
+        .with_missing_placeholder("-")
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in map",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.dolor }}
+   |               ^^^^^^
+   |
+   = reason: not found in map
+",
+    );
+}
+
 #[test]
 fn render_inline_expr_map_optional_key_chain_long() {
     let engine = Engine::new();
@@ -168,171 +242,138 @@ fn render_inline_expr_map_optional_key_chain_long() {
     assert_eq!(result, "lorem ");
 }
 
-#[cfg(feature = "unicode")]
 #[test]
-fn render_inline_expr_map_index_unicode_ident() {
+fn render_inline_expr_coalesce() {
     let engine = Engine::new();
     let result = engine
-        .compile("lorem {{ ipsum.привіт }}")
+        .compile(r#"{{ ipsum ?? "x" }} {{ dolor.sit ?? amet ?? "x" }} {{ sit?.amet ?? "x" }}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: { привіт: "sit"} })
+        .render(&engine, value! { ipsum: None, amet: "y", sit: {} })
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem sit");
+    assert_eq!(result, "x y x");
 }
 
 #[test]
-fn render_inline_expr_list_index() {
+fn render_inline_expr_coalesce_first_found() {
     let engine = Engine::new();
     let result = engine
-        .compile("lorem {{ ipsum.1 }}")
+        .compile(r#"{{ ipsum ?? dolor ?? "x" }} {{ sit ?? 0 }}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: ["sit", "amet"] })
+        .render(&engine, value! { ipsum: false, dolor: "y", sit: "" })
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem amet");
+    assert_eq!(result, "false ");
 }
 
+#[cfg(feature = "filters")]
 #[test]
-fn render_inline_expr_custom_formatter() {
+fn render_inline_expr_coalesce_with_filter() {
     let mut engine = Engine::new();
-    engine.add_formatter("format_list", format_list);
+    engine.add_filter("upper", str::to_uppercase);
     let result = engine
-        .compile("lorem {{ ipsum | format_list }}")
+        .compile(r#"{{ ipsum | upper ?? dolor | upper }} {{ sit ?? amet | upper }}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: ["sit", "amet"] })
+        .render(&engine, value! { dolor: "y", sit: "z", amet: "w" })
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem sit;amet");
+    assert_eq!(result, "Y z");
 }
 
 #[test]
-fn render_inline_expr_default_formatter_err() {
-    let mut engine = Engine::new();
-    engine.set_default_formatter(&format_list);
-    let err = engine
-        .compile("lorem {{ ipsum }}")
+fn render_inline_expr_concat() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"{{ ipsum ~ "/" ~ dolor.sit ~ 1 ~ 2.5 ~ true ~ dolor?.x }} {{ "a"~"b" }}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: { sit: "amet"} })
+        .render(&engine, value! { ipsum: "lorem", dolor: { sit: 0 } })
         .to_string()
-        .unwrap_err();
-    assert_format_err(
-        &err,
-        "expected list",
-        "
-  --> <anonymous>:1:10
-   |
- 1 | lorem {{ ipsum }}
-   |          ^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .unwrap();
+    assert_eq!(result, "lorem/012.5true ab");
 }
 
 #[test]
-fn render_inline_expr_custom_formatter_err() {
+fn render_inline_expr_concat_not_escaped_twice() {
     let mut engine = Engine::new();
-    engine.add_formatter("format_list", format_list);
-    let err = engine
-        .compile("lorem {{ ipsum | format_list }}")
+    engine.set_default_formatter(&fmt::escape_html);
+    let result = engine
+        .compile(r#"{{ ipsum ~ "&" ~ dolor }}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: { sit: "amet"} })
+        .render(&engine, value! { ipsum: "<", dolor: ">" })
         .to_string()
-        .unwrap_err();
-    assert_format_err(
-        &err,
-        "expected list",
-        "
-  --> <anonymous>:1:18
-   |
- 1 | lorem {{ ipsum | format_list }}
-   |                  ^^^^^^^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .unwrap();
+    assert_eq!(result, "&lt;&amp;&gt;");
 }
 
-fn format_list(f: &mut fmt::Formatter<'_>, v: &Value) -> fmt::Result {
-    match v {
-        Value::List(list) => {
-            for (i, item) in list.iter().enumerate() {
-                if i != 0 {
-                    f.write_char(';')?;
-                }
-                fmt::default(f, item)?;
-            }
-            Ok(())
-        }
-        _ => Err("expected list".to_string())?,
-    }
+#[cfg(feature = "filters")]
+#[test]
+fn render_inline_expr_concat_with_filter_and_coalesce() {
+    let mut engine = Engine::new();
+    engine.add_filter("upper", str::to_uppercase);
+    let result = engine
+        .compile(r#"{{ ipsum | upper ~ dolor ?? "-" ~ sit | upper }}"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: "a", sit: "b" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "A-B");
 }
 
 #[test]
-fn render_inline_expr_err_unknown_filter_or_formatter() {
+fn render_inline_expr_concat_in_block() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {{ ipsum | unknown }}")
+    let result = engine
+        .compile(r#"{% set x = ipsum ~ dolor %}{{ x }}{% if x == "ab" ~ 1 %}!{% endif %}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: true })
+        .render(&engine, value! { ipsum: "ab", dolor: 1 })
         .to_string()
-        .unwrap_err();
-    assert_err(
-        &err,
-        "unknown filter or formatter",
-        "
-  --> <anonymous>:1:18
-   |
- 1 | lorem {{ ipsum | unknown }}
-   |                  ^^^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .unwrap();
+    assert_eq!(result, "ab1!");
 }
 
+#[cfg(feature = "filters")]
 #[test]
-fn render_inline_expr_err_unknown_filter_found_formatter() {
+fn render_inline_expr_group() {
     let mut engine = Engine::new();
-    engine.add_formatter("another", |_, _| Ok(()));
-    let err = engine
-        .compile("lorem {{ ipsum | another | unknown }}")
+    engine.add_filter("upper", str::to_uppercase);
+    let result = engine
+        .compile(r#"{{ (ipsum ~ " " ~ dolor) | upper }} {{ (ipsum | upper) ~ (sit ?? dolor) }} {{ ((ipsum)) }}"#)
         .unwrap()
-        .render(&engine, value! { ipsum: true })
+        .render(&engine, value! { ipsum: "lorem", dolor: "amet" })
         .to_string()
-        .unwrap_err();
-    assert_err(
-        &err,
-        "expected filter, found formatter",
-        "
-  --> <anonymous>:1:18
-   |
- 1 | lorem {{ ipsum | another | unknown }}
-   |                  ^^^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .unwrap();
+    assert_eq!(result, "LOREM AMET LOREMamet lorem");
 }
 
 #[test]
-fn render_inline_expr_err_unknown_filter() {
+fn render_inline_expr_group_in_block() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"{% if (ipsum ~ dolor) == "ab" %}!{% endif %}{% for x in (sit ?? amet) %}{{ x }}{% endfor %}"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: "a", dolor: "b", amet: ["c", "d"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "!cd");
+}
+
+#[test]
+fn render_inline_expr_concat_err_unformattable() {
     let engine = Engine::new();
     let err = engine
-        .compile("lorem {{ ipsum | another | unknown }}")
+        .compile("lorem {{ ipsum ~ dolor ~ sit }}")
         .unwrap()
-        .render(&engine, value! { ipsum: true })
+        .render(&engine, value! { ipsum: "a", dolor: 1, sit: [] })
         .to_string()
         .unwrap_err();
-    assert_err(
+    assert_format_err(
         &err,
-        "unknown filter",
+        "expression evaluated to unformattable type list",
         "
-  --> <anonymous>:1:18
+  --> <anonymous>:1:26
    |
- 1 | lorem {{ ipsum | another | unknown }}
-   |                  ^^^^^^^
+ 1 | lorem {{ ipsum ~ dolor ~ sit }}
+   |                          ^^^
    |
    = reason: REASON
 ",
@@ -340,12 +381,12 @@ fn render_inline_expr_err_unknown_filter() {
 }
 
 #[test]
-fn render_inline_expr_err_unrenderable() {
+fn render_inline_expr_concat_err_unformattable_first() {
     let engine = Engine::new();
     let err = engine
-        .compile("lorem {{ ipsum }}")
+        .compile("lorem {{ ipsum ~ dolor }}")
         .unwrap()
-        .render(&engine, value! { ipsum: {} })
+        .render(&engine, value! { ipsum: {}, dolor: 1 })
         .to_string()
         .unwrap_err();
     assert_format_err(
@@ -354,7 +395,7 @@ fn render_inline_expr_err_unrenderable() {
         "
   --> <anonymous>:1:10
    |
- 1 | lorem {{ ipsum }}
+ 1 | lorem {{ ipsum ~ dolor }}
    |          ^^^^^
    |
    = reason: REASON
@@ -363,91 +404,126 @@ fn render_inline_expr_err_unrenderable() {
 }
 
 #[test]
-fn render_inline_expr_err_cannot_index_into_none() {
+fn render_inline_expr_coalesce_in_block() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {{ ipsum.dolor }}")
+    let result = engine
+        .compile(
+            "{% set x = ipsum ?? 1 %}{{ x }}{% if dolor ?? true %}!{% endif %}\
+             {% for i in sit ?? amet %}{{ i }}{% endfor %}",
+        )
         .unwrap()
-        .render(&engine, value! { ipsum: None })
+        .render(&engine, value! { ipsum: None, amet: [2, 3] })
         .to_string()
-        .unwrap_err();
-    assert_err(
-        &err,
-        "none does not support key-based access",
-        "
-  --> <anonymous>:1:15
-   |
- 1 | lorem {{ ipsum.dolor }}
-   |               ^^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .unwrap();
+    assert_eq!(result, "1!23");
 }
 
 #[test]
-fn render_inline_expr_err_cannot_index_into_string() {
+fn render_inline_expr_coalesce_err_last_not_found() {
     let engine = Engine::new();
     let err = engine
-        .compile("lorem {{ ipsum.dolor }}")
+        .compile("{{ ipsum ?? dolor.sit }}")
         .unwrap()
-        .render(&engine, value! { ipsum: "testing..." })
+        .render(&engine, value! { dolor: {} })
         .to_string()
         .unwrap_err();
     assert_err(
         &err,
-        "string does not support key-based access",
+        "not found in map",
         "
-  --> <anonymous>:1:15
+  --> <anonymous>:1:18
    |
- 1 | lorem {{ ipsum.dolor }}
-   |               ^^^^^^
+ 1 | {{ ipsum ?? dolor.sit }}
+   |                  ^^^^
    |
    = reason: REASON
 ",
     );
 }
 
+#[cfg(feature = "unicode")]
 #[test]
-fn render_inline_expr_err_cannot_index_list_with_string() {
+fn render_inline_expr_map_index_unicode_ident() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {{ ipsum.dolor }}")
+    let result = engine
+        .compile("lorem {{ ipsum.привіт }}")
         .unwrap()
-        .render(&engine, value! { ipsum: ["test", "ing..."] })
+        .render(&engine, value! { ipsum: { привіт: "sit"} })
         .to_string()
-        .unwrap_err();
-    assert_err(
-        &err,
-        "list does not support key-based access",
-        "
-  --> <anonymous>:1:15
-   |
- 1 | lorem {{ ipsum.dolor }}
-   |               ^^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .unwrap();
+    assert_eq!(result, "lorem sit");
 }
 
 #[test]
-fn render_inline_expr_err_cannot_index_map_with_integer() {
+fn render_inline_expr_list_index() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {{ ipsum.123 }}")
+    let result = engine
+        .compile("lorem {{ ipsum.1 }}")
         .unwrap()
-        .render(&engine, value! { ipsum: { test: "ing...", } })
+        .render(&engine, value! { ipsum: ["sit", "amet"] })
         .to_string()
-        .unwrap_err();
-    assert_err(
+        .unwrap();
+    assert_eq!(result, "lorem amet");
+}
+
+#[test]
+fn render_inline_expr_list_negative_index() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {{ ipsum.-1 }} {{ ipsum.-2.0 }} {{ ipsum.-0.0 }} {{ ipsum?.-4 }}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { ipsum: [["sit"], ["amet"], "consectetur"] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem consectetur amet sit ");
+}
+
+#[test]
+fn render_inline_expr_list_index_radix() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {{ ipsum.0x1 }} {{ ipsum.0o2 }} {{ ipsum.0b0 }} {{ ipsum.-0x1 }} {{ ipsum.0_1 }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: ["sit", "amet", "consectetur"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem amet consectetur sit consectetur amet");
+}
+
+#[test]
+fn render_inline_expr_custom_formatter() {
+    let mut engine = Engine::new();
+    engine.add_formatter("format_list", format_list);
+    let result = engine
+        .compile("lorem {{ ipsum | format_list }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: ["sit", "amet"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem sit;amet");
+}
+
+#[test]
+fn render_inline_expr_default_formatter_err() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&format_list);
+    let err = engine
+        .compile("lorem {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { sit: "amet"} })
+        .to_string()
+        .unwrap_err();
+    assert_format_err(
         &err,
-        "map does not support integer-based access",
+        "expected list",
         "
-  --> <anonymous>:1:15
+  --> <anonymous>:1:10
    |
- 1 | lorem {{ ipsum.123 }}
-   |               ^^^^
+ 1 | lorem {{ ipsum }}
+   |          ^^^^^
    |
    = reason: REASON
 ",
@@ -455,22 +531,156 @@ fn render_inline_expr_err_cannot_index_map_with_integer() {
 }
 
 #[test]
-fn render_inline_expr_err_index_out_of_bounds() {
-    let engine = Engine::new();
+fn render_inline_expr_default_formatter_owned() {
+    let prefix = String::from("> ");
+    let mut engine = Engine::new();
+    engine.set_default_formatter_owned(move |f, value| {
+        f.write_str(&prefix)?;
+        fmt::default(f, value)
+    });
+    let result = engine
+        .compile("lorem {{ ipsum }} {{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "sit", dolor: 1 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem > sit > 1");
+}
+
+#[test]
+fn render_inline_expr_type_formatter() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    engine.add_type_formatter(ValueKind::List, format_list);
+    engine.add_formatter("format_list", format_list);
+    let result = engine
+        .compile(
+            "{{ ipsum }} {{ dolor }} {% autoescape off %}{{ dolor }}{% endautoescape %} {{ sit | format_list }}",
+        )
+        .unwrap()
+        .render(
+            &engine,
+            value! { ipsum: ["<sit>", "amet"], dolor: "<b>", sit: ["a", "b"] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<sit>;amet &lt;b> <b> a;b");
+}
+
+#[test]
+fn render_inline_expr_type_formatter_replaced() {
+    let mut engine = Engine::new();
+    assert!(!engine.add_type_formatter(ValueKind::Integer, |f, _| {
+        f.write_str("one")?;
+        Ok(())
+    }));
+    assert!(engine.add_type_formatter(ValueKind::Integer, |f, _| {
+        f.write_str("two")?;
+        Ok(())
+    }));
+    let result = engine
+        .compile("{{ ipsum }} {{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: 1, dolor: 1.5 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "two 1.5");
+}
+
+#[test]
+fn render_inline_expr_chained_formatter() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    engine.add_type_formatter(ValueKind::List, format_list);
+    engine.add_chained_formatter(|f, v| match v {
+        Value::Integer(n) if *n < 0 => Err(fmt::Error::from("negative")),
+        Value::Integer(n) => {
+            write!(f, "#{n}")?;
+            Ok(fmt::Handled::Yes)
+        }
+        _ => Ok(fmt::Handled::No),
+    });
+    engine.add_chained_formatter(|f, v| match v {
+        Value::Integer(_) => {
+            f.write_str("unreachable")?;
+            Ok(fmt::Handled::Yes)
+        }
+        Value::Bool(b) => {
+            f.write_str(if *b { "yes" } else { "no" })?;
+            Ok(fmt::Handled::Yes)
+        }
+        _ => Ok(fmt::Handled::No),
+    });
+    let template = engine
+        .compile(
+            "{{ ipsum }} {{ dolor }} {{ sit }} {{ amet }} {% autoescape off %}{{ ipsum }} {{ dolor }}{% endautoescape %}",
+        )
+        .unwrap();
+    let result = template
+        .render(
+            &engine,
+            value! { ipsum: 1, dolor: true, sit: ["<a>", "b"], amet: "<c>" },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "#1 yes <a>;b &lt;c> 1 true");
+
+    let err = template
+        .render(
+            &engine,
+            value! { ipsum: -1, dolor: true, sit: [], amet: "" },
+        )
+        .to_string()
+        .unwrap_err();
+    assert_format_err(
+        &err,
+        "negative",
+        "
+  --> <anonymous>:1:4
+   |
+ 1 | {{ ipsum }} {{ dolor }} {{ sit }} {{ amet }} {% autoescape off %}{{ ipsum }} {{ dolor }}{% endautoescape %}
+   |    ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_chained_formatter_always_handled() {
+    let mut engine = Engine::new();
+    engine.add_chained_formatter(fmt::always_handled(fmt::escape_html));
+    engine.add_chained_formatter(|f, _| {
+        f.write_str("unreachable")?;
+        Ok(fmt::Handled::Yes)
+    });
+    let result = engine
+        .compile("{{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "<b>" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "&lt;b&gt;");
+}
+
+#[test]
+fn render_inline_expr_custom_formatter_err() {
+    let mut engine = Engine::new();
+    engine.add_formatter("format_list", format_list);
     let err = engine
-        .compile("lorem {{ ipsum.123 }}")
+        .compile("lorem {{ ipsum | format_list }}")
         .unwrap()
-        .render(&engine, value! { ipsum: ["test", "ing..."] })
+        .render(&engine, value! { ipsum: { sit: "amet"} })
         .to_string()
         .unwrap_err();
-    assert_err(
+    assert_format_err(
         &err,
-        "index out of bounds, the length is 2",
+        "expected list",
         "
-  --> <anonymous>:1:15
+  --> <anonymous>:1:18
    |
- 1 | lorem {{ ipsum.123 }}
-   |               ^^^^
+ 1 | lorem {{ ipsum | format_list }}
+   |                  ^^^^^^^^^^^
    |
    = reason: REASON
 ",
@@ -478,309 +688,3172 @@ fn render_inline_expr_err_index_out_of_bounds() {
 }
 
 #[test]
-fn render_inline_expr_err_not_found_in_map() {
+fn render_inline_expr_custom_formatter_write_escaped() {
+    let mut engine = Engine::new();
+    engine.add_formatter("escape_list", |f, v| match v {
+        Value::List(list) => {
+            for (i, item) in list.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                match item {
+                    Value::String(s) => f.write_escaped(s, |c| match c {
+                        '<' => Some("&lt;"),
+                        '&' => Some("&amp;"),
+                        _ => None,
+                    })?,
+                    v => fmt::default(f, v)?,
+                }
+            }
+            Ok(())
+        }
+        v => fmt::default(f, v),
+    });
+    let result = engine
+        .compile("{{ ipsum | escape_list }}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { ipsum: ["<b>", "a & b", 1, "Crème <", ""] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "&lt;b>, a &amp; b, 1, Crème &lt;, ");
+}
+
+fn format_list(f: &mut fmt::Formatter<'_>, v: &Value) -> fmt::Result {
+    match v {
+        Value::List(list) => {
+            for (i, item) in list.iter().enumerate() {
+                if i != 0 {
+                    f.write_char(';')?;
+                }
+                fmt::default(f, item)?;
+            }
+            Ok(())
+        }
+        _ => Err("expected list".to_string())?,
+    }
+}
+
+#[test]
+fn render_inline_expr_err_unknown_filter_or_formatter() {
     let engine = Engine::new();
     let err = engine
-        .compile("lorem {{ ipsum.dolor }}")
+        .compile("lorem {{ ipsum | unknown }}")
         .unwrap()
-        .render(&engine, value! { ipsum : { } })
+        .render(&engine, value! { ipsum: true })
         .to_string()
         .unwrap_err();
     assert_err(
         &err,
-        "not found in map",
+        "unknown filter or formatter",
         "
-  --> <anonymous>:1:15
+  --> <anonymous>:1:18
    |
- 1 | lorem {{ ipsum.dolor }}
-   |               ^^^^^^
+ 1 | lorem {{ ipsum | unknown }}
+   |                  ^^^^^^^
    |
    = reason: REASON
 ",
     );
 }
 
-fn falsy() -> Vec<Value> {
-    vec![
-        Value::None,
-        Value::Bool(false),
-        Value::Integer(0),
-        Value::Float(0.0),
-        Value::String(String::new()),
-        Value::List(vec![]),
-        Value::Map(BTreeMap::new()),
-    ]
-}
-
-fn truthy() -> Vec<Value> {
-    vec![
-        Value::Bool(true),
-        Value::Integer(1337),
-        Value::Float(13.37),
-        Value::String("testing".into()),
-        Value::from([1, 2, 3]),
-        Value::from([("a", 1i64), ("b", 2i64)]),
-    ]
+#[test]
+fn render_inline_expr_err_unknown_filter_found_formatter() {
+    let mut engine = Engine::new();
+    engine.add_formatter("another", |_, _| Ok(()));
+    let err = engine
+        .compile("lorem {{ ipsum | another | unknown }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: true })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected filter, found formatter",
+        "
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {{ ipsum | another | unknown }}
+   |                  ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
 }
 
 #[test]
-fn render_if_statement_cond_true() {
-    for value in truthy() {
-        let engine = Engine::new();
-        let result = engine
-            .compile("lorem {% if ipsum %}{{ sit }}{% else %}{{ amet }}{% endif %}")
-            .unwrap()
-            .render(&engine, value! { ipsum: value.clone(), sit: "consectetur" })
-            .to_string()
-            .unwrap();
-        assert_eq!(result, "lorem consectetur");
-    }
+fn render_inline_expr_err_unknown_filter() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum | another | unknown }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: true })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unknown filter",
+        "
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {{ ipsum | another | unknown }}
+   |                  ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_unrenderable() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: {} })
+        .to_string()
+        .unwrap_err();
+    assert_format_err(
+        &err,
+        "expression evaluated to unformattable type map",
+        "
+  --> <anonymous>:1:10
+   |
+ 1 | lorem {{ ipsum }}
+   |          ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_cannot_index_into_none() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: None })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "none does not support key-based access",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.dolor }}
+   |               ^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_cannot_index_into_string() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "testing..." })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "string does not support key-based access",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.dolor }}
+   |               ^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_cannot_index_list_with_string() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: ["test", "ing..."] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "list does not support key-based access",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.dolor }}
+   |               ^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_cannot_index_map_with_integer() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.123 }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { test: "ing...", } })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "map does not support integer-based access",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.123 }}
+   |               ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_cannot_index_map_with_negative_integer() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.-1 }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { test: "ing...", } })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "map does not support integer-based access",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.-1 }}
+   |               ^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_negative_index_out_of_bounds() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.-3 }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: ["test", "ing..."] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "index out of bounds, the length is 2",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.-3 }}
+   |               ^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_index_out_of_bounds() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.123 }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: ["test", "ing..."] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "index out of bounds, the length is 2",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.123 }}
+   |               ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_inline_expr_err_not_found_in_map() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {{ ipsum.dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum : { } })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in map",
+        "
+  --> <anonymous>:1:15
+   |
+ 1 | lorem {{ ipsum.dolor }}
+   |               ^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+fn falsy() -> Vec<Value> {
+    vec![
+        Value::None,
+        Value::Bool(false),
+        Value::Integer(0),
+        Value::Float(0.0),
+        Value::String(String::new()),
+        Value::List(vec![]),
+        Value::Map(BTreeMap::new()),
+    ]
+}
+
+fn truthy() -> Vec<Value> {
+    vec![
+        Value::Bool(true),
+        Value::Integer(1337),
+        Value::Float(13.37),
+        Value::String("testing".into()),
+        Value::from([1, 2, 3]),
+        Value::from([("a", 1i64), ("b", 2i64)]),
+    ]
+}
+
+#[test]
+fn render_if_statement_cond_true() {
+    for value in truthy() {
+        let engine = Engine::new();
+        let result = engine
+            .compile("lorem {% if ipsum %}{{ sit }}{% else %}{{ amet }}{% endif %}")
+            .unwrap()
+            .render(&engine, value! { ipsum: value.clone(), sit: "consectetur" })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "lorem consectetur");
+    }
+}
+
+#[test]
+fn render_if_statement_cond_false() {
+    for value in falsy() {
+        let engine = Engine::new();
+        let result = engine
+            .compile("lorem {% if ipsum.dolor %}{{ sit }}{% else %}{{ amet }}{% endif %}")
+            .unwrap()
+            .render(
+                &engine,
+                value! { ipsum: { dolor: value.clone() }, amet: "consectetur" },
+            )
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "lorem consectetur");
+    }
+}
+
+#[test]
+fn render_if_statement_cond_not() {
+    for value in falsy() {
+        let engine = Engine::new();
+        let result = engine
+            .compile("lorem {% if not ipsum.dolor %}{{ sit }}{% else %}{{ amet }}{% endif %}")
+            .unwrap()
+            .render(
+                &engine,
+                value! { ipsum: {dolor: value.clone()}, sit: "consectetur" },
+            )
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "lorem consectetur");
+    }
+}
+
+#[test]
+fn render_if_statement_else_if_cond_false() {
+    for value in falsy() {
+        let engine = Engine::new();
+        let result = engine
+            .compile("lorem {% if ipsum %} dolor {% else if sit %} amet {% endif %}, consectetur")
+            .unwrap()
+            .render(&engine, value! { ipsum: value.clone(), sit: value.clone() })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "lorem , consectetur");
+    }
+}
+
+#[test]
+fn render_if_statement_else_if_cond_true() {
+    for (t, f) in zip(truthy(), falsy()) {
+        let engine = Engine::new();
+        let result = engine
+            .compile("lorem {% if ipsum %} dolor {% else if sit %} amet {% endif %}, consectetur")
+            .unwrap()
+            .render(&engine, value! { ipsum: f, sit: t })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "lorem  amet , consectetur");
+    }
+}
+
+#[test]
+fn render_if_statement_else_if_cond_not() {
+    for falsy in falsy() {
+        let engine = Engine::new();
+        let result = engine
+            .compile(
+                "lorem {% if ipsum %} dolor {% else if not sit %} amet {% endif %}, consectetur",
+            )
+            .unwrap()
+            .render(&engine, value! { ipsum: falsy.clone(), sit: falsy })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, "lorem  amet , consectetur");
+    }
+}
+
+#[test]
+fn render_if_statement_elif() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% if a %}a{% elif b %}b{% else if c %}c{% elif d %}d{% else %}e{% endif %}")
+        .unwrap();
+    for (var, exp) in [("a", "a"), ("b", "b"), ("c", "c"), ("d", "d"), ("x", "e")] {
+        let mut map = BTreeMap::from([("a", false), ("b", false), ("c", false), ("d", false)]);
+        map.insert(var, true);
+        let result = template.render(&engine, &map).to_string().unwrap();
+        assert_eq!(result, exp);
+    }
+}
+
+#[test]
+fn render_if_statement_multi() {
+    let engine = Engine::new();
+    let template = engine
+        .compile(
+            r#"
+{%- if a -%} a
+{%- else if b -%} b
+{%- else if c -%} c
+{%- else if d -%} d
+{%- else if e -%} e
+{%- else -%} f
+{%- endif -%}
+"#,
+        )
+        .unwrap();
+
+    let mut map = BTreeMap::from([
+        ("a", false),
+        ("b", false),
+        ("c", false),
+        ("d", false),
+        ("e", false),
+    ]);
+    let result = template.render(&engine, &map).to_string().unwrap();
+    assert_eq!(result, "f");
+    for var in ["a", "b", "c", "d", "e"] {
+        map.insert(var, true);
+        let result = template.render(&engine, &map).to_string().unwrap();
+        assert_eq!(result, var);
+        map.insert(var, false);
+    }
+}
+
+#[test]
+fn render_if_statement_test_empty() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% if ipsum is empty %}empty{% else %}not empty{% endif %}")
+        .unwrap();
+    let tests = [
+        (Value::None, "empty"),
+        (Value::from(""), "empty"),
+        (Value::from("lorem"), "not empty"),
+        (Value::from(Vec::<Value>::new()), "empty"),
+        (Value::from([1]), "not empty"),
+        (Value::from(BTreeMap::<String, Value>::new()), "empty"),
+        (Value::from([("lorem", 1)]), "not empty"),
+    ];
+    for (ipsum, exp) in tests {
+        let result = template
+            .render(&engine, value! { ipsum: ipsum.clone() })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "ipsum: {ipsum:?}");
+    }
+}
+
+#[test]
+fn render_if_statement_test_not_empty() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% if ipsum is not empty %}a{% endif %}{% if not ipsum is not empty %}b{% endif %}{% if not ipsum is empty %}c{% endif %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: [1, 2] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "ac");
+}
+
+#[test]
+fn render_if_statement_test_defined() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% if ipsum is defined %}a{% endif %}{% if dolor is defined %}b{% endif %}{% if ipsum.sit is not defined %}c{% endif %}{% if ipsum.amet is defined %}d{% endif %}{% if ipsum.amet.consectetur is defined %}e{% endif %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { amet: None } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "acd");
+}
+
+#[test]
+fn render_if_statement_test_defined_in_loop() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for item in items %}{% if item.name is defined and item.name is string %}{{ item.name }}{% else %}-{% endif %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { items: [{ name: "lorem" }, {}, { name: 1 }] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem--");
+}
+
+#[test]
+fn render_if_statement_test_types() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% if ipsum is none %}none{% else if ipsum is string %}string{% else if ipsum is number %}number{% else if ipsum is list %}list{% else if ipsum is map %}map{% else if ipsum is bool %}bool{% endif %}")
+        .unwrap();
+    let tests = [
+        (Value::None, "none"),
+        (Value::from("lorem"), "string"),
+        (Value::from(1), "number"),
+        (Value::from(1.5), "number"),
+        (Value::from([1]), "list"),
+        (Value::from([("lorem", 1)]), "map"),
+        (Value::from(false), "bool"),
+    ];
+    for (ipsum, exp) in tests {
+        let result = template
+            .render(&engine, value! { ipsum: ipsum.clone() })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "ipsum: {ipsum:?}");
+    }
+}
+
+#[test]
+fn render_if_statement_test_none_err_not_found() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% if ipsum is none %}{% endif %}")
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        "
+  --> <anonymous>:1:13
+   |
+ 1 | lorem {% if ipsum is none %}{% endif %}
+   |             ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_if_statement_test_empty_err_type() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% if ipsum is empty %}{% endif %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: 0 })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected string, list, or map, but expression evaluated to integer",
+        "
+  --> <anonymous>:1:13
+   |
+ 1 | lorem {% if ipsum is empty %}{% endif %}
+   |             ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_if_statement_compare() {
+    let engine = Engine::new();
+    let tests = [
+        ("1 == 1", true),
+        ("1 == 2", false),
+        ("1 != 2", true),
+        ("1 < 2", true),
+        ("2 <= 2", true),
+        ("3 > 2", true),
+        ("2 >= 3", false),
+        ("1 == 1.0", true),
+        ("1.5 > 1", true),
+        ("-1 < 0.5", true),
+        (r#""a" < "b""#, true),
+        (r#""a" == "a""#, true),
+        ("true == true", true),
+        ("true != false", true),
+        ("not 1 == 1", false),
+    ];
+    for (cond, exp) in tests {
+        let result = engine
+            .compile(format!(
+                "{{% if {cond} %}}true{{% else %}}false{{% endif %}}"
+            ))
+            .unwrap()
+            .render(&engine, Value::None)
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp.to_string(), "cond: {cond}");
+    }
+}
+
+#[test]
+fn render_if_statement_compare_vars() {
+    let engine = Engine::new();
+    let template = engine
+        .compile(r#"{% for user in users %}{% if user.age >= 18 %}{{ user.name }} {% else if user.status == "active" %}? {% endif %}{% endfor %}"#)
+        .unwrap();
+    let result = template
+        .render(
+            &engine,
+            value! {
+                users: [
+                    { name: "John", age: 42, status: "active" },
+                    { name: "Jane", age: 16, status: "active" },
+                    { name: "Bob", age: 17, status: "inactive" },
+                ]
+            },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "John ? ");
+}
+
+#[test]
+fn render_if_statement_compare_err_incompatible() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% if ipsum > "dolor" %}{% endif %}"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: 1 })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot compare integer with string using `>`",
+        r#"
+  --> <anonymous>:1:13
+   |
+ 1 | lorem {% if ipsum > "dolor" %}{% endif %}
+   |             ^^^^^^^^^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_if_statement_compare_err_unordered() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% if ipsum < dolor %}{% endif %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: [1], dolor: [2] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot compare list with list using `<`",
+        "
+  --> <anonymous>:1:13
+   |
+ 1 | lorem {% if ipsum < dolor %}{% endif %}
+   |             ^^^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_if_statement_and_or() {
+    let engine = Engine::new();
+    let tests = [
+        ("true and true", true),
+        ("true and false", false),
+        ("false or true", true),
+        ("false or false", false),
+        ("false and false or true", true),
+        ("true or false and false", true),
+        ("false or true and false", false),
+        ("not false and true", true),
+        ("not true and true", false),
+        ("not true or not false", true),
+        ("1 < 2 and 2 < 3 and not 3 < 4", false),
+        (r#""a" == "b" or 1 == 1"#, true),
+    ];
+    for (cond, exp) in tests {
+        let result = engine
+            .compile(format!(
+                "{{% if {cond} %}}true{{% else %}}false{{% endif %}}"
+            ))
+            .unwrap()
+            .render(&engine, Value::None)
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp.to_string(), "cond: {cond}");
+    }
+}
+
+#[test]
+fn render_if_statement_and_or_else_if() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% if ipsum and dolor %}a{% else if ipsum or dolor %}b{% else %}c{% endif %}")
+        .unwrap();
+    let tests = [
+        (true, true, "a"),
+        (true, false, "b"),
+        (false, true, "b"),
+        (false, false, "c"),
+    ];
+    for (ipsum, dolor, exp) in tests {
+        let result = template
+            .render(&engine, value! { ipsum: ipsum, dolor: dolor })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp);
+    }
+}
+
+#[test]
+fn render_if_statement_and_or_short_circuit() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(
+            "{% if ipsum or dolor.sit %}a{% endif %}{% if not ipsum and dolor.sit %}b{% endif %}",
+        )
+        .unwrap()
+        .render(&engine, value! { ipsum: true })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "a");
+}
+
+#[test]
+fn render_if_statement_and_or_long_chain() {
+    let engine = Engine::new();
+    // This is kept fairly short because the lexer searches from every token to
+    // the end of the tag, so compiling a single tag is quadratic in the number
+    // of tokens. Thousands of operands take seconds in debug builds.
+    let cond = vec!["ipsum"; 500].join(" and ");
+    let result = engine
+        .compile(format!("{{% if {cond} or dolor %}}a{{% endif %}}"))
+        .unwrap()
+        .render(&engine, value! { ipsum: true, dolor: false })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "a");
+}
+
+#[test]
+fn render_for_statement_list() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_filtered_list() {
+    let mut engine = Engine::new();
+    engine.add_filter("pop", |mut list: Vec<Value>| {
+        list.pop();
+        list
+    });
+    let result = engine
+        .compile("lorem {% for ipsum in dolor | pop %}{{ ipsum }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem tes");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_filtered_list_err_expected_arg_type() {
+    let mut engine = Engine::new();
+    engine.add_filter("take", |list: Vec<Value>, n: usize| {
+        list.into_iter().take(n).collect::<Vec<_>>()
+    });
+    let err = engine
+        .compile(r#"lorem {% for ipsum in dolor | take: 2 | take: "a" %}{% endfor %}"#)
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "filter expected usize argument, found string",
+        r#"
+  --> <anonymous>:1:47
+   |
+ 1 | lorem {% for ipsum in dolor | take: 2 | take: "a" %}{% endfor %}
+   |                                               ^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_filtered_list_err_unknown_filter() {
+    let mut engine = Engine::new();
+    engine.add_filter("pop", |mut list: Vec<Value>| {
+        list.pop();
+        list
+    });
+    let err = engine
+        .compile("lorem {% for ipsum in dolor | pop | unknown %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unknown filter",
+        "
+  --> <anonymous>:1:37
+   |
+ 1 | lorem {% for ipsum in dolor | pop | unknown %}{% endfor %}
+   |                                     ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_filtered_list_err_expected_value_type() {
+    let mut engine = Engine::new();
+    engine.add_filter("len", |list: Vec<Value>| list.len() as i64);
+    engine.add_filter("pop", |mut list: Vec<Value>| {
+        list.pop();
+        list
+    });
+    let err = engine
+        .compile("lorem {% for ipsum in dolor | len | pop %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "filter expected list value, found integer",
+        "
+  --> <anonymous>:1:37
+   |
+ 1 | lorem {% for ipsum in dolor | len | pop %}{% endfor %}
+   |                                     ^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_filtered_list_err_not_iterable() {
+    let mut engine = Engine::new();
+    engine.add_filter("len", |list: Vec<Value>| list.len() as i64);
+    let err = engine
+        .compile("lorem {% for ipsum in dolor | len %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected iterable, but expression evaluated to integer",
+        "
+  --> <anonymous>:1:23
+   |
+ 1 | lorem {% for ipsum in dolor | len %}{% endfor %}
+   |                       ^^^^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_else_statement() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% else %}sit{% endfor %} amet")
+        .unwrap();
+    let tests = [
+        (value! { dolor: ["t", "e", "s", "t"] }, "lorem test amet"),
+        (value! { dolor: [] }, "lorem sit amet"),
+    ];
+    for (ctx, exp) in tests {
+        let result = template.render(&engine, &ctx).to_string().unwrap();
+        assert_eq!(result, exp);
+    }
+}
+
+#[test]
+fn render_for_else_statement_empty_map() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum, dolor in sit %}{{ ipsum }}{% else %}amet{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { sit: {} })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem amet");
+}
+
+#[test]
+fn render_for_else_statement_empty_range() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% for i in 0..n %}{{ i }}{% else %}none{% endfor %}|{% for i in 1..=n reversed %}{{ i }}{% else %}none{% endfor %}")
+        .unwrap();
+    let result = template
+        .render(&engine, value! { n: 0 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "none|none");
+    let result = template
+        .render(&engine, value! { n: 2 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "01|21");
+}
+
+#[test]
+fn render_for_else_statement_break() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for ipsum in dolor %}{{ ipsum }}{% break %}{% else %}sit{% endfor %} amet")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "t amet");
+}
+
+#[test]
+fn render_for_else_statement_nested() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for row in rows %}[{% for x in row %}{{ x }}{% else %}{{ loop.index }}{% endfor %}]{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { rows: [[1, 2], [], [3]] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "[12][1][3]");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_else_statement_filtered_list() {
+    let mut engine = Engine::new();
+    engine.add_filter("pop", |mut list: Vec<Value>| {
+        list.pop();
+        list
+    });
+    let result = engine
+        .compile("lorem {% for ipsum in dolor | pop %}{{ ipsum }}{% else %}sit{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem sit");
+}
+
+#[test]
+fn render_for_statement_map() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum, dolor in sit %}{{ ipsum }},{{ dolor.0 }} {% endfor %}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { sit: { a: ["t"], b: ["e"], c: ["s"], d: ["t"] } },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem a,t b,e c,s d,t ");
+}
+
+#[test]
+fn render_for_statement_reversed() {
+    let engine = Engine::new();
+    let source = "{% for x in xs reversed %}{{ loop.index }}:{{ x }}/{{ loop.length }}\
+                  {% if loop.first %}^{% endif %}{% if loop.last %}${% endif %} {% endfor %}";
+    let ctx = value! { xs: ["a", "b", "c"] };
+    let template = engine.compile(source).unwrap();
+    let expected = "0:c/3^ 1:b/3 2:a/3$ ";
+
+    let result = template.render_from(&engine, &ctx).to_string().unwrap();
+    assert_eq!(result, expected);
+
+    let result = template.render(&engine, &ctx).to_string().unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn render_for_statement_reversed_many_vars() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for a, b, c in rows reversed %}{{ a }}{{ b }}{{ c }};{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { rows: [[1, 2, 3], [4, 5, 6]] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "456;123;");
+}
+
+#[test]
+fn render_for_statement_reversed_variable_named_reversed() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for x in reversed %}{{ x }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { reversed: [1, 2] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "12");
+}
+
+#[test]
+fn render_for_statement_reversed_err_map() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for k, v in ipsum reversed %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { a: 1 } })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot iterate over a map in reverse, maps are always iterated in key order",
+        "
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% for k, v in ipsum reversed %}{% endfor %}
+   |                      ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_loop_fields() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %}{{ loop.index }},{{ loop.first }},{{ loop.last }},{{ ipsum }}  {% endfor %}")
+        .unwrap()
+        .render(&engine, value!{ dolor: ["t", "e", "s", "t"] }).to_string().unwrap();
+    assert_eq!(
+        result,
+        "lorem 0,true,false,t  1,false,false,e  2,false,false,s  3,false,true,t  "
+    );
+}
+
+#[test]
+fn render_for_statement_loop_index_and_length() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %}{{ loop.index0 }},{{ loop.index1 }},{{ loop.length }},{{ ipsum }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem 0,1,3,t 1,2,3,e 2,3,3,s ");
+}
+
+#[test]
+fn render_for_statement_loop_length_map() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(
+            "lorem {% for ipsum, dolor in sit %}{{ loop.index1 }}/{{ loop.length }} {% endfor %}",
+        )
+        .unwrap()
+        .render(&engine, value! { sit: { a: "t", b: "e" } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem 1/2 2/2 ");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_loop_length_owned() {
+    let mut engine = Engine::new();
+    engine.add_filter("to_owned", Value::to_owned);
+    let result = engine
+        .compile("lorem {% for ipsum in dolor | to_owned %}{{ loop.index1 }}/{{ loop.length }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem 1/4 2/4 3/4 4/4 ");
+}
+
+#[test]
+fn render_for_statement_loop_optional_access() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %}{{ loop?.notindex }}{{ ipsum }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test");
+}
+
+#[test]
+fn render_for_statement_loop_map() {
+    let mut engine = Engine::new();
+    engine.add_formatter("debug", |f, v| {
+        writeln!(f, "{v:?}")?;
+        Ok(())
+    });
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %} {{ loop | debug }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        r#"lorem  Map({"first": Bool(true), "index": Integer(0), "index0": Integer(0), "index1": Integer(1), "last": Bool(false), "length": Integer(4)})
+  Map({"first": Bool(false), "index": Integer(1), "index0": Integer(1), "index1": Integer(2), "last": Bool(false), "length": Integer(4)})
+  Map({"first": Bool(false), "index": Integer(2), "index0": Integer(2), "index1": Integer(3), "last": Bool(false), "length": Integer(4)})
+  Map({"first": Bool(false), "index": Integer(3), "index0": Integer(3), "index1": Integer(4), "last": Bool(true), "length": Integer(4)})
+ "#
+    );
+}
+
+#[test]
+fn render_err_contains_template_name() {
+    let mut engine = Engine::new();
+    engine.add_template("test", "{{ ipsum }}").unwrap();
+    let err = engine
+        .template("test")
+        .render(value! {})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        "
+  --> test:1:4
+   |
+ 1 | {{ ipsum }}
+   |    ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_not_found_in_map() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for ipsum in dolor %} {{ loop.xxx }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in map",
+        "
+  --> <anonymous>:1:39
+   |
+ 1 | lorem {% for ipsum in dolor %} {{ loop.xxx }} {% endfor %}
+   |                                       ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_cannot_index_into_map() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for ipsum in dolor %} {{ loop.123 }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot index into map with integer",
+        "
+  --> <anonymous>:1:39
+   |
+ 1 | lorem {% for ipsum in dolor %} {{ loop.123 }} {% endfor %}
+   |                                       ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_cannot_index_into_string() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for ipsum, dolor in sit %} {{ ipsum.xxx }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { sit: {t: "e", s: "t"} })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot index into string",
+        "
+  --> <anonymous>:1:45
+   |
+ 1 | lorem {% for ipsum, dolor in sit %} {{ ipsum.xxx }} {% endfor %}
+   |                                             ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_cannot_index_into_loop_field() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for ipsum in dolor %} {{ loop.first.xxx }} {% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot index into bool",
+        "
+  --> <anonymous>:1:45
+   |
+ 1 | lorem {% for ipsum in dolor %} {{ loop.first.xxx }} {% endfor %}
+   |                                             ^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_filtered_map() {
+    let mut engine = Engine::new();
+    engine.add_filter("rm", |mut map: BTreeMap<String, Value>, key: &str| {
+        map.remove(key);
+        map
+    });
+    let result = engine
+        .compile(r#"lorem {% for ipsum, dolor in sit | rm: "d" %}{{ ipsum }},{{ dolor.0 }} {% endfor %}"#)
+        .unwrap()
+        .render(&engine, value!{ sit: { a: ["t"], b: ["e"], c: ["s"], d: ["t"] } }).to_string().unwrap();
+    assert_eq!(result, "lorem a,t b,e c,s ");
+}
+
+#[test]
+fn render_for_statement_nested_borrowed_list() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "lorem {{ ipsum }} ").unwrap();
+    let result = engine
+        .compile(r#"lorem {% for ipsum in dolor %}{% include "nested" %}{% endfor %}"#)
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem lorem t lorem e lorem s lorem t ");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_nested_owned_list() {
+    let mut engine = Engine::new();
+    engine.add_filter("to_owned", Value::to_owned);
+    engine.add_template("nested", "lorem {{ ipsum }} ").unwrap();
+    let result = engine
+        .compile(r#"lorem {% for ipsum in dolor | to_owned %}{% include "nested" %}{% endfor %}"#)
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem lorem t lorem e lorem s lorem t ");
+}
+
+#[test]
+fn render_for_statement_nested_borrowed_map() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("nested", "lorem {{ ipsum }} {{ dolor }} ")
+        .unwrap();
+    let result = engine
+        .compile(r#"lorem {% for ipsum, dolor in sit %}{% include "nested" %}{% endfor %}"#)
+        .unwrap()
+        .render(&engine, value! { sit: { a: "t", b: "e", c: "s", d: "t" } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem lorem a t lorem b e lorem c s lorem d t ");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_for_statement_nested_owned_map() {
+    let mut engine = Engine::new();
+    engine.add_filter("to_owned", Value::to_owned);
+    engine
+        .add_template("nested", "lorem {{ ipsum }} {{ dolor }} ")
+        .unwrap();
+    let result = engine
+        .compile(
+            r#"lorem {% for ipsum, dolor in sit | to_owned %}{% include "nested" %}{% endfor %}"#,
+        )
+        .unwrap()
+        .render(&engine, value! { sit: { a: "t", b: "e", c: "s", d: "t" } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem lorem a t lorem b e lorem c s lorem d t ");
+}
+
+#[test]
+fn render_for_statement_nested_loop_fields() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "nested",
+            "{{ loop.index }},{{ loop.first }},{{ loop.last }},{{ ipsum }}",
+        )
+        .unwrap();
+    let result = engine
+        .compile(r#"lorem {% for ipsum in dolor %}{% include "nested" %} {% endfor %}"#)
+        .unwrap()
+        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        "lorem 0,true,false,t 1,false,false,e 2,false,false,s 3,false,true,t "
+    );
+}
+
+#[test]
+fn render_for_statement_break() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %}{% if ipsum.stop %}{% break %}{% endif %}{{ ipsum.name }} {% endfor %}sit")
+        .unwrap()
+        .render(
+            &engine,
+            value! { dolor: [{ name: "a", stop: false }, { name: "b", stop: false }, { name: "c", stop: true }, { name: "d", stop: false }] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem a b sit");
+}
+
+#[test]
+fn render_for_statement_continue() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% for ipsum in dolor %}{% if ipsum.skip %}{% continue %}{% endif %}{{ ipsum.name }} {% endfor %}sit")
+        .unwrap()
+        .render(
+            &engine,
+            value! { dolor: [{ name: "a", skip: true }, { name: "b", skip: false }, { name: "c", skip: true }, { name: "d", skip: false }] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem b d sit");
+}
+
+#[test]
+fn render_for_statement_break_nested_loop() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for ipsum in dolor %}{% for sit in ipsum %}{% if sit.stop %}{% break %}{% endif %}{{ sit.name }}{% endfor %};{% endfor %}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { dolor: [[{ name: "a", stop: false }, { name: "b", stop: true }], [{ name: "c", stop: false }, { name: "d", stop: false }]] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "a;cd;");
+}
+
+#[test]
+fn render_for_statement_break_inside_with() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for ipsum in dolor %}{% with ipsum as sit %}{% if sit %}{% break %}{% endif %}{% endwith %}{% endfor %}{{ sit }}")
+        .unwrap()
+        .render(&engine, value! { dolor: [false, true, false], sit: "amet" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "amet");
+}
+
+#[test]
+fn render_for_statement_continue_inside_with() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for ipsum in dolor %}{% with ipsum as sit %}{% if sit.skip %}{% continue %}{% endif %}{{ sit.name }}{% endwith %}{% endfor %} {{ sit }}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { dolor: [{ name: "a", skip: true }, { name: "b", skip: false }], sit: "amet" },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "b amet");
+}
+
+#[test]
+fn render_for_statement_break_inside_autoescape() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    let result = engine
+        .compile("{% for ipsum in dolor %}{% autoescape off %}{% break %}{% endautoescape %}{% endfor %}{{ sit }}")
+        .unwrap()
+        .render(&engine, value! { dolor: [true], sit: "<b>" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "&lt;b>");
+}
+
+#[test]
+fn render_for_statement_err_not_iterable() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: true })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected iterable, but expression evaluated to bool",
+        "
+  --> <anonymous>:1:23
+   |
+ 1 | lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}
+   |                       ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_list_with_two_vars() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for _, ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["sit", "amet"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack list item into two variables",
+        "
+  --> <anonymous>:1:14
+   |
+ 1 | lorem {% for _, ipsum in dolor %}{{ ipsum }}{% endfor %}
+   |              ^^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_list_with_many_vars() {
+    let result = Engine::new()
+        .compile("{% for id, label, count in rows %}{{ id }}:{{ label }}={{ count }};{% endfor %}")
+        .unwrap()
+        .render(&Engine::new(), value! { rows: [[1, "a", 2], [3, "b", 4]] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "1:a=2;3:b=4;");
+}
+
+#[test]
+fn render_for_statement_list_with_many_vars_nested_and_loop() {
+    let result = Engine::new()
+        .compile(
+            "{% for x, y, z in rows %}{{ loop.index }}{{ x.name }}{{ y }}{{ z.k }}{% endfor %}",
+        )
+        .unwrap()
+        .render(
+            &Engine::new(),
+            value! { rows: [[{ name: "a" }, 1, { k: 2 }]] },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "0a12");
+}
+
+#[test]
+fn render_for_statement_err_list_with_many_vars_wrong_length() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for a, b, c in dolor %}{{ a }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: [[1, 2, 3], [1, 2]] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack list item of length 2 into 3 variables",
+        "
+  --> <anonymous>:1:14
+   |
+ 1 | lorem {% for a, b, c in dolor %}{{ a }}{% endfor %}
+   |              ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_list_with_many_vars_not_a_list() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for a, b, c in dolor %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: ["sit"] })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack string into 3 variables",
+        "
+  --> <anonymous>:1:14
+   |
+ 1 | lorem {% for a, b, c in dolor %}{% endfor %}
+   |              ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_map_with_many_vars() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for a, b, c in dolor %}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: { sit: "amet" } })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack map item into 3 variables",
+        "
+  --> <anonymous>:1:14
+   |
+ 1 | lorem {% for a, b, c in dolor %}{% endfor %}
+   |              ^^^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_map_with_one_var() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { dolor: { sit: "amet" }})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "cannot unpack map item into one variable",
+        "
+  --> <anonymous>:1:14
+   |
+ 1 | lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}
+   |              ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_for_statement_err_loop_var_scope() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% for _, ipsum in dolor %}{% endfor %}{{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { dolor: { ipsum: false }})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        "
+  --> <anonymous>:1:49
+   |
+ 1 | lorem {% for _, ipsum in dolor %}{% endfor %}{{ ipsum }}
+   |                                                 ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_with_statement() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% with ipsum as dolor %}{{ dolor }}{% endwith %} sit")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test", dolor: false })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit")
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_with_statement_nested_references_outer() {
+    let mut engine = Engine::new();
+    engine.add_filter("upper", str::to_uppercase);
+    let result = engine
+        .compile("{% with ipsum as x %}{% with x | upper as y %}{{ x }} {{ y }}{% endwith %}{% endwith %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "test TEST")
+}
+
+#[test]
+fn render_with_statement_nested_chained() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% with ipsum as x %}{% with x.dolor as y %}{% with y.sit as z %}{{ z }}{% endwith %}{% endwith %}{% endwith %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: { sit: "amet" } } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "amet")
+}
+
+#[test]
+fn render_with_statement_nested_shadowing() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% with ipsum as x %}{{ x.dolor }} {% with x.dolor as x %}{{ x }}{% endwith %} {{ x.dolor }}{% endwith %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "sit" } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "sit sit sit")
+}
+
+#[test]
+fn render_with_statement_multiple() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% with ipsum.dolor as x, ipsum.sit as y %}{{ x }} {{ y }}{% endwith %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "amet", sit: 42 } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "amet 42")
+}
+
+#[test]
+fn render_with_statement_multiple_references_earlier() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% with ipsum.dolor as x, x.sit as y, y as x %}{{ x }}{% endwith %} {{ x }}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { ipsum: { dolor: { sit: "amet" } }, x: "outer" },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "amet outer")
+}
+
+#[test]
+fn render_with_statement_multiple_err_var_scope() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("{% with ipsum as x, ipsum as y %}{% endwith %}{{ y }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        "
+  --> <anonymous>:1:50
+   |
+ 1 | {% with ipsum as x, ipsum as y %}{% endwith %}{{ y }}
+   |                                                  ^--
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_with_statement_err_var_scope() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("lorem {% with ipsum as dolor %}{{ dolor }}{% endwith %}{{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        "
+  --> <anonymous>:1:59
+   |
+ 1 | lorem {% with ipsum as dolor %}{{ dolor }}{% endwith %}{{ dolor }}
+   |                                                           ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_set_statement() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% set dolor = ipsum.sit %}lorem {{ dolor }} {{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: { sit: "test" } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test test");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_set_statement_filter() {
+    let mut engine = Engine::new();
+    engine.add_filter("upper", str::to_uppercase);
+    let result = engine
+        .compile("{% set dolor = ipsum | upper %}lorem {{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem TEST");
+}
+
+#[test]
+fn render_set_statement_shadowing() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{{ ipsum }} {% set ipsum = dolor %}{{ ipsum }} {% set ipsum = sit %}{{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "a", dolor: "b", sit: "c" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "a b c");
+}
+
+#[test]
+fn render_set_statement_scope() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for x in xs %}{% set ipsum = x %}{{ ipsum }}{% endfor %} {% if true %}{% set ipsum = dolor %}{{ ipsum }}{% endif %} {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { xs: ["t", "e"], ipsum: "a", dolor: "b" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "te b a");
+}
+
+#[test]
+fn render_set_statement_loop_break_and_continue() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% for x in xs %}{% set ipsum = x %}{% if ipsum.skip %}{% continue %}{% endif %}{% if ipsum.stop %}{% break %}{% endif %}{{ ipsum.name }}{% endfor %} {{ ipsum }}")
+        .unwrap()
+        .render(
+            &engine,
+            value! {
+                xs: [
+                    { name: "a", skip: true, stop: false },
+                    { name: "b", skip: false, stop: false },
+                    { name: "c", skip: false, stop: true },
+                ],
+                ipsum: "outer"
+            },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "b outer");
+}
+
+#[test]
+fn render_set_statement_include() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("nested", "{% set ipsum = dolor %}{{ ipsum }}")
+        .unwrap();
+    let result = engine
+        .compile(r#"{% include "nested" %} {{ ipsum }}"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: "a", dolor: "b" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "b a");
+}
+
+#[test]
+fn render_set_statement_err_var_scope() {
+    let engine = Engine::new();
+    let err = engine
+        .compile("{% with ipsum as sit %}{% set dolor = sit %}{% endwith %}{{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        "
+  --> <anonymous>:1:61
+   |
+ 1 | {% with ipsum as sit %}{% set dolor = sit %}{% endwith %}{{ dolor }}
+   |                                                             ^^^^^
+   |
+   = reason: REASON
+",
+    );
+}
+
+#[test]
+fn render_raw_statement() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem {% raw %}{{ ipsum }} {% if dolor %}{# sit #}{% endraw %} {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "test" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem {{ ipsum }} {% if dolor %}{# sit #} test");
+}
+
+#[test]
+fn render_raw_statement_trim() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("lorem \n {%- raw -%} \n {{ ipsum }} \n {%- endraw -%} \n dolor")
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem{{ ipsum }}dolor");
+}
+
+#[test]
+fn render_raw_statement_custom_syntax() {
+    let engine = Engine::with_syntax(Syntax::builder().expr("<{", "}>").block("<[", "]>").build());
+    let result = engine
+        .compile("<[ raw ]><{ lorem }> {% endraw %}<[ endraw ]>")
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<{ lorem }> {% endraw %}");
+}
+
+#[test]
+fn render_comment_custom_syntax() {
+    let syntax = Syntax::builder()
+        .expr("<{", "}>")
+        .block("<[", "]>")
+        .comment("<#", "#>")
+        .build();
+    let engine = Engine::with_syntax(syntax);
+    let result = engine
+        .compile("lorem <# ipsum {# dolor #> sit \n <#- amet -#>\n <{ x }>")
+        .unwrap()
+        .render(&engine, value! { x: "consectetur" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem  sitconsectetur");
+}
+
+#[test]
+fn render_comment_syntax_not_configured() {
+    let engine = Engine::with_syntax(Syntax::builder().expr("{{", "}}").build());
+    let result = engine
+        .compile("lorem {# ipsum #} {{ dolor }}")
+        .unwrap()
+        .render(&engine, value! { dolor: "sit" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem {# ipsum #} sit");
+}
+
+#[test]
+fn render_case_insensitive_keywords() {
+    let syntax = Syntax::builder()
+        .expr("{{", "}}")
+        .block("{%", "%}")
+        .case_insensitive_keywords(true)
+        .build();
+    let engine = Engine::with_syntax(syntax);
+    let result = engine
+        .compile(
+            "{% FOR x IN xs %}{% If x Is empty %}E{% ELSE IF NOT y AND x == \"lorem\" %}L{% Elif TRUE %}{{ x }}{% EndIf %}{% ENDFOR %} \
+             {% Raw %}{% if %}{% ENDRAW %}",
+        )
+        .unwrap()
+        .render(&engine, value! { xs: ["", "lorem", "ipsum"], y: false })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "ELipsum {% if %}");
+}
+
+#[test]
+fn render_case_sensitive_keywords_ident() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{% if If %}{{ If }} {{ ENDIF }}{% endif %}")
+        .unwrap()
+        .render(&engine, value! { If: "lorem", ENDIF: "ipsum" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem ipsum");
+}
+
+#[test]
+fn render_keyword_member_names() {
+    let engine = Engine::new();
+    let result = engine
+        .compile("{{ user.set }} {{ items.raw }} {{ user?.if }} {{ user.block.0 }}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { user: { set: "lorem", if: "ipsum", block: ["sit"] }, items: { raw: "dolor" } },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem dolor ipsum sit");
+}
+
+#[test]
+fn render_contextual_keywords_ident() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(
+            "{{ block }} {{ raw.0 }} {% for set in raw %}{{ set }}{% endfor %} \
+             {% if is and not break %}{{ is }}{% endif %}",
+        )
+        .unwrap()
+        .render(
+            &engine,
+            value! { block: "lorem", raw: ["ipsum", "dolor"], is: "sit", break: false },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem ipsum ipsumdolor sit");
+}
+
+#[test]
+fn render_autoescape_statement() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    let result = engine
+        .compile("{{ ipsum }} {% autoescape off %}{{ ipsum }}{% endautoescape %} {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "<b>" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "&lt;b> <b> &lt;b>");
+}
+
+#[test]
+fn render_autoescape_statement_nested() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    let result = engine
+        .compile(
+            "{% autoescape off %}{{ ipsum }}{% autoescape on %}{{ ipsum }}{% endautoescape %}{{ ipsum }}{% endautoescape %}",
+        )
+        .unwrap()
+        .render(&engine, value! { ipsum: "<" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<&lt;<");
+}
+
+#[test]
+fn render_autoescape_statement_include() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    engine.add_template("nested", "{{ ipsum }}").unwrap();
+    let result = engine
+        .compile(r#"{% autoescape off %}{% include "nested" %}{% endautoescape %}{% include "nested" %}"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: "<" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<&lt;");
+}
+
+#[test]
+fn render_autoescape_statement_explicit_formatter() {
+    let mut engine = Engine::new();
+    engine.add_formatter("escape_lt", escape_lt);
+    let result = engine
+        .compile("{% autoescape off %}{{ ipsum | escape_lt }}{% endautoescape %}")
+        .unwrap()
+        .render(&engine, value! { ipsum: "<" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "&lt;");
+}
+
+#[test]
+fn render_autoescape_statement_escape_html() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&fmt::escape_html);
+    engine.add_formatter("safe", fmt::default);
+    let result = engine
+        .compile(
+            "{{ ipsum }} {{ ipsum | safe }} {% autoescape off %}{{ ipsum }}{% endautoescape %} {{ dolor }} {{ sit }}",
+        )
+        .unwrap()
+        .render(
+            &engine,
+            value! { ipsum: r#"<a href="x">'&amp;'</a>"#, dolor: 1.5, sit: None },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        r#"&lt;a href=&quot;x&quot;&gt;&#x27;&amp;amp;&#x27;&lt;/a&gt; <a href="x">'&amp;'</a> <a href="x">'&amp;'</a> 1.5 "#
+    );
+}
+
+fn escape_lt(f: &mut fmt::Formatter<'_>, v: &Value) -> fmt::Result {
+    match v {
+        Value::String(s) => write!(f, "{}", s.replace('<', "&lt;"))?,
+        v => fmt::default(f, v)?,
+    }
+    Ok(())
+}
+
+#[test]
+fn render_include_statement() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include "nested" %} sit"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[test]
+fn render_include_with_statement() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ dolor }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include "nested" with ipsum %} sit"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[cfg(feature = "filters")]
+#[test]
+fn render_include_with_statement_owned() {
+    let mut engine = Engine::new();
+    engine.add_filter("to_owned", Value::to_owned);
+    engine.add_template("nested", "{{ dolor }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include "nested" with ipsum | to_owned %} sit"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[test]
+fn render_include_statement_parent_template_scope() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include "nested" %} sit"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[test]
+fn render_include_statement_err_parent_template_scope() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+    let err = engine
+        .compile(r#"lorem {% include "nested" with ipsum %} sit"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        r#"
+  --> nested:1:4
+   |
+ 1 | {{ ipsum.dolor }}
+   |    ^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_statement_err_unknown_template() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% include "nested" %} sit"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unknown template",
+        r#"
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {% include "nested" %} sit
+   |                  ^^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_statement_ignore_missing() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ ipsum }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include "nested" ignore missing %} {% include "missing" ignore missing %}{% include name ignore missing with dolor %} sit"#)
+        .unwrap()
+        .render(&engine, value! { ipsum: "test", name: "missing", dolor: {} })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test  sit");
+}
+
+#[test]
+fn render_include_statement_ignore_missing_err_nested() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ ipsum }}").unwrap();
+    let err = engine
+        .compile(r#"lorem {% include "nested" ignore missing %} sit"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        r#"
+  --> nested:1:4
+   |
+ 1 | {{ ipsum }}
+   |    ^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_statement_ignore_missing_err_template_fn() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% include "nested" ignore missing %} sit"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_template_fn(|_| Err(String::from("failed to load template")))
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "failed to load template",
+        r#"
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {% include "nested" ignore missing %} sit
+   |                  ^^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_statement_var() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include name %} sit"#)
+        .unwrap()
+        .render(&engine, value! { name: "nested", ipsum: { dolor: "test" }})
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[test]
+fn render_include_with_statement_var() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ dolor }}").unwrap();
+    let result = engine
+        .compile(r#"lorem {% include page.name with ipsum %} sit"#)
+        .unwrap()
+        .render(
+            &engine,
+            value! { page: { name: "nested" }, ipsum: { dolor: "test" }},
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[test]
+fn render_include_statement_var_err_not_string() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% include name %} sit"#)
+        .unwrap()
+        .render(&engine, value! { name: 123 })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "expected string, but expression evaluated to integer",
+        r#"
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {% include name %} sit
+   |                  ^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_statement_var_err_unknown_template() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% include name %} sit"#)
+        .unwrap()
+        .render(&engine, value! { name: "nested" })
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unknown template",
+        r#"
+  --> <anonymous>:1:18
+   |
+ 1 | lorem {% include name %} sit
+   |                  ^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_block_statement() {
+    let result = Engine::new()
+        .compile("lorem {% block ipsum %}{{ dolor }}{% endblock %} sit")
+        .unwrap()
+        .render(&Engine::new(), value! { dolor: "test" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test sit");
+}
+
+#[test]
+fn render_extends_statement() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "base",
+            "<{% block title %}lorem{% endblock %}> {% block body %}ipsum{% endblock %}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "base" %} ignored {% block body %}{{ dolor }}{% endblock %}"#,
+        )
+        .unwrap();
+    let result = engine
+        .template("child")
+        .render(value! { dolor: "test" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<lorem> test");
+}
+
+#[test]
+fn render_extends_statement_multi_level() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "base",
+            "{% block outer %}[{% block inner %}lorem{% endblock %}]{% endblock %} {% block other %}ipsum{% endblock %}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "middle",
+            r#"{% extends "base" %}{% block outer %}({% block inner %}dolor{% endblock %}){% endblock %}{% block other %}sit{% endblock %}"#,
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "middle" %}{% block inner %}amet{% endblock %}"#,
+        )
+        .unwrap();
+    let result = engine
+        .template("child")
+        .render(Value::None)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "(amet) sit");
+    let result = engine
+        .template("middle")
+        .render(Value::None)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "(dolor) sit");
+}
+
+#[test]
+fn render_extends_statement_base_scope() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "base",
+            "{% for item in items %}{% block item %}{{ item }}{% endblock %}{% endfor %}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "base" %}{% block item %}<{{ item }}>{% endblock %}"#,
+        )
+        .unwrap();
+    let result = engine
+        .template("child")
+        .render(value! { items: [1, 2, 3] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<1><2><3>");
+}
+
+#[test]
+fn render_extends_statement_base_with_and_set() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "base",
+            r#"{% set title = "lorem" %}{% with ipsum as subtitle %}{% block body %}{% endblock %}{% endwith %}"#,
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "base" %}{% block body %}{{ title }} {{ subtitle }}{% endblock %}"#,
+        )
+        .unwrap();
+    let result = engine
+        .template("child")
+        .render(value! { ipsum: "dolor" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem dolor");
+}
+
+#[test]
+fn render_extends_statement_include() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("base", "{% block body %}lorem{% endblock %}")
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "base" %}{% block body %}{{ ipsum }}{% endblock %}"#,
+        )
+        .unwrap();
+    let result = engine
+        .compile(r#"{% include "child" with dolor %} {% include "base" %}"#)
+        .unwrap()
+        .render(&engine, value! { dolor: { ipsum: "test" } })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "test lorem");
+}
+
+#[test]
+fn render_extends_statement_err_unknown_template() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("child", r#"{% extends "base" %}"#)
+        .unwrap();
+    let err = engine
+        .template("child")
+        .render(Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "unknown template",
+        r#"
+  --> child:1:12
+   |
+ 1 | {% extends "base" %}
+   |            ^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_extends_statement_err_in_block() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("base", "{% block body %}{% endblock %}")
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "base" %}{% block body %}{{ ipsum }}{% endblock %}"#,
+        )
+        .unwrap();
+    let err = engine
+        .template("child")
+        .render(Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in this scope",
+        r#"
+  --> child:1:40
+   |
+ 1 | {% extends "base" %}{% block body %}{{ ipsum }}{% endblock %}
+   |                                        ^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_extends_statement_err_max_include_depth() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("cycle", r#"{% extends "cycle" %}"#)
+        .unwrap();
+    let err = engine
+        .template("cycle")
+        .render(Value::None)
+        .with_max_include_depth(4)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum include depth (4)"
+    );
+}
+
+#[test]
+fn render_with_block() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "base",
+            "{% block outer %}[{% block inner %}lorem{% endblock %}]{% endblock %} {% block other %}{{ ipsum }}{% endblock %}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "middle",
+            r#"{% extends "base" %}{% block outer %}({% block inner %}dolor{% endblock %}){% endblock %}"#,
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "middle" %}{% block inner %}{{ ipsum }}{% endblock %}"#,
+        )
+        .unwrap();
+    let tests = [
+        ("child", "outer", "(sit)"),
+        ("child", "inner", "sit"),
+        ("child", "other", "sit"),
+        ("middle", "outer", "(dolor)"),
+        ("base", "outer", "[lorem]"),
+        ("base", "inner", "lorem"),
+    ];
+    for (template, block, exp) in tests {
+        let result = engine
+            .template(template)
+            .render_block(block, value! { ipsum: "sit" })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "template: {template}, block: {block}");
+    }
+    let chunks = engine
+        .template("child")
+        .render(value! { ipsum: "sit" })
+        .with_block("outer")
+        .into_chunks()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["(", "sit", ")"]);
+}
+
+#[test]
+fn render_with_block_err_unknown_block() {
+    let mut engine = Engine::new();
+    engine
+        .add_template(
+            "base",
+            "{% block body %}{% block inner %}{% endblock %}{% endblock %}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "child",
+            r#"{% extends "base" %}{% block title %}{% endblock %}{% block body %}{% endblock %}"#,
+        )
+        .unwrap();
+    let err = engine
+        .template("child")
+        .render_block("lorem", Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Render);
+    assert_eq!(err.template_name(), Some("child"));
+    assert_eq!(
+        err.to_string(),
+        "render error: unknown block `lorem`, available blocks: `body`, `inner`, `title`"
+    );
+
+    let err = engine
+        .compile("lorem")
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_block("ipsum")
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: unknown block `ipsum`, the template has no blocks"
+    );
+}
+
+#[test]
+fn render_include_raw_statement() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"lorem {% include_raw "ipsum" %} sit"#)
+        .unwrap()
+        .render(&engine, value! { dolor: "test" })
+        .with_include_raw_fn(|name| {
+            assert_eq!(name, "ipsum");
+            Ok(String::from("{{ dolor }} <b>"))
+        })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem {{ dolor }} <b> sit");
+}
+
+#[test]
+fn render_include_raw_statement_not_escaped() {
+    let mut engine = Engine::new();
+    engine.set_default_formatter(&escape_lt);
+    let result = engine
+        .compile(r#"{% include_raw "ipsum" %}"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_include_raw_fn(|_| Ok(String::from("<b>")))
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "<b>");
+}
+
+#[test]
+fn render_include_raw_statement_err_no_fn() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% include_raw "ipsum" %} sit"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "no raw include function set",
+        r#"
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% include_raw "ipsum" %} sit
+   |                      ^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_raw_statement_err_fn() {
+    let engine = Engine::new();
+    let err = engine
+        .compile(r#"lorem {% include_raw "ipsum" %} sit"#)
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_include_raw_fn(|name| Err(format!("file `{name}` not found")))
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "file `ipsum` not found",
+        r#"
+  --> <anonymous>:1:22
+   |
+ 1 | lorem {% include_raw "ipsum" %} sit
+   |                      ^^^^^^^
+   |
+   = reason: REASON
+"#,
+    );
+}
+
+#[test]
+fn render_include_statement_err_max_include_depth() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("cycle", r#"{% include "cycle" %}"#)
+        .unwrap();
+    let err = engine
+        .template("cycle")
+        .render(Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum include depth (64)"
+    );
+}
+
+#[test]
+fn render_err_accessors() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("nested", "lorem\n{{ ipsum.dolor }}")
+        .unwrap();
+    let err = engine
+        .template("nested")
+        .render(value! { ipsum: { sit: "amet" } })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Render);
+    assert_eq!(err.template_name(), Some("nested"));
+    assert_eq!(err.reason(), Some("not found in map"));
+    assert_eq!(err.span(), Some(14..20));
+    assert_eq!(err.line_column(), Some((2, 9)));
+}
+
+#[test]
+fn render_include_statement_err_max_include_depth_kind() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("cycle", r#"{% include "cycle" %}"#)
+        .unwrap();
+    let err = engine
+        .template("cycle")
+        .render(Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MaxIncludeDepth);
+    assert_eq!(err.span(), None);
+    assert_eq!(err.line_column(), None);
+}
+
+#[test]
+fn render_include_statement_err_max_include_depth_renderer() {
+    let mut engine = Engine::new();
+    engine.set_max_include_depth(128);
+    engine
+        .add_template("cycle", r#"{% include "cycle" %}"#)
+        .unwrap();
+    let err = engine
+        .template("cycle")
+        .render(Value::None)
+        .with_max_include_depth(4)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum include depth (4)"
+    );
+}
+
+#[test]
+fn render_include_statement_err_max_include_depth_render_options() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("cycle", r#"{% include "cycle" %}"#)
+        .unwrap();
+    let options = RenderOptions::new().with_max_include_depth(4);
+    let err = engine
+        .template("cycle")
+        .render(Value::None)
+        .with_options(&options)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum include depth (4)"
+    );
+}
+
+#[test]
+fn render_options_default_does_not_override_renderer() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("cycle", r#"{% include "cycle" %}"#)
+        .unwrap();
+    let err = engine
+        .template("cycle")
+        .render(Value::None)
+        .with_max_include_depth(4)
+        .with_options(&RenderOptions::default())
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum include depth (4)"
+    );
 }
 
 #[test]
-fn render_if_statement_cond_false() {
-    for value in falsy() {
-        let engine = Engine::new();
+fn render_trim_blocks() {
+    let mut engine = Engine::new();
+    engine.set_trim_blocks(true);
+    let tests = [
+        ("{% if t %}\nlorem\n{% endif %}\n", "lorem\n"),
+        ("{% if t %}\r\nlorem\r\n{% endif %}\r\n", "lorem\r\n"),
+        ("{% if t %}\n\nlorem{% endif %}", "\nlorem"),
+        ("{% if t %} \nlorem{% endif %}", " \nlorem"),
+        ("{% if t %}\rlorem{% endif %}", "\rlorem"),
+        ("{{ t }}\n{# lorem #}\nipsum", "true\n\nipsum"),
+        ("{% if t -%}\n\n  lorem{% endif %}", "lorem"),
+        ("{% raw %}\n{{ lorem }}\n{% endraw %}\n", "{{ lorem }}\n"),
+    ];
+    for (source, exp) in tests {
         let result = engine
-            .compile("lorem {% if ipsum.dolor %}{{ sit }}{% else %}{{ amet }}{% endif %}")
+            .compile(source)
             .unwrap()
-            .render(
-                &engine,
-                value! { ipsum: { dolor: value.clone() }, amet: "consectetur" },
-            )
+            .render(&engine, value! { t: true })
             .to_string()
             .unwrap();
-        assert_eq!(result, "lorem consectetur");
+        assert_eq!(result, exp, "{source:?}");
     }
 }
 
 #[test]
-fn render_if_statement_cond_not() {
-    for value in falsy() {
-        let engine = Engine::new();
+fn render_lstrip_blocks() {
+    let mut engine = Engine::new();
+    engine.set_lstrip_blocks(true);
+    let tests = [
+        ("  {% if t %}lorem{% endif %}", "lorem"),
+        (
+            "lorem\n \t {% if t %}ipsum\n  {% endif %}",
+            "lorem\nipsum\n",
+        ),
+        ("lorem\r\n  {% if t %}ipsum{% endif %}", "lorem\r\nipsum"),
+        ("lorem  {% if t %}ipsum{% endif %}", "lorem  ipsum"),
+        ("{{ t }}  {% if t %}ipsum{% endif %}", "true  ipsum"),
+        ("lorem\n  {{ t }}\n  {# ipsum #}", "lorem\n  true\n  "),
+        ("lorem\n  {%- if t %}ipsum{% endif %}", "loremipsum"),
+        (
+            "{% raw %}\n  {{ lorem }}\n  {% endraw %}",
+            "\n  {{ lorem }}\n",
+        ),
+    ];
+    for (source, exp) in tests {
         let result = engine
-            .compile("lorem {% if not ipsum.dolor %}{{ sit }}{% else %}{{ amet }}{% endif %}")
+            .compile(source)
             .unwrap()
-            .render(
-                &engine,
-                value! { ipsum: {dolor: value.clone()}, sit: "consectetur" },
-            )
+            .render(&engine, value! { t: true })
             .to_string()
             .unwrap();
-        assert_eq!(result, "lorem consectetur");
+        assert_eq!(result, exp, "{source:?}");
     }
 }
 
 #[test]
-fn render_if_statement_else_if_cond_false() {
-    for value in falsy() {
-        let engine = Engine::new();
+fn render_trim_blocks_and_lstrip_blocks() {
+    let mut engine = Engine::new();
+    engine.set_trim_blocks(true);
+    engine.set_lstrip_blocks(true);
+    let tests = [
+        (
+            "<ul>\n  {% for x in xs %}\n    <li>{{ x }}</li>\n  {% endfor %}\n</ul>\n",
+            "<ul>\n    <li>1</li>\n    <li>2</li>\n</ul>\n",
+        ),
+        (
+            "<ul>\r\n  {% for x in xs %}\r\n    <li>{{ x }}</li>\r\n  {% endfor %}\r\n</ul>\r\n",
+            "<ul>\r\n    <li>1</li>\r\n    <li>2</li>\r\n</ul>\r\n",
+        ),
+        (
+            "{% for x in xs %}\n  {% if x == 1 %}\n  one\n  {% endif %}\n{% endfor %}\n",
+            "  one\n",
+        ),
+    ];
+    for (source, exp) in tests {
         let result = engine
-            .compile("lorem {% if ipsum %} dolor {% else if sit %} amet {% endif %}, consectetur")
+            .compile(source)
             .unwrap()
-            .render(&engine, value! { ipsum: value.clone(), sit: value.clone() })
+            .render(&engine, value! { xs: [1, 2] })
             .to_string()
             .unwrap();
-        assert_eq!(result, "lorem , consectetur");
+        assert_eq!(result, exp, "{source:?}");
     }
 }
 
 #[test]
-fn render_if_statement_else_if_cond_true() {
-    for (t, f) in zip(truthy(), falsy()) {
-        let engine = Engine::new();
+fn render_trim_comments() {
+    let mut engine = Engine::new();
+    engine.set_trim_comments(true);
+    let tests = [
+        ("lorem\n{# ipsum #}\ndolor", "loremdolor"),
+        ("lorem \r\n\t{# ipsum #} \r\ndolor", "loremdolor"),
+        ("{# ipsum #}\nlorem\n", "lorem\n"),
+        ("lorem\n{#- ipsum -#}\ndolor", "loremdolor"),
+        ("lorem\n{# ipsum #}", "lorem"),
+        ("{{ t }}\n{% if t %}\nlorem{% endif %}", "true\n\nlorem"),
+        (
+            "lorem\n{% raw %}\n{# ipsum #}\n{% endraw %}",
+            "lorem\n\n{# ipsum #}\n",
+        ),
+    ];
+    for (source, exp) in tests {
         let result = engine
-            .compile("lorem {% if ipsum %} dolor {% else if sit %} amet {% endif %}, consectetur")
+            .compile(source)
             .unwrap()
-            .render(&engine, value! { ipsum: f, sit: t })
+            .render(&engine, value! { t: true })
             .to_string()
             .unwrap();
-        assert_eq!(result, "lorem  amet , consectetur");
+        assert_eq!(result, exp, "{source:?}");
     }
 }
 
 #[test]
-fn render_if_statement_else_if_cond_not() {
-    for falsy in falsy() {
-        let engine = Engine::new();
+fn render_trim_trailing_newline() {
+    let engine = Engine::new();
+    let tests = [
+        ("lorem", "lorem"),
+        ("lorem\n", "lorem"),
+        ("lorem\r\n", "lorem"),
+        ("lorem\n\n", "lorem\n"),
+        ("lorem\r", "lorem\r"),
+        ("\n", ""),
+        ("", ""),
+    ];
+    for (source, exp) in tests {
         let result = engine
-            .compile(
-                "lorem {% if ipsum %} dolor {% else if not sit %} amet {% endif %}, consectetur",
-            )
+            .compile(source)
             .unwrap()
-            .render(&engine, value! { ipsum: falsy.clone(), sit: falsy })
+            .render(&engine, Value::None)
+            .trim_trailing_newline()
             .to_string()
             .unwrap();
-        assert_eq!(result, "lorem  amet , consectetur");
+        assert_eq!(result, exp, "source: {source:?}");
     }
 }
 
 #[test]
-fn render_if_statement_multi() {
+fn render_trim_trailing_newline_to_writer() {
     let engine = Engine::new();
-    let template = engine
-        .compile(
-            r#"
-{%- if a -%} a
-{%- else if b -%} b
-{%- else if c -%} c
-{%- else if d -%} d
-{%- else if e -%} e
-{%- else -%} f
-{%- endif -%}
-"#,
-        )
+    let mut buf = Vec::new();
+    engine
+        .compile("{{ lorem }}\n")
+        .unwrap()
+        .render(&engine, value! { lorem: "ipsum" })
+        .trim_trailing_newline()
+        .to_writer(&mut buf)
         .unwrap();
-
-    let mut map = BTreeMap::from([
-        ("a", false),
-        ("b", false),
-        ("c", false),
-        ("d", false),
-        ("e", false),
-    ]);
-    let result = template.render(&engine, &map).to_string().unwrap();
-    assert_eq!(result, "f");
-    for var in ["a", "b", "c", "d", "e"] {
-        map.insert(var, true);
-        let result = template.render(&engine, &map).to_string().unwrap();
-        assert_eq!(result, var);
-        map.insert(var, false);
-    }
+    assert_eq!(buf, b"ipsum");
 }
 
 #[test]
-fn render_for_statement_list() {
+fn render_trim_trailing_newline_render_options() {
     let engine = Engine::new();
+    let options = RenderOptions::new().trim_trailing_newline();
     let result = engine
-        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .compile("{{ lorem }}\n")
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, value! { lorem: "ipsum" })
+        .with_options(&options)
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem test");
+    assert_eq!(result, "ipsum");
 }
 
-#[cfg(feature = "filters")]
 #[test]
-fn render_for_statement_filtered_list() {
-    let mut engine = Engine::new();
-    engine.add_filter("pop", |mut list: Vec<Value>| {
-        list.pop();
-        list
-    });
-    let result = engine
-        .compile("lorem {% for ipsum in dolor | pop %}{{ ipsum }}{% endfor %}")
-        .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
-        .to_string()
+fn render_to_vec() {
+    let engine = Engine::new();
+    let template = engine.compile("lorem {{ ipsum }}\n").unwrap();
+
+    let result = template
+        .render(&engine, value! { ipsum: "dölor" })
+        .trim_trailing_newline()
+        .to_vec()
         .unwrap();
-    assert_eq!(result, "lorem tes");
+    assert_eq!(result, "lorem dölor".as_bytes());
+
+    let err = template.render(&engine, Value::None).to_vec().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Render);
 }
 
 #[test]
-fn render_for_statement_map() {
+fn render_buffered_to_writer() {
     let engine = Engine::new();
-    let result = engine
-        .compile("lorem {% for ipsum, dolor in sit %}{{ ipsum }},{{ dolor.0 }} {% endfor %}")
-        .unwrap()
-        .render(
-            &engine,
-            value! { sit: { a: ["t"], b: ["e"], c: ["s"], d: ["t"] } },
-        )
-        .to_string()
+    let template = engine.compile("lorem {{ ipsum }}").unwrap();
+
+    let mut buf = Vec::new();
+    template
+        .render(&engine, value! { ipsum: "dolor" })
+        .buffered_to_writer(&mut buf)
         .unwrap();
-    assert_eq!(result, "lorem a,t b,e c,s d,t ");
-}
+    assert_eq!(buf, b"lorem dolor");
 
-#[test]
-fn render_for_statement_loop_fields() {
-    let engine = Engine::new();
-    let result = engine
-        .compile("lorem {% for ipsum in dolor %}{{ loop.index }},{{ loop.first }},{{ loop.last }},{{ ipsum }}  {% endfor %}")
-        .unwrap()
-        .render(&engine, value!{ dolor: ["t", "e", "s", "t"] }).to_string().unwrap();
-    assert_eq!(
-        result,
-        "lorem 0,true,false,t  1,false,false,e  2,false,false,s  3,false,true,t  "
-    );
+    let mut buf = Vec::new();
+    let err = template
+        .render(&engine, Value::None)
+        .buffered_to_writer(&mut buf)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Render);
+    assert!(buf.is_empty());
 }
 
 #[test]
-fn render_for_statement_loop_optional_access() {
+fn render_with_globals() {
     let engine = Engine::new();
+    let globals = value! { lorem: "x", ipsum: { dolor: "y" }, sit: "z", amet: "w" };
     let result = engine
-        .compile("lorem {% for ipsum in dolor %}{{ loop?.notindex }}{{ ipsum }}{% endfor %}")
+        .compile("{{ lorem }} {{ ipsum.dolor }} {% set sit = 1 %}{{ sit }} {{ amet ?? \"n\" }}")
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, value! { lorem: "a", amet: None })
+        .with_globals(&globals)
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem test");
+    assert_eq!(result, "a y 1 n");
 }
 
 #[test]
-fn render_for_statement_loop_map() {
+fn render_with_globals_include_with() {
     let mut engine = Engine::new();
-    engine.add_formatter("debug", |f, v| {
-        writeln!(f, "{v:?}")?;
-        Ok(())
-    });
+    engine
+        .add_template("nested", "{{ lorem }} {{ ipsum }}")
+        .unwrap();
+    let globals = value! { lorem: "x", ipsum: "y" };
     let result = engine
-        .compile("lorem {% for ipsum in dolor %} {{ loop | debug }} {% endfor %}")
+        .compile(r#"{% include "nested" with dolor %} {% include "nested" %}"#)
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, value! { ipsum: "a", dolor: { lorem: "b" } })
+        .with_globals(&globals)
         .to_string()
         .unwrap();
-    assert_eq!(
-        result,
-        r#"lorem  Map({"first": Bool(true), "index": Integer(0), "last": Bool(false)})
-  Map({"first": Bool(false), "index": Integer(1), "last": Bool(false)})
-  Map({"first": Bool(false), "index": Integer(2), "last": Bool(false)})
-  Map({"first": Bool(false), "index": Integer(3), "last": Bool(true)})
- "#
-    );
+    assert_eq!(result, "b y x a");
 }
 
 #[test]
-fn render_err_contains_template_name() {
-    let mut engine = Engine::new();
-    engine.add_template("test", "{{ ipsum }}").unwrap();
+fn render_with_globals_into_chunks() {
+    let engine = Engine::new();
+    let globals = value! { ipsum: "y" };
+    let template = engine.compile("lorem {{ ipsum }}").unwrap();
+    let chunks: Vec<_> = template
+        .render(&engine, Value::None)
+        .with_globals(&globals)
+        .into_chunks()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["lorem ", "y"]);
+}
+
+#[test]
+fn render_with_globals_err_not_found() {
+    let engine = Engine::new();
+    let globals = value! { ipsum: {} };
     let err = engine
-        .template("test")
-        .render(value! {})
+        .compile("{{ lorem }}")
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_globals(&globals)
         .to_string()
         .unwrap_err();
     assert_err(
         &err,
         "not found in this scope",
         "
-  --> test:1:4
+  --> <anonymous>:1:4
    |
- 1 | {{ ipsum }}
+ 1 | {{ lorem }}
    |    ^^^^^
    |
    = reason: REASON
+",
+    );
+    let err = engine
+        .compile("{{ ipsum.dolor }}")
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_globals(&globals)
+        .to_string()
+        .unwrap_err();
+    assert_err(
+        &err,
+        "not found in map",
+        "
+  --> <anonymous>:1:9
+   |
+ 1 | {{ ipsum.dolor }}
+   |         ^^^^^^
+   |
+   = reason: REASON
 ",
     );
 }
 
 #[test]
-fn render_for_statement_err_not_found_in_map() {
+fn render_undefined_empty() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"[{{ lorem }}] {% if ipsum %}x{% else %}y{% endif %} {{ dolor ?? "z" }} {{ sit?.amet }}"#)
+        .unwrap()
+        .render(&engine, value! { sit: {} })
+        .with_undefined_behavior(Undefined::Empty)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "[] y z ");
+}
+
+#[test]
+fn render_undefined_empty_err_missing_field() {
     let engine = Engine::new();
     let err = engine
-        .compile("lorem {% for ipsum in dolor %} {{ loop.xxx }} {% endfor %}")
+        .compile("{{ lorem.ipsum }}")
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, value! { lorem: {} })
+        .with_undefined_behavior(Undefined::Empty)
         .to_string()
         .unwrap_err();
     assert_err(
         &err,
         "not found in map",
         "
-  --> <anonymous>:1:39
+  --> <anonymous>:1:9
    |
- 1 | lorem {% for ipsum in dolor %} {{ loop.xxx }} {% endfor %}
-   |                                       ^^^^
+ 1 | {{ lorem.ipsum }}
+   |         ^^^^^^
    |
    = reason: REASON
 ",
@@ -788,258 +3861,442 @@ fn render_for_statement_err_not_found_in_map() {
 }
 
 #[test]
-fn render_for_statement_err_cannot_index_into_map() {
+fn render_undefined_keep() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(
+            "{{ lorem }} {{- ipsum.dolor | unknown }} {{ sit }} {% if amet %}x{% else %}y{% endif %} {{ amet ?? consectetur }}",
+        )
+        .unwrap()
+        .render(&engine, value! { sit: "a" })
+        .with_undefined_behavior(Undefined::Keep)
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        "{{ lorem }}{{- ipsum.dolor | unknown }} a y {{ amet ?? consectetur }}"
+    );
+}
+
+#[test]
+fn render_undefined_keep_any_var() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(
+            "{{ sit ~ lorem }} {{ (lorem ~ sit) }} {{ lorem ?? sit }} {{ sit ?? lorem }} \
+             {% for x in xs %}{{ x ~ ipsum }}{% if x == sit %}={% endif %}{% endfor %}",
+        )
+        .unwrap()
+        .render(&engine, value! { sit: "a", xs: ["a", "b"] })
+        .with_undefined_behavior(Undefined::Keep)
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        "{{ sit ~ lorem }} {{ (lorem ~ sit) }} a a {{ x ~ ipsum }}={{ x ~ ipsum }}"
+    );
+}
+
+#[test]
+fn render_undefined_keep_into_chunks() {
+    let engine = Engine::new();
+    let chunks: Vec<_> = engine
+        .compile("lorem {{ ipsum }} {% for x in xs %}{{ x }}{{ dolor }}{% endfor %}")
+        .unwrap()
+        .render(&engine, value! { xs: [1, 2] })
+        .with_undefined_behavior(Undefined::Keep)
+        .into_chunks()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        chunks,
+        [
+            "lorem ",
+            "{{ ipsum }}",
+            " ",
+            "1",
+            "{{ dolor }}",
+            "2",
+            "{{ dolor }}"
+        ]
+    );
+}
+
+#[test]
+fn render_undefined_with_options() {
+    let engine = Engine::new();
+    let options = RenderOptions::new().with_undefined_behavior(Undefined::Keep);
+    let result = engine
+        .compile("{{ lorem }}")
+        .unwrap()
+        .render(&engine, Value::None)
+        .with_options(&options)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "{{ lorem }}");
+}
+
+#[test]
+fn render_into_chunks() {
+    let engine = Engine::new();
+    let chunks: Vec<_> = engine
+        .compile("lorem {% for x in xs %}{{ x }},{% endfor %} {% if true %}ipsum{% endif %}")
+        .unwrap()
+        .render(&engine, value! { xs: [1, 2] })
+        .into_chunks()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["lorem ", "1", ",", "2", ",", " ", "ipsum"]);
+}
+
+#[test]
+fn render_into_chunks_include() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "{{ x }}!").unwrap();
+    engine
+        .add_template("base", r#"<{% include "nested" with y %}>"#)
+        .unwrap();
+    let chunks: Vec<_> = engine
+        .template("base")
+        .render(value! { y: { x: "lorem" } })
+        .into_chunks()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["<", "lorem", "!", ">"]);
+}
+
+#[test]
+fn render_into_chunks_matches_to_string() {
+    let mut engine = Engine::new();
+    engine
+        .add_template("nested", "{{ user.name }}\r\n")
+        .unwrap();
+    let sources = [
+        "a{% include \"nested\" %}",
+        "{% for user in users %}{% include \"nested\" %}{% endfor %}",
+        "{{ users.0.name }}\n\n",
+        "{{ users.0.name }}\r",
+        "\n",
+        "",
+    ];
+    let ctx = value! { users: [{ name: "John" }, { name: "Jane" }], user: { name: "Bob" } };
+    for source in sources {
+        let template = engine.compile(source).unwrap();
+        for trim in [false, true] {
+            let render = || {
+                let renderer = template.render(&engine, &ctx);
+                match trim {
+                    true => renderer.trim_trailing_newline(),
+                    false => renderer,
+                }
+            };
+            let chunks: Vec<_> = render().into_chunks().collect::<Result<_, _>>().unwrap();
+            assert!(chunks.iter().all(|c| !c.is_empty()));
+            assert_eq!(
+                chunks.concat(),
+                render().to_string().unwrap(),
+                "source: {source:?}, trim: {trim}"
+            );
+        }
+    }
+}
+
+#[test]
+fn render_into_chunks_err() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {% for ipsum in dolor %} {{ loop.123 }} {% endfor %}")
-        .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
-        .to_string()
-        .unwrap_err();
-    assert_err(
-        &err,
-        "cannot index into map with integer",
-        "
-  --> <anonymous>:1:39
-   |
- 1 | lorem {% for ipsum in dolor %} {{ loop.123 }} {% endfor %}
-   |                                       ^^^^
-   |
-   = reason: REASON
-",
-    );
+    let template = engine.compile("lorem {{ ipsum }} dolor").unwrap();
+    let mut chunks = template.render(&engine, Value::None).into_chunks();
+    assert_eq!(chunks.next().unwrap().unwrap(), "lorem ");
+    let err = chunks.next().unwrap().unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found in this scope");
+    assert!(chunks.next().is_none());
 }
 
 #[test]
-fn render_for_statement_err_cannot_index_into_string() {
+fn render_max_output_size() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {% for ipsum, dolor in sit %} {{ ipsum.xxx }} {% endfor %}")
-        .unwrap()
-        .render(&engine, value! { sit: {t: "e", s: "t"} })
+    let template = engine.compile("lorem {{ ipsum }}").unwrap();
+    let result = template
+        .render(&engine, value! { ipsum: "dolor" })
+        .with_max_output_size(11)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem dolor");
+    let err = template
+        .render(&engine, value! { ipsum: "dolor" })
+        .with_max_output_size(10)
         .to_string()
         .unwrap_err();
-    assert_err(
-        &err,
-        "cannot index into string",
-        "
-  --> <anonymous>:1:45
-   |
- 1 | lorem {% for ipsum, dolor in sit %} {{ ipsum.xxx }} {% endfor %}
-   |                                             ^^^^
-   |
-   = reason: REASON
-",
+    assert_eq!(err.kind(), ErrorKind::MaxOutputSize);
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum output size (10 bytes)"
     );
 }
 
 #[test]
-fn render_for_statement_err_cannot_index_into_loop_field() {
+fn render_max_output_size_to_writer() {
     let engine = Engine::new();
+    let mut buf = Vec::new();
     let err = engine
-        .compile("lorem {% for ipsum in dolor %} {{ loop.first.xxx }} {% endfor %}")
+        .compile("{% for x in xs %}{{ x }}{% endfor %}")
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
-        .to_string()
+        .render(&engine, value! { xs: ["lorem", "ipsum", "dolor"] })
+        .with_max_output_size(12)
+        .to_writer(&mut buf)
         .unwrap_err();
-    assert_err(
-        &err,
-        "cannot index into bool",
-        "
-  --> <anonymous>:1:45
-   |
- 1 | lorem {% for ipsum in dolor %} {{ loop.first.xxx }} {% endfor %}
-   |                                             ^^^^
-   |
-   = reason: REASON
-",
-    );
+    assert_eq!(err.kind(), ErrorKind::MaxOutputSize);
+    assert_eq!(buf, b"loremipsum");
 }
 
-#[cfg(feature = "filters")]
 #[test]
-fn render_for_statement_filtered_map() {
+fn render_max_output_size_engine_and_options() {
     let mut engine = Engine::new();
-    engine.add_filter("rm", |mut map: BTreeMap<String, Value>, key: &str| {
-        map.remove(key);
-        map
-    });
-    let result = engine
-        .compile(r#"lorem {% for ipsum, dolor in sit | rm: "d" %}{{ ipsum }},{{ dolor.0 }} {% endfor %}"#)
-        .unwrap()
-        .render(&engine, value!{ sit: { a: ["t"], b: ["e"], c: ["s"], d: ["t"] } }).to_string().unwrap();
-    assert_eq!(result, "lorem a,t b,e c,s ");
+    engine.set_max_output_size(4);
+    engine.add_template("nested", "ipsum").unwrap();
+    let template = engine.compile(r#"{% include "nested" %}"#).unwrap();
+    let err = template
+        .render(&engine, Value::None)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MaxOutputSize);
+    let options = RenderOptions::new().with_max_output_size(5);
+    let result = template
+        .render(&engine, Value::None)
+        .with_options(&options)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "ipsum");
 }
 
 #[test]
-fn render_for_statement_nested_borrowed_list() {
+fn render_max_output_size_formatter_ignores_error() {
     let mut engine = Engine::new();
-    engine.add_template("nested", "lorem {{ ipsum }} ").unwrap();
-    let result = engine
-        .compile(r#"lorem {% for ipsum in dolor %}{% include "nested" %}{% endfor %}"#)
+    engine.add_formatter("lossy", |f, v| {
+        let _ = write!(f, "{v:?}");
+        Ok(())
+    });
+    let err = engine
+        .compile("{{ lorem | lossy }}")
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, value! { lorem: "ipsum" })
+        .with_max_output_size(2)
         .to_string()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MaxOutputSize);
+}
+
+#[test]
+fn render_max_output_size_into_chunks() {
+    let engine = Engine::new();
+    let template = engine
+        .compile("{% for x in xs %}{{ x }}{% endfor %}")
         .unwrap();
-    assert_eq!(result, "lorem lorem t lorem e lorem s lorem t ");
+    let mut chunks = template
+        .render(&engine, value! { xs: ["lorem", "ipsum", "dolor"] })
+        .with_max_output_size(12)
+        .into_chunks();
+    assert_eq!(chunks.next().unwrap().unwrap(), "lorem");
+    assert_eq!(chunks.next().unwrap().unwrap(), "ipsum");
+    let err = chunks.next().unwrap().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "render error: reached maximum output size (12 bytes)"
+    );
+    assert!(chunks.next().is_none());
+}
+
+#[cfg(feature = "filters")]
+#[derive(Default)]
+struct Events(Vec<String>);
+
+#[cfg(feature = "filters")]
+impl upon::Profiler for Events {
+    fn filter(&mut self, name: &str, _: std::time::Duration) {
+        self.0.push(format!("filter {name}"));
+    }
+
+    fn include(&mut self, name: &str, _: std::time::Duration) {
+        self.0.push(format!("include {name}"));
+    }
 }
 
 #[cfg(feature = "filters")]
 #[test]
-fn render_for_statement_nested_owned_list() {
+fn render_profiler() {
     let mut engine = Engine::new();
-    engine.add_filter("to_owned", Value::to_owned);
-    engine.add_template("nested", "lorem {{ ipsum }} ").unwrap();
+    engine.add_filter("upper", str::to_uppercase);
+    engine.add_filter("len", |s: &str| s.len() as i64);
+    engine.add_template("nested", "{{ sit | upper }}").unwrap();
+    let mut events = Events::default();
     let result = engine
-        .compile(r#"lorem {% for ipsum in dolor | to_owned %}{% include "nested" %}{% endfor %}"#)
+        .compile(r#"{{ ipsum | upper }} {% if ipsum | len > 1 %}{% include "nested" with dolor %}{% endif %}"#)
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, value! { ipsum: "lorem", dolor: { sit: "amet" } })
+        .with_profiler(&mut events)
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem lorem t lorem e lorem s lorem t ");
+    assert_eq!(result, "LOREM AMET");
+    assert_eq!(
+        events.0,
+        [
+            "filter upper",
+            "filter len",
+            "include nested",
+            "filter upper"
+        ]
+    );
 }
 
+#[cfg(feature = "filters")]
 #[test]
-fn render_for_statement_nested_borrowed_map() {
+fn render_profiler_filter_err() {
     let mut engine = Engine::new();
-    engine
-        .add_template("nested", "lorem {{ ipsum }} {{ dolor }} ")
-        .unwrap();
-    let result = engine
-        .compile(r#"lorem {% for ipsum, dolor in sit %}{% include "nested" %}{% endfor %}"#)
+    engine.add_filter("fail", |_: Value| Err::<Value, _>("oops"));
+    let mut events = Events::default();
+    let err = engine
+        .compile("{{ ipsum | fail }}")
         .unwrap()
-        .render(&engine, value! { sit: { a: "t", b: "e", c: "s", d: "t" } })
+        .render(&engine, value! { ipsum: 1 })
+        .with_profiler(&mut events)
         .to_string()
-        .unwrap();
-    assert_eq!(result, "lorem lorem a t lorem b e lorem c s lorem d t ");
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Filter);
+    assert_eq!(events.0, ["filter fail"]);
 }
 
-#[cfg(feature = "filters")]
 #[test]
-fn render_for_statement_nested_owned_map() {
-    let mut engine = Engine::new();
-    engine.add_filter("to_owned", Value::to_owned);
-    engine
-        .add_template("nested", "lorem {{ ipsum }} {{ dolor }} ")
-        .unwrap();
-    let result = engine
-        .compile(
-            r#"lorem {% for ipsum, dolor in sit | to_owned %}{% include "nested" %}{% endfor %}"#,
-        )
-        .unwrap()
-        .render(&engine, value! { sit: { a: "t", b: "e", c: "s", d: "t" } })
+fn render_cancel() {
+    let engine = Engine::new();
+    let template = engine.compile("lorem {{ ipsum }}").unwrap();
+    let cancel = AtomicBool::new(false);
+    let result = template
+        .render(&engine, value! { ipsum: "dolor" })
+        .with_cancel(&cancel)
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem lorem a t lorem b e lorem c s lorem d t ");
+    assert_eq!(result, "lorem dolor");
+    cancel.store(true, Ordering::Relaxed);
+    let err = template
+        .render(&engine, value! { ipsum: "dolor" })
+        .with_cancel(&cancel)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Cancelled);
+    assert_eq!(err.to_string(), "render error: rendering was cancelled");
 }
 
 #[test]
-fn render_for_statement_nested_loop_fields() {
+fn render_cancel_while_rendering() {
+    let cancel = Arc::new(AtomicBool::new(false));
     let mut engine = Engine::new();
+    let flag = cancel.clone();
+    engine.add_formatter("stop", move |f, v| {
+        if *v == Value::Integer(2) {
+            flag.store(true, Ordering::Relaxed);
+        }
+        fmt::default(f, v)
+    });
     engine
         .add_template(
             "nested",
-            "{{ loop.index }},{{ loop.first }},{{ loop.last }},{{ ipsum }}",
+            "{% for i in 0..1000000000 %}{{ i | stop }}{% endfor %}",
         )
         .unwrap();
-    let result = engine
-        .compile(r#"lorem {% for ipsum in dolor %}{% include "nested" %} {% endfor %}"#)
+    let template = engine.compile(r#"lorem {% include "nested" %}"#).unwrap();
+
+    let mut buf = Vec::new();
+    let err = template
+        .render(&engine, Value::None)
+        .with_cancel(&cancel)
+        .to_writer(&mut buf)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Cancelled);
+    assert_eq!(err.template_name(), Some("nested"));
+    assert_eq!(buf, b"lorem 012");
+
+    cancel.store(false, Ordering::Relaxed);
+    let chunks: Vec<_> = template
+        .render(&engine, Value::None)
+        .with_cancel(&cancel)
+        .into_chunks()
+        .collect();
+    assert_eq!(chunks.len(), 5);
+    assert_eq!(chunks[3].as_deref().unwrap(), "2");
+    assert_eq!(chunks[4].as_ref().unwrap_err().kind(), ErrorKind::Cancelled);
+}
+
+#[test]
+fn render_include_with_statement_inside_with_statement() {
+    let mut engine = Engine::new();
+    engine.add_template("nested", "").unwrap();
+    engine
+        .compile(r#"{% with false as x %} {% include "nested" with false %} {% endwith %}"#)
         .unwrap()
-        .render(&engine, value! { dolor: ["t", "e", "s", "t"] })
+        .render(&engine, Value::None)
         .to_string()
         .unwrap();
-    assert_eq!(
-        result,
-        "lorem 0,true,false,t 1,false,false,e 2,false,false,s 3,false,true,t "
-    );
 }
 
 #[test]
-fn render_for_statement_err_not_iterable() {
+fn render_to_writer() {
     let engine = Engine::new();
-    let err = engine
-        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}")
+    let mut w = Writer::new();
+    engine
+        .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { dolor: true })
-        .to_string()
-        .unwrap_err();
-    assert_err(
-        &err,
-        "expected iterable, but expression evaluated to bool",
-        "
-  --> <anonymous>:1:23
-   |
- 1 | lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}
-   |                       ^^^^^
-   |
-   = reason: REASON
-",
-    );
+        .render(&engine, value! { ipsum : "test" })
+        .to_writer(&mut w)
+        .unwrap();
+    assert_eq!(w.into_string(), "lorem test");
 }
 
 #[test]
-fn render_for_statement_err_list_with_two_vars() {
+fn render_to_writer_err_io() {
     let engine = Engine::new();
+    let mut w = Writer::with_max(1);
     let err = engine
-        .compile("lorem {% for _, ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { dolor: ["sit", "amet"] })
-        .to_string()
+        .render(&engine, value! { ipsum : "test" })
+        .to_writer(&mut w)
         .unwrap_err();
-    assert_err(
-        &err,
-        "cannot unpack list item into two variables",
-        "
-  --> <anonymous>:1:14
-   |
- 1 | lorem {% for _, ipsum in dolor %}{{ ipsum }}{% endfor %}
-   |              ^^^^^^^^
-   |
-   = reason: REASON
-",
-    );
+    assert_eq!(format!("{err:#}"), "io error");
+    assert_eq!(format!("{:#}", err.source().unwrap()), "address in use");
 }
 
 #[test]
-fn render_for_statement_err_map_with_one_var() {
+fn render_to_writer_err_io_kind() {
     let engine = Engine::new();
+    let mut w = Writer::with_max(1);
     let err = engine
-        .compile("lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}")
+        .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { dolor: { sit: "amet" }})
-        .to_string()
+        .render(&engine, value! { ipsum : "test" })
+        .to_writer(&mut w)
         .unwrap_err();
-    assert_err(
-        &err,
-        "cannot unpack map item into one variable",
-        "
-  --> <anonymous>:1:14
-   |
- 1 | lorem {% for ipsum in dolor %}{{ ipsum }}{% endfor %}
-   |              ^^^^^
-   |
-   = reason: REASON
-",
-    );
+    assert_eq!(err.kind(), ErrorKind::Io);
+    assert_eq!(err.span(), None);
 }
 
 #[test]
-fn render_for_statement_err_loop_var_scope() {
+fn render_to_writer_err_not_io() {
     let engine = Engine::new();
+    let mut w = Writer::with_max(1);
     let err = engine
-        .compile("lorem {% for _, ipsum in dolor %}{% endfor %}{{ ipsum }}")
+        .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { dolor: { ipsum: false }})
-        .to_string()
+        .render(&engine, value! { dolor : "test" })
+        .to_writer(&mut w)
         .unwrap_err();
     assert_err(
         &err,
         "not found in this scope",
         "
-  --> <anonymous>:1:49
+  --> <anonymous>:1:10
    |
- 1 | lorem {% for _, ipsum in dolor %}{% endfor %}{{ ipsum }}
-   |                                                 ^^^^^
+ 1 | lorem {{ ipsum }}
+   |          ^^^^^
    |
    = reason: REASON
 ",
@@ -1047,252 +4304,341 @@ fn render_for_statement_err_loop_var_scope() {
 }
 
 #[test]
-fn render_with_statement() {
+fn render_to_fmt_writer() {
+    let engine = Engine::new();
+    let mut w = Writer::new();
+    engine
+        .compile("lorem {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum : "test" })
+        .to_fmt_writer(&mut w)
+        .unwrap();
+    assert_eq!(w.into_string(), "lorem test");
+}
+
+#[test]
+fn render_to_fmt_writer_trim_trailing_newline() {
     let engine = Engine::new();
-    let result = engine
-        .compile("lorem {% with ipsum as dolor %}{{ dolor }}{% endwith %} sit")
+    let mut buf = String::from("dolor\n");
+    engine
+        .compile("lorem {{ ipsum }}\n")
         .unwrap()
-        .render(&engine, value! { ipsum: "test", dolor: false })
-        .to_string()
+        .render(&engine, value! { ipsum : "test" })
+        .trim_trailing_newline()
+        .to_fmt_writer(&mut buf)
         .unwrap();
-    assert_eq!(result, "lorem test sit")
+    assert_eq!(buf, "dolor\nlorem test");
 }
 
 #[test]
-fn render_with_statement_err_var_scope() {
+fn render_to_fmt_writer_err_fmt() {
+    let engine = Engine::new();
+    let mut w = Writer::with_max(1);
+    let err = engine
+        .compile("lorem {{ ipsum }}")
+        .unwrap()
+        .render(&engine, value! { ipsum : "test" })
+        .to_fmt_writer(&mut w)
+        .unwrap_err();
+    assert_eq!(format!("{err:#}"), "format error");
+    assert_eq!(err.kind(), ErrorKind::Format);
+    assert_eq!(err.span(), None);
+}
+
+#[test]
+fn render_to_fmt_writer_err_not_fmt() {
     let engine = Engine::new();
+    let mut w = Writer::with_max(1);
     let err = engine
-        .compile("lorem {% with ipsum as dolor %}{{ dolor }}{% endwith %}{{ dolor }}")
+        .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { ipsum: "test" })
-        .to_string()
+        .render(&engine, value! { dolor : "test" })
+        .to_fmt_writer(&mut w)
         .unwrap_err();
     assert_err(
         &err,
         "not found in this scope",
         "
-  --> <anonymous>:1:59
+  --> <anonymous>:1:10
    |
- 1 | lorem {% with ipsum as dolor %}{{ dolor }}{% endwith %}{{ dolor }}
-   |                                                           ^^^^^
+ 1 | lorem {{ ipsum }}
+   |          ^^^^^
    |
    = reason: REASON
 ",
     );
 }
 
+#[track_caller]
+fn assert_format_err(err: &Error, reason: &str, pretty: &str) {
+    let display = format!("format error: {reason}");
+    let display_alt = format!("format error\n{}", pretty.replace("REASON", reason));
+    assert_eq!(err.to_string(), display);
+    assert_eq!(format!("{err:#}"), display_alt);
+}
+
+#[track_caller]
+fn assert_err(err: &Error, reason: &str, pretty: &str) {
+    let display = format!("render error: {reason}");
+    let display_alt = format!("render error\n{}", pretty.replace("REASON", reason));
+    assert_eq!(err.to_string(), display);
+    assert_eq!(format!("{err:#}"), display_alt);
+}
+
 #[test]
-fn render_include_statement() {
+fn render_whitespace_trace() {
+    let engine = Engine::new();
+    let tests = [
+        ("lorem {{ ipsum }} dolor", "lorem ipsum dolor"),
+        ("lorem {{- ipsum -}} dolor", "lorem« »ipsum« »dolor"),
+        ("lorem \t{{- ipsum }}\n", "lorem« \\t»ipsum\n"),
+        ("{{ ipsum -}}  \n  {{- ipsum }}", "ipsum«  \\n  »ipsum"),
+        ("lorem\n{#- comment -#}\ndolor", "lorem«\\n»«\\n»dolor"),
+        ("{% if true -%}\n  lorem\n{%- endif %}", "«\\n  »lorem«\\n»"),
+    ];
+    for (source, exp) in tests {
+        let result = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, value! { ipsum: "ipsum" })
+            .with_whitespace_trace()
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "source: {source:?}");
+    }
+}
+
+#[test]
+fn render_whitespace_trace_include() {
     let mut engine = Engine::new();
-    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+    engine.add_template("nested", "\n  {{- lorem }}").unwrap();
     let result = engine
-        .compile(r#"lorem {% include "nested" %} sit"#)
+        .compile("{% include \"nested\" -%}\n")
         .unwrap()
-        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .render(&engine, value! { lorem: "ipsum" })
+        .with_whitespace_trace()
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem test sit");
+    assert_eq!(result, "«\\n  »ipsum«\\n»");
 }
 
 #[test]
-fn render_include_with_statement() {
+fn render_template_vars() {
     let mut engine = Engine::new();
-    engine.add_template("nested", "{{ dolor }}").unwrap();
+    engine
+        .add_template(
+            "nested",
+            "{{ __template__ }}:{% for name in __template_stack__ %}{{ name }},{% endfor %}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "base",
+            "{{ __template__ }} {% block body %}{% endblock %} {{ __template__ }}",
+        )
+        .unwrap();
+    engine
+        .add_template(
+            "main",
+            r#"{% extends "base" %}{% block body %}{{ __template__ }} {% include "nested" with lorem %}{% endblock %}"#,
+        )
+        .unwrap();
     let result = engine
-        .compile(r#"lorem {% include "nested" with ipsum %} sit"#)
-        .unwrap()
-        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .template("main")
+        .render(value! { lorem: {} })
+        .with_template_vars()
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem test sit");
+    assert_eq!(result, "base main nested:base,main,nested, base");
 }
 
-#[cfg(feature = "filters")]
 #[test]
-fn render_include_with_statement_owned() {
-    let mut engine = Engine::new();
-    engine.add_filter("to_owned", Value::to_owned);
-    engine.add_template("nested", "{{ dolor }}").unwrap();
+fn render_template_vars_anonymous() {
+    let engine = Engine::new();
     let result = engine
-        .compile(r#"lorem {% include "nested" with ipsum | to_owned %} sit"#)
+        .compile("{% if __template__ is none and __template_stack__.0 is none %}ok{% endif %}")
         .unwrap()
-        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .render(&engine, Value::None)
+        .with_template_vars()
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem test sit");
+    assert_eq!(result, "ok");
 }
 
 #[test]
-fn render_include_statement_parent_template_scope() {
+fn render_template_vars_disabled() {
     let mut engine = Engine::new();
-    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+    engine.add_template("main", "{{ __template__ }}").unwrap();
     let result = engine
-        .compile(r#"lorem {% include "nested" %} sit"#)
-        .unwrap()
-        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .template("main")
+        .render(value! { __template__: "lorem" })
         .to_string()
         .unwrap();
-    assert_eq!(result, "lorem test sit");
+    assert_eq!(result, "lorem");
 }
 
 #[test]
-fn render_include_statement_err_parent_template_scope() {
-    let mut engine = Engine::new();
-    engine.add_template("nested", "{{ ipsum.dolor }}").unwrap();
+fn render_for_statement_range() {
+    let engine = Engine::new();
+    let tests = [
+        ("{% for i in 0..4 %}{{ i }}{% endfor %}", "0123"),
+        ("{% for i in 1..=3 %}{{ i }}{% endfor %}", "123"),
+        ("{% for i in -2..n %}{{ i }},{% endfor %}", "-2,-1,0,1,2,"),
+        ("{% for i in m..=n %}{{ i }}{% endfor %}", "123"),
+        ("{% for i in 3..3 %}{{ i }}{% endfor %}", ""),
+        ("{% for i in 3..=3 %}{{ i }}{% endfor %}", "3"),
+        ("{% for i in 5..1 %}{{ i }}{% endfor %}", ""),
+        ("{% for i in 5..=1 %}{{ i }}{% endfor %}", ""),
+        ("{% for i in 0..4 reversed %}{{ i }}{% endfor %}", "3210"),
+        ("{% for i in m..=n reversed %}{{ i }}{% endfor %}", "321"),
+        ("{% for i in 5..1 reversed %}{{ i }}{% endfor %}", ""),
+        (
+            "{% for i in 10..13 reversed %}{{ i }}{{ loop.index }}{{ loop.length }}{{ loop.last }} {% endfor %}",
+            "1203false 1113false 1023true ",
+        ),
+        (
+            "{% for i in 10..13 %}{{ loop.index }}{{ loop.index1 }}{{ loop.length }}{{ loop.first }}{{ loop.last }} {% endfor %}",
+            "013truefalse 123falsefalse 233falsetrue ",
+        ),
+        (
+            "{% for i in 0..10 %}{% if i == 3 %}{% break %}{% endif %}{{ i }}{% endfor %}",
+            "012",
+        ),
+    ];
+    for (source, exp) in tests {
+        let result = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, value! { m: 1, n: 3 })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "source: {source}");
+    }
+}
+
+#[test]
+fn render_for_statement_range_loop_length_extremes() {
+    let engine = Engine::new();
+    let tests = [
+        ("{% for i in min..=max %}", "none,false"),
+        ("{% for i in 0..=max %}", "none,false"),
+        ("{% for i in 0..max %}", "9223372036854775807,false"),
+        ("{% for i in min..0 %}", "none,false"),
+        ("{% for i in max..=max %}", "1,true"),
+        ("{% for i in min..=max reversed %}", "none,false"),
+    ];
+    for (source, exp) in tests {
+        let source = format!(
+            r#"{source}{{{{ loop.length ?? "none" }}}},{{{{ loop.last }}}}{{% break %}}{{% endfor %}}"#
+        );
+        let result = engine
+            .compile(&source)
+            .unwrap()
+            .render(&engine, value! { min: i64::MIN, max: i64::MAX })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "source: {source}");
+    }
+}
+
+#[test]
+fn render_for_statement_range_err_not_integer() {
+    let engine = Engine::new();
     let err = engine
-        .compile(r#"lorem {% include "nested" with ipsum %} sit"#)
+        .compile("{% for i in 0..n %}{{ i }}{% endfor %}")
         .unwrap()
-        .render(&engine, value! { ipsum: { dolor: "test" }})
+        .render(&engine, value! { n: "3" })
         .to_string()
         .unwrap_err();
     assert_err(
         &err,
-        "not found in this scope",
-        r#"
-  --> nested:1:4
+        "expected integer, but expression evaluated to string",
+        "
+  --> <anonymous>:1:16
    |
- 1 | {{ ipsum.dolor }}
-   |    ^^^^^
+ 1 | {% for i in 0..n %}{{ i }}{% endfor %}
+   |                ^--
    |
    = reason: REASON
-"#,
+",
     );
 }
 
 #[test]
-fn render_include_statement_err_unknown_template() {
+fn render_for_statement_range_err_two_vars() {
     let engine = Engine::new();
     let err = engine
-        .compile(r#"lorem {% include "nested" %} sit"#)
+        .compile("{% for i, j in 0..3 %}{{ i }}{% endfor %}")
         .unwrap()
         .render(&engine, Value::None)
         .to_string()
         .unwrap_err();
     assert_err(
         &err,
-        "unknown template",
-        r#"
-  --> <anonymous>:1:18
+        "cannot unpack range item into two variables",
+        "
+  --> <anonymous>:1:8
    |
- 1 | lorem {% include "nested" %} sit
-   |                  ^^^^^^^^
+ 1 | {% for i, j in 0..3 %}{{ i }}{% endfor %}
+   |        ^^^^
    |
    = reason: REASON
-"#,
-    );
-}
-
-#[test]
-fn render_include_statement_err_max_include_depth() {
-    let mut engine = Engine::new();
-    engine
-        .add_template("cycle", r#"{% include "cycle" %}"#)
-        .unwrap();
-    let err = engine
-        .template("cycle")
-        .render(Value::None)
-        .to_string()
-        .unwrap_err();
-    assert_eq!(
-        err.to_string(),
-        "render error: reached maximum include depth (64)"
+",
     );
 }
 
 #[test]
-fn render_include_statement_err_max_include_depth_renderer() {
-    let mut engine = Engine::new();
-    engine.set_max_include_depth(128);
-    engine
-        .add_template("cycle", r#"{% include "cycle" %}"#)
-        .unwrap();
+fn render_for_statement_range_err_many_vars() {
+    let engine = Engine::new();
     let err = engine
-        .template("cycle")
-        .render(Value::None)
-        .with_max_include_depth(4)
+        .compile("{% for i, j, k in 0..3 %}{{ i }}{% endfor %}")
+        .unwrap()
+        .render(&engine, Value::None)
         .to_string()
         .unwrap_err();
-    assert_eq!(
-        err.to_string(),
-        "render error: reached maximum include depth (4)"
+    assert_err(
+        &err,
+        "cannot unpack range item into 3 variables",
+        "
+  --> <anonymous>:1:8
+   |
+ 1 | {% for i, j, k in 0..3 %}{{ i }}{% endfor %}
+   |        ^^^^^^^
+   |
+   = reason: REASON
+",
     );
 }
 
 #[test]
-fn render_include_with_statement_inside_with_statement() {
-    let mut engine = Engine::new();
-    engine.add_template("nested", "").unwrap();
-    engine
-        .compile(r#"{% with false as x %} {% include "nested" with false %} {% endwith %}"#)
-        .unwrap()
-        .render(&engine, Value::None)
-        .to_string()
-        .unwrap();
-}
+fn render_value_serialized() {
+    #[derive(serde::Serialize)]
+    struct Ctx {
+        ipsum: &'static str,
+    }
 
-#[test]
-fn render_to_writer() {
     let engine = Engine::new();
-    let mut w = Writer::new();
-    engine
+    let ctx = Ctx { ipsum: "dolor" };
+    let result = engine
         .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { ipsum : "test" })
-        .to_writer(&mut w)
+        .render_value(&engine, upon::Serialized(&ctx))
+        .to_string()
         .unwrap();
-    assert_eq!(w.into_string(), "lorem test");
-}
-
-#[test]
-fn render_to_writer_err_io() {
-    let engine = Engine::new();
-    let mut w = Writer::with_max(1);
-    let err = engine
-        .compile("lorem {{ ipsum }}")
-        .unwrap()
-        .render(&engine, value! { ipsum : "test" })
-        .to_writer(&mut w)
-        .unwrap_err();
-    assert_eq!(format!("{err:#}"), "io error");
-    assert_eq!(format!("{:#}", err.source().unwrap()), "address in use");
+    assert_eq!(result, "lorem dolor");
 }
 
 #[test]
-fn render_to_writer_err_not_io() {
+fn render_value_serialized_err() {
     let engine = Engine::new();
-    let mut w = Writer::with_max(1);
     let err = engine
         .compile("lorem {{ ipsum }}")
         .unwrap()
-        .render(&engine, value! { dolor : "test" })
-        .to_writer(&mut w)
+        .render_value(
+            &engine,
+            upon::Serialized(std::collections::BTreeMap::from([((1, 2), "ipsum")])),
+        )
+        .to_string()
         .unwrap_err();
-    assert_err(
-        &err,
-        "not found in this scope",
-        "
-  --> <anonymous>:1:10
-   |
- 1 | lorem {{ ipsum }}
-   |          ^^^^^
-   |
-   = reason: REASON
-",
-    );
-}
-
-#[track_caller]
-fn assert_format_err(err: &Error, reason: &str, pretty: &str) {
-    let display = format!("format error: {reason}");
-    let display_alt = format!("format error\n{}", pretty.replace("REASON", reason));
-    assert_eq!(err.to_string(), display);
-    assert_eq!(format!("{err:#}"), display_alt);
-}
-
-#[track_caller]
-fn assert_err(err: &Error, reason: &str, pretty: &str) {
-    let display = format!("render error: {reason}");
-    let display_alt = format!("render error\n{}", pretty.replace("REASON", reason));
-    assert_eq!(err.to_string(), display);
-    assert_eq!(format!("{err:#}"), display_alt);
+    assert_eq!(err.to_string(), "serialize error: map key must be a string");
 }
@@ -1,6 +1,9 @@
 mod helpers;
 
-use upon::{Engine, Value, ValueAccess, ValueAccessOp, ValueMember};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use upon::{Engine, Value, ValueAccess, ValueAccessOp, ValueFnError, ValueMember};
 
 use crate::helpers::Writer;
 
@@ -39,6 +42,53 @@ fn render_from_ref() {
     assert_eq!(result, "lorem test");
 }
 
+#[test]
+fn render_value_owned() {
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"lorem {{ ipsum.dolor }}"#)
+        .unwrap()
+        .render_value(
+            &engine,
+            Value::from([("ipsum", Value::from([("dolor", "test")]))]),
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test");
+}
+
+#[test]
+fn render_value_borrowed() {
+    let mut engine = Engine::new();
+    engine.add_template("t1", "lorem {{ ipsum }}").unwrap();
+    engine.add_template("t2", "dolor {{ ipsum }}").unwrap();
+    let ctx = Value::from([("ipsum", "test")]);
+    let mut results = Vec::new();
+    for name in ["t1", "t2"] {
+        let result = engine
+            .template(name)
+            .render_value(&ctx)
+            .to_string()
+            .unwrap();
+        results.push(result);
+    }
+    assert_eq!(results, ["lorem test", "dolor test"]);
+}
+
+#[test]
+fn render_value_into_value() {
+    let engine = Engine::new();
+    let mut ctx = BTreeMap::new();
+    ctx.insert("ipsum", "test");
+    let result = engine
+        .compile(r#"lorem {{ ipsum }}"#)
+        .unwrap()
+        .render_value(&engine, ctx)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test");
+}
+
 #[test]
 fn render_to_writer_from() {
     let engine = Engine::new();
@@ -77,6 +127,54 @@ fn render_with_value_fn() {
     assert_eq!(err.to_string(), "render error: not found");
 }
 
+#[test]
+fn render_with_value_fn_into_chunks() {
+    let engine = Engine::new();
+    let template = engine.compile(r#"lorem {{ ipsum.dolor }}"#).unwrap();
+    let chunks: Vec<_> = template
+        .render_from_fn(&engine, test_value_fn)
+        .into_chunks()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["lorem ", "test"]);
+}
+
+#[test]
+fn render_with_value_fn_with_globals() {
+    let engine = Engine::new();
+    let globals = Value::from([
+        ("ipsum", Value::from([("dolor", "x")])),
+        ("sit", Value::from("y")),
+    ]);
+    let template = engine
+        .compile(r#"lorem {{ ipsum.dolor }} {{ sit }}"#)
+        .unwrap();
+
+    let result = template
+        .render_from_fn(&engine, test_value_fn)
+        .with_globals(&globals)
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test y");
+
+    let chunks: Vec<_> = template
+        .render_from_fn(&engine, test_value_fn)
+        .with_globals(&globals)
+        .into_chunks()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["lorem ", "test", " ", "y"]);
+
+    let err = engine
+        .compile(r#"lorem {{ amet }}"#)
+        .unwrap()
+        .render_from_fn(&engine, test_value_fn)
+        .with_globals(&globals)
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found");
+}
+
 #[test]
 fn render_with_value_fn_optional_access() {
     let engine = Engine::new();
@@ -122,6 +220,119 @@ fn render_to_writer_with_value_fn() {
     assert_eq!(err.to_string(), "render error: not found");
 }
 
+#[test]
+fn render_with_value_fn_mut() {
+    let engine = Engine::new();
+    let template = engine
+        .compile(r#"{{ ipsum }} {{ ipsum }} {{ dolor }}"#)
+        .unwrap();
+
+    let mut calls = 0;
+    let mut cache = BTreeMap::new();
+    let result = template
+        .render_from_fn(&engine, |path: &[ValueMember<'_>]| {
+            let key = match path[0].access {
+                ValueAccess::Key(key) => key.to_owned(),
+                _ => return Err("expected key"),
+            };
+            let value = cache.entry(key).or_insert_with_key(|key| {
+                calls += 1;
+                Value::from(key.to_uppercase())
+            });
+            Ok(value.clone())
+        })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "IPSUM IPSUM DOLOR");
+    assert_eq!(calls, 2);
+}
+
+#[test]
+fn render_with_value_fn_err_at_member() {
+    let engine = Engine::new();
+    let template = engine.compile(r#"lorem {{ ipsum.dolor.sit }}"#).unwrap();
+
+    let err = template
+        .render_from_fn(&engine, |_: &[ValueMember<'_>]| {
+            Err::<Value, _>(ValueFnError::at_member(1, "not found"))
+        })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found");
+    assert_eq!(err.span(), Some(14..20));
+
+    let err = template
+        .render_from_fn(&engine, |_: &[ValueMember<'_>]| {
+            Err::<Value, _>(ValueFnError::at_member(3, "not found"))
+        })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.span(), Some(9..24));
+
+    let err = template
+        .render_from_fn(&engine, |_: &[ValueMember<'_>]| {
+            Err::<Value, _>(ValueFnError::new("not found"))
+        })
+        .into_chunks()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_err();
+    assert_eq!(err.span(), Some(9..24));
+}
+
+#[test]
+fn render_with_cow_value_fn() {
+    let store = BTreeMap::from([
+        ("ipsum", Value::String(String::from("test"))),
+        ("dolor", Value::from([1, 2, 3])),
+    ]);
+    let engine = Engine::new();
+    let result = engine
+        .compile(r#"lorem {{ ipsum }} {% for x in dolor %}{{ x }}{% endfor %}{{ sit.amet }}"#)
+        .unwrap()
+        .render_from_cow_fn(&engine, |path| test_cow_value_fn(&store, path))
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test 123");
+
+    let err = engine
+        .compile(r#"lorem {{ sit }}"#)
+        .unwrap()
+        .render_from_cow_fn(&engine, |path| test_cow_value_fn(&store, path))
+        .to_string()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "render error: not found");
+}
+
+#[test]
+fn render_template_ref_with_cow_value_fn() {
+    let store = Value::String(String::from("test"));
+    let mut engine = Engine::new();
+    engine.add_template("lorem", "lorem {{ ipsum }}").unwrap();
+    let result = engine
+        .template("lorem")
+        .render_from_cow_fn(|_| Ok::<_, String>(Cow::Borrowed(&store)))
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "lorem test");
+}
+
+// a test value function that borrows top level values from the store
+fn test_cow_value_fn<'a>(
+    store: &'a BTreeMap<&str, Value>,
+    path: &[ValueMember<'_>],
+) -> Result<Cow<'a, Value>, String> {
+    match path {
+        [ValueMember {
+            access: ValueAccess::Key(k),
+            ..
+        }] => store
+            .get(*k)
+            .map(Cow::Borrowed)
+            .ok_or_else(|| String::from("not found")),
+        _ => Ok(Cow::Owned(Value::None)),
+    }
+}
+
 // a test value function that returns "test" for `ipsum.dolor`
 fn test_value_fn(path: &[ValueMember<'_>]) -> Result<Value, String> {
     let mut prev_access_op = ValueAccessOp::Direct;
@@ -0,0 +1,586 @@
+#![cfg(feature = "stdlib")]
+#![cfg(feature = "serde")]
+
+use upon::filters::stdlib;
+use upon::{value, Engine, Value};
+
+#[test]
+fn render_filter_slugify() {
+    let mut engine = Engine::new();
+    engine.add_filter("slugify", stdlib::slugify);
+    let result = engine
+        .compile("/posts/{{ title | slugify }}")
+        .unwrap()
+        .render(&engine, value! { title: "Hello, World! It's 2024" })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "/posts/hello-world-it-s-2024");
+}
+
+#[test]
+fn slugify() {
+    let tests = [
+        ("", ""),
+        ("lorem", "lorem"),
+        ("Lorem Ipsum", "lorem-ipsum"),
+        ("lorem   ipsum\t\ndolor", "lorem-ipsum-dolor"),
+        ("lorem-ipsum", "lorem-ipsum"),
+        ("lorem--ipsum", "lorem-ipsum"),
+        ("lorem_ipsum.dolor", "lorem-ipsum-dolor"),
+        ("  lorem ipsum  ", "lorem-ipsum"),
+        ("--lorem--", "lorem"),
+        ("!?", ""),
+        ("lorem 🚀 ipsum", "lorem-ipsum"),
+        ("lorem🚀ipsum", "lorem-ipsum"),
+        ("ÀÉÎ õü", "àéî-õü"),
+        ("foo—bar", "foo-bar"),
+        ("l’été", "l-été"),
+        ("lorem\u{a0}ipsum«dolor»", "lorem-ipsum-dolor"),
+    ];
+    for (input, exp) in tests {
+        assert_eq!(stdlib::slugify(input), exp, "input: {input:?}");
+    }
+}
+
+#[test]
+fn render_filter_indent_dedent() {
+    let mut engine = Engine::new();
+    engine.add_filter("indent", stdlib::indent);
+    engine.add_filter("dedent", stdlib::dedent);
+    let result = engine
+        .compile("fn main() {\n    {{ body | dedent | indent: 4 }}\n}")
+        .unwrap()
+        .render(
+            &engine,
+            value! { body: "  let x = 1;\n  if x {\n    y();\n  }" },
+        )
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        "fn main() {\n    let x = 1;\n    if x {\n      y();\n    }\n}"
+    );
+}
+
+#[test]
+fn render_filter_indent_err_negative() {
+    let mut engine = Engine::new();
+    engine.add_filter("indent", stdlib::indent);
+    let err = engine
+        .compile("{{ s | indent: -2 }}")
+        .unwrap()
+        .render(&engine, value! { s: "lorem" })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "filter error
+
+  --> <anonymous>:1:16
+   |
+ 1 | {{ s | indent: -2 }}
+   |                ^^-
+   |
+   = reason: expected non-negative width, found -2
+"
+    );
+}
+
+#[test]
+fn render_filter_indent_err_too_large() {
+    let mut engine = Engine::new();
+    engine.add_filter("indent", stdlib::indent);
+    let err = engine
+        .compile("{{ s | indent: 9223372036854775807 }}")
+        .unwrap()
+        .render(&engine, value! { s: "lorem" })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "filter error
+
+  --> <anonymous>:1:16
+   |
+ 1 | {{ s | indent: 9223372036854775807 }}
+   |                ^^^^^^^^^^^^^^^^^^^
+   |
+   = reason: expected width of at most 1024, found 9223372036854775807
+"
+    );
+}
+
+#[test]
+fn indent() {
+    let tests = [
+        ("", ""),
+        ("lorem", "lorem"),
+        ("lorem\nipsum", "lorem\n  ipsum"),
+        ("lorem\nipsum\n", "lorem\n  ipsum\n"),
+        ("lorem\n\nipsum", "lorem\n\n  ipsum"),
+        ("lorem\r\n\r\nipsum\r\n", "lorem\r\n\r\n  ipsum\r\n"),
+        ("lorem\n \nipsum", "lorem\n   \n  ipsum"),
+        ("\nlorem", "\n  lorem"),
+    ];
+    for (input, exp) in tests {
+        assert_eq!(stdlib::indent(input, 2).unwrap(), exp, "input: {input:?}");
+    }
+    assert_eq!(stdlib::indent("lorem\nipsum", 0).unwrap(), "lorem\nipsum");
+    assert_eq!(
+        stdlib::indent_first("lorem\n\nipsum", 1).unwrap(),
+        " lorem\n\n ipsum"
+    );
+    assert_eq!(stdlib::indent_first("", 1).unwrap(), "");
+}
+
+#[test]
+fn dedent() {
+    let tests = [
+        ("", ""),
+        ("lorem", "lorem"),
+        ("  lorem", "lorem"),
+        ("  lorem\n    ipsum\n  dolor", "lorem\n  ipsum\ndolor"),
+        ("  lorem\n ipsum", " lorem\nipsum"),
+        ("  lorem\n\n  ipsum\n", "lorem\n\nipsum\n"),
+        ("  lorem\n \n      \n  ipsum", "lorem\n\n\nipsum"),
+        ("  lorem\r\n    ipsum\r\n", "lorem\r\n  ipsum\r\n"),
+        ("\tlorem\n\t\tipsum", "lorem\n\tipsum"),
+        ("\tlorem\n  ipsum", "\tlorem\n  ipsum"),
+        ("lorem\n  ipsum", "lorem\n  ipsum"),
+        ("   ", ""),
+    ];
+    for (input, exp) in tests {
+        assert_eq!(stdlib::dedent(input), exp, "input: {input:?}");
+    }
+}
+
+#[test]
+fn render_filter_starts_with_ends_with_contains() {
+    let mut engine = Engine::new();
+    engine.add_filter("starts_with", stdlib::starts_with);
+    engine.add_filter("ends_with", stdlib::ends_with);
+    engine.add_filter("contains", stdlib::contains);
+    let template = engine
+        .compile(
+            r#"{% if path | starts_with: "/api" %}api{% endif %}
+{%- if path | ends_with: ".json" %} json{% endif %}
+{%- if not path | contains: "users" %} other{% endif %}"#,
+        )
+        .unwrap();
+    let tests = [
+        ("/api/users.json", "api json"),
+        ("/api/posts", "api other"),
+        ("/users.json", " json"),
+        ("/", " other"),
+    ];
+    for (path, exp) in tests {
+        let result = template
+            .render(&engine, value! { path: path })
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "path: {path:?}");
+    }
+}
+
+#[test]
+fn render_filter_starts_with_err_not_string() {
+    let mut engine = Engine::new();
+    engine.add_filter("starts_with", stdlib::starts_with);
+    let err = engine
+        .compile(r#"{% if path | starts_with: "/api" %}{% endif %}"#)
+        .unwrap()
+        .render(&engine, value! { path: 42 })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        r#"render error
+
+  --> <anonymous>:1:14
+   |
+ 1 | {% if path | starts_with: "/api" %}{% endif %}
+   |              ^^^^^^^^^^^
+   |
+   = reason: filter expected &str value, found integer
+"#
+    );
+}
+
+#[test]
+fn contains() {
+    let tests = [
+        ("", "", true),
+        ("lorem", "", true),
+        ("", "lorem", false),
+        ("lorem ipsum", "m i", true),
+        ("lorem ipsum", "M I", false),
+        ("Crème Brûlée", "ème", true),
+        ("Crème Brûlée", "Creme", false),
+        ("lorem 🚀 ipsum", "🚀", true),
+        ("été", "\u{e9}t", true),
+        ("e\u{301}te", "\u{e9}", false),
+    ];
+    for (s, substring, exp) in tests {
+        assert_eq!(
+            stdlib::contains(s, substring),
+            exp,
+            "s: {s:?}, substring: {substring:?}"
+        );
+    }
+}
+
+#[test]
+fn render_filter_first_last_nth_slice() {
+    let mut engine = Engine::new();
+    engine.add_filter("first", stdlib::first);
+    engine.add_filter("last", stdlib::last);
+    engine.add_filter("nth", stdlib::nth);
+    engine.add_filter("slice", stdlib::slice);
+    let result = engine
+        .compile(
+            "{{ xs | first }} {{ xs | last }} {{ xs | nth: -2 }} \
+             {% for x in xs | slice: 1, -1 %}{{ x }}{% endfor %} \
+             {{ ys | first ?? \"none\" }}",
+        )
+        .unwrap()
+        .render(&engine, value! { xs: ["a", "b", "c", "d"], ys: [] })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "a d c bc none");
+}
+
+#[test]
+fn nth() {
+    let list = [Value::from(1), Value::from(2), Value::from(3)];
+    let tests = [
+        (0, Some(Value::from(1))),
+        (2, Some(Value::from(3))),
+        (3, None),
+        (-1, Some(Value::from(3))),
+        (-3, Some(Value::from(1))),
+        (-4, None),
+        (i64::MIN, None),
+        (i64::MAX, None),
+    ];
+    for (index, exp) in tests {
+        assert_eq!(stdlib::nth(&list, index), exp, "index: {index}");
+    }
+    assert_eq!(stdlib::nth(&[], 0), None);
+}
+
+#[test]
+fn slice() {
+    let list = [
+        Value::from(1),
+        Value::from(2),
+        Value::from(3),
+        Value::from(4),
+    ];
+    let tests: [(i64, i64, &[i64]); 9] = [
+        (0, 4, &[1, 2, 3, 4]),
+        (1, 3, &[2, 3]),
+        (2, 2, &[]),
+        (3, 1, &[]),
+        (-3, -1, &[2, 3]),
+        (-100, 2, &[1, 2]),
+        (2, 100, &[3, 4]),
+        (100, 200, &[]),
+        (i64::MIN, i64::MAX, &[1, 2, 3, 4]),
+    ];
+    for (start, end, exp) in tests {
+        let exp: Vec<_> = exp.iter().map(|&i| Value::from(i)).collect();
+        assert_eq!(stdlib::slice(&list, start, end), exp, "{start}..{end}");
+    }
+    assert!(stdlib::slice(&[], 0, 1).is_empty());
+}
+
+#[test]
+fn render_filter_timestamp() {
+    let mut engine = Engine::new();
+    engine.add_filter("timestamp", stdlib::timestamp);
+    engine.add_filter("timestamp_fmt", stdlib::timestamp_fmt);
+    let result = engine
+        .compile(r#"{{ created | timestamp }} {{ created | timestamp_fmt: "date" }}"#)
+        .unwrap()
+        .render(&engine, value! { created: 1_000_000_000 })
+        .to_string()
+        .unwrap();
+    assert_eq!(result, "2001-09-09T01:46:40Z 2001-09-09");
+}
+
+#[test]
+fn render_filter_timestamp_fmt_err_unknown_format() {
+    let mut engine = Engine::new();
+    engine.add_filter("timestamp_fmt", stdlib::timestamp_fmt);
+    let err = engine
+        .compile(r#"{{ created | timestamp_fmt: "time" }}"#)
+        .unwrap()
+        .render(&engine, value! { created: 0 })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "filter error: unknown timestamp format `time`, expected `date` or `datetime`"
+    );
+}
+
+#[test]
+fn timestamp() {
+    let tests = [
+        (0, "1970-01-01T00:00:00Z"),
+        (-1, "1969-12-31T23:59:59Z"),
+        (86_399, "1970-01-01T23:59:59Z"),
+        (951_782_400, "2000-02-29T00:00:00Z"),
+        (1_700_000_000, "2023-11-14T22:13:20Z"),
+        (253_402_300_799, "9999-12-31T23:59:59Z"),
+        (-62_135_596_800, "0001-01-01T00:00:00Z"),
+    ];
+    for (secs, exp) in tests {
+        assert_eq!(stdlib::timestamp(secs), exp, "secs: {secs}");
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn render_filter_json() {
+    let mut engine = Engine::new();
+    engine.add_filter("json", stdlib::json);
+    engine.add_filter("json_fmt", stdlib::json_fmt);
+    let result = engine
+        .compile(r#"{{ config | json }}|{{ config | json_fmt: "pretty" }}"#)
+        .unwrap()
+        .render(&engine, value! { config: { debug: true, tags: ["a", 2] } })
+        .to_string()
+        .unwrap();
+    assert_eq!(
+        result,
+        r#"{"debug":true,"tags":["a",2]}|{
+  "debug": true,
+  "tags": [
+    "a",
+    2
+  ]
+}"#
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn render_filter_json_fmt_err_unknown_format() {
+    let mut engine = Engine::new();
+    engine.add_filter("json_fmt", stdlib::json_fmt);
+    let err = engine
+        .compile(r#"{{ config | json_fmt: "yaml" }}"#)
+        .unwrap()
+        .render(&engine, value! { config: {} })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "filter error: unknown json format `yaml`, expected `compact` or `pretty`"
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json() {
+    let tests = [
+        (value! { a: None }, r#"{"a":null}"#),
+        (value! { a: 1.5 }, r#"{"a":1.5}"#),
+        (value! { a: f64::NAN }, r#"{"a":null}"#),
+        (
+            value! { a: "</script><!-- & -->" },
+            r#"{"a":"\u003c/script\u003e\u003c!-- \u0026 --\u003e"}"#,
+        ),
+        (
+            value! { a: "\u{2028}\u{2029}\"\n" },
+            r#"{"a":"\u2028\u2029\"\n"}"#,
+        ),
+        (
+            Value::map().insert("<key>", 1).build(),
+            r#"{"\u003ckey\u003e":1}"#,
+        ),
+    ];
+    for (value, exp) in tests {
+        assert_eq!(stdlib::json(&value).unwrap(), exp);
+    }
+}
+
+#[test]
+fn add_stdlib_filters() {
+    let mut engine = Engine::new();
+    engine.add_stdlib_filters();
+    let tests = [
+        (r#"{{ s | upper }}"#, "LOREM IPSUM"),
+        (r#"{{ s | lower }}"#, "lorem ipsum"),
+        (r#"{{ p | trim }}"#, "dolor"),
+        (
+            r#"{{ p | indent: 2 }}|{{ p | indent_first: 1 }}|{{ p | dedent }}"#,
+            "  dolor\n|   dolor\n|dolor\n",
+        ),
+        (r#"{{ s | len }} {{ xs | len }} {{ m | len }}"#, "11 3 2"),
+        (
+            r#"{{ n | default: "none" }} {{ e | default: "none" }}"#,
+            "none ",
+        ),
+        (r#"{{ xs | join: ", " }}"#, "1, two, true"),
+        (
+            r#"{{ xs | reverse | join: "" }} {{ s | reverse }}"#,
+            "truetwo1 muspI meroL",
+        ),
+        (
+            r#"{{ xs | first }} {{ xs | last }} {{ xs | nth: 1 }} {{ xs | slice: 1, 3 | join: "" }}"#,
+            "1 true two twotrue",
+        ),
+        (r#"{{ s | replace: " ", "_" }}"#, "Lorem_Ipsum"),
+        (
+            r#"{{ s | starts_with: "Lorem" }} {{ s | ends_with: "Lorem" }} {{ s | contains: "m I" }}"#,
+            "true false true",
+        ),
+        (r#"{{ s | slugify }}"#, "lorem-ipsum"),
+        (r#"{{ t | timestamp_fmt: "date" }}"#, "2023-11-14"),
+        (
+            r#"{{ i | add: 2 }} {{ i | sub: 9 }} {{ i | mul: 3 }} {{ i | div: 2 }}"#,
+            "9 -2 21 3",
+        ),
+        (
+            r#"{{ i | add: 0.5 }} {{ f | sub: 1 }} {{ f | mul: 2 }} {{ i | div: 2.0 }}"#,
+            "7.5 0.5 3 3.5",
+        ),
+    ];
+    for (source, exp) in tests {
+        let result = engine
+            .compile(source)
+            .unwrap()
+            .render(
+                &engine,
+                value! {
+                    s: "Lorem Ipsum",
+                    p: "  dolor\n",
+                    xs: [1, "two", true],
+                    m: { a: 1, b: 2 },
+                    n: None,
+                    e: "",
+                    t: 1_700_000_000,
+                    i: 7,
+                    f: 1.5,
+                },
+            )
+            .to_string()
+            .unwrap();
+        assert_eq!(result, exp, "source: {source}");
+    }
+}
+
+#[test]
+fn add_stdlib_filters_err_len() {
+    let mut engine = Engine::new();
+    engine.add_stdlib_filters();
+    let err = engine
+        .compile("{{ n | len }}")
+        .unwrap()
+        .render(&engine, value! { n: 42 })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "filter error
+
+  --> <anonymous>:1:8
+   |
+ 1 | {{ n | len }}
+   |        ^^^
+   |
+   = reason: expected string, list, or map, found integer
+"
+    );
+}
+
+#[test]
+fn add_stdlib_filters_err_join() {
+    let mut engine = Engine::new();
+    engine.add_stdlib_filters();
+    let err = engine
+        .compile(r#"{{ xs | join: "," }}"#)
+        .unwrap()
+        .render(&engine, value! { xs: [1, [2]] })
+        .to_string()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "filter error: expression evaluated to unformattable type list"
+    );
+}
+
+#[test]
+fn add_stdlib_filters_err_arithmetic() {
+    let mut engine = Engine::new();
+    engine.add_stdlib_filters();
+    let tests = [
+        (
+            "{{ n | add: 1 }}",
+            "integer overflow while adding",
+            "{{ n | add: 1 }}",
+            "       ^^^",
+        ),
+        (
+            "{{ m | sub: 1 }}",
+            "integer overflow while subtracting",
+            "{{ m | sub: 1 }}",
+            "       ^^^",
+        ),
+        (
+            "{{ n | mul: 2 }}",
+            "integer overflow while multiplying",
+            "{{ n | mul: 2 }}",
+            "       ^^^",
+        ),
+        (
+            "{{ m | div: -1 }}",
+            "integer overflow while dividing",
+            "{{ m | div: -1 }}",
+            "       ^^^",
+        ),
+        (
+            "{{ n | div: 0 }}",
+            "division by zero",
+            "{{ n | div: 0 }}",
+            "            ^",
+        ),
+        (
+            "{{ n | div: 0.0 }}",
+            "division by zero",
+            "{{ n | div: 0.0 }}",
+            "            ^^^",
+        ),
+        (
+            "{{ s | add: 1 }}",
+            "expected integer or float, found string",
+            "{{ s | add: 1 }}",
+            "   ^",
+        ),
+        (
+            "{{ n | add: s }}",
+            "expected integer or float, found string",
+            "{{ n | add: s }}",
+            "            ^",
+        ),
+    ];
+    for (source, reason, line, marker) in tests {
+        let err = engine
+            .compile(source)
+            .unwrap()
+            .render(&engine, value! { n: i64::MAX, m: i64::MIN, s: "1" })
+            .to_string()
+            .unwrap_err();
+        let pretty = format!("{err:#}");
+        assert!(
+            pretty.contains(&format!("= reason: {reason}\n")),
+            "source: {source}\n{pretty}"
+        );
+        assert!(
+            pretty.contains(&format!(" 1 | {line}\n   | {marker}")),
+            "source: {source}\n{pretty}"
+        );
+    }
+}
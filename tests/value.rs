@@ -0,0 +1,253 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use upon::Value;
+
+fn hash(v: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    v.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn value_eq_float_nan() {
+    let a = Value::Float(f64::NAN);
+    let b = Value::Float(-f64::NAN);
+    assert_eq!(a, a);
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+    assert_ne!(a, Value::Float(0.0));
+}
+
+#[test]
+fn value_eq_float_zero() {
+    let a = Value::Float(0.0);
+    let b = Value::Float(-0.0);
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+}
+
+#[test]
+fn value_eq_different_variants() {
+    assert_ne!(Value::Integer(1), Value::Float(1.0));
+    assert_ne!(Value::None, Value::Bool(false));
+    assert_ne!(Value::from(""), Value::None);
+}
+
+#[test]
+fn value_hash_set() {
+    let values = [
+        Value::None,
+        Value::from(true),
+        Value::from(1),
+        Value::from(1.5),
+        Value::from("lorem"),
+        Value::from(["lorem", "ipsum"]),
+        Value::from([("lorem", 1)]),
+    ];
+    let mut set = HashSet::new();
+    for v in values.iter().chain(values.iter()) {
+        set.insert(v.clone());
+    }
+    assert_eq!(set.len(), values.len());
+    for v in &values {
+        assert!(set.contains(v));
+    }
+}
+
+#[test]
+fn value_hash_structural() {
+    let a = Value::from([("lorem", Value::from([1, 2])), ("ipsum", Value::from(0.0))]);
+    let b = Value::from([("ipsum", Value::from(-0.0)), ("lorem", Value::from([1, 2]))]);
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+    assert_ne!(Value::from([1, 2]), Value::from([2, 1]));
+}
+
+#[test]
+fn value_get() {
+    let v = Value::from([
+        (
+            "lorem",
+            Value::from([("ipsum", Value::from(["dolor", "sit"]))]),
+        ),
+        ("amet", Value::from([("0", true)])),
+    ]);
+    assert_eq!(v.get(""), Some(&v));
+    assert_eq!(v.get("lorem.ipsum.0"), Some(&Value::from("dolor")));
+    assert_eq!(v.get("lorem.ipsum.1"), Some(&Value::from("sit")));
+    assert_eq!(v.get("lorem.ipsum.-1"), Some(&Value::from("sit")));
+    assert_eq!(v.get("lorem.ipsum.-2"), Some(&Value::from("dolor")));
+    assert_eq!(v.get("lorem.ipsum.-0"), Some(&Value::from("dolor")));
+    assert_eq!(v.get("amet.0"), Some(&Value::from(true)));
+}
+
+#[test]
+fn value_get_not_found() {
+    let v = Value::from([("lorem", Value::from(["ipsum"]))]);
+    assert_eq!(v.get("dolor"), None);
+    assert_eq!(v.get("lorem.1"), None);
+    assert_eq!(v.get("lorem.-2"), None);
+    assert_eq!(v.get("lorem.+0"), None);
+    assert_eq!(v.get("lorem.ipsum"), None);
+    assert_eq!(v.get("lorem.0.sit"), None);
+    assert_eq!(v.get("lorem."), None);
+}
+
+#[test]
+fn value_get_mut() {
+    let mut v = Value::from([("lorem", Value::from(["ipsum", "dolor"]))]);
+    *v.get_mut("lorem.-1").unwrap() = Value::from("sit");
+    assert_eq!(v, Value::from([("lorem", Value::from(["ipsum", "sit"]))]));
+    assert!(v.get_mut("lorem.2").is_none());
+}
+
+#[test]
+fn value_merge() {
+    let mut v = Value::from([
+        (
+            "lorem",
+            Value::from([("ipsum", Value::from(1)), ("dolor", Value::from(2))]),
+        ),
+        ("sit", Value::from([1, 2])),
+        ("amet", Value::from("a")),
+    ]);
+    v.merge(Value::from([
+        (
+            "lorem",
+            Value::from([("dolor", Value::from(3)), ("consectetur", Value::from(4))]),
+        ),
+        ("sit", Value::from([3])),
+        ("amet", Value::None),
+        ("adipiscing", Value::from(true)),
+    ]));
+    assert_eq!(
+        v,
+        Value::from([
+            (
+                "lorem",
+                Value::from([
+                    ("ipsum", Value::from(1)),
+                    ("dolor", Value::from(3)),
+                    ("consectetur", Value::from(4)),
+                ]),
+            ),
+            ("sit", Value::from([3])),
+            ("amet", Value::None),
+            ("adipiscing", Value::from(true)),
+        ])
+    );
+}
+
+#[test]
+fn value_merge_replaces_non_maps() {
+    let mut v = Value::from([("lorem", 1)]);
+    v.merge(Value::from(["ipsum"]));
+    assert_eq!(v, Value::from(["ipsum"]));
+    v.merge(Value::from([("dolor", 2)]));
+    assert_eq!(v, Value::from([("dolor", 2)]));
+}
+
+#[test]
+fn value_map_builder() {
+    let v = Value::map()
+        .insert("lorem", 1)
+        .insert(String::from("ipsum"), Value::list(["dolor"]))
+        .insert("sit", Value::map().insert("amet", true))
+        .insert("lorem", 2)
+        .build();
+    assert_eq!(
+        v,
+        Value::from([
+            ("ipsum", Value::from(["dolor"])),
+            ("lorem", Value::from(2)),
+            ("sit", Value::from([("amet", true)])),
+        ])
+    );
+    assert_eq!(Value::from(Value::map()), Value::Map(Default::default()));
+}
+
+#[test]
+fn value_list() {
+    assert_eq!(Value::list(vec!["a", "b"]), Value::from(["a", "b"]));
+    assert_eq!(Value::list(Vec::<i64>::new()), Value::List(vec![]));
+}
+
+#[test]
+fn value_as_number() {
+    assert_eq!(Value::from(7).as_i64(), Some(7));
+    assert_eq!(Value::from(7).as_f64(), Some(7.0));
+    assert_eq!(Value::from(0.5).as_i64(), None);
+    assert_eq!(Value::from(0.5).as_f64(), Some(0.5));
+    assert_eq!(Value::from(true).as_i64(), None);
+    assert_eq!(Value::from("7").as_f64(), None);
+    assert_eq!(Value::None.as_f64(), None);
+}
+
+#[test]
+fn value_as_and_into() {
+    assert_eq!(Value::from(false).as_bool(), Some(false));
+    assert_eq!(Value::None.as_bool(), None);
+    assert_eq!(Value::from("lorem").as_str(), Some("lorem"));
+    assert_eq!(Value::from(["lorem"]).as_str(), None);
+    assert_eq!(
+        Value::from(["lorem"]).as_list(),
+        Some(&[Value::from("lorem")][..])
+    );
+    assert_eq!(Value::from([("lorem", 1)]).as_list(), None);
+    assert!(Value::from([("lorem", 1)]).as_map().is_some());
+    assert_eq!(Value::from("lorem").as_map(), None);
+    assert_eq!(
+        Value::from("lorem").into_string(),
+        Some(String::from("lorem"))
+    );
+    assert_eq!(Value::from(1).into_string(), None);
+    assert_eq!(Value::from([1]).into_list(), Some(vec![Value::from(1)]));
+    assert_eq!(Value::None.into_list(), None);
+    assert_eq!(Value::from([("lorem", 1)]).into_map().unwrap().len(), 1);
+    assert_eq!(Value::from([1]).into_map(), None);
+}
+
+#[test]
+fn value_take() {
+    let mut v = Value::from(["lorem", "ipsum"]);
+    let taken = v.get_mut("1").unwrap().take();
+    assert_eq!(taken, Value::from("ipsum"));
+    assert_eq!(v, Value::List(vec![Value::from("lorem"), Value::None]));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn value_from_json() {
+    let json = serde_json::json!({
+        "none": null,
+        "bool": true,
+        "int": -7,
+        "float": 1.5,
+        "string": "lorem",
+        "list": [1, "ipsum"],
+        "map": { "dolor": [] },
+    });
+    let exp = Value::from([
+        ("none", Value::None),
+        ("bool", Value::from(true)),
+        ("int", Value::from(-7)),
+        ("float", Value::from(1.5)),
+        ("string", Value::from("lorem")),
+        (
+            "list",
+            Value::List(vec![Value::from(1), Value::from("ipsum")]),
+        ),
+        ("map", Value::from([("dolor", Value::List(vec![]))])),
+    ]);
+    assert_eq!(Value::from(json), exp);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn value_from_json_integer_overflow() {
+    let json = serde_json::json!([u64::MAX, i64::MAX, i64::MIN]);
+    let exp = Value::from([i64::MAX, i64::MAX, i64::MIN]);
+    assert_eq!(Value::from(json), exp);
+}